@@ -0,0 +1,128 @@
+/*
+    *Api*: Lets other programs (e.g. a web service) query and submit to the peer, without going through standard input.
+    - Requests that are sent to the peer's main loop over a channel, each with a channel for its reply.
+    - (With the `http-api` feature) An HTTP server that serves these requests as JSON endpoints.
+*/
+
+use super::{block::Block, peer::Metrics, transaction::Transaction};
+use tokio::sync::{mpsc::UnboundedSender, oneshot};
+
+/* Requests for the peer to handle, each replying with:
+    (1) The index of the last block in the main chain
+    (2) The main chain block at an index, if any
+    (3) Whether a transaction was validated and added to the pool (and broadcast), or why not
+    (4) The ids of connected peers
+    (5) Counts of the blocks and transactions the peer has handled
+*/
+#[derive(Debug)]
+pub enum ApiRequest {
+    Height {
+        reply: oneshot::Sender<usize>,
+    },
+    Block {
+        idx: usize,
+        reply: oneshot::Sender<Option<Block>>,
+    },
+    Txn {
+        txn: Transaction,
+        reply: oneshot::Sender<Result<(), String>>,
+    },
+    Peers {
+        reply: oneshot::Sender<Vec<String>>,
+    },
+    Metrics {
+        reply: oneshot::Sender<Metrics>,
+    },
+}
+
+pub type ApiSender = UnboundedSender<ApiRequest>;
+
+// Send a request to the peer and wait for its reply, or None if the peer has shut down
+pub async fn request<T>(
+    sender: &ApiSender,
+    request: impl FnOnce(oneshot::Sender<T>) -> ApiRequest,
+) -> Option<T> {
+    let (reply, response) = oneshot::channel();
+    sender.send(request(reply)).ok()?;
+    response.await.ok()
+}
+
+#[cfg(feature = "http-api")]
+pub use server::{router, serve};
+
+#[cfg(feature = "http-api")]
+mod server {
+    use super::{request, ApiRequest, ApiSender};
+    use crate::{block::Block, peer::Metrics, transaction::Transaction};
+    use axum::{
+        extract::{Path, State},
+        http::StatusCode,
+        routing::{get, post},
+        Json, Router,
+    };
+    use serde_json::{json, Value};
+
+    type ApiResult = Result<Json<Value>, (StatusCode, String)>;
+
+    fn unavailable() -> (StatusCode, String) {
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "Peer is unavailable".to_string(),
+        )
+    }
+
+    // Routes for `GET /chain/height`, `GET /block/:idx`, `POST /txn`, `GET /peers`, and `GET /metrics`
+    pub fn router(sender: ApiSender) -> Router {
+        Router::new()
+            .route("/chain/height", get(height))
+            .route("/block/:idx", get(block))
+            .route("/txn", post(txn))
+            .route("/peers", get(peers))
+            .route("/metrics", get(metrics))
+            .with_state(sender)
+    }
+
+    // Serve the routes at an address (e.g. "127.0.0.1:8080") until the server fails
+    pub async fn serve(addr: &str, sender: ApiSender) -> std::io::Result<()> {
+        let listener = tokio::net::TcpListener::bind(addr).await?;
+        axum::serve(listener, router(sender)).await
+    }
+
+    async fn height(State(sender): State<ApiSender>) -> ApiResult {
+        let height: usize = request(&sender, |reply| ApiRequest::Height { reply })
+            .await
+            .ok_or_else(unavailable)?;
+        Ok(Json(json!({ "height": height })))
+    }
+
+    async fn block(State(sender): State<ApiSender>, Path(idx): Path<usize>) -> ApiResult {
+        let block: Block = request(&sender, |reply| ApiRequest::Block { idx, reply })
+            .await
+            .ok_or_else(unavailable)?
+            .ok_or((StatusCode::NOT_FOUND, format!("No block at index {}", idx)))?;
+        Ok(Json(json!(block)))
+    }
+
+    async fn txn(State(sender): State<ApiSender>, Json(txn): Json<Transaction>) -> ApiResult {
+        let hash: String = txn.hash.clone();
+        request(&sender, |reply| ApiRequest::Txn { txn, reply })
+            .await
+            .ok_or_else(unavailable)?
+            .map_err(|e| (StatusCode::UNPROCESSABLE_ENTITY, e))?;
+        Ok(Json(json!({ "hash": hash })))
+    }
+
+    async fn peers(State(sender): State<ApiSender>) -> ApiResult {
+        let peers: Vec<String> = request(&sender, |reply| ApiRequest::Peers { reply })
+            .await
+            .ok_or_else(unavailable)?;
+        Ok(Json(json!({ "peers": peers })))
+    }
+
+    async fn metrics(State(sender): State<ApiSender>) -> ApiResult {
+        let metrics: Metrics = request(&sender, |reply| ApiRequest::Metrics { reply })
+            .await
+            .ok_or_else(unavailable)?;
+        Ok(Json(json!(metrics)))
+    }
+}
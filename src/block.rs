@@ -1,17 +1,33 @@
 /*
     *Block*: Provides the block and Proof-of-Work mining algorithm.
     - Block internals.
-    - Methods for hashing, mining, and validating blocks.
+    - Methods for hashing, mining, signing, and validating blocks.
     - Result and error types from handling new blocks.
 */
 
-use super::{crypt, util::abbrev};
-use chrono::{DateTime, Utc};
+use super::{crypt, transaction::Transaction, util::abbrev};
+use chrono::Utc;
+use libp2p::identity::{Keypair, PublicKey};
 use log::info;
 use serde::{Deserialize, Serialize};
-use to_binary::BinaryString;
+use std::sync::atomic::{AtomicBool, Ordering};
 
-const DIFFICULTY_PREFIX: &str = "00";
+// Default number of leading zero bits required of a block's (32-byte) hash
+pub const DEFAULT_DIFFICULTY: usize = 2;
+// Default contents of the genesis block
+pub const GENESIS_DATA: &str = "genesis";
+pub const GENESIS_TIMESTAMP: i64 = 1730051971;
+// Contents of a block mined without transactions, which isn't parsed for any
+pub const EMPTY_BLOCK_DATA: &str = "empty";
+// Prefix of the bytes that a block's hash is computed from, naming the layout of the rest
+const BLOCK_HASH_TAG: &[u8] = b"powp2p-block-v2";
+// How far (in seconds) a received block's timestamp may be ahead of the local clock
+pub const MAX_FUTURE_DRIFT: i64 = 2 * 60 * 60;
+
+// Number of threads to mine with in parallel, one per available core
+pub fn available_threads() -> usize {
+    std::thread::available_parallelism().map_or(1, |n| n.get())
+}
 
 #[derive(Clone, Debug, Serialize, Deserialize, Hash, PartialEq, Eq)]
 pub struct Block {
@@ -23,121 +39,305 @@ pub struct Block {
     pub timestamp: i64,
     // reference to the previous block's hash
     pub prev_hash: String,
+    // number of leading zero bits required of the hash
+    pub difficulty: usize,
     // arbitrary value controlled by miner to find a valid block hash
     pub nonce: u64,
     // hash of the above
     pub hash: String,
+    // public key of the miner, and their signature of the hash (both empty for the genesis block)
+    #[serde(default)]
+    pub miner_pubk: String,
+    #[serde(default)]
+    pub sig: String,
 }
 
 /* Block core operations */
 impl Block {
     // Construct a genesis block
     pub fn genesis() -> Block {
-        let (idx, data, timestamp, prev_hash, nonce) = (
+        Self::genesis_with(DEFAULT_DIFFICULTY)
+    }
+
+    // Construct a genesis block, declaring the difficulty that its chain is mined at
+    pub fn genesis_with(difficulty: usize) -> Block {
+        Self::custom_genesis(GENESIS_DATA, GENESIS_TIMESTAMP, difficulty)
+    }
+
+    // Construct a genesis block with the given contents, e.g. to start a network that is distinct from the default one
+    pub fn custom_genesis(data: &str, timestamp: i64, difficulty: usize) -> Block {
+        let (idx, data, prev_hash, nonce) = (
             0,
-            "genesis".to_string(),
-            1730051971,
+            data.to_string(),
             crypt::encode_bytes_to_hex(crypt::ZERO_U32),
             0,
         );
-        let hash: String = Self::compute_hash(idx, &data, timestamp, &prev_hash, nonce);
+        let hash: String =
+            Self::compute_hash(idx, &data, timestamp, &prev_hash, difficulty, nonce, "");
         Block {
             idx,
             data,
             timestamp,
             prev_hash,
+            difficulty,
             nonce,
             hash,
+            miner_pubk: String::new(),
+            sig: String::new(),
         }
     }
 
-    // Find a valid nonce and hash to construct a new block
-    pub fn mine_block(last_block: &Block, data: &str) -> Block {
+    // Find a valid nonce and hash to construct a new block signed by the miner, inheriting the difficulty of the last block
+    pub fn mine_block(last_block: &Block, data: &str, keys: &Keypair) -> Block {
+        Self::mine_block_at(last_block, data, last_block.difficulty, keys)
+    }
+
+    // Find a valid nonce and hash to construct a new block signed by the miner at the given difficulty
+    pub fn mine_block_at(
+        last_block: &Block,
+        data: &str,
+        difficulty: usize,
+        keys: &Keypair,
+    ) -> Block {
+        Self::mine_block_cancellable(last_block, data, difficulty, keys, &AtomicBool::new(false))
+            .expect("mining is never cancelled")
+    }
+
+    // Find a valid nonce and hash to construct a new block, giving up (before trying the next nonce) once `cancel` is set
+    pub fn mine_block_cancellable(
+        last_block: &Block,
+        data: &str,
+        difficulty: usize,
+        keys: &Keypair,
+        cancel: &AtomicBool,
+    ) -> Option<Block> {
+        Self::mine_block_with(last_block, data, difficulty, keys, cancel, 1, 0)
+    }
+
+    // Find a valid nonce and hash to construct a new block, starting the search from the given nonce rather than 0
+    pub fn mine_block_from(
+        last_block: &Block,
+        data: &str,
+        difficulty: usize,
+        keys: &Keypair,
+        start_nonce: u64,
+    ) -> Block {
+        let cancel = AtomicBool::new(false);
+        Self::mine_block_with(last_block, data, difficulty, keys, &cancel, 1, start_nonce)
+            .expect("mining is never cancelled")
+    }
+
+    /* Find a valid nonce and hash to construct a new block signed by the miner, inheriting the difficulty of the
+    last block, by searching disjoint nonces on each of the given number of threads (e.g. `available_threads()`). */
+    pub fn mine_block_parallel(
+        last_block: &Block,
+        data: &str,
+        threads: usize,
+        keys: &Keypair,
+    ) -> Block {
+        let cancel = AtomicBool::new(false);
+        Self::mine_block_with(
+            last_block,
+            data,
+            last_block.difficulty,
+            keys,
+            &cancel,
+            threads,
+            0,
+        )
+        .expect("mining is never cancelled")
+    }
+
+    fn mine_block_with(
+        last_block: &Block,
+        data: &str,
+        difficulty: usize,
+        keys: &Keypair,
+        cancel: &AtomicBool,
+        threads: usize,
+        start_nonce: u64,
+    ) -> Option<Block> {
         let idx = last_block.idx + 1;
         let prev_hash = last_block.hash.clone();
+        let miner_pubk: String = crypt::encode_pubk_to_hex(keys.public());
 
-        let now: DateTime<Utc> = Utc::now();
+        // never date a block before its parent, e.g. if the parent's miner has a clock that is slightly ahead
+        let timestamp: i64 = Utc::now().timestamp().max(last_block.timestamp);
         info!(
             "mining block for:\n
                 Block {{ idx: {}, data: {}, timestamp: {}, prev_hash: {}, nonce: ?, hash: ? }}",
-            idx, data, now, prev_hash
+            idx, data, timestamp, prev_hash
         );
 
-        let mut nonce: u64 = 0;
-        loop {
-            let hash: String = Self::compute_hash(idx, data, now.timestamp(), &prev_hash, nonce);
-            let BinaryString(hash_bin) =
-                BinaryString::from_hex(&hash).expect("can convert hex string to binary");
-
-            if hash_bin.starts_with(DIFFICULTY_PREFIX) {
-                info!(
-                    "mine_block(): mined! \n nonce: {}, hash: {}, hash (bin repr): {}",
-                    nonce, hash, hash_bin
-                );
-                return Self {
+        /* each thread k searches the nonces k, k + threads, k + 2*threads, ..., until any of them finds a valid one.
+        once a thread runs out of nonces, it moves its block to a later timestamp (which is also hashed) and starts over */
+        let threads: u64 = threads.max(1) as u64;
+        let found = AtomicBool::new(false);
+        let search = |first: u64| -> Option<(i64, u64, String)> {
+            let mut timestamp: i64 = timestamp;
+            let mut nonce: u64 = start_nonce.saturating_add(first);
+            loop {
+                if cancel.load(Ordering::Relaxed) || found.load(Ordering::Relaxed) {
+                    info!("mine_block(): stopped searching at nonce {}", nonce);
+                    return None;
+                }
+                let hash: [u8; 32] = Self::hash_bytes(
                     idx,
-                    data: data.to_string(),
-                    timestamp: now.timestamp(),
-                    prev_hash: prev_hash.clone(),
+                    data,
+                    timestamp,
+                    &prev_hash,
+                    difficulty,
                     nonce,
-                    hash,
+                    &miner_pubk,
+                );
+
+                let zero_bits: usize = leading_zero_bits(&hash);
+                if zero_bits >= difficulty {
+                    let hash: String = crypt::encode_bytes_to_hex(hash);
+                    info!(
+                        "mine_block(): mined! \n nonce: {}, hash: {}, leading zero bits: {}",
+                        nonce, hash, zero_bits
+                    );
+                    found.store(true, Ordering::Relaxed);
+                    return Some((timestamp, nonce, hash));
+                }
+                nonce = match nonce.checked_add(threads) {
+                    Some(next) => next,
+                    None => {
+                        timestamp = Utc::now().timestamp().max(timestamp + 1);
+                        info!(
+                            "mine_block(): exhausted all nonces, retrying at timestamp {}",
+                            timestamp
+                        );
+                        first
+                    }
                 };
             }
-            nonce += 1;
-        }
+        };
+        let (timestamp, nonce, hash): (i64, u64, String) = if threads == 1 {
+            search(0)?
+        } else {
+            std::thread::scope(|scope| {
+                let workers: Vec<_> = (0..threads)
+                    .map(|first| scope.spawn(move || search(first)))
+                    .collect();
+                // the remaining workers stop (and are joined by the scope) once one of them finds a valid nonce
+                workers
+                    .into_iter()
+                    .find_map(|worker| worker.join().expect("mining thread doesn't panic"))
+            })?
+        };
+
+        let sig: Vec<u8> = keys
+            .sign(hash.as_bytes())
+            .expect("can sign with an ed25519 keypair");
+        Some(Self {
+            idx,
+            data: data.to_string(),
+            timestamp,
+            prev_hash,
+            difficulty,
+            nonce,
+            hash,
+            miner_pubk,
+            sig: crypt::encode_bytes_to_hex(sig),
+        })
     }
 
-    // Compute the hex-string of a sha256 hash (i.e. a 32-byte array) of a block
-    fn compute_hash(
+    /* Compute the hex-string of a sha256 hash (i.e. a 32-byte array) of a block.
+    The hash is taken over a fixed byte layout of the block's fields, in this order:
+        "powp2p-block-v2" | idx (u64) | data (str) | timestamp (i64) | prev_hash (str) | difficulty (u64) | nonce (u64) | miner_pubk (str)
+    where integers are 8 bytes little-endian, and strings are their utf-8 length (u64) followed by their utf-8 bytes.
+    The miner's key is hashed (and so signed) so that a block can't be re-signed and passed off by anyone else.
+    Changing the layout changes every block's hash, so splits the network. */
+    pub(crate) fn compute_hash(
         idx: usize,
         data: &str,
         timestamp: i64,
-        prev_hash: &String,
+        prev_hash: &str,
+        difficulty: usize,
         nonce: u64,
+        miner_pubk: &str,
     ) -> String {
+        crypt::encode_bytes_to_hex(Self::hash_bytes(
+            idx, data, timestamp, prev_hash, difficulty, nonce, miner_pubk,
+        ))
+    }
+
+    // The sha256 hash of a block as bytes, which mining checks before encoding only the one it finds
+    fn hash_bytes(
+        idx: usize,
+        data: &str,
+        timestamp: i64,
+        prev_hash: &str,
+        difficulty: usize,
+        nonce: u64,
+        miner_pubk: &str,
+    ) -> [u8; 32] {
         use sha2::{Digest, Sha256};
 
-        // create a sha256 hasher instance
+        let put_str = |hasher: &mut Sha256, s: &str| {
+            hasher.update((s.len() as u64).to_le_bytes());
+            hasher.update(s.as_bytes());
+        };
         let mut hasher: Sha256 = Sha256::new();
-
-        // translate the block from a json value -> string -> byte array &[u8], used as input data to the hasher
-        let json: serde_json::Value = serde_json::json!({
-            "idx": idx,
-            "data": data,
-            "timestamp": timestamp,
-            "prev_hash": prev_hash,
-            "nonce": nonce
-        });
-        hasher.update(json.to_string().as_bytes());
+        hasher.update(BLOCK_HASH_TAG);
+        hasher.update((idx as u64).to_le_bytes());
+        put_str(&mut hasher, data);
+        hasher.update(timestamp.to_le_bytes());
+        put_str(&mut hasher, prev_hash);
+        hasher.update((difficulty as u64).to_le_bytes());
+        hasher.update(nonce.to_le_bytes());
+        put_str(&mut hasher, miner_pubk);
 
         // retrieve hash result
-        let hash: [u8; 32] = hasher
+        hasher
             .finalize() // Sha256 -> GenericArray<u8, U32>
-            .into(); // GenericArray<u8, U32> -> [u8; 32].
-
-        crypt::encode_bytes_to_hex(hash)
+            .into() // GenericArray<u8, U32> -> [u8; 32].
     }
 
     // Validate a block as its own entity
     pub fn validate(&self) -> Result<(), NextBlockErr> {
-        //   check if block's hash has a valid number of leading zeros
-        let BinaryString(hash_binary) =
-            BinaryString::from_hex(&self.hash).expect("Can convert hex string to binary");
-        if !hash_binary.starts_with(DIFFICULTY_PREFIX) && self.idx != 0 {
-            // ignore the genesis block
+        // the genesis block isn't mined, so is checked against its fixed contents instead
+        if self.idx == 0 {
+            return self.validate_genesis();
+        }
+        //   check if block's hash has a valid number of leading zero bits
+        if !meets_difficulty(&self.hash, self.difficulty) {
             return Err(NextBlockErr::DifficultyCheckFailed {
                 idx: self.idx,
                 hash: self.hash.clone(),
-                difficulty_prefix: DIFFICULTY_PREFIX.to_string(),
+                difficulty: self.difficulty,
             });
         }
         //  check if block's hash is indeed the correct hash of itself.
+        self.validate_hash()?;
+        //  check if block's hash was signed by its miner.
+        self.validate_signature()
+    }
+
+    /* Validate a block at idx 0 as a genesis block: it has no parent and a consistent hash.
+    Its data and timestamp are chosen by whoever starts the network, so chains are told apart by their genesis hash. */
+    fn validate_genesis(&self) -> Result<(), NextBlockErr> {
+        if self.prev_hash != crypt::encode_bytes_to_hex(crypt::ZERO_U32) {
+            return Err(NextBlockErr::InvalidGenesis {
+                idx: self.idx,
+                hash: self.hash.clone(),
+            });
+        }
+        self.validate_hash()
+    }
+
+    // Check the block's hash is the hash of its contents
+    fn validate_hash(&self) -> Result<(), NextBlockErr> {
         let computed_hash = Self::compute_hash(
             self.idx,
             &self.data,
             self.timestamp,
             &self.prev_hash,
+            self.difficulty,
             self.nonce,
+            &self.miner_pubk,
         );
         if self.hash != computed_hash {
             return Err(NextBlockErr::InconsistentHash {
@@ -146,10 +346,49 @@ impl Block {
                 computed_hash,
             });
         }
+        Ok(())
+    }
 
+    // Check the block's signature is its miner's signature of its hash
+    fn validate_signature(&self) -> Result<(), NextBlockErr> {
+        let invalid = |reason: String| NextBlockErr::InvalidBlockSignature {
+            idx: self.idx,
+            hash: self.hash.clone(),
+            reason,
+        };
+        let pubk: PublicKey = crypt::decode_hex_to_pubk(&self.miner_pubk, crypt::PUBK_U8S_LEN)
+            .map_err(|e| invalid(e.to_string()))?;
+        let sig: Vec<u8> = crypt::decode_hex_to_bytes(&self.sig, crypt::SIG_U8S_LEN)
+            .map_err(|e| invalid(e.to_string()))?;
+        if !pubk.verify(self.hash.as_bytes(), &sig) {
+            return Err(invalid(format!(
+                "signature doesn't match miner {}",
+                abbrev(&self.miner_pubk)
+            )));
+        }
         Ok(())
     }
 
+    // The transactions stored in the block's data, as either a list or (in older blocks) a single transaction, or none for an empty block
+    pub fn transactions(&self) -> Vec<Transaction> {
+        Self::parse_transactions(&self.data).unwrap_or_default()
+    }
+
+    // The transactions stored in block data, or None if it holds something other than transactions
+    pub fn parse_transactions(data: &str) -> Option<Vec<Transaction>> {
+        if data == EMPTY_BLOCK_DATA {
+            return Some(vec![]);
+        }
+        serde_json::from_str::<Vec<Transaction>>(data)
+            .or_else(|_| serde_json::from_str::<Transaction>(data).map(|txn| vec![txn]))
+            .ok()
+    }
+
+    // Block data storing a batch of transactions
+    pub fn transactions_data(txns: &[Transaction]) -> String {
+        serde_json::to_string(txns).expect("can serialize transactions")
+    }
+
     // Validate two consecutive blocks
     pub fn validate_parent(&self, parent: &Block) -> Result<(), NextBlockErr> {
         parent.validate()?;
@@ -161,8 +400,68 @@ impl Block {
                 parent_hash: parent.hash.to_string(),
             });
         }
+        if self.timestamp < parent.timestamp {
+            return Err(NextBlockErr::TimestampRegressed {
+                idx: self.idx,
+                timestamp: self.timestamp,
+                parent_timestamp: parent.timestamp,
+            });
+        }
+        Ok(())
+    }
+
+    /* Check the block isn't dated too far ahead of the local clock.
+    As this depends on when it is checked, it is only used on freshly received blocks rather than in `validate`. */
+    pub fn validate_timestamp(&self) -> Result<(), NextBlockErr> {
+        self.validate_timestamp_at(Utc::now().timestamp())
+    }
+
+    pub fn validate_timestamp_at(&self, now: i64) -> Result<(), NextBlockErr> {
+        if self.timestamp > now.saturating_add(MAX_FUTURE_DRIFT) {
+            return Err(NextBlockErr::TimestampTooFarAhead {
+                idx: self.idx,
+                timestamp: self.timestamp,
+                now,
+            });
+        }
         Ok(())
     }
+
+    // Expected number of hashes needed to mine the block, i.e. 2^difficulty
+    pub fn work(&self) -> u128 {
+        1u128
+            .checked_shl(self.difficulty as u32)
+            .unwrap_or(u128::MAX)
+    }
+
+    /* Whether this block should replace a competing block at the same height as the main chain tip.
+    The block with more work wins, then the one with the lexicographically smaller hash, so that every peer
+    settles on the same tip regardless of the order in which the competing blocks arrive. */
+    pub fn beats(&self, other: &Block) -> bool {
+        (self.work(), std::cmp::Reverse(&self.hash))
+            > (other.work(), std::cmp::Reverse(&other.hash))
+    }
+}
+
+// Count the leading zero bits of a hash, across as many of its bytes as are zero
+pub fn leading_zero_bits(hash: &[u8]) -> usize {
+    let zero_bytes: usize = hash.iter().take_while(|&&byte| byte == 0).count();
+    zero_bytes * 8
+        + hash
+            .get(zero_bytes)
+            .map_or(0, |byte| byte.leading_zeros() as usize)
+}
+
+// Count the leading zero bits of a hex-encoded 32-byte hash, or None if it isn't one
+fn hash_leading_zero_bits(hash: &str) -> Option<usize> {
+    crypt::decode_hex_to_bytes(&hash.to_string(), 32)
+        .ok()
+        .map(|bytes| leading_zero_bits(&bytes))
+}
+
+// Check a hex-encoded hash has at least `difficulty` leading zero bits, which one that isn't a 32-byte hash never does
+pub fn meets_difficulty(hash: &str, difficulty: usize) -> bool {
+    hash_leading_zero_bits(hash).is_some_and(|bits| bits >= difficulty)
 }
 
 impl std::fmt::Display for Block {
@@ -175,22 +474,50 @@ impl std::fmt::Display for Block {
             Data:            {}\n\
             Previous Hash:   {}\n\
             Hash:            {}\n\
+            Miner:           {}\n\
             ================================================",
-            self.idx, self.data, self.prev_hash, self.hash,
+            self.idx,
+            self.data,
+            self.prev_hash,
+            self.hash,
+            abbrev(&self.miner_pubk),
         )
     }
 }
 
 /* Blocks: Ensures a valid subchain i.e. a non-empty sequence of blocks where each block correctly references the preceding one */
 #[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(try_from = "Vec<Block>")]
 pub struct Blocks(Vec<Block>);
 
+// Blocks received from elsewhere (e.g. in a message) must be non-empty, as every use of them assumes so;
+// they are otherwise validated where they are used, as a chain, fork or range
+impl TryFrom<Vec<Block>> for Blocks {
+    type Error = NextBlockErr;
+    fn try_from(vec: Vec<Block>) -> Result<Self, Self::Error> {
+        if vec.is_empty() {
+            return Err(NextBlockErr::NoBlocks);
+        }
+        Ok(Blocks(vec))
+    }
+}
+
 impl Blocks {
     // Construct a genesis block
     pub fn genesis() -> Blocks {
         Blocks(vec![Block::genesis()])
     }
 
+    // Construct a genesis block at the given difficulty
+    pub fn genesis_with(difficulty: usize) -> Blocks {
+        Blocks(vec![Block::genesis_with(difficulty)])
+    }
+
+    // Construct a genesis block with the given contents
+    pub fn custom_genesis(data: &str, timestamp: i64, difficulty: usize) -> Blocks {
+        Blocks(vec![Block::custom_genesis(data, timestamp, difficulty)])
+    }
+
     // Safe constructor
     pub fn from_vec(vec: Vec<Block>) -> Result<Blocks, NextBlockErr> {
         let blocks = Blocks(vec);
@@ -203,6 +530,24 @@ impl Blocks {
         self.0
     }
 
+    // Validate a segment of blocks, e.g. received as a range, and that its first block follows the given parent hash and idx
+    pub fn validate_connecting(
+        &self,
+        expected_first_prev_hash: &str,
+        expected_first_idx: usize,
+    ) -> Result<(), NextBlockErr> {
+        let first: &Block = self.0.first().ok_or(NextBlockErr::NoBlocks)?;
+        if first.prev_hash != expected_first_prev_hash || first.idx != expected_first_idx {
+            return Err(NextBlockErr::InvalidParent {
+                idx: first.idx,
+                prev_hash: first.prev_hash.clone(),
+                parent_idx: expected_first_idx.saturating_sub(1),
+                parent_hash: expected_first_prev_hash.to_string(),
+            });
+        }
+        self.validate()
+    }
+
     pub fn validate(&self) -> Result<(), NextBlockErr> {
         let mut curr: &Block = self.0.first().ok_or(NextBlockErr::NoBlocks)?;
         curr.validate()?;
@@ -215,9 +560,16 @@ impl Blocks {
         Ok(())
     }
 
-    // Mine a new valid block from given data
-    pub fn mine_block(&mut self, data: &str) {
-        let new_block = Block::mine_block(self.last(), data);
+    // Sum of the work of every block
+    pub fn total_work(&self) -> u128 {
+        self.0
+            .iter()
+            .fold(0, |work, b| work.saturating_add(b.work()))
+    }
+
+    // Mine a new valid block from given data at the given difficulty
+    pub fn mine_block(&mut self, data: &str, difficulty: usize, keys: &Keypair) {
+        let new_block = Block::mine_block_at(self.last(), data, difficulty, keys);
         self.0.push(new_block)
     }
 
@@ -239,6 +591,10 @@ impl Blocks {
         self.0.len()
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
     // Safe push to tail
     pub fn push_back(&mut self, new_block: Block) -> Result<(), NextBlockErr> {
         new_block.validate()?;
@@ -247,18 +603,31 @@ impl Blocks {
         Ok(())
     }
 
-    // Safe push to head
+    // Safe push to head; validating the old head against the new block checks both the hash link and idx contiguity at the seam
     pub fn push_front(&mut self, new_block: Block) -> Result<(), NextBlockErr> {
         new_block.validate()?;
         self.first().validate_parent(&new_block)?;
         self.0.insert(0, new_block);
+        debug_assert!(
+            self.validate().is_ok(),
+            "push_front produced invalid blocks"
+        );
         Ok(())
     }
 
-    // Safe append between two valid subchains
+    // Safe append between two valid subchains, reporting the position of the first invalid block in the suffix
     pub fn append(&mut self, mut suffix: Blocks) -> Result<(), NextBlockErr> {
-        suffix.validate()?;
-        suffix.first().validate_parent(self.last())?;
+        let mut parent: &Block = self.last();
+        for (position, block) in suffix.iter().enumerate() {
+            block
+                .validate()
+                .and_then(|()| block.validate_parent(parent))
+                .map_err(|e| NextBlockErr::InvalidSuffix {
+                    position,
+                    e: Box::new(e),
+                })?;
+            parent = block;
+        }
         self.0.append(&mut suffix.0);
         Ok(())
     }
@@ -302,9 +671,21 @@ impl Blocks {
         self.0.iter().find(|block| prop(block))
     }
 
-    pub fn iter(&self) -> std::slice::Iter<Block> {
+    // The blocks at positions `from` to `to` inclusive, or None if the range is empty or out of bounds
+    pub fn range(&self, from: usize, to: usize) -> Option<Blocks> {
+        if from > to || to >= self.len() {
+            return None;
+        }
+        Blocks::from_vec(self.0[from..=to].to_vec()).ok()
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Block> {
         self.0.iter()
     }
+
+    pub fn contains_hash(&self, hash: &str) -> bool {
+        self.0.iter().any(|block| block.hash == hash)
+    }
 }
 
 impl std::fmt::Display for Blocks {
@@ -316,6 +697,57 @@ impl std::fmt::Display for Blocks {
     }
 }
 
+/* BlockHeaderSummary: A block without its data, for light clients to check the linkage and proof-of-work of a chain
+before fetching the full blocks they need */
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct BlockHeaderSummary {
+    pub idx: usize,
+    pub hash: String,
+    pub prev_hash: String,
+    pub timestamp: i64,
+    pub difficulty: usize,
+}
+
+impl From<&Block> for BlockHeaderSummary {
+    fn from(block: &Block) -> Self {
+        BlockHeaderSummary {
+            idx: block.idx,
+            hash: block.hash.clone(),
+            prev_hash: block.prev_hash.clone(),
+            timestamp: block.timestamp,
+            difficulty: block.difficulty,
+        }
+    }
+}
+
+// Check that each header meets its difficulty (excluding a genesis header) and references the preceding one
+pub fn verify_headers(headers: &[BlockHeaderSummary]) -> Result<(), NextBlockErr> {
+    if headers.is_empty() {
+        return Err(NextBlockErr::NoBlocks);
+    }
+    for header in headers.iter().filter(|h| h.idx != 0) {
+        if !meets_difficulty(&header.hash, header.difficulty) {
+            return Err(NextBlockErr::DifficultyCheckFailed {
+                idx: header.idx,
+                hash: header.hash.clone(),
+                difficulty: header.difficulty,
+            });
+        }
+    }
+    for pair in headers.windows(2) {
+        let (parent, header) = (&pair[0], &pair[1]);
+        if parent.hash != header.prev_hash || parent.idx + 1 != header.idx {
+            return Err(NextBlockErr::InvalidParent {
+                idx: header.idx,
+                prev_hash: header.prev_hash.clone(),
+                parent_idx: parent.idx,
+                parent_hash: parent.hash.clone(),
+            });
+        }
+    }
+    Ok(())
+}
+
 // The result of adding a new block to a blockchain network
 #[derive(Debug)]
 pub enum NextBlockResult {
@@ -392,18 +824,25 @@ impl std::fmt::Display for NextBlockResult {
 }
 
 // For validating whether one block is a valid next block for another.
+// Non-exhaustive, so that library consumers matching on it keep compiling as variants are added.
 #[derive(Debug, Clone)]
+#[non_exhaustive]
 pub enum NextBlockErr {
     DifficultyCheckFailed {
         idx: usize,
         hash: String,
-        difficulty_prefix: String,
-    },
+        difficulty: usize,
+    }, // Block's hash doesn't have the number of leading zero bits it declares
+    DifficultyBelowMinimum {
+        idx: usize,
+        difficulty: usize,
+        minimum: usize,
+    }, // Block declares a lower difficulty than the chain it is being added to allows
     InconsistentHash {
         idx: usize,
         hash: String,
         computed_hash: String,
-    },
+    }, // Block's stored hash isn't the hash of its contents
     InvalidGenesis {
         idx: usize,
         hash: String,
@@ -421,12 +860,51 @@ pub enum NextBlockErr {
     StrayOrphan {
         idx: usize,
         hash: String,
-    }, // Block represents an orphan block that doesn't prepend to any orphaned branches,
+    }, // Block represents an orphan block that doesn't prepend to any orphaned branches
     Duplicate {
         idx: usize,
         hash: String,
     }, // Block exists in the main chain, forks, or orphans
     NoBlocks, // Block used in a context with an empty chain or fork
+    InvalidSuffix {
+        position: usize,
+        e: Box<NextBlockErr>,
+    }, // Block at the given position of a suffix can't be appended to a chain
+    TimestampRegressed {
+        idx: usize,
+        timestamp: i64,
+        parent_timestamp: i64,
+    }, // Block is dated before its parent
+    TimestampTooFarAhead {
+        idx: usize,
+        timestamp: i64,
+        now: i64,
+    }, // Block is dated too far ahead of the local clock
+    UnrelatedGenesis {
+        genesis_hash: String,
+    }, // Chain starts from a different genesis block than the main chain
+    InvalidRollback {
+        n: usize,
+        len: usize,
+    }, // Rollback would remove no blocks, or the genesis block
+    InvalidBlockSignature {
+        idx: usize,
+        hash: String,
+        reason: String,
+    }, // Block's signature is missing, malformed, or not its miner's signature of its hash
+    ReorgBelowCheckpoint {
+        fork_idx: usize,
+        checkpoint_idx: usize,
+    }, // Switching to a chain or fork would replace main chain blocks that are buried below the checkpoint
+    OrphanDropped {
+        idx: usize,
+        hash: String,
+    }, // Block's orphaned branch was evicted as soon as it was stored, as the orphan pool is full
+    InvalidCoinbase {
+        idx: usize,
+        hash: String,
+        reason: String,
+    }, // Block's coinbase is misplaced, claims a reward other than the chain's, or rewards someone or somewhere else
 }
 
 impl std::fmt::Display for NextBlockErr {
@@ -435,14 +913,30 @@ impl std::fmt::Display for NextBlockErr {
             NextBlockErr::DifficultyCheckFailed {
                 idx,
                 hash,
-                difficulty_prefix,
+                difficulty,
+            } => {
+                match hash_leading_zero_bits(hash) {
+                    Some(bits) => write!(
+                        f,
+                        "Block {}'s hash {} has {} leading zero bits, short of the difficulty target of {}.",
+                        idx, hash, bits, difficulty
+                    ),
+                    None => write!(
+                        f,
+                        "Block {}'s hash {} isn't a 32-byte hex hash, so can't meet the difficulty target of {}.",
+                        idx, hash, difficulty
+                    ),
+                }
+            }
+            NextBlockErr::DifficultyBelowMinimum {
+                idx,
+                difficulty,
+                minimum,
             } => {
                 write!(
                     f,
-                    "Block {}'s hash binary {} does not meet the difficulty target {}.",
-                    idx,
-                    BinaryString::from_hex(hash).expect("can convert hex string to binary"),
-                    difficulty_prefix
+                    "Block {} declares difficulty {} which is below the chain's minimum difficulty {}.",
+                    idx, difficulty, minimum
                 )
             }
             NextBlockErr::InconsistentHash {
@@ -504,6 +998,85 @@ impl std::fmt::Display for NextBlockErr {
             NextBlockErr::NoBlocks => {
                 write!(f, "Encountered an empty chain or fork.")
             }
+            NextBlockErr::InvalidSuffix { position, e } => {
+                write!(
+                    f,
+                    "Block at position {} of the appended blocks is invalid: {}",
+                    position, e
+                )
+            }
+            NextBlockErr::TimestampRegressed {
+                idx,
+                timestamp,
+                parent_timestamp,
+            } => {
+                write!(
+                    f,
+                    "Block {}'s timestamp {} is earlier than its parent's timestamp {}.",
+                    idx, timestamp, parent_timestamp
+                )
+            }
+            NextBlockErr::InvalidRollback { n, len } => {
+                write!(
+                    f,
+                    "Cannot roll back {} block(s) of a main chain of length {}, which must keep its genesis block.",
+                    n, len
+                )
+            }
+            NextBlockErr::UnrelatedGenesis { genesis_hash } => {
+                write!(
+                    f,
+                    "Chain starts from genesis block with hash {}, which is unrelated to the main chain's genesis.",
+                    abbrev(genesis_hash)
+                )
+            }
+            NextBlockErr::TimestampTooFarAhead {
+                idx,
+                timestamp,
+                now,
+            } => {
+                write!(
+                    f,
+                    "Block {}'s timestamp {} is more than {}s ahead of the local time {}.",
+                    idx, timestamp, MAX_FUTURE_DRIFT, now
+                )
+            }
+            NextBlockErr::InvalidBlockSignature { idx, hash, reason } => {
+                write!(
+                    f,
+                    "Block {} with hash {} has an invalid signature: {}.",
+                    idx,
+                    abbrev(hash),
+                    reason
+                )
+            }
+            NextBlockErr::ReorgBelowCheckpoint {
+                fork_idx,
+                checkpoint_idx,
+            } => {
+                write!(
+                    f,
+                    "Cannot switch to a chain or fork from block {}, which would replace blocks at or below the checkpoint at block {}.",
+                    fork_idx, checkpoint_idx
+                )
+            }
+            NextBlockErr::OrphanDropped { idx, hash } => {
+                write!(
+                    f,
+                    "Dropped orphaned block {} with hash {}, as its branch doesn't fit in the orphan pool.",
+                    idx,
+                    abbrev(hash)
+                )
+            }
+            NextBlockErr::InvalidCoinbase { idx, hash, reason } => {
+                write!(
+                    f,
+                    "Block {} with hash {} has an invalid coinbase: {}.",
+                    idx,
+                    abbrev(hash),
+                    reason
+                )
+            }
         }
     }
 }
@@ -5,33 +5,125 @@
 */
 
 use super::{
-    block::{Block, Blocks, NextBlockErr, NextBlockResult},
+    block::{
+        Block, BlockHeaderSummary, Blocks, NextBlockErr, NextBlockResult, DEFAULT_DIFFICULTY,
+        GENESIS_DATA, GENESIS_TIMESTAMP,
+    },
+    crypt,
     fork::{ForkId, Forks, Orphans},
+    ledger::Ledger,
+    merkle::TxnInclusionProof,
+    transaction::Transaction,
+};
+use chrono::Utc;
+use libp2p::{
+    identity::{Keypair, PublicKey},
+    PeerId,
 };
 use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, sync::Arc};
+
+/* Parameters that a chain is created with, and which all of its blocks must satisfy.
+The genesis block is built from these, so chains created with different configs have different genesis hashes,
+and peers with different configs form separate networks. */
+#[derive(Clone, Debug)]
+pub struct GenesisConfig {
+    pub timestamp: i64,
+    pub data: String,
+    pub difficulty: usize,
+}
+
+impl Default for GenesisConfig {
+    fn default() -> Self {
+        GenesisConfig {
+            timestamp: GENESIS_TIMESTAMP,
+            data: GENESIS_DATA.to_string(),
+            difficulty: DEFAULT_DIFFICULTY,
+        }
+    }
+}
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Chain {
     main: Blocks,
     forks: Forks,
     orphans: Orphans,
+    // difficulty that blocks are mined at, and the minimum they may declare, as set by the genesis block
+    difficulty: usize,
+    // depth below which main chain blocks are final and can't be switched away from (0 for no checkpoint), set locally
+    #[serde(skip)]
+    checkpoint_depth: usize,
+    // depth behind the tip below which forks that end there are pruned (0 for no pruning), set locally
+    #[serde(skip)]
+    fork_prune_depth: usize,
+    // amount that a block's coinbase must reward its miner with (0 for no reward), set locally
+    #[serde(skip)]
+    block_reward: u64,
+    // <block hash, idx of the main chain block>, rebuilt rather than serialized
+    #[serde(skip)]
+    block_index: HashMap<String, usize>,
+    // total work of the main chain, kept up to date alongside the indexes
+    #[serde(skip)]
+    main_work: u128,
+    // <transaction hash, idx of the main chain block containing it>, rebuilt rather than serialized
+    #[serde(skip)]
+    txn_index: HashMap<String, usize>,
 }
 
 /* Chain core operations */
 impl Chain {
     pub fn genesis() -> Self {
-        Self {
-            main: Blocks::genesis(),
+        Self::genesis_with(GenesisConfig::default())
+    }
+
+    pub fn genesis_with(config: GenesisConfig) -> Self {
+        let mut chain = Self {
+            main: Blocks::custom_genesis(&config.data, config.timestamp, config.difficulty),
             forks: Forks::new(),
             orphans: Orphans::new(),
-        }
+            difficulty: config.difficulty,
+            checkpoint_depth: 0,
+            fork_prune_depth: 0,
+            block_reward: 0,
+            block_index: HashMap::new(),
+            main_work: 0,
+            txn_index: HashMap::new(),
+        };
+        chain.index_blocks(0);
+        chain
     }
 
-    // Swap the main chain to a local fork if valid and longer.
+    /* Swap the main chain to the local fork with the most work, if valid and it has more work (or equal work and is longer),
+    unless it branches from below the checkpoint, in which case forks that do so are discarded.
+    Forks that end too far behind the (possibly new) tip are then pruned. */
     pub fn choose_fork(&mut self) -> Result<ChainStatus, NextBlockErr> {
-        if let Some((_, fork_id)) = self.forks.longest() {
-            let (main_len, other_len) = (self.last().idx + 1, fork_id.end_idx + 1);
-            if main_len < other_len {
+        let status = self.switch_to_heaviest_fork();
+        self.prune_forks();
+        status
+    }
+
+    fn switch_to_heaviest_fork(&mut self) -> Result<ChainStatus, NextBlockErr> {
+        let (main_len, main_work) = (self.last().idx + 1, self.main_work);
+        let heaviest_fork: Option<(ForkId, u128)> = self
+            .forks
+            .iter()
+            .map(|fork| (Forks::identify(fork), self.fork_work(fork)))
+            // ties are broken by the lexicographically smaller endpoint hash, so that every peer promotes the same fork
+            .max_by_key(|(fork_id, work)| {
+                (
+                    *work,
+                    fork_id.end_idx,
+                    std::cmp::Reverse(fork_id.end_hash.clone()),
+                )
+            });
+        if let Some((fork_id, other_work)) = heaviest_fork {
+            let other_len = fork_id.end_idx + 1;
+            if (main_work, main_len) < (other_work, other_len) {
+                if let Err(e) = self.check_reorg(fork_id.fork_idx) {
+                    let forkpoints: Vec<String> = self.forkpoints();
+                    self.forks.retain_forkpoints(&forkpoints);
+                    return Err(e);
+                }
                 // remove the fork from the fork pool
                 let fork: Blocks = self
                     .forks
@@ -42,81 +134,146 @@ impl Chain {
                     self.main.split_off_until(|b| b.hash == *fork_id.fork_hash);
                 // if the removed suffix is non-empty, insert it as a fork
                 if let Some(suffix) = main_suffix {
+                    self.unindex_blocks(&suffix);
                     self.forks.insert(suffix);
                 }
                 // append the fork to the truncated main chain
                 Blocks::append(&mut self.main, fork)?;
-                // delete all previous forks that don't fork from the new chain
-                let forkpoints: Vec<String> = self.main.iter().map(|b| b.hash.clone()).collect();
+                self.index_blocks(fork_id.fork_idx + 1);
+                // delete all previous forks that don't fork from the new chain, or that fork from below the checkpoint
+                let forkpoints: Vec<String> = self.forkpoints();
                 self.forks.retain_forkpoints(&forkpoints);
 
                 Ok(ChainStatus::ChooseOther {
                     main_len,
+                    main_work,
                     other_len,
+                    other_work,
+                    fork_idx: fork_id.fork_idx,
                 })
             } else {
                 Ok(ChainStatus::KeepMain {
                     main_len,
-                    other_len: Some(other_len),
+                    main_work,
+                    other: Some((other_len, other_work)),
                 })
             }
         } else {
             Ok(ChainStatus::KeepMain {
-                main_len: self.len(),
-                other_len: None,
+                main_len,
+                main_work,
+                other: None,
             })
         }
     }
 
-    // Swap the main chain to a remote chain if valid and longer.
+    // Swap the main chain to a remote chain if valid and it has more work (or equal work and is longer), unless it branches from below the checkpoint.
     pub fn choose_chain(&mut self, other: Chain) -> Result<ChainStatus, NextBlockErr> {
         other.validate()?;
+        other
+            .main
+            .iter()
+            .try_for_each(|b| self.check_difficulty(b).and(self.check_coinbase(b)))?;
 
-        let (main_genesis, other_genesis) = (self.main.first(), other.main.first());
-        if main_genesis != other_genesis {
-            return Err(NextBlockErr::InvalidGenesis {
-                idx: other_genesis.idx,
-                hash: other_genesis.hash.clone(),
+        // the genesis hashes commit to the rest of the genesis blocks, which have already been validated
+        if self.genesis_hash() != other.genesis_hash() {
+            return Err(NextBlockErr::UnrelatedGenesis {
+                genesis_hash: other.genesis_hash().to_string(),
             });
         }
         let (main_len, other_len) = (self.last().idx + 1, other.last().idx + 1);
-        if main_len < other_len {
+        let (main_work, other_work) = (self.main_work, other.main.total_work());
+        if (main_work, main_len) < (other_work, other_len) {
+            // the last block in common, which exists as both chains start from the same genesis block
+            let fork_idx: usize = self
+                .main
+                .iter()
+                .zip(other.main.iter())
+                .take_while(|(main, other)| main.hash == other.hash)
+                .count()
+                - 1;
+            self.check_reorg(fork_idx)?;
             self.main = other.main.clone();
-            // delete all previous forks that don't fork from the new chain
-            let forkpoints: Vec<String> = self.main.iter().map(|b| b.hash.clone()).collect();
+            self.block_index.clear();
+            self.main_work = 0;
+            self.txn_index.clear();
+            self.index_blocks(0);
+            // delete all previous forks that don't fork from the new chain, or that fork from below the checkpoint
+            let forkpoints: Vec<String> = self.forkpoints();
             self.forks.retain_forkpoints(&forkpoints);
 
             Ok(ChainStatus::ChooseOther {
                 main_len,
+                main_work,
                 other_len,
+                other_work,
+                fork_idx,
             })
         } else {
             Ok(ChainStatus::KeepMain {
                 main_len,
-                other_len: Some(other_len),
+                main_work,
+                other: Some((other_len, other_work)),
             })
         }
     }
 
-    // Try to store a new block in either the main chain or fork pool
-    pub fn store_new_block(&mut self, block: Block) -> Result<NextBlockResult, NextBlockErr> {
+    /* Run the checks that `store_new_block` does on a block, without storing it.
+    Returns the block's parent in the main chain or forks, if it has one there, or None if it would be stored as an orphan. */
+    pub fn check_block(&self, block: &Block) -> Result<Option<&Block>, NextBlockErr> {
         block.validate()?;
+        self.check_difficulty(block)?;
+        self.check_coinbase(block)?;
 
-        let is_duplicate = |b: &Block| b.hash == block.hash;
         let is_parent = |b: &Block| block.validate_parent(b).is_ok();
 
         // Search for block in the main chain and forks
-        if self.find(&is_duplicate).is_some() || self.forks.find(&is_duplicate).is_some() {
-            Err(NextBlockErr::Duplicate {
+        if self.main_by_hash(&block.hash).is_some() || self.forks.contains_hash(&block.hash) {
+            return Err(NextBlockErr::Duplicate {
                 idx: block.idx,
-                hash: block.hash,
-            })
+                hash: block.hash.clone(),
+            });
+        }
+        // Search for parent block in the main chain, then in the forks
+        let parent: Option<&Block> = self
+            .main_by_hash(&block.prev_hash)
+            .filter(|b| is_parent(b))
+            .or_else(|| self.forks.find(&is_parent).map(|(_, _, parent)| parent));
+        // Encountered a genesis block not in the main chain
+        if parent.is_none() && block.idx == 0 {
+            return Err(NextBlockErr::InvalidGenesis {
+                idx: block.idx,
+                hash: block.hash.clone(),
+            });
         }
+        Ok(parent)
+    }
+
+    // Try to store a new block in either the main chain or fork pool
+    pub fn store_new_block(&mut self, block: Block) -> Result<NextBlockResult, NextBlockErr> {
+        self.check_block(&block)?;
+
+        let is_parent = |b: &Block| block.validate_parent(b).is_ok();
+
         // Search for parent block in the main chain.
-        else if let Some(parent) = self.find(&is_parent) {
+        if let Some(parent) = self.main_by_hash(&block.prev_hash).filter(|b| is_parent(b)) {
             // See if we can append the block to the main chain
             if self.last().hash == parent.hash {
                 Blocks::push_back(&mut self.main, block)?;
+                self.index_blocks(self.last().idx);
+                Ok(NextBlockResult::ExtendedMain {
+                    end_idx: self.last().idx,
+                    end_hash: self.last().hash.clone(),
+                })
+            }
+            // If it competes with the tip and wins the tie-break, swap the tip out into a single-block fork
+            else if block.idx == self.last().idx && block.beats(self.last()) {
+                let tip: Blocks = self
+                    .split_off(block.idx)
+                    .expect("main chain has a tip after the new block's parent");
+                Blocks::push_back(&mut self.main, block)?;
+                self.index_blocks(self.last().idx);
+                self.forks.insert(tip);
                 Ok(NextBlockResult::ExtendedMain {
                     end_idx: self.last().idx,
                     end_hash: self.last().hash.clone(),
@@ -150,16 +307,15 @@ impl Chain {
                 Ok(fork_id.into_new_fork_result())
             }
         }
-        // Encountered a genesis block not in the main chain
-        else if block.idx == 0 {
-            Err(NextBlockErr::InvalidGenesis {
-                idx: block.idx,
-                hash: block.hash,
-            })
-        }
         // Otherwise, insert a new single-block orphan branch, and report a missing block that connects it to the current network
         else {
-            self.orphans.insert(Blocks::from_vec(vec![block.clone()])?);
+            let (orphan_id, _) = self.orphans.insert(Blocks::from_vec(vec![block.clone()])?);
+            if self.orphans.get(&orphan_id).is_none() {
+                return Err(NextBlockErr::OrphanDropped {
+                    idx: block.idx,
+                    hash: block.hash,
+                });
+            }
             Err(NextBlockErr::MissingParent {
                 parent_idx: block.idx - 1,
                 parent_hash: block.prev_hash,
@@ -170,11 +326,14 @@ impl Chain {
     // Try to store a fork if valid and forks from the main chain
     pub fn store_new_fork(&mut self, blocks: Blocks) -> Result<ForkId, NextBlockErr> {
         blocks.validate()?;
+        blocks
+            .iter()
+            .try_for_each(|b| self.check_difficulty(b).and(self.check_coinbase(b)))?;
 
         let first_block = blocks.first();
         let is_parent = |b: &Block| first_block.validate_parent(b).is_ok();
 
-        if self.find(&is_parent).is_some() {
+        if self.common_ancestor(&blocks).is_some_and(is_parent) {
             let fork_id = self.forks.insert(blocks);
             Ok(fork_id)
         }
@@ -195,11 +354,11 @@ impl Chain {
     // Try to store a block in an orphan branch to be attached as a new fork
     pub fn store_orphan_block(&mut self, block: Block) -> Result<NextBlockResult, NextBlockErr> {
         block.validate()?;
-
-        let is_duplicate = |b: &Block| b.hash == block.hash;
+        self.check_difficulty(&block)?;
+        self.check_coinbase(&block)?;
 
         // Search for block in the orphans.
-        if self.orphans.find(is_duplicate).is_some() {
+        if self.orphans.contains_hash(&block.hash) {
             Err(NextBlockErr::Duplicate {
                 idx: block.idx,
                 hash: block.hash,
@@ -207,9 +366,15 @@ impl Chain {
         }
         // Lookup the block as the forkpoint for any orphan branches
         else if self.orphans.get_mut(&block.hash).is_some() {
-            // Try to extend the orphan branch from the front
-            let orphan_id = self.orphans.extend_orphan(block)?;
-            let orphan = self.orphans.get(&orphan_id).unwrap();
+            // Try to extend the orphan branch from the front, which may evict it if it no longer fits
+            let (orphan_id, _) = self.orphans.extend_orphan(block.clone())?;
+            let orphan = self
+                .orphans
+                .get(&orphan_id)
+                .ok_or(NextBlockErr::OrphanDropped {
+                    idx: block.idx,
+                    hash: block.hash,
+                })?;
             // Try to store the orphan branch as a valid fork from the main chain
             let fork_id = self.store_new_fork(orphan.clone())?;
             // Remove the extended orphan from the pool, and return the new fork
@@ -223,16 +388,86 @@ impl Chain {
         }
     }
 
+    // Link up orphaned branches, and store those that now branch from the main chain as new forks
+    pub fn link_orphans(&mut self) -> Vec<ForkId> {
+        self.orphans.try_link();
+        let attached: Vec<String> = self
+            .orphans
+            .forkpoints()
+            .into_iter()
+            .filter_map(|(_, forkpoint)| {
+                self.main_by_hash(&forkpoint).is_some().then_some(forkpoint)
+            })
+            .collect();
+        attached
+            .into_iter()
+            .filter_map(|forkpoint| {
+                let orphan: Blocks = self.orphans.remove(&forkpoint)?;
+                self.store_new_fork(orphan).ok()
+            })
+            .collect()
+    }
+
     // Mine a new valid block from given data
-    pub fn mine_block(&mut self, data: &str) {
-        self.main.mine_block(data)
+    pub fn mine_block(&mut self, data: &str, keys: &Keypair) {
+        debug_assert!(self.verify_tip().is_ok(), "mining on top of an invalid tip");
+        self.main.mine_block(data, self.difficulty, keys);
+        self.index_blocks(self.last().idx);
+    }
+
+    // Mine a new valid block from given data, refusing to do so if the tip of the main chain is invalid
+    pub fn try_mine_block(&mut self, data: &str, keys: &Keypair) -> Result<(), NextBlockErr> {
+        self.verify_tip()?;
+        self.main.mine_block(data, self.difficulty, keys);
+        self.index_blocks(self.last().idx);
+        Ok(())
+    }
+
+    // Mine a new block from given transactions, placing a coinbase that rewards the miner before them
+    pub fn mine_block_with_reward(&mut self, data: &str, keys: &Keypair, reward: u64) {
+        let data: String = self.coinbase_data(data, &keys.public(), reward);
+        self.mine_block(&data, keys);
     }
 
-    // Validate chain expecting its first block to begin at idx 0
+    // Data for the next block, with a coinbase that rewards its miner and collects the transactions' fees, placed before them.
+    // Data that isn't transactions, or that has neither a reward nor fees to collect, is left as is.
+    pub fn coinbase_data(&self, data: &str, miner_pubk: &PublicKey, reward: u64) -> String {
+        let Some(txns) = Block::parse_transactions(data) else {
+            return data.to_string();
+        };
+        let amount: u64 = reward.saturating_add(Transaction::total_fees(&txns));
+        if amount == 0 {
+            return data.to_string();
+        }
+        let coinbase: Transaction = Transaction::coinbase(
+            PeerId::from(miner_pubk.clone()).to_string(),
+            amount,
+            self.last().idx + 1,
+            Utc::now().timestamp(),
+        );
+        Block::transactions_data(&[vec![coinbase], txns].concat())
+    }
+
+    // Validate the tip of the main chain, and that it correctly references its parent
+    pub fn verify_tip(&self) -> Result<(), NextBlockErr> {
+        let tip: &Block = self.last();
+        tip.validate()?;
+        match self.len().checked_sub(2).and_then(|i| self.idx(i)) {
+            Some(parent) => tip.validate_parent(parent),
+            None if tip.idx == 0 => Ok(()),
+            None => Err(NextBlockErr::InvalidGenesis {
+                idx: tip.idx,
+                hash: tip.hash.clone(),
+            }),
+        }
+    }
+
+    // Validate chain expecting its first block to begin at idx 0, and all blocks to meet the chain's minimum difficulty
     pub fn validate(&self) -> Result<(), NextBlockErr> {
         let first_block: &Block = self.main.first();
         if first_block.idx == 0 {
-            Blocks::validate(&self.main)
+            Blocks::validate(&self.main)?;
+            self.main.iter().try_for_each(|b| self.check_difficulty(b))
         } else {
             Err(NextBlockErr::InvalidGenesis {
                 idx: first_block.idx,
@@ -244,26 +479,247 @@ impl Chain {
 
 /* Chain auxiliary functions */
 impl Chain {
-    // Constructor
+    // Constructor, taking the difficulty from the genesis block
     pub fn from_vec(blocks: Vec<Block>) -> Result<Chain, NextBlockErr> {
-        let chain = Chain {
-            main: Blocks::from_vec(blocks)?,
+        let main: Blocks = Blocks::from_vec(blocks)?;
+        let mut chain = Chain {
+            difficulty: main.first().difficulty,
+            checkpoint_depth: 0,
+            fork_prune_depth: 0,
+            block_reward: 0,
+            main,
             forks: Forks::new(),
             orphans: Orphans::new(),
+            block_index: HashMap::new(),
+            main_work: 0,
+            txn_index: HashMap::new(),
         };
         chain.validate()?;
+        chain.index_blocks(0);
         Ok(chain)
     }
 
+    /* Constructor from the longest valid prefix of the blocks, e.g. those salvaged from a corrupt file,
+    returning how many blocks after it were dropped. Fails only if the genesis block itself is invalid. */
+    pub fn from_vec_lossy(blocks: Vec<Block>) -> Result<(Chain, usize), NextBlockErr> {
+        let total: usize = blocks.len();
+        let mut prefix: Vec<Block> = vec![];
+        for block in blocks {
+            let valid: bool = match prefix.last() {
+                None => block.validate().is_ok(),
+                Some(parent) => {
+                    block.validate().is_ok()
+                        && block.validate_parent(parent).is_ok()
+                        && block.difficulty >= prefix[0].difficulty
+                }
+            };
+            if !valid {
+                break;
+            }
+            prefix.push(block);
+        }
+        let dropped: usize = total - prefix.len();
+        Ok((Chain::from_vec(prefix)?, dropped))
+    }
+
     // Destructor
     pub fn to_vec(self) -> Vec<Block> {
         self.main.to_vec()
     }
 
+    // The main chain without block data, e.g. for light clients
+    pub fn to_headers(&self) -> Vec<BlockHeaderSummary> {
+        self.main.iter().map(BlockHeaderSummary::from).collect()
+    }
+
+    // Serialize the main chain along with its forks and orphans
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string(self)
+    }
+
+    /* Deserialize a chain with its forks and orphans, validating the main chain and
+    dropping any forks that no longer attach to it. */
+    pub fn from_json(json: &str) -> Result<Chain, Box<dyn std::error::Error>> {
+        Chain::restore(serde_json::from_str(json)?)
+    }
+
+    // Serialize the main chain along with its forks and orphans, compactly rather than human-readably
+    pub fn to_bytes(&self) -> Result<Vec<u8>, bincode::Error> {
+        bincode::serialize(self)
+    }
+
+    // Deserialize a chain as serialized by `to_bytes`, validating it as `from_json` does
+    pub fn from_bytes(bytes: &[u8]) -> Result<Chain, Box<dyn std::error::Error>> {
+        Chain::restore(bincode::deserialize(bytes)?)
+    }
+
+    // Rebuild the state of a deserialized chain that isn't serialized, after validating it
+    fn restore(mut chain: Chain) -> Result<Chain, Box<dyn std::error::Error>> {
+        chain.difficulty = chain.main.first().difficulty;
+        chain.validate()?;
+        let forkpoints: Vec<String> = chain.forkpoints();
+        chain.forks.retain_forkpoints(&forkpoints);
+        chain.index_blocks(0);
+        Ok(chain)
+    }
+
     pub fn len(&self) -> usize {
         self.main.len()
     }
 
+    // Work of the chain ending at a fork's tip: the main chain up to the forkpoint, followed by the fork
+    fn fork_work(&self, fork: &Blocks) -> u128 {
+        let fork_idx: usize = fork.first().idx - 1;
+        self.main
+            .iter()
+            .take(fork_idx + 1)
+            .fold(fork.total_work(), |work, b| work.saturating_add(b.work()))
+    }
+
+    pub fn total_work(&self) -> u128 {
+        self.main_work
+    }
+
+    // Summary of the main chain's tip, e.g. for monitoring
+    pub fn head(&self) -> HeadInfo {
+        let tip: &Block = self.last();
+        HeadInfo {
+            height: tip.idx,
+            tip_hash: tip.hash.clone(),
+            tip_timestamp: tip.timestamp,
+            total_work: self.main_work,
+            num_forks: self.forks.len(),
+        }
+    }
+
+    // Finalize main chain blocks that are at least `depth` blocks below the tip, or none if 0
+    pub fn set_checkpoint_depth(&mut self, depth: usize) {
+        self.checkpoint_depth = depth;
+    }
+
+    pub fn checkpoint_depth(&self) -> usize {
+        self.checkpoint_depth
+    }
+
+    // Prune forks that end at least `depth` blocks behind the tip, or none if 0
+    pub fn set_fork_prune_depth(&mut self, depth: usize) {
+        self.fork_prune_depth = depth;
+    }
+
+    pub fn fork_prune_depth(&self) -> usize {
+        self.fork_prune_depth
+    }
+
+    // Remove the forks that end too far behind the tip, except the one that ends highest
+    fn prune_forks(&mut self) {
+        let tip_idx: usize = self.last().idx;
+        if self.fork_prune_depth > 0 && tip_idx >= self.fork_prune_depth {
+            self.forks
+                .prune_older_than(tip_idx - self.fork_prune_depth + 1);
+        }
+    }
+
+    // Require a block's coinbase (if any) to reward its miner with the given amount
+    pub fn set_block_reward(&mut self, reward: u64) {
+        self.block_reward = reward;
+    }
+
+    pub fn block_reward(&self) -> u64 {
+        self.block_reward
+    }
+
+    // The idx of the highest final block of the main chain, if any
+    pub fn checkpoint(&self) -> Option<usize> {
+        let tip_idx: usize = self.last().idx;
+        (self.checkpoint_depth > 0 && tip_idx >= self.checkpoint_depth)
+            .then(|| tip_idx - self.checkpoint_depth)
+    }
+
+    // Check that switching to a chain or fork that branches from the given idx wouldn't replace any final blocks
+    fn check_reorg(&self, fork_idx: usize) -> Result<(), NextBlockErr> {
+        match self.checkpoint() {
+            Some(checkpoint_idx) if fork_idx < checkpoint_idx => {
+                Err(NextBlockErr::ReorgBelowCheckpoint {
+                    fork_idx,
+                    checkpoint_idx,
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // Hashes of the main chain blocks that forks may branch from, i.e. those from the checkpoint onwards
+    fn forkpoints(&self) -> Vec<String> {
+        let from_idx: usize = self.checkpoint().unwrap_or(0);
+        self.main
+            .iter()
+            .skip(from_idx)
+            .map(|b| b.hash.clone())
+            .collect()
+    }
+
+    pub fn difficulty(&self) -> usize {
+        self.difficulty
+    }
+
+    // Check a block declares at least the chain's difficulty (its hash is checked against its own declared difficulty)
+    fn check_difficulty(&self, block: &Block) -> Result<(), NextBlockErr> {
+        if block.difficulty < self.difficulty {
+            return Err(NextBlockErr::DifficultyBelowMinimum {
+                idx: block.idx,
+                difficulty: block.difficulty,
+                minimum: self.difficulty,
+            });
+        }
+        Ok(())
+    }
+
+    // Check a block's coinbase (if any) comes first, and rewards the block's miner for that block with the chain's reward
+    fn check_coinbase(&self, block: &Block) -> Result<(), NextBlockErr> {
+        let invalid = |reason: String| NextBlockErr::InvalidCoinbase {
+            idx: block.idx,
+            hash: block.hash.clone(),
+            reason,
+        };
+        let txns: Vec<Transaction> = block.transactions();
+        let positions: Vec<usize> = (0..txns.len()).filter(|&i| txns[i].is_coinbase()).collect();
+        let coinbase: &Transaction = match positions[..] {
+            [] => return Ok(()),
+            [0] => &txns[0],
+            _ => {
+                return Err(invalid(
+                    "it must be the block's first and only coinbase".to_string(),
+                ))
+            }
+        };
+        let fees: u64 = Transaction::total_fees(&txns);
+        if coinbase.amount != self.block_reward.saturating_add(fees) {
+            return Err(invalid(format!(
+                "it claims {} rather than the block reward of {} plus fees of {}",
+                coinbase.amount, self.block_reward, fees
+            )));
+        }
+        if coinbase.coinbase_idx() != Some(block.idx) {
+            return Err(invalid(
+                "it rewards the miner of a different block".to_string(),
+            ));
+        }
+        let miner: String = crypt::decode_hex_to_pubk(&block.miner_pubk, crypt::PUBK_U8S_LEN)
+            .map(|pubk| PeerId::from(pubk).to_string())
+            .map_err(|e| invalid(e.to_string()))?;
+        if coinbase.receiver != miner {
+            return Err(invalid(format!(
+                "it rewards {} rather than the block's miner",
+                coinbase.receiver
+            )));
+        }
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.main.is_empty()
+    }
+
     pub fn find<'a, P>(&'a self, prop: &P) -> Option<&'a Block>
     where
         P: Fn(&Block) -> bool,
@@ -275,46 +731,306 @@ impl Chain {
         self.main.get(idx)
     }
 
+    // Find a block by its hash in the main chain, and otherwise in the forks
+    pub fn get_by_hash(&self, hash: &str) -> Option<&Block> {
+        let has_hash = |b: &Block| b.hash == hash;
+        self.main_by_hash(hash)
+            .or_else(|| self.forks.find(&has_hash).map(|(_, _, block)| block))
+    }
+
+    // Number of main chain blocks from a block to the tip inclusive, or None if the block isn't in the main chain
+    pub fn confirmations(&self, hash: &str) -> Option<usize> {
+        self.main_by_hash(hash)
+            .map(|block| self.last().idx - block.idx + 1)
+    }
+
+    // Whether a block is in the main chain with at least `min` confirmations
+    pub fn is_confirmed(&self, hash: &str, min: usize) -> bool {
+        self.confirmations(hash).is_some_and(|n| n >= min)
+    }
+
+    // Find a block by its hash in the main chain, in constant time
+    pub fn main_by_hash(&self, hash: &str) -> Option<&Block> {
+        self.block_index.get(hash).and_then(|idx| self.idx(*idx))
+    }
+
+    // The main chain block that a fork branches from (its forkpoint), or None if the fork doesn't attach to the main chain
+    pub fn common_ancestor(&self, fork: &Blocks) -> Option<&Block> {
+        let first: &Block = fork.first();
+        self.main_by_hash(&first.prev_hash)
+            .filter(|forkpoint| forkpoint.idx + 1 == first.idx)
+    }
+
+    // The main chain's blocks from idx `from` to idx `to` inclusive, or None if these aren't in the main chain
+    pub fn range(&self, from: usize, to: usize) -> Option<Blocks> {
+        self.main.range(from, to)
+    }
+
+    // A read-only copy of the main chain as it is now, to be taken whenever the main chain changes and shared from there
+    pub fn snapshot(&self) -> ChainSnapshot {
+        ChainSnapshot {
+            main: Arc::new(self.main.clone()),
+            head: self.head(),
+        }
+    }
+
+    // Return up to n ancestors of a block in the main chain, nearest first, by following prev_hash pointers
+    pub fn walk_back(&self, start_hash: &str, n: usize) -> Vec<&Block> {
+        let mut ancestors: Vec<&Block> = vec![];
+        let mut curr: &Block = match self.main_by_hash(start_hash) {
+            Some(block) => block,
+            None => return ancestors,
+        };
+        while ancestors.len() < n {
+            match curr.idx.checked_sub(1).and_then(|idx| self.idx(idx)) {
+                Some(parent) if parent.hash == curr.prev_hash => {
+                    ancestors.push(parent);
+                    curr = parent;
+                }
+                _ => break,
+            }
+        }
+        ancestors
+    }
+
+    // Hashes of the main chain's blocks in order, summarising it for comparison with another peer's chain
+    pub fn to_hashes(&self) -> Vec<String> {
+        self.main.iter().map(|block| block.hash.clone()).collect()
+    }
+
+    // The last block that the main chain shares with another chain (given by its hashes in order), or None if their genesis blocks differ
+    pub fn fork_point(&self, other_hashes: &[String]) -> Option<(usize, String)> {
+        self.main
+            .iter()
+            .zip(other_hashes)
+            .take_while(|(block, hash)| &block.hash == *hash)
+            .last()
+            .map(|(block, _)| (block.idx, block.hash.clone()))
+    }
+
     pub fn last(&self) -> &Block {
         self.main.last()
     }
 
+    // Blocks of the main chain
+    pub fn blocks(&self) -> &Blocks {
+        &self.main
+    }
+
+    // Blocks of the main chain in order, from the genesis block to the tip
+    pub fn iter(&self) -> std::slice::Iter<'_, Block> {
+        self.main.iter()
+    }
+
+    pub fn genesis_hash(&self) -> &str {
+        &self.main.first().hash
+    }
+
+    /* Remove the last n blocks of the main chain (never the genesis block), returning them.
+    They are kept as a fork, so that they can still be chosen again if they end up with the most work. */
+    pub fn rollback(&mut self, n: usize) -> Result<Blocks, NextBlockErr> {
+        let len: usize = self.len();
+        if n == 0 || n >= len {
+            return Err(NextBlockErr::InvalidRollback { n, len });
+        }
+        let removed: Blocks = self.split_off(len - n).ok_or(NextBlockErr::NoBlocks)?;
+        // the blocks may no longer be valid as a fork (e.g. if the block reward has since changed), in which case they are put back
+        if let Err(e) = self.store_new_fork(removed.clone()) {
+            self.main.append(removed)?;
+            self.index_blocks(len - n);
+            return Err(e);
+        }
+        Ok(removed)
+    }
+
     pub fn split_off(&mut self, len: usize) -> Option<Blocks> {
-        self.main.split_off(len)
+        let suffix: Option<Blocks> = self.main.split_off(len);
+        if let Some(suffix) = &suffix {
+            self.unindex_blocks(suffix);
+        }
+        suffix
+    }
+
+    // Lookup the main chain block containing a transaction
+    pub fn find_txn(&self, txn_hash: &str) -> Option<&Block> {
+        self.txn_index.get(txn_hash).and_then(|idx| self.idx(*idx))
+    }
+
+    // Prove that a main chain block contains a transaction, e.g. for light clients
+    pub fn prove_txn(&self, txn_hash: &str) -> Option<TxnInclusionProof> {
+        self.find_txn(txn_hash)
+            .and_then(|block| TxnInclusionProof::new(block, txn_hash))
+    }
+
+    // Balances of all accounts, as of the main chain tip
+    pub fn ledger(&self) -> Ledger {
+        Ledger::from_blocks(self.main.iter())
     }
 
-    pub fn forks<'a>(&'a self) -> &'a Forks {
+    pub fn balance_of(&self, peer: &str) -> i64 {
+        self.ledger().balance_of(peer)
+    }
+
+    // Index the hashes, work, and transactions of all main chain blocks from the given idx onwards
+    fn index_blocks(&mut self, from_idx: usize) {
+        for block in self.main.iter().skip(from_idx) {
+            self.block_index.insert(block.hash.clone(), block.idx);
+            self.main_work = self.main_work.saturating_add(block.work());
+            for txn in block.transactions() {
+                self.txn_index.insert(txn.hash, block.idx);
+            }
+        }
+    }
+
+    // Remove the hashes, work, and transactions of blocks no longer in the main chain from the indexes
+    fn unindex_blocks(&mut self, blocks: &Blocks) {
+        for block in blocks.iter() {
+            self.block_index.remove(&block.hash);
+            self.main_work = self.main_work.saturating_sub(block.work());
+        }
+        for txn in blocks.iter().flat_map(|b| b.transactions()) {
+            self.txn_index.remove(&txn.hash);
+        }
+    }
+
+    pub fn forks(&self) -> &Forks {
         &self.forks
     }
 
+    /* Every competing chain tip, paired with the length of the chain ending at it, longest first.
+    The main tip is included as a fork from the genesis block, and comes before forks of equal length. */
+    pub fn fork_tips(&self) -> Vec<(ForkId, usize)> {
+        let (genesis, tip) = (self.main.first(), self.last());
+        let main_id = ForkId {
+            fork_hash: genesis.hash.clone(),
+            fork_idx: genesis.idx,
+            end_hash: tip.hash.clone(),
+            end_idx: tip.idx,
+        };
+        let mut tips: Vec<(ForkId, usize)> = std::iter::once(main_id)
+            .chain(self.forks.ids())
+            .map(|id| {
+                let len = id.end_idx + 1;
+                (id, len)
+            })
+            .collect();
+        tips.sort_by(|(_, len), (_, other_len)| other_len.cmp(len));
+        tips
+    }
+
     pub fn print_forks(&self) {
         self.forks.print()
     }
 
-    pub fn orphans<'a>(&'a self) -> &'a Orphans {
+    pub fn orphans(&self) -> &Orphans {
         &self.orphans
     }
 
+    // Bound the orphan pool to a number of branches and a total number of blocks, evicting the oldest that don't fit
+    pub fn set_orphan_limits(&mut self, max_branches: usize, max_blocks: usize) -> Vec<Blocks> {
+        self.orphans.set_limits(max_branches, max_blocks)
+    }
+
+    pub fn remove_orphan(&mut self, forkpoint: &String) -> Option<Blocks> {
+        self.orphans.remove(forkpoint)
+    }
+
     pub fn print_orphans(&self) {
         self.orphans.print()
     }
 }
 
+impl<'a> IntoIterator for &'a Chain {
+    type Item = &'a Block;
+    type IntoIter = std::slice::Iter<'a, Block>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
 impl std::fmt::Display for Chain {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         writeln!(f, "{}", self.main)
     }
 }
 
+/* A read-only view of the main chain as of when it was taken, which stays the same as the chain itself moves on.
+It is cheap to clone and can be sent across threads, e.g. to serve reads without going through the peer. */
+#[derive(Clone, Debug)]
+pub struct ChainSnapshot {
+    main: Arc<Blocks>,
+    head: HeadInfo,
+}
+
+impl ChainSnapshot {
+    pub fn head(&self) -> &HeadInfo {
+        &self.head
+    }
+
+    pub fn len(&self) -> usize {
+        self.main.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.main.is_empty()
+    }
+
+    pub fn last(&self) -> &Block {
+        self.main.last()
+    }
+
+    pub fn idx(&self, idx: usize) -> Option<&Block> {
+        self.main.get(idx)
+    }
+
+    // Lookup a main chain block by its hash
+    pub fn get_by_hash(&self, hash: &str) -> Option<&Block> {
+        self.main.find(&|b: &Block| b.hash == hash)
+    }
+
+    pub fn range(&self, from: usize, to: usize) -> Option<Blocks> {
+        self.main.range(from, to)
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Block> {
+        self.main.iter()
+    }
+}
+
+// Height, tip, and total work of the main chain, and how many forks compete with it
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HeadInfo {
+    pub height: usize,
+    pub tip_hash: String,
+    pub tip_timestamp: i64,
+    pub total_work: u128,
+    pub num_forks: usize,
+}
+
+impl std::fmt::Display for HeadInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "Height: {}\nTip: {}\nTimestamp: {}\nTotal work: {}\nForks: {}",
+            self.height, self.tip_hash, self.tip_timestamp, self.total_work, self.num_forks
+        )
+    }
+}
+
 #[derive(Debug)]
 pub enum ChainStatus {
     KeepMain {
         main_len: usize,
-        other_len: Option<usize>,
+        main_work: u128,
+        other: Option<(usize, u128)>, // length and work of the compared chain or fork, if any
     },
     ChooseOther {
         main_len: usize,
+        main_work: u128,
         other_len: usize,
+        other_work: u128,
+        fork_idx: usize, // index of the last block that the old and new main chains have in common
     },
 }
 
@@ -323,29 +1039,37 @@ impl std::fmt::Display for ChainStatus {
         match self {
             ChainStatus::KeepMain {
                 main_len,
-                other_len,
+                main_work,
+                other,
             } => {
-                if let Some(other_len) = other_len {
+                if let Some((other_len, other_work)) = other {
                     write!(
                         f,
-                        "Keeping current main chain with length {}.\n\
-                    Alternative chain or fork that was compared has total length {}.",
-                        main_len, other_len
+                        "Keeping current main chain with length {} and work {}.\n\
+                    Alternative chain or fork that was compared has total length {} and work {}.",
+                        main_len, main_work, other_len, other_work
                     )
                 } else {
-                    write!(f, "Updated main chain has length {}.", main_len)
+                    write!(
+                        f,
+                        "Updated main chain has length {} and work {}.",
+                        main_len, main_work
+                    )
                 }
             }
             ChainStatus::ChooseOther {
                 main_len,
+                main_work,
                 other_len,
+                other_work,
+                ..
             } => {
                 write!(
                     f,
-                    "Choosing other chain or fork with length {}. \n\
-                     Old main chain had length {}.\n\
+                    "Choosing other chain or fork with length {} and work {}. \n\
+                     Old main chain had length {} and work {}.\n\
                      Storing old main as a fork if valid.",
-                    other_len, main_len
+                    other_len, other_work, main_len, main_work
                 )
             }
         }
@@ -1,8 +1,15 @@
-use libp2p::core::PublicKey;
+use super::file;
+use libp2p::{
+    core::PublicKey,
+    identity::{ed25519, Keypair},
+};
 use std::fmt;
 
 pub const ZERO_U32: [u8; 32] = [0; 32];
 pub const ZERO_U64: [u8; 64] = [0; 64];
+// Lengths of an ed25519 public key (in its protobuf encoding) and signature
+pub const PUBK_U8S_LEN: usize = 36;
+pub const SIG_U8S_LEN: usize = 64;
 
 #[derive(Debug)]
 pub enum HexDecodeErr {
@@ -23,6 +30,67 @@ impl fmt::Display for HexDecodeErr {
     }
 }
 
+#[derive(Debug)]
+pub enum KeyFileErr {
+    Read { path: String, msg: String },
+    Write { path: String, msg: String },
+    Corrupt { path: String, msg: String },
+}
+
+impl fmt::Display for KeyFileErr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyFileErr::Read { path, msg } => {
+                write!(
+                    f,
+                    "Key File Read Error: couldn't read \"{}\": {}",
+                    path, msg
+                )
+            }
+            KeyFileErr::Write { path, msg } => {
+                write!(
+                    f,
+                    "Key File Write Error: couldn't write \"{}\": {}",
+                    path, msg
+                )
+            }
+            KeyFileErr::Corrupt { path, msg } => {
+                write!(
+                    f,
+                    "Key File Corrupt: \"{}\" is not a 64-byte ed25519 keypair: {}",
+                    path, msg
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for KeyFileErr {}
+
+// Read an ed25519 keypair (as its raw 64 bytes) from a file, or generate and write one if the file doesn't exist
+pub fn load_or_create_keypair(path: &str) -> Result<Keypair, KeyFileErr> {
+    match std::fs::read(path) {
+        Ok(mut bytes) => ed25519::Keypair::decode(&mut bytes)
+            .map(Keypair::Ed25519)
+            .map_err(|e| KeyFileErr::Corrupt {
+                path: path.to_string(),
+                msg: e.to_string(),
+            }),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            let keys: ed25519::Keypair = ed25519::Keypair::generate();
+            file::write_private_blocking(path, keys.encode()).map_err(|e| KeyFileErr::Write {
+                path: path.to_string(),
+                msg: e.to_string(),
+            })?;
+            Ok(Keypair::Ed25519(keys))
+        }
+        Err(e) => Err(KeyFileErr::Read {
+            path: path.to_string(),
+            msg: e.to_string(),
+        }),
+    }
+}
+
 pub fn encode_pubk_to_hex(pubk: PublicKey) -> String {
     hex::encode(pubk.into_protobuf_encoding())
 }
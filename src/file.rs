@@ -1,26 +1,204 @@
 /*
     *File*: Provides auxiliary access to local storage.
-    - Functions for loading and saving the blockchain state (from `blocks.json`).
+    - Functions for loading and saving the blockchain state, including forks and orphans (from `blocks.json`),
+      as JSON or (for files ending in `.bin`) as a smaller and faster binary encoding.
+    - Functions for loading and saving the pending transaction pool (from `pool.json`).
+    - Functions for loading and saving the banned peers (from `bans.json`).
+    - Functions for replacing files atomically, so that a crash mid-write never leaves a truncated file.
+    - Functions for salvaging what can be read of a corrupt chain file.
+    - Functions for appending accepted blocks to an archive (one JSON block per line), as an audit trail separate from the chain file.
 */
 
-use super::{block::Block, chain::Chain};
+use super::{block::Block, chain::Chain, mempool::Mempool, transaction::Transaction};
 use log::info;
+use serde::de::{DeserializeSeed, Deserializer, IgnoredAny, MapAccess, SeqAccess, Visitor};
+use std::{fmt, io, path::PathBuf};
 use tokio::fs;
 
-// reads all locally stored blocks
+// The file that contents are written to before being renamed over the target, in the same directory so that the rename is atomic
+pub fn temp_path_for(file_name: &str) -> PathBuf {
+    PathBuf::from(format!("{}.tmp", file_name))
+}
+
+// (over)writes a file by writing a temporary file and renaming it over the target, leaving the target intact on failure
+pub async fn write_atomic(file_name: &str, contents: impl AsRef<[u8]>) -> io::Result<()> {
+    let temp_path: PathBuf = temp_path_for(file_name);
+    fs::write(&temp_path, contents).await?;
+    if let Err(e) = fs::rename(&temp_path, file_name).await {
+        let _ = fs::remove_file(&temp_path).await;
+        return Err(e);
+    }
+    Ok(())
+}
+
+/* as `write_atomic`, for use outside of an async context, for a file that only its owner may read or write (e.g. a private key).
+On unix, the temporary file is created afresh with these permissions, so the contents are never readable by others. */
+pub fn write_private_blocking(file_name: &str, contents: impl AsRef<[u8]>) -> io::Result<()> {
+    use std::io::Write;
+    let temp_path: PathBuf = temp_path_for(file_name);
+    match std::fs::remove_file(&temp_path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options
+        .open(&temp_path)
+        .and_then(|mut temp| temp.write_all(contents.as_ref()))
+        .and_then(|()| std::fs::rename(&temp_path, file_name))
+        .inspect_err(|_| {
+            let _ = std::fs::remove_file(&temp_path);
+        })
+}
+
+// Encodings that a chain can be stored in
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChainFormat {
+    Json, // human-readable, the default
+    Bin,  // compact binary (bincode), for files ending in `.bin`
+}
+
+impl ChainFormat {
+    pub fn from_path(file_name: &str) -> Self {
+        match std::path::Path::new(file_name).extension() {
+            Some(ext) if ext == "bin" => ChainFormat::Bin,
+            _ => ChainFormat::Json,
+        }
+    }
+}
+
+// reads a locally stored chain, or a plain list of main chain blocks as saved by older versions
 pub async fn read_chain(file_name: &str) -> Result<Chain, Box<dyn std::error::Error>> {
-    let content: Vec<u8> = fs::read(file_name).await?;
-    let blocks: Vec<Block> = serde_json::from_slice(&content)?;
-    let chain: Chain = Chain::from_vec(blocks)?;
+    let chain: Chain = match ChainFormat::from_path(file_name) {
+        ChainFormat::Json => {
+            let content: String = fs::read_to_string(file_name).await?;
+            match serde_json::from_str::<Vec<Block>>(&content) {
+                Ok(blocks) => Chain::from_vec(blocks)?,
+                Err(_) => Chain::from_json(&content)?,
+            }
+        }
+        ChainFormat::Bin => Chain::from_bytes(&fs::read(file_name).await?)?,
+    };
     info!("read_local_blocks()");
     Ok(chain)
 }
 
-// (over)writes all locally stored blocks
+/* Salvages the longest valid prefix of the main chain from a JSON chain file that can't be read as a whole,
+e.g. one with a corrupt block or trailing garbage, returning how many of the blocks that could be parsed were dropped.
+Forks and orphans are not recovered. */
+pub async fn recover_chain(file_name: &str) -> Result<(Chain, usize), Box<dyn std::error::Error>> {
+    let content: String = fs::read_to_string(file_name).await?;
+    let mut blocks: Vec<Block> = vec![];
+    // the blocks before the first that can't be parsed are kept, however the rest of the file fails
+    let _ = BlockPrefix(&mut blocks).deserialize(&mut serde_json::Deserializer::from_str(&content));
+    let (chain, dropped) = Chain::from_vec_lossy(blocks)?;
+    info!("recover_chain()");
+    Ok((chain, dropped))
+}
+
+// Collects the main chain blocks of a chain (or plain list of blocks) as they are parsed, up to the first that can't be
+struct BlockPrefix<'a>(&'a mut Vec<Block>);
+
+impl<'de> DeserializeSeed<'de> for BlockPrefix<'_> {
+    type Value = ();
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de> Visitor<'de> for BlockPrefix<'_> {
+    type Value = ();
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a chain, or a list of blocks")
+    }
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        while let Some(block) = seq.next_element::<Block>()? {
+            self.0.push(block);
+        }
+        Ok(())
+    }
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        while let Some(key) = map.next_key::<String>()? {
+            if key == "main" {
+                return map.next_value_seed(self);
+            }
+            map.next_value::<IgnoredAny>()?;
+        }
+        Ok(())
+    }
+}
+
+// (over)writes the locally stored chain, with its forks and orphans
 pub async fn write_chain(chain: &Chain, file_name: &str) -> Result<(), Box<dyn std::error::Error>> {
-    let blocks: Vec<Block> = chain.clone().to_vec();
-    let json: String = serde_json::to_string(&blocks)?;
-    fs::write(file_name, &json).await?;
+    match ChainFormat::from_path(file_name) {
+        ChainFormat::Json => write_atomic(file_name, chain.to_json()?).await?,
+        ChainFormat::Bin => write_atomic(file_name, chain.to_bytes()?).await?,
+    }
     info!("write_local_chain()");
     Ok(())
 }
+
+/* appends a block to an archive as a line of JSON, creating the archive if needed.
+Blocking rather than async, as it is called while handling a block, and an append is small. */
+pub fn append_block(block: &Block, file_name: &str) -> Result<(), Box<dyn std::error::Error>> {
+    use std::io::Write;
+
+    let mut line: String = serde_json::to_string(block)?;
+    line.push('\n');
+    std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(file_name)?
+        .write_all(line.as_bytes())?;
+    info!("append_block()");
+    Ok(())
+}
+
+// reads the blocks of an archive in the order they were appended, leaving it to the caller to validate them
+pub async fn read_blocks_jsonl(file_name: &str) -> Result<Vec<Block>, Box<dyn std::error::Error>> {
+    let content: String = fs::read_to_string(file_name).await?;
+    let blocks: Vec<Block> = content
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(serde_json::from_str)
+        .collect::<Result<_, _>>()?;
+    info!("read_blocks_jsonl()");
+    Ok(blocks)
+}
+
+// reads a locally stored transaction pool, leaving it to the caller to validate the transactions
+pub async fn read_pool(file_name: &str) -> Result<Vec<Transaction>, Box<dyn std::error::Error>> {
+    let content: String = fs::read_to_string(file_name).await?;
+    let txns: Vec<Transaction> = serde_json::from_str(&content)?;
+    info!("read_pool()");
+    Ok(txns)
+}
+
+// (over)writes the locally stored transaction pool, from highest to lowest priority
+pub async fn write_pool(pool: &Mempool, file_name: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let json: String = serde_json::to_string(&pool.iter().collect::<Vec<&Transaction>>())?;
+    write_atomic(file_name, &json).await?;
+    info!("write_pool()");
+    Ok(())
+}
+
+// reads the locally stored peer ids of banned peers
+pub async fn read_bans(file_name: &str) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let content: String = fs::read_to_string(file_name).await?;
+    let bans: Vec<String> = serde_json::from_str(&content)?;
+    info!("read_bans()");
+    Ok(bans)
+}
+
+// (over)writes the locally stored peer ids of banned peers
+pub async fn write_bans(
+    bans: &[String],
+    file_name: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let json: String = serde_json::to_string(bans)?;
+    write_atomic(file_name, &json).await?;
+    info!("write_bans()");
+    Ok(())
+}
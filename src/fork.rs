@@ -7,7 +7,7 @@ use super::{
     util::abbrev,
 };
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 
 #[derive(Clone, Debug)]
 pub struct ForkId {
@@ -37,6 +37,64 @@ impl ForkId {
     }
 }
 
+// Summary of a fork or orphaned branch, for json output: where it branches from, where it ends, its length, and the work of its blocks
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BranchSummary {
+    pub forkpoint_idx: usize,
+    pub forkpoint: String,
+    pub endpoint_idx: usize,
+    pub endpoint: String,
+    pub len: usize,
+    pub work: u128,
+}
+
+impl BranchSummary {
+    pub fn new(branch: &Blocks) -> Self {
+        let ForkId {
+            fork_hash,
+            fork_idx,
+            end_hash,
+            end_idx,
+        } = Forks::identify(branch);
+        BranchSummary {
+            forkpoint_idx: fork_idx,
+            forkpoint: fork_hash,
+            endpoint_idx: end_idx,
+            endpoint: end_hash,
+            len: branch.len(),
+            work: branch.total_work(),
+        }
+    }
+}
+
+impl std::fmt::Display for BranchSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "from (idx: {}, hash: {}) to (idx: {}, hash: {}), {} block(s) with work {}",
+            self.forkpoint_idx,
+            abbrev(&self.forkpoint),
+            self.endpoint_idx,
+            abbrev(&self.endpoint),
+            self.len,
+            self.work
+        )
+    }
+}
+
+// Summaries of branches, ordered by where they branch from and end, so that output is stable
+fn summarise<'a>(branches: impl Iterator<Item = &'a Blocks>) -> Vec<BranchSummary> {
+    let mut summaries: Vec<BranchSummary> = branches.map(BranchSummary::new).collect();
+    summaries.sort_by(|a, b| {
+        (a.forkpoint_idx, a.endpoint_idx, &a.endpoint).cmp(&(
+            b.forkpoint_idx,
+            b.endpoint_idx,
+            &b.endpoint,
+        ))
+    });
+    summaries
+}
+
 // Forks are represented as a set of forkpoints (from the main chain) from which various branches arise and
 // share common prefixes of blocks.
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -84,6 +142,14 @@ impl Forks {
         None
     }
 
+    // Whether any fork contains a block with the given hash
+    pub fn contains_hash(&self, hash: &str) -> bool {
+        self.0
+            .values()
+            .flat_map(|forks_from| forks_from.values())
+            .any(|fork| fork.contains_hash(hash))
+    }
+
     pub fn get<'a>(&'a self, forkpoint: &String, endpoint: &String) -> Option<&'a Blocks> {
         self.0.get(forkpoint).and_then(|forks| forks.get(endpoint))
     }
@@ -101,17 +167,31 @@ impl Forks {
         })
     }
 
-    // Return a reference to the longest stored fork
-    pub fn longest<'a>(&'a self) -> Option<(&'a Blocks, ForkId)> {
-        let longest_fork: Option<(&'a Blocks, ForkId)> = None;
+    // Iterate over every stored fork across all forkpoints
+    pub fn iter(&self) -> impl Iterator<Item = &Blocks> {
+        self.0.values().flat_map(|forks| forks.values())
+    }
 
-        self.0
-            .values()
-            .flat_map(|forks| forks.values())
-            .fold(longest_fork, |longest, current| match longest {
-                Some(fork) if fork.0.len() >= current.len() => Some(fork),
-                _ => Some((current, Self::identify(current))),
-            })
+    /* Summaries of every fork, those whose chains end highest first, then those whose own blocks have the most work.
+    Ties are broken by where they branch from and end, so that output is stable. */
+    pub fn summaries(&self) -> Vec<BranchSummary> {
+        let mut summaries: Vec<BranchSummary> = summarise(self.iter());
+        summaries.sort_by_key(|s| std::cmp::Reverse((s.endpoint_idx, s.work)));
+        summaries
+    }
+
+    // Identifiers of every fork across all forkpoints
+    pub fn ids(&self) -> Vec<ForkId> {
+        self.iter().map(Self::identify).collect()
+    }
+
+    // Total number of forks across all forkpoints
+    pub fn len(&self) -> usize {
+        self.0.values().map(|forks| forks.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
     }
 
     pub fn retain_forkpoints(&mut self, forkpoints: &[String]) {
@@ -119,6 +199,24 @@ impl Forks {
         self.0.retain(|forkpoint, _| forkpoints.contains(forkpoint));
     }
 
+    /* Remove the forks that end below an idx, returning how many were removed.
+    The fork that ends highest is always kept, as the one most likely to still overtake the main chain. */
+    pub fn prune_older_than(&mut self, min_tip_idx: usize) -> usize {
+        let ids: Vec<ForkId> = self.ids();
+        let highest_end: Option<String> = ids
+            .iter()
+            .max_by_key(|id| (id.end_idx, std::cmp::Reverse(id.end_hash.clone())))
+            .map(|id| id.end_hash.clone());
+        let stale: Vec<ForkId> = ids
+            .into_iter()
+            .filter(|id| id.end_idx < min_tip_idx && Some(&id.end_hash) != highest_end.as_ref())
+            .collect();
+        for id in &stale {
+            self.remove(&id.fork_hash, &id.end_hash);
+        }
+        stale.len()
+    }
+
     pub fn remove(&mut self, forkpoint: &String, endpoint: &String) -> Option<Blocks> {
         // Remove the fork matching the (forkpoint, endpoint)
         let fork = self
@@ -169,15 +267,9 @@ impl Forks {
     }
 
     pub fn print(&self) {
-        for (_, forks_from) in self.0.iter() {
-            for (i, (_, fork)) in forks_from.iter().enumerate() {
-                let id = Self::identify(fork);
-                println!(
-                    "Fork from (idx: {}, hash: {}) #{}:",
-                    id.fork_idx,
-                    abbrev(&id.fork_hash),
-                    i
-                );
+        for (i, summary) in self.summaries().iter().enumerate() {
+            println!("Fork #{} {}:", i, summary);
+            if let Some(fork) = self.get(&summary.forkpoint, &summary.endpoint) {
                 fork.iter().for_each(|block| println!("{}", block));
             }
         }
@@ -193,21 +285,49 @@ impl Default for Forks {
 // Orphan branches are represented as a disjoint set of chains that are constructed backwards.
 // We do not track whether each orphan branch has blocks in common i.e. are forks of each other;
 // they are used to connect an orphan node back to the main chain as fast as possible, at which point it forms a fork.
+// The pool is bounded in both branches and blocks, evicting the oldest branches first, so that a peer flooded
+// with disconnected blocks doesn't grow without limit.
+
+pub const DEFAULT_MAX_ORPHAN_BRANCHES: usize = 100;
+pub const DEFAULT_MAX_ORPHAN_BLOCKS: usize = 1000;
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct Orphans(HashMap<String, Blocks>); // <fork point, orphaned branch>
+#[serde(from = "HashMap<String, Blocks>", into = "HashMap<String, Blocks>")]
+pub struct Orphans {
+    branches: HashMap<String, Blocks>, // <fork point, orphaned branch>
+    // fork points from the oldest inserted branch to the newest, rebuilt rather than serialized
+    order: VecDeque<String>,
+    max_branches: usize,
+    max_blocks: usize,
+}
 pub type OrphanId = String; // fork hash
 
 impl Orphans {
     pub fn new() -> Self {
-        Orphans(HashMap::new())
+        Self::with_limits(DEFAULT_MAX_ORPHAN_BRANCHES, DEFAULT_MAX_ORPHAN_BLOCKS)
     }
 
-    pub fn find<'a, P>(&'a self, prop: P) -> Option<(OrphanId, &'a Blocks, &'a Block)>
+    pub fn with_limits(max_branches: usize, max_blocks: usize) -> Self {
+        Orphans {
+            branches: HashMap::new(),
+            order: VecDeque::new(),
+            max_branches,
+            max_blocks,
+        }
+    }
+
+    // Change the limits, evicting the oldest branches that no longer fit
+    pub fn set_limits(&mut self, max_branches: usize, max_blocks: usize) -> Vec<Blocks> {
+        self.max_branches = max_branches;
+        self.max_blocks = max_blocks;
+        self.evict()
+    }
+
+    pub fn find<P>(&self, prop: P) -> Option<(OrphanId, &Blocks, &Block)>
     where
         P: Fn(&Block) -> bool,
     {
-        for (forkpoint, orphan) in self.0.iter() {
+        for (forkpoint, orphan) in self.branches.iter() {
             if let Some(b) = Blocks::find(orphan, &prop) {
                 return Some((forkpoint.clone(), orphan, b));
             }
@@ -215,34 +335,130 @@ impl Orphans {
         None
     }
 
+    // Whether any orphan branch contains a block with the given hash
+    pub fn contains_hash(&self, hash: &str) -> bool {
+        self.branches
+            .values()
+            .any(|orphan| orphan.contains_hash(hash))
+    }
+
     pub fn get<'a>(&'a self, forkpoint: &String) -> Option<&'a Blocks> {
-        self.0.get(forkpoint)
+        self.branches.get(forkpoint)
     }
 
     pub fn get_mut<'a>(&'a mut self, forkpoint: &String) -> Option<&'a mut Blocks> {
-        self.0.get_mut(forkpoint)
+        self.branches.get_mut(forkpoint)
     }
 
-    pub fn insert(&mut self, orphan: Blocks) -> OrphanId {
+    pub fn len(&self) -> usize {
+        self.branches.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.branches.is_empty()
+    }
+
+    // Total number of blocks across all orphaned branches
+    pub fn num_blocks(&self) -> usize {
+        self.branches.values().map(|orphan| orphan.len()).sum()
+    }
+
+    /* Insert a branch as the newest, then evict the oldest branches until the pool is within its limits.
+    Returns the evicted branches, which include the new one if it alone is too long to fit. */
+    pub fn insert(&mut self, orphan: Blocks) -> (OrphanId, Vec<Blocks>) {
         let orphan_id: String = orphan.first().prev_hash.clone();
-        self.0.insert(orphan_id.clone(), orphan);
-        orphan_id
+        if self.branches.insert(orphan_id.clone(), orphan).is_some() {
+            self.order.retain(|forkpoint| *forkpoint != orphan_id);
+        }
+        self.order.push_back(orphan_id.clone());
+        (orphan_id, self.evict())
     }
 
     pub fn remove(&mut self, forkpoint: &String) -> Option<Blocks> {
-        self.0.remove(forkpoint)
+        let orphan = self.branches.remove(forkpoint)?;
+        self.order.retain(|f| f != forkpoint);
+        Some(orphan)
+    }
+
+    // Remove the oldest branches while there are too many branches or blocks
+    fn evict(&mut self) -> Vec<Blocks> {
+        let mut evicted: Vec<Blocks> = vec![];
+        while self.len() > self.max_branches || self.num_blocks() > self.max_blocks {
+            match self.order.pop_front() {
+                Some(oldest) => evicted.extend(self.branches.remove(&oldest)),
+                None => break,
+            }
+        }
+        evicted
     }
 
-    pub fn extend_orphan(&mut self, block: Block) -> Result<OrphanId, NextBlockErr> {
+    pub fn summaries(&self) -> Vec<BranchSummary> {
+        summarise(self.branches.values())
+    }
+
+    // The missing parent (its idx and hash) of each orphaned branch
+    pub fn forkpoints(&self) -> Vec<(usize, String)> {
+        self.branches
+            .iter()
+            .map(|(forkpoint, orphan)| (orphan.first().idx.saturating_sub(1), forkpoint.clone()))
+            .collect()
+    }
+
+    // Prepend the missing parent of a branch, making it the newest branch, and evicting as in `insert`
+    pub fn extend_orphan(&mut self, block: Block) -> Result<(OrphanId, Vec<Blocks>), NextBlockErr> {
         let mut orphan: Blocks = self.remove(&block.hash).unwrap();
         Blocks::push_front(&mut orphan, block.clone())?;
         Ok(self.insert(orphan))
     }
 
+    /* Join each branch onto the end of the branch holding its missing parent, until no two branches link up.
+    Returns the ids of the branches that others were joined onto; a branch that can't validly be joined is dropped. */
+    pub fn try_link(&mut self) -> Vec<OrphanId> {
+        let mut linked: Vec<OrphanId> = vec![];
+        while let Some((prefix_id, suffix_id)) = self.branches.iter().find_map(|(id, orphan)| {
+            let end_hash: &String = &orphan.last().hash;
+            self.branches
+                .contains_key(end_hash)
+                .then(|| (id.clone(), end_hash.clone()))
+        }) {
+            let suffix: Blocks = self.remove(&suffix_id).expect("suffix branch exists");
+            let prefix: &mut Blocks = self
+                .branches
+                .get_mut(&prefix_id)
+                .expect("prefix branch exists");
+            if prefix.append(suffix).is_ok() && !linked.contains(&prefix_id) {
+                linked.push(prefix_id);
+            }
+        }
+        linked
+    }
+
     pub fn print(&self) {
-        for (i, orphan) in self.0.iter().enumerate() {
-            println!("Orphaned branch {}:\n\t{:?}\n", i, orphan.1);
+        for (i, orphan) in self
+            .order
+            .iter()
+            .filter_map(|f| self.branches.get(f))
+            .enumerate()
+        {
+            println!("Orphaned branch {}:\n\t{:?}\n", i, orphan);
+        }
+    }
+}
+
+// Orphans are serialized as just their branches, and take the default limits when deserialized
+impl From<HashMap<String, Blocks>> for Orphans {
+    fn from(branches: HashMap<String, Blocks>) -> Self {
+        let mut orphans = Orphans::new();
+        for orphan in branches.into_values() {
+            orphans.insert(orphan);
         }
+        orphans
+    }
+}
+
+impl From<Orphans> for HashMap<String, Blocks> {
+    fn from(orphans: Orphans) -> Self {
+        orphans.branches
     }
 }
 
@@ -0,0 +1,72 @@
+/*
+    *Ledger*:
+    - Account balances, computed by folding over the transactions of a sequence of blocks.
+    - Methods for checking whether an account can afford a transaction.
+*/
+
+use super::{block::Block, transaction::Transaction};
+use std::collections::HashMap;
+
+// Balance that every account starts with before any transactions
+pub const GENESIS_ALLOCATION: i64 = 1000;
+
+// Initial balance of an account, used to bootstrap balances alongside the coins that coinbases mint
+pub fn genesis_allocation(_account: &str) -> i64 {
+    GENESIS_ALLOCATION
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Ledger {
+    // <account, balance>, only holding accounts that appear in a transaction
+    balances: HashMap<String, i64>,
+}
+
+impl Ledger {
+    pub fn new() -> Self {
+        Ledger::default()
+    }
+
+    // Credit receivers and debit senders for every transaction in the blocks, in order (coinbases only credit their receivers).
+    // Fees are debited from senders here, and credited to miners through the coinbases that collect them.
+    pub fn from_blocks<'a>(blocks: impl IntoIterator<Item = &'a Block>) -> Self {
+        let mut ledger = Ledger::new();
+        for txn in blocks.into_iter().flat_map(|block| block.transactions()) {
+            ledger.apply(&txn);
+        }
+        ledger
+    }
+
+    pub fn apply(&mut self, txn: &Transaction) {
+        let amount: i64 = i64::try_from(txn.amount).unwrap_or(i64::MAX);
+        if !txn.is_coinbase() {
+            let debit: i64 = txn
+                .total_debit()
+                .and_then(|debit| i64::try_from(debit).ok())
+                .unwrap_or(i64::MAX);
+            let sender: &mut i64 = self.account(&txn.sender);
+            *sender = sender.saturating_sub(debit);
+        }
+        let receiver: &mut i64 = self.account(&txn.receiver);
+        *receiver = receiver.saturating_add(amount);
+    }
+
+    pub fn balance_of(&self, account: &str) -> i64 {
+        self.balances
+            .get(account)
+            .copied()
+            .unwrap_or_else(|| genesis_allocation(account))
+    }
+
+    // Check that the sender can afford a transaction's amount and fee, given the amount already committed to pending ones
+    pub fn can_afford(&self, txn: &Transaction, pending_debits: u64) -> bool {
+        let debits: i128 =
+            i128::from(pending_debits) + i128::from(txn.amount) + i128::from(txn.fee);
+        i128::from(self.balance_of(&txn.sender)) >= debits
+    }
+
+    fn account(&mut self, account: &str) -> &mut i64 {
+        self.balances
+            .entry(account.to_string())
+            .or_insert_with(|| genesis_allocation(account))
+    }
+}
@@ -1,16 +1,34 @@
 #[macro_use]
 pub mod util;
+pub mod api;
 pub mod block;
 pub mod chain;
 pub mod crypt;
 pub mod file;
 pub mod fork;
+pub mod ledger;
+pub mod mempool;
+pub mod merkle;
 pub mod message;
+pub mod network;
 pub mod peer;
 pub mod swarm;
+pub mod swarm_flood;
 pub mod transaction;
 pub mod tests {
+    pub mod api;
     pub mod block;
     pub mod chain;
+    pub mod common;
+    pub mod crypt;
+    pub mod file;
+    pub mod ledger;
+    pub mod mempool;
+    pub mod merkle;
+    pub mod message;
+    pub mod network;
+    pub mod peer;
+    pub mod swarm;
     pub mod transaction;
+    pub mod util;
 }
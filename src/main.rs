@@ -1,9 +1,29 @@
-use powp2p;
+use powp2p::peer::PeerConfig;
 
-// RUST_LOG=info cargo run --bin main
+// RUST_LOG=info cargo run --bin main [--relay]
 #[tokio::main]
 async fn main() {
     pretty_env_logger::init();
-    let mut peer = powp2p::peer::set_up_peer().await;
+    let config = PeerConfig::from_args(std::env::args().skip(1));
+    let http_addr: Option<String> = config.http_addr.clone();
+    let mut peer = powp2p::peer::set_up_peer(config).await;
+    if let Some(addr) = http_addr {
+        serve_http(addr, peer.api_sender());
+    }
     peer.run().await
 }
+
+#[cfg(feature = "http-api")]
+fn serve_http(addr: String, sender: powp2p::api::ApiSender) {
+    tokio::spawn(async move {
+        println!("Serving HTTP api on {}", addr);
+        if let Err(e) = powp2p::api::serve(&addr, sender).await {
+            eprintln!("Error serving HTTP api on {}: {}", addr, e)
+        }
+    });
+}
+
+#[cfg(not(feature = "http-api"))]
+fn serve_http(_addr: String, _sender: powp2p::api::ApiSender) {
+    eprintln!("Ignoring `--http`, which requires building with `--features http-api`")
+}
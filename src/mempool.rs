@@ -0,0 +1,172 @@
+/*
+    *Mempool*:
+    - Pool of pending transactions, ordered by priority (fee, then age) and bounded in size.
+    - Methods for adding, removing, and taking the highest-priority transactions.
+*/
+
+use super::{block::Block, transaction::Transaction};
+use std::{cmp::Reverse, collections::BTreeMap};
+
+pub const DEFAULT_MEMPOOL_CAPACITY: usize = 1000;
+
+// Priority of a transaction, highest fee first, then earliest, with the hash breaking ties so that distinct transactions never collide
+type Priority = (Reverse<u64>, i64, String);
+
+fn priority(txn: &Transaction) -> Priority {
+    (Reverse(txn.fee), txn.timestamp, txn.hash.clone())
+}
+
+#[derive(Clone, Debug)]
+pub struct Mempool {
+    txns: BTreeMap<Priority, Transaction>,
+    capacity: usize,
+}
+
+impl Default for Mempool {
+    fn default() -> Self {
+        Mempool::new(DEFAULT_MEMPOOL_CAPACITY)
+    }
+}
+
+impl Mempool {
+    pub fn new(capacity: usize) -> Self {
+        Mempool {
+            txns: BTreeMap::new(),
+            capacity,
+        }
+    }
+
+    /* Add a transaction, evicting the lowest-priority one if full, unless that would be the new transaction itself.
+    A transaction that takes the same sequence number as a pending one from the same sender instead replaces it,
+    as long as it pays a higher fee, or the same fee and is newer, and returns it as evicted. */
+    pub fn insert(&mut self, txn: Transaction) -> Result<Option<Transaction>, MempoolErr> {
+        let key: Priority = priority(&txn);
+        if self.txns.contains_key(&key) {
+            return Err(MempoolErr::Duplicate { hash: txn.hash });
+        }
+        let conflict: Option<Priority> = self.txns.iter().find_map(|(pending_key, pending)| {
+            pending.conflicts_with(&txn).then(|| pending_key.clone())
+        });
+        // the priority of the pending transaction is its fee, timestamp, and hash
+        if let Some(pending_key) = conflict {
+            if (pending_key.0 .0, pending_key.1) >= (txn.fee, txn.timestamp) {
+                return Err(MempoolErr::ReplacementUnderpriced {
+                    hash: txn.hash,
+                    pending_hash: pending_key.2,
+                });
+            }
+            let replaced: Option<Transaction> = self.txns.remove(&pending_key);
+            self.txns.insert(key, txn);
+            return Ok(replaced);
+        }
+        let mut evicted: Option<Transaction> = None;
+        if self.txns.len() >= self.capacity {
+            match self.txns.last_key_value() {
+                Some((lowest, _)) if *lowest > key => {
+                    evicted = self.txns.pop_last().map(|(_, txn)| txn);
+                }
+                _ => {
+                    return Err(MempoolErr::Full {
+                        capacity: self.capacity,
+                    })
+                }
+            }
+        }
+        self.txns.insert(key, txn);
+        Ok(evicted)
+    }
+
+    pub fn contains(&self, txn: &Transaction) -> bool {
+        self.txns.contains_key(&priority(txn))
+    }
+
+    pub fn remove(&mut self, txn: &Transaction) -> bool {
+        self.txns.remove(&priority(txn)).is_some()
+    }
+
+    // Remove the transactions of a block that has been added to the chain, returning how many were pending
+    pub fn remove_block(&mut self, block: &Block) -> usize {
+        block
+            .transactions()
+            .iter()
+            .filter(|txn| self.remove(txn))
+            .count()
+    }
+
+    // Remove the transactions that expired before the given time, returning how many there were
+    pub fn remove_expired(&mut self, now: i64) -> usize {
+        let len: usize = self.txns.len();
+        self.txns.retain(|_, txn| txn.valid_until >= now);
+        len - self.txns.len()
+    }
+
+    // Take up to n of the highest-priority transactions
+    pub fn take(&mut self, n: usize) -> Vec<Transaction> {
+        std::iter::from_fn(|| self.txns.pop_first().map(|(_, txn)| txn))
+            .take(n)
+            .collect()
+    }
+
+    // Transactions from highest to lowest priority
+    pub fn iter(&self) -> impl Iterator<Item = &Transaction> {
+        self.txns.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.txns.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.txns.is_empty()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+}
+
+// Return transactions to the pool (e.g. from a cancelled miner), dropping any that are duplicates or don't fit
+impl Extend<Transaction> for Mempool {
+    fn extend<I: IntoIterator<Item = Transaction>>(&mut self, txns: I) {
+        for txn in txns {
+            let _ = self.insert(txn);
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum MempoolErr {
+    Duplicate { hash: String },
+    Full { capacity: usize },
+    ReplacementUnderpriced { hash: String, pending_hash: String },
+}
+
+impl std::fmt::Display for MempoolErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MempoolErr::Duplicate { hash } => {
+                write!(
+                    f,
+                    "Duplicate: transaction ({}) is already in the pool",
+                    hash
+                )
+            }
+            MempoolErr::Full { capacity } => {
+                write!(
+                    f,
+                    "Pool Full: pool is at capacity ({}) with higher-priority transactions",
+                    capacity
+                )
+            }
+            MempoolErr::ReplacementUnderpriced { hash, pending_hash } => {
+                write!(
+                    f,
+                    "Replacement Underpriced: transaction ({}) doesn't outbid pending transaction ({}) with the same sender and nonce, by a higher fee or (at the same fee) a later timestamp",
+                    hash, pending_hash
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for MempoolErr {}
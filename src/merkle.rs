@@ -0,0 +1,126 @@
+/*
+    *Merkle*:
+    - Merkle trees over the transactions of a block, whose root summarises all of them.
+    - Proofs that a transaction is included in a block, which can be checked without the rest of the block or chain.
+*/
+
+use super::{block::Block, crypt, transaction::Transaction};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+// Hash a leaf (a transaction hash), tagged so that it can never be mistaken for an inner node
+fn hash_leaf(txn_hash: &str) -> String {
+    let mut hasher: Sha256 = Sha256::new();
+    hasher.update([0u8]);
+    hasher.update(txn_hash.as_bytes());
+    crypt::encode_bytes_to_hex(hasher.finalize())
+}
+
+// Hash a pair of sibling nodes in sorted order, so that proofs don't need to record which side each sibling is on
+fn hash_nodes(a: &str, b: &str) -> String {
+    let (left, right) = if a <= b { (a, b) } else { (b, a) };
+    let mut hasher: Sha256 = Sha256::new();
+    hasher.update([1u8]);
+    hasher.update(left.as_bytes());
+    hasher.update(right.as_bytes());
+    crypt::encode_bytes_to_hex(hasher.finalize())
+}
+
+// Hash each pair of nodes in a level of the tree, carrying an odd node out up to the next level as it is
+fn next_level(level: &[String]) -> Vec<String> {
+    level
+        .chunks(2)
+        .map(|pair| match pair {
+            [a, b] => hash_nodes(a, b),
+            [a] => a.clone(),
+            _ => unreachable!("chunks have one or two nodes"),
+        })
+        .collect()
+}
+
+// Root of the tree whose leaves are the given transaction hashes, or all zeros if there are none
+pub fn merkle_root(txn_hashes: &[String]) -> String {
+    let mut level: Vec<String> = txn_hashes.iter().map(|h| hash_leaf(h)).collect();
+    if level.is_empty() {
+        return crypt::encode_bytes_to_hex(crypt::ZERO_U32);
+    }
+    while level.len() > 1 {
+        level = next_level(&level);
+    }
+    level.remove(0)
+}
+
+// Siblings of the path from the leaf at an index up to the root, or None if there is no such leaf
+pub fn merkle_proof(txn_hashes: &[String], idx: usize) -> Option<Vec<String>> {
+    let mut level: Vec<String> = txn_hashes.iter().map(|h| hash_leaf(h)).collect();
+    if idx >= level.len() {
+        return None;
+    }
+    let (mut idx, mut proof) = (idx, vec![]);
+    while level.len() > 1 {
+        // an odd node out has no sibling at this level
+        if let Some(sibling) = level.get(idx ^ 1) {
+            proof.push(sibling.clone());
+        }
+        level = next_level(&level);
+        idx /= 2;
+    }
+    Some(proof)
+}
+
+// Root of the tree over a block's transactions
+pub fn block_merkle_root(block: &Block) -> String {
+    let txn_hashes: Vec<String> = block
+        .transactions()
+        .iter()
+        .map(|t| t.hash.clone())
+        .collect();
+    merkle_root(&txn_hashes)
+}
+
+/* A transaction along with the block that contains it, and the path from it to the root of the block's transactions.
+The root is computed from the block's transactions rather than committed to by the block's hash, so the root recorded
+in a proof is only informative: a proof is verified against a root the client trusts, e.g. one computed from its own copy of the block. */
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TxnInclusionProof {
+    pub block_hash: String,
+    pub block_idx: usize,
+    pub merkle_root: String,
+    pub proof: Vec<String>,
+    pub txn: Transaction,
+}
+
+impl TxnInclusionProof {
+    // Prove that a block contains a transaction, or None if it doesn't
+    pub fn new(block: &Block, txn_hash: &str) -> Option<Self> {
+        let txns: Vec<Transaction> = block.transactions();
+        let idx: usize = txns.iter().position(|t| t.hash == txn_hash)?;
+        let txn_hashes: Vec<String> = txns.iter().map(|t| t.hash.clone()).collect();
+        Some(TxnInclusionProof {
+            block_hash: block.hash.clone(),
+            block_idx: block.idx,
+            merkle_root: merkle_root(&txn_hashes),
+            proof: merkle_proof(&txn_hashes, idx)?,
+            txn: txns[idx].clone(),
+        })
+    }
+
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string(self)
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+}
+
+// Check that a proof's transaction was signed by its sender (if since expired), and that its path leads to a trusted root
+pub fn verify_proof(proof: &TxnInclusionProof, trusted_root: &str) -> bool {
+    let root: String = proof
+        .proof
+        .iter()
+        .fold(hash_leaf(&proof.txn.hash), |node, sibling| {
+            hash_nodes(&node, sibling)
+        });
+    Transaction::validate_signed(&proof.txn).is_ok() && root == trusted_root
+}
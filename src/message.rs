@@ -1,11 +1,70 @@
 /*
     *Message*: Provides the message forms communicated between peers.
     - Messages for requesting and responding with chains or new blocks.
+    - Messages for comparing chains by their block hashes.
     - Messages for broadcasting new transactions.
+    - The wire format of messages: json, compressed if large.
 */
 
-use super::{block, chain, transaction, util::abbrev};
-use serde::{Deserialize, Serialize};
+use super::{block, chain, swarm::MAX_MESSAGE_SIZE, transaction, util::abbrev};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::borrow::Cow;
+
+// Prefix marking a compressed message, which can't begin a json message, so that plain messages from older peers still parse
+pub const COMPRESSED_PREFIX: &[u8] = b"\0lz4";
+// Size (in bytes) of json below which a message isn't worth compressing
+pub const COMPRESS_THRESHOLD: usize = 1024;
+// Largest size (in bytes) a compressed message may claim to decompress to, so that a peer can't make us allocate much more than
+// a message can be sent as. Larger json is sent uncompressed, so still can't be sent as a message.
+pub const MAX_DECOMPRESSED_SIZE: usize = 2 * MAX_MESSAGE_SIZE; // 20mb
+
+// Encode a message as json, compressing it (behind a prefix) if it is large enough to benefit
+pub fn encode_msg<T: Serialize>(msg: &T) -> Result<Vec<u8>, serde_json::Error> {
+    Ok(compress_json(serde_json::to_vec(msg)?))
+}
+
+// Compress a message's json (behind a prefix) if it is large enough to benefit
+pub fn compress_json(json: Vec<u8>) -> Vec<u8> {
+    if json.len() < COMPRESS_THRESHOLD || json.len() > MAX_DECOMPRESSED_SIZE {
+        return json;
+    }
+    let compressed: Vec<u8> = lz4_flex::compress_prepend_size(&json);
+    if COMPRESSED_PREFIX.len() + compressed.len() >= json.len() {
+        return json;
+    }
+    [COMPRESSED_PREFIX, &compressed].concat()
+}
+
+// Decode a message that is either compressed behind the prefix, or plain json
+pub fn decode_msg<T: DeserializeOwned>(data: &[u8]) -> Result<T, Box<dyn std::error::Error>> {
+    Ok(serde_json::from_slice(&msg_json(data)?)?)
+}
+
+// The json of a message, decompressed if it is behind the prefix
+pub fn msg_json(data: &[u8]) -> Result<Cow<'_, [u8]>, Box<dyn std::error::Error>> {
+    match data.strip_prefix(COMPRESSED_PREFIX) {
+        Some(compressed) => {
+            let (size, compressed) = match compressed.split_first_chunk::<4>() {
+                Some((size, compressed)) => (u32::from_le_bytes(*size) as usize, compressed),
+                None => return Err("compressed message is missing its size".into()),
+            };
+            if size > MAX_DECOMPRESSED_SIZE {
+                return Err(
+                    format!("compressed message claims too large a size, {} bytes", size).into(),
+                );
+            }
+            Ok(Cow::Owned(lz4_flex::decompress(compressed, size)?))
+        }
+        None => Ok(Cow::Borrowed(data)),
+    }
+}
+
+// Name of a message's variant (e.g. "NewBlock"), read from the start of its json without decoding the rest
+pub fn msg_variant(json: &[u8]) -> Option<&str> {
+    let rest: &[u8] = json.strip_prefix(b"{\"")?;
+    let end: usize = rest.iter().position(|&b| b == b'"')?;
+    std::str::from_utf8(&rest[..end]).ok()
+}
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum PowMessage {
@@ -23,6 +82,7 @@ pub enum PowMessage {
         hash: String,
         target: Option<String>, // either to a specific peer (Some) or all peers (None)
         source: String,
+        ttl: u8, // number of times the request may still be forwarded by peers that don't have the block
     },
     BlockResponse {
         block: block::Block,
@@ -34,6 +94,35 @@ pub enum PowMessage {
         block: block::Block,
         source: String,
     },
+    BlockRangeRequest {
+        from_idx: usize,
+        to_idx: usize,
+        target: Option<String>, // either to a specific peer (Some) or all peers (None)
+        source: String,
+    },
+    BlockRangeResponse {
+        blocks: block::Blocks,
+        target: String, // always to the specific requesting peer
+        source: String,
+    },
+    HeadersRequest {
+        target: Option<String>, // either to a specific peer (Some) or all peers (None)
+        source: String,
+    },
+    HeadersResponse {
+        target: String, // always to the specific requesting peer
+        source: String,
+        headers: Vec<block::BlockHeaderSummary>,
+    },
+    ChainDigestRequest {
+        target: String, // always to a specific peer, whose chain we are comparing against
+        source: String,
+    },
+    ChainDigest {
+        target: String, // always to the specific requesting peer
+        source: String,
+        hashes: Vec<String>, // hashes of the main chain's blocks, in order
+    },
 }
 
 impl PowMessage {
@@ -43,7 +132,29 @@ impl PowMessage {
             | PowMessage::ChainResponse { source, .. }
             | PowMessage::BlockRequest { source, .. }
             | PowMessage::BlockResponse { source, .. }
-            | PowMessage::NewBlock { source, .. } => source,
+            | PowMessage::NewBlock { source, .. }
+            | PowMessage::BlockRangeRequest { source, .. }
+            | PowMessage::BlockRangeResponse { source, .. }
+            | PowMessage::HeadersRequest { source, .. }
+            | PowMessage::HeadersResponse { source, .. }
+            | PowMessage::ChainDigestRequest { source, .. }
+            | PowMessage::ChainDigest { source, .. } => source,
+        }
+    }
+    // Name of the message type, e.g. for grouping statistics
+    pub fn kind(&self) -> &'static str {
+        match self {
+            PowMessage::ChainRequest { .. } => "chain request",
+            PowMessage::ChainResponse { .. } => "chain response",
+            PowMessage::BlockRequest { .. } => "block request",
+            PowMessage::BlockResponse { .. } => "block response",
+            PowMessage::NewBlock { .. } => "new block",
+            PowMessage::BlockRangeRequest { .. } => "block range request",
+            PowMessage::BlockRangeResponse { .. } => "block range response",
+            PowMessage::HeadersRequest { .. } => "headers request",
+            PowMessage::HeadersResponse { .. } => "headers response",
+            PowMessage::ChainDigestRequest { .. } => "chain digest request",
+            PowMessage::ChainDigest { .. } => "chain digest",
         }
     }
 }
@@ -56,6 +167,27 @@ impl std::fmt::Display for PowMessage {
                 write!(f, "Chain response that has length {}", chain.len())
             }
             PowMessage::NewBlock { block, .. } => write!(f, "New block with idx {}", block.idx),
+            PowMessage::BlockRangeRequest {
+                from_idx, to_idx, ..
+            } => write!(f, "Block range request for idxs {} to {}", from_idx, to_idx),
+            // an empty range can't be decoded, but is described rather than assumed away
+            PowMessage::BlockRangeResponse { blocks, .. } if blocks.is_empty() => {
+                write!(f, "Block range response with no blocks")
+            }
+            PowMessage::BlockRangeResponse { blocks, .. } => write!(
+                f,
+                "Block range response for idxs {} to {}",
+                blocks.first().idx,
+                blocks.last().idx
+            ),
+            PowMessage::HeadersRequest { .. } => write!(f, "Headers request"),
+            PowMessage::HeadersResponse { headers, .. } => {
+                write!(f, "Headers response that has length {}", headers.len())
+            }
+            PowMessage::ChainDigestRequest { .. } => write!(f, "Chain digest request"),
+            PowMessage::ChainDigest { hashes, .. } => {
+                write!(f, "Chain digest that has length {}", hashes.len())
+            }
             PowMessage::BlockRequest { idx, hash, .. } => write!(
                 f,
                 "Block request for idx {} with hash {}",
@@ -86,6 +218,11 @@ impl TxnMessage {
             TxnMessage::NewTransaction { source, .. } => source,
         }
     }
+    pub fn kind(&self) -> &'static str {
+        match &self {
+            TxnMessage::NewTransaction { .. } => "new transaction",
+        }
+    }
 }
 impl std::fmt::Display for TxnMessage {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
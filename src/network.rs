@@ -0,0 +1,191 @@
+/*
+    *Network*: The interface through which a peer talks to the network, independent of the messaging protocol.
+    - A trait for broadcasting messages and managing peers, implemented by a swarm for each protocol.
+    - Sets up a swarm for the chosen protocol: GossipSub (the default) or FloodSub (for interop with simpler peers).
+*/
+
+use super::{
+    message::{PowMessage, TxnMessage},
+    swarm::{self, Delivered, SwarmConfig, SwarmErr},
+    swarm_flood,
+    util::abbrev,
+};
+use libp2p::{
+    core::{connection::PendingConnectionError, network::ConnectionCounters},
+    futures::future::BoxFuture,
+    gossipsub::error::PublishError,
+    identity::Keypair,
+    swarm::{DialError, SwarmEvent},
+    Multiaddr, PeerId,
+};
+use log::info;
+use tokio::sync::mpsc::UnboundedSender;
+
+// Messaging protocol to broadcast blocks and transactions with
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Protocol {
+    #[default]
+    Gossipsub,
+    Floodsub,
+}
+
+impl std::str::FromStr for Protocol {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "gossipsub" => Ok(Protocol::Gossipsub),
+            "floodsub" => Ok(Protocol::Floodsub),
+            _ => Err(format!("unknown protocol \"{}\"", s)),
+        }
+    }
+}
+
+// What the peer needs to know about an event from the swarm, which is otherwise only logged
+pub enum NetworkEvent {
+    Connected(PeerId),
+    Other,
+}
+
+// Number of connections the swarm currently has, to compare against its limits
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ConnectionCounts {
+    pub pending_incoming: u32,
+    pub established_incoming: u32,
+    pub established_outgoing: u32,
+}
+
+impl From<&ConnectionCounters> for ConnectionCounts {
+    fn from(counters: &ConnectionCounters) -> Self {
+        ConnectionCounts {
+            pending_incoming: counters.num_pending_incoming(),
+            established_incoming: counters.num_established_incoming(),
+            established_outgoing: counters.num_established_outgoing(),
+        }
+    }
+}
+
+/* A swarm that broadcasts the peer's messages, and tells it about the peers it finds.
+Publishing fails with `PublishError::InsufficientPeers` when there is no one to send to, so that the message can be retried. */
+pub trait Broadcast: Send {
+    fn publish_pow_msg(&mut self, msg: &PowMessage) -> Result<(), PublishError>;
+    fn publish_txn_msg(&mut self, msg: &TxnMessage) -> Result<(), PublishError>;
+    // Every known peer, with the addresses it was discovered at and whether we are connected to it
+    fn get_peers(&mut self) -> Vec<(PeerId, Vec<Multiaddr>, bool)>;
+
+    fn local_peer_id(&self) -> PeerId;
+    fn listen_addrs(&self) -> Vec<Multiaddr>;
+    fn is_connected(&self, peer_id: &PeerId) -> bool;
+    fn connection_counts(&self) -> ConnectionCounts;
+    fn dial_addr(&mut self, addr: Multiaddr) -> Result<(), DialError>;
+    fn dial_peer(&mut self, peer_id: &PeerId) -> Result<(), DialError>;
+    // Exchange messages with a peer, e.g. once it connects
+    fn add_peer(&mut self, peer_id: &PeerId);
+    // Stop exchanging messages with a peer until it is unbanned (and added again)
+    fn ban_peer(&mut self, peer_id: &PeerId);
+    fn unban_peer(&mut self, peer_id: &PeerId);
+    // Forward a message to the local peer as though it was received from the network
+    fn forward_pow_msg(&self, msg: PowMessage);
+    // Drive the swarm until its next event
+    fn next_event(&mut self) -> BoxFuture<'_, NetworkEvent>;
+
+    fn connected_peers(&mut self) -> Vec<PeerId> {
+        self.get_peers()
+            .into_iter()
+            .filter_map(|(peer, _, connected)| connected.then_some(peer))
+            .collect()
+    }
+    fn discovered_peers(&mut self) -> Vec<PeerId> {
+        self.get_peers()
+            .into_iter()
+            .filter_map(|(peer, addrs, _)| (!addrs.is_empty()).then_some(peer))
+            .collect()
+    }
+    fn peer_addresses(&mut self, peer_id: &PeerId) -> Vec<Multiaddr> {
+        self.get_peers()
+            .into_iter()
+            .find(|(peer, _, _)| peer == peer_id)
+            .map_or(vec![], |(_, addrs, _)| addrs)
+    }
+}
+
+// Set up a swarm for the given protocol, listening on an address
+pub async fn set_up_network(
+    protocol: Protocol,
+    keys: Keypair,
+    listen_addr: &str,
+    config: &SwarmConfig,
+    pow_sender: UnboundedSender<Delivered<PowMessage>>,
+    txn_sender: UnboundedSender<Delivered<TxnMessage>>,
+) -> Result<Box<dyn Broadcast>, SwarmErr> {
+    Ok(match protocol {
+        Protocol::Gossipsub => Box::new(
+            swarm::set_up_blockchain_swarm(keys, listen_addr, config, pow_sender, txn_sender)
+                .await?,
+        ),
+        Protocol::Floodsub => Box::new(
+            swarm_flood::set_up_flood_swarm(keys, listen_addr, config, pow_sender, txn_sender)
+                .await?,
+        ),
+    })
+}
+
+// Log a swarm event, for debugging purposes, and summarise it for the peer
+pub(crate) fn network_event<T: std::fmt::Debug, E: std::fmt::Debug>(
+    swarm_event: SwarmEvent<T, E>,
+) -> NetworkEvent {
+    match swarm_event {
+        SwarmEvent::ConnectionEstablished { peer_id, .. } => {
+            update!(
+                "Connection established with PeerId({})",
+                abbrev(&peer_id.to_string())
+            );
+            return NetworkEvent::Connected(peer_id);
+        }
+        SwarmEvent::ConnectionClosed { peer_id, .. } => update!(
+            "Connection closed with PeerId({})",
+            abbrev(&peer_id.to_string())
+        ),
+        SwarmEvent::NewListenAddr {
+            listener_id,
+            address,
+            ..
+        } => info!("SwarmEvent: {:?} listening on {}", listener_id, address),
+        SwarmEvent::Dialing(peer_id) => info!(
+            "SwarmEvent: dialling PeerId({})",
+            abbrev(&peer_id.to_string())
+        ),
+        SwarmEvent::IncomingConnection {
+            local_addr,
+            send_back_addr,
+        } => info!(
+            "SwarmEvent: incoming connection on addr {:?} with send-back addr {}",
+            local_addr, send_back_addr
+        ),
+        // a peer that dialled us once we were at our connection limits, which is refused without affecting anyone else
+        SwarmEvent::IncomingConnectionError {
+            send_back_addr,
+            error: PendingConnectionError::ConnectionLimit(limit),
+            ..
+        } => update!(
+            "Refused an incoming connection from {}, at the connection limit ({})",
+            send_back_addr,
+            limit
+        ),
+        SwarmEvent::UnreachableAddr {
+            peer_id,
+            address,
+            error,
+            ..
+        } => update!(
+            "Couldn't reach PeerId({}) at {}: {}",
+            abbrev(&peer_id.to_string()),
+            address,
+            error
+        ),
+        SwarmEvent::UnknownPeerUnreachableAddr { address, error } => {
+            update!("Couldn't reach a peer at {}: {}", address, error)
+        }
+        _ => info!("Unhandled swarm event: {:?}", swarm_event),
+    }
+    NetworkEvent::Other
+}
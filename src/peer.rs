@@ -1,43 +1,111 @@
 /*
     *Peer*: The peer's logic on the local machine, which the entire application is architected around.
-    - Manages a Swarm object (for communicating with peers in the network).
+    - Manages a Swarm object, for whichever protocol is configured (for communicating with peers in the network).
     - Manages std input events (for command-line interactions).
     - Manages a local Chain object (which it both adds new mined blocks to and synchronises with other peers' chains).
     - Manages a local Transaction pool (which it may mine new blocks for).
 */
 
 use super::{
-    block::{Block, NextBlockErr, NextBlockResult},
-    chain::{self, Chain},
-    file,
-    message::{PowMessage, TxnMessage},
-    swarm::{self as swarm, BlockchainBehaviour},
-    transaction::Transaction,
-    util::abbrev,
+    api::{ApiRequest, ApiSender},
+    block::{self, Block, NextBlockErr, NextBlockResult, EMPTY_BLOCK_DATA},
+    chain::{self, Chain, ChainSnapshot, ChainStatus, GenesisConfig, HeadInfo},
+    crypt, file,
+    ledger::Ledger,
+    mempool::Mempool,
+    merkle::{self, TxnInclusionProof},
+    message::{encode_msg, PowMessage, TxnMessage},
+    network::{self, Broadcast, ConnectionCounts, NetworkEvent, Protocol},
+    swarm::{self as swarm, Delivered, Roles, SourceCheck, SwarmConfig},
+    transaction::{Transaction, TransactionErr},
+    util::{abbrev, RateCounter},
 };
-use libp2p::{
-    futures::StreamExt,
-    swarm::{Swarm, SwarmEvent},
-    PeerId,
+use chrono::{DateTime, Utc};
+use libp2p::{gossipsub::error::PublishError, identity::Keypair, Multiaddr, PeerId};
+use log::{debug, info};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
 };
-use log::info;
-use std::collections::HashSet;
 use tokio::{
-    io::AsyncBufReadExt,
-    sync::mpsc::{self, UnboundedReceiver},
+    io::{AsyncBufReadExt, AsyncRead, BufReader, Lines},
+    sync::{
+        broadcast,
+        mpsc::{self, UnboundedReceiver, UnboundedSender},
+        watch,
+    },
+    time::{Instant, Interval},
 };
 
 const DEFAULT_FILE_PATH: &str = "blocks.json";
+// File storing the transaction pool between runs
+const DEFAULT_POOL_PATH: &str = "pool.json";
+// File storing the peer ids of banned peers between runs
+const DEFAULT_BANS_PATH: &str = "bans.json";
+// File storing the node's keypair, so that it keeps the same peer id across restarts
+pub const DEFAULT_KEY_PATH: &str = "node_key";
+// Maximum number of pooled transactions packed into a block by `mine`
+const MAX_BLOCK_TXNS: usize = 10;
+// Number of times a block request may be forwarded by peers that don't have the block
+pub const BLOCK_REQUEST_TTL: u8 = 3;
+// Default maximum number of blocks sent in response to a block range request, to keep responses within the message size limit
+pub const DEFAULT_MAX_RANGE_BLOCKS: usize = 100;
+// How long we wait for a response to a block request before re-sending it, doubling after each attempt, and how many attempts we make
+pub const BLOCK_REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+pub const MAX_BLOCK_REQUEST_ATTEMPTS: u32 = 4;
+// How often we check for block requests that have timed out
+const BLOCK_REQUEST_RETRY_PERIOD: Duration = Duration::from_secs(5);
+// How often we re-request the missing parents of orphaned branches, how many times, and how long before giving up on them
+const ORPHAN_RETRY_PERIOD: Duration = Duration::from_secs(60);
+pub const MAX_ORPHAN_RETRIES: u32 = 5;
+pub const ORPHAN_TTL: Duration = Duration::from_secs(600);
+// How often we evict expired transactions from the pool
+const POOL_SWEEP_PERIOD: Duration = Duration::from_secs(60);
+// How often to retry publishing messages that couldn't be sent for lack of peers, and how many to keep meanwhile
+const OUTBOUND_RETRY_PERIOD: Duration = Duration::from_secs(5);
+pub const MAX_OUTBOUND_QUEUE: usize = 100;
+// Number of invalid blocks or transactions a peer may send before it is banned automatically,
+// and number of peers to keep counts for, beyond which the peer with the fewest is forgotten
+pub const MAX_INVALID_MSGS: u32 = 10;
+pub const MAX_INVALID_MSG_PEERS: usize = 256;
+// Period of the autosave timer until autosaving is turned on, which it doesn't fire for meanwhile
+const DEFAULT_AUTOSAVE_PERIOD: Duration = Duration::from_secs(60);
+// Number of events buffered for each subscriber, beyond which a slow subscriber misses the oldest
+const EVENT_CAPACITY: usize = 256;
+
+// Lines of commands from standard input, or any other source (e.g. a piped script)
+type CommandLines = Lines<BufReader<Box<dyn AsyncRead + Unpin + Send>>>;
 
 /* Events for the peer to handle, either:
     (1) Local inputs from the terminal
     (2) Remote chain messages from miners in the network
     (3) Remote transaction messages from peers in the network
+    (4) The end of the startup wait for peers, with the reason it ended
+    (5) A block mined in the background
+    (6) A periodic retry of requests for the missing parents of orphans
+    (7) Requests from other programs, e.g. over HTTP
+    (8) A periodic sweep of expired transactions from the pool
+    (9) A periodic retry of messages that couldn't be published for lack of peers
+    (10) A periodic save of the chain, if it has changed
+    (11) A periodic retry of block requests that haven't been responded to
 */
 enum EventType {
     Std(String),
-    Pow(PowMessage),
-    Txn(TxnMessage),
+    Pow(Delivered<PowMessage>),
+    Txn(Delivered<TxnMessage>),
+    PeerWaitOver(&'static str),
+    Mined(Block),
+    RetryOrphans,
+    Api(ApiRequest),
+    SweepPool,
+    FlushOutbound,
+    Autosave,
+    RetryRequests,
 }
 
 /* A Peer consists of:
@@ -46,14 +114,266 @@ enum EventType {
 (3) A channel to receive transaction messages forwarded from the network behaviour
 (4) A local blockchain
 (5) A map of disconnected forks. New entries are created when receiving blocks further ahead than the main chain.
-(6) A local transaction pool */
+(6) A local transaction pool, ordered by priority and bounded in size
+(7) Whether to respond to block requests from other peers
+(8) The time we last received a message from each remote peer
+(9) Startup options
+(10) Recent rates of messages received and published
+(11) The deadline of the startup wait for peers (if still waiting), and the commands deferred until it ends
+(12) The blocks we requested that haven't arrived yet, and a timer to re-send the requests that time out
+(13) A channel to receive blocks mined in the background, the block currently being mined (if any),
+     and whether to keep mining pooled transactions
+(14) The node's keypair, identifying it on the network and signing its transactions
+(15) A timer to retry requests for the missing parents of orphans, and the retries made for each orphan
+(16) A channel to publish events to subscribers, and one holding a snapshot of the main chain as of its latest change
+(17) A channel to receive requests from other programs, e.g. over HTTP
+(18) The peers whose messages we ignore, and the number of invalid messages received from each peer
+(19) A timer to evict expired transactions from the pool
+(20) The messages that couldn't be published for lack of peers, oldest first, and a timer to retry them
+(21) Whether we may mine blocks without transactions
+(22) Counts of the blocks and transactions accepted or rejected, and of changes to the chain
+(23) Whether the chain has changed since it was last saved, and a timer to save it automatically (if turned on) */
 pub struct Peer {
-    from_stdin: tokio::io::Lines<tokio::io::BufReader<tokio::io::Stdin>>,
-    pow_receiver: UnboundedReceiver<PowMessage>,
-    txn_receiver: UnboundedReceiver<TxnMessage>,
-    swarm: Swarm<BlockchainBehaviour>,
+    from_stdin: CommandLines,
+    stdin_open: bool,
+    pow_receiver: UnboundedReceiver<Delivered<PowMessage>>,
+    txn_receiver: UnboundedReceiver<Delivered<TxnMessage>>,
+    swarm: Box<dyn Broadcast>,
     chain: Chain,
-    txns: HashSet<Transaction>,
+    txns: Mempool,
+    serve_blocks: bool,
+    last_seen: HashMap<String, i64>,
+    config: PeerConfig,
+    rates: NetworkRates,
+    peer_wait: Option<Instant>,
+    deferred_cmds: Vec<String>,
+    outstanding_requests: HashMap<String, OutstandingRequest>,
+    request_timer: Interval,
+    mined_sender: UnboundedSender<Block>,
+    mined_receiver: UnboundedReceiver<Block>,
+    miner: Option<MiningTask>,
+    auto_mine: bool,
+    keys: Keypair,
+    orphan_timer: Interval,
+    orphan_retries: HashMap<String, OrphanRetry>,
+    events: broadcast::Sender<PeerEvent>,
+    snapshot: watch::Sender<ChainSnapshot>,
+    api_sender: ApiSender,
+    api_receiver: UnboundedReceiver<ApiRequest>,
+    banned: HashSet<PeerId>,
+    invalid_msgs: HashMap<PeerId, u32>,
+    pool_timer: Interval,
+    outbound: VecDeque<OutboundMsg>,
+    outbound_timer: Interval,
+    mine_policy: MinePolicy,
+    metrics: Metrics,
+    chain_dirty: bool,
+    autosave: Option<Duration>,
+    autosave_timer: Interval,
+}
+
+// Events that a peer publishes to its subscribers, alongside printing them
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PeerEvent {
+    BlockAccepted { idx: usize, hash: String },
+    ForkSwitched { old_len: usize, new_len: usize },
+    TxnAdded { hash: String },
+    PeerConnected { peer_id: String },
+    HeadChanged { head: HeadInfo },
+}
+
+// A block request that hasn't been responded to, with the number of times it was sent and when it was last sent
+struct OutstandingRequest {
+    idx: usize,
+    ttl: u8,
+    attempts: u32,
+    sent: Instant,
+}
+
+// Requests made for the missing parent of an orphaned branch, since it was first seen
+struct OrphanRetry {
+    attempts: u32,
+    since: Instant,
+}
+
+// A message to publish to the network, on the topic of its type
+#[derive(Clone, Debug)]
+enum OutboundMsg {
+    Pow(PowMessage),
+    Txn(TxnMessage),
+}
+
+// Whether a message was sent to peers, queued to retry once there are peers to send it to, or dropped
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PublishStatus {
+    Published,
+    Queued,
+    Dropped,
+}
+
+// Whether `mine empty` may mine a block without transactions, e.g. to keep the chain advancing in liveness tests
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MinePolicy {
+    AllowEmpty,
+    #[default]
+    RequireTxns,
+}
+
+// A block being mined in the background, with the signal to cancel it and the pooled transactions it contains
+struct MiningTask {
+    cancel: Arc<AtomicBool>,
+    tip_hash: String,
+    txns: Vec<Transaction>,
+}
+
+// Startup options for a peer, set from command-line arguments
+#[derive(Clone, Debug, Default)]
+pub struct PeerConfig {
+    // forward gossip without storing or validating blocks and transactions
+    pub relay: bool,
+    // minimum difficulty of a freshly created chain, if not the default
+    pub difficulty: Option<usize>,
+    // timestamp and data of the genesis block of a freshly created chain, if not the default
+    pub genesis_timestamp: Option<i64>,
+    pub genesis_data: Option<String>,
+    // how long to defer broadcasting commands at startup until a peer connects, if at all
+    pub wait_for_peers: Option<Duration>,
+    // whether `mine empty` may mine blocks without transactions
+    pub mine_policy: MinePolicy,
+    // maximum number of blocks to send in response to a block range request, if not the default
+    pub max_range_blocks: Option<usize>,
+    // file to load (or create) the node's keypair from, if not the default
+    pub key_path: Option<String>,
+    // addresses of peers to dial at startup, for networks where they can't be discovered by mdns
+    pub bootstrap: Vec<Multiaddr>,
+    // address to listen for other peers on, if not the default
+    pub listen_addr: Option<String>,
+    // address to serve the HTTP api on (with the `http-api` feature), if at all
+    pub http_addr: Option<String>,
+    // messaging protocol, which must match that of the other peers
+    pub protocol: Protocol,
+    // gossipsub parameters
+    pub swarm: SwarmConfig,
+    // depth below which main chain blocks are final, if at all
+    pub checkpoint_depth: Option<usize>,
+    // depth behind the tip at which forks are pruned, if at all
+    pub fork_prune_depth: Option<usize>,
+    // amount that miners reward themselves with through each block's coinbase, if at all
+    pub block_reward: Option<u64>,
+    // file to append every block that extends the main chain to, as an audit trail, if at all
+    pub archive_path: Option<String>,
+}
+
+impl PeerConfig {
+    pub fn from_args<I: Iterator<Item = String>>(args: I) -> Self {
+        let mut config = PeerConfig {
+            listen_addr: std::env::var("P2P_LISTEN_ADDR").ok(),
+            protocol: std::env::var("P2P_PROTOCOL")
+                .ok()
+                .and_then(|protocol| protocol.parse::<Protocol>().ok())
+                .unwrap_or_default(),
+            ..PeerConfig::default()
+        };
+        let mut args = args;
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--relay" => config.relay = true,
+                "--difficulty" => match args.next().map(|n| n.parse::<usize>()) {
+                    Some(Ok(n)) => config.difficulty = Some(n),
+                    _ => eprintln!("Ignoring `--difficulty`, which expects a whole number"),
+                },
+                "--genesis-timestamp" => match args.next().map(|n| n.parse::<i64>()) {
+                    Some(Ok(n)) => config.genesis_timestamp = Some(n),
+                    _ => eprintln!(
+                        "Ignoring `--genesis-timestamp`, which expects a number of seconds since the epoch"
+                    ),
+                },
+                "--genesis-data" => match args.next() {
+                    Some(data) => config.genesis_data = Some(data),
+                    None => eprintln!("Ignoring `--genesis-data`, which expects some text"),
+                },
+                "--allow-empty-blocks" => config.mine_policy = MinePolicy::AllowEmpty,
+                "--max-range-blocks" => match args.next().map(|n| n.parse::<usize>()) {
+                    Some(Ok(n)) if n > 0 => config.max_range_blocks = Some(n),
+                    _ => eprintln!(
+                        "Ignoring `--max-range-blocks`, which expects a positive whole number"
+                    ),
+                },
+                "--bootstrap" => match args.next().map(|addr| addr.parse::<Multiaddr>()) {
+                    Some(Ok(addr)) => config.bootstrap.push(addr),
+                    _ => eprintln!("Ignoring `--bootstrap`, which expects a multiaddr"),
+                },
+                "--listen" => match args.next() {
+                    Some(addr) => config.listen_addr = Some(addr),
+                    None => eprintln!("Ignoring `--listen`, which expects a multiaddr"),
+                },
+                "--strict-source" => config.swarm.source_check = SourceCheck::Strict,
+                "--role" => match args.next().map(|r| r.parse::<Roles>()) {
+                    Some(Ok(roles)) => config.swarm.roles = roles,
+                    _ => eprintln!("Ignoring `--role`, which expects all, blocks, or txns"),
+                },
+                "--protocol" => match args.next().map(|p| p.parse::<Protocol>()) {
+                    Some(Ok(protocol)) => config.protocol = protocol,
+                    _ => eprintln!("Ignoring `--protocol`, which expects gossipsub or floodsub"),
+                },
+                "--checkpoint-depth" => match args.next().map(|n| n.parse::<usize>()) {
+                    Some(Ok(n)) => config.checkpoint_depth = Some(n),
+                    _ => eprintln!("Ignoring `--checkpoint-depth`, which expects a whole number"),
+                },
+                "--fork-prune-depth" => match args.next().map(|n| n.parse::<usize>()) {
+                    Some(Ok(n)) => config.fork_prune_depth = Some(n),
+                    _ => eprintln!("Ignoring `--fork-prune-depth`, which expects a whole number"),
+                },
+                "--block-reward" => match args.next().map(|n| n.parse::<u64>()) {
+                    Some(Ok(n)) => config.block_reward = Some(n),
+                    _ => eprintln!("Ignoring `--block-reward`, which expects a whole number"),
+                },
+                "--archive" => match args.next() {
+                    Some(path) => config.archive_path = Some(path),
+                    None => eprintln!("Ignoring `--archive`, which expects a file path"),
+                },
+                "--http" => match args.next() {
+                    Some(addr) => config.http_addr = Some(addr),
+                    None => eprintln!("Ignoring `--http`, which expects an address"),
+                },
+                "--key" => match args.next() {
+                    Some(path) => config.key_path = Some(path),
+                    None => eprintln!("Ignoring `--key`, which expects a file path"),
+                },
+                "--wait-for-peers" => match args.next().map(|n| n.parse::<u64>()) {
+                    Some(Ok(secs)) => config.wait_for_peers = Some(Duration::from_secs(secs)),
+                    _ => {
+                        eprintln!("Ignoring `--wait-for-peers`, which expects a number of seconds")
+                    }
+                },
+                _ => eprintln!("Ignoring unrecognised argument \"{}\"", arg),
+            }
+        }
+        config
+    }
+
+    // Apply the options that the chain itself enforces, e.g. to a loaded or freshly created chain
+    pub fn configure_chain(&self, chain: &mut Chain) {
+        if let Some(depth) = self.checkpoint_depth {
+            chain.set_checkpoint_depth(depth);
+        }
+        if let Some(depth) = self.fork_prune_depth {
+            chain.set_fork_prune_depth(depth);
+        }
+        if let Some(reward) = self.block_reward {
+            chain.set_block_reward(reward);
+        }
+    }
+
+    // Settings for the genesis block of a freshly created chain
+    pub fn genesis_config(&self) -> GenesisConfig {
+        let default = GenesisConfig::default();
+        GenesisConfig {
+            timestamp: self.genesis_timestamp.unwrap_or(default.timestamp),
+            data: self.genesis_data.clone().unwrap_or(default.data),
+            difficulty: self.difficulty.unwrap_or(default.difficulty),
+        }
+    }
 }
 
 impl Peer {
@@ -62,94 +382,605 @@ impl Peer {
     2. Handles local commands from the standard input   */
     pub async fn run(&mut self) {
         println!("Enter `help` to see the command menu.");
+        // listen for ctrl-c once, so that a signal arriving between steps isn't missed
+        let shutdown = tokio::signal::ctrl_c();
+        tokio::pin!(shutdown);
         loop {
-            let evt: Option<EventType> = {
-                tokio::select! {
-                    pow_event = self.pow_receiver.recv()
-                        => Some(EventType::Pow(pow_event.expect("pow event exists"))),
-                    txn_event = self.txn_receiver.recv()
-                        => Some(EventType::Txn(txn_event.expect("txn event exists"))),
-                    std_event = self.from_stdin.next_line()
-                        => Some(EventType::Std(std_event.expect("can get line").expect("can read line from stdin"))),
-                    swarm_event = self.swarm.select_next_some()
-                        => { Self::handle_swarm_event(swarm_event); None }
+            tokio::select! {
+                _ = &mut shutdown => break,
+                _ = self.step() => {}
+            }
+        }
+        println!("\nShutting down...");
+        self.persist().await;
+    }
+    // Save the chain, transaction pool, and banned peers to the default files, logging (rather than failing on) any error.
+    pub async fn persist(&mut self) {
+        match self
+            .persist_to(DEFAULT_FILE_PATH, DEFAULT_POOL_PATH, DEFAULT_BANS_PATH)
+            .await
+        {
+            Ok(()) => println!(
+                "Saved chain to \"{}\", transaction pool to \"{}\", and banned peers to \"{}\".",
+                DEFAULT_FILE_PATH, DEFAULT_POOL_PATH, DEFAULT_BANS_PATH
+            ),
+            Err(e) => eprintln!("Error saving state before shutting down: \"{}\"", e),
+        }
+    }
+    pub async fn persist_to(
+        &mut self,
+        chain_path: &str,
+        pool_path: &str,
+        bans_path: &str,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        file::write_chain(&self.chain, chain_path).await?;
+        // only saving to the file the chain is loaded from leaves nothing for autosave to do
+        if chain_path == DEFAULT_FILE_PATH {
+            self.chain_dirty = false;
+        }
+        file::write_pool(&self.txns, pool_path).await?;
+        let bans: Vec<String> = self.banned.iter().map(PeerId::to_string).collect();
+        file::write_bans(&bans, bans_path).await
+    }
+    /* Wait for and handle a single event.
+    Network events are polled first, and at most one command is read per step, so that a burst of
+    piped commands can't starve the handling of remote messages. */
+    pub(crate) async fn step(&mut self) {
+        let wait_deadline: Instant = self.peer_wait.unwrap_or_else(Instant::now);
+        let evt: Option<EventType> = {
+            tokio::select! {
+                biased;
+                pow_event = self.pow_receiver.recv()
+                    => Some(EventType::Pow(pow_event.expect("pow event exists"))),
+                txn_event = self.txn_receiver.recv()
+                    => Some(EventType::Txn(txn_event.expect("txn event exists"))),
+                mined_block = self.mined_receiver.recv()
+                    => Some(EventType::Mined(mined_block.expect("mined block exists"))),
+                api_request = self.api_receiver.recv()
+                    => Some(EventType::Api(api_request.expect("api request exists"))),
+                network_event = self.swarm.next_event()
+                    => match network_event {
+                        NetworkEvent::Connected(peer_id) => {
+                            // gossip with every connected peer, including those dialled directly rather than discovered by mdns
+                            if !self.banned.contains(&peer_id) {
+                                self.swarm.add_peer(&peer_id);
+                            }
+                            self.emit(PeerEvent::PeerConnected { peer_id: peer_id.to_string() });
+                            self.peer_wait.is_some().then_some(EventType::PeerWaitOver("a peer connected"))
+                        }
+                        NetworkEvent::Other => None,
+                    },
+                _ = self.orphan_timer.tick()
+                    => Some(EventType::RetryOrphans),
+                _ = self.pool_timer.tick()
+                    => Some(EventType::SweepPool),
+                _ = self.outbound_timer.tick(), if !self.outbound.is_empty()
+                    => Some(EventType::FlushOutbound),
+                _ = self.autosave_timer.tick(), if self.autosave.is_some()
+                    => Some(EventType::Autosave),
+                _ = self.request_timer.tick(), if !self.outstanding_requests.is_empty()
+                    => Some(EventType::RetryRequests),
+                _ = tokio::time::sleep_until(wait_deadline), if self.peer_wait.is_some()
+                    => Some(EventType::PeerWaitOver("timed out")),
+                std_event = self.from_stdin.next_line(), if self.stdin_open
+                    => match std_event {
+                        Ok(Some(cmd)) => Some(EventType::Std(cmd)),
+                        // stop reading once the input is exhausted, e.g. at the end of a piped script
+                        Ok(None) => { self.stdin_open = false; info!("Reached end of standard input."); None }
+                        Err(e) => { eprintln!("Error reading from standard input: {}", e); None }
+                    },
+            }
+        };
+        if let Some(event) = evt {
+            println!("{} New Event {}", "-".repeat(40), "-".repeat(40));
+            match event {
+                // skip messages for topics we didn't subscribe to, e.g. those sent to us directly
+                EventType::Pow((msg, _)) if !self.config.swarm.roles.blocks => {
+                    debug!("Skipping block message as we don't handle blocks: {}", msg)
                 }
-            };
-            if let Some(event) = evt {
-                println!("{} New Event {}", "-".repeat(40), "-".repeat(40));
-                match event {
-                    EventType::Pow(msg) => self.handle_pow_event(msg),
-                    EventType::Txn(msg) => self.handle_txn_event(msg),
-                    EventType::Std(cmd) => self.handle_std_event(&cmd).await,
+                EventType::Txn((msg, _)) if !self.config.swarm.roles.txns => {
+                    debug!(
+                        "Skipping transaction message as we don't handle transactions: {}",
+                        msg
+                    )
+                }
+                EventType::Pow((msg, delivered_by)) => self.handle_pow_event(msg, delivered_by),
+                EventType::Txn((msg, delivered_by)) => self.handle_txn_event(msg, delivered_by),
+                EventType::Std(cmd) => self.handle_std_event(&cmd).await,
+                EventType::PeerWaitOver(reason) => self.stop_waiting_for_peers(reason).await,
+                EventType::Mined(block) => self.handle_mined_block(block),
+                EventType::RetryOrphans => self.retry_orphans_at(Instant::now()),
+                EventType::Api(request) => self.handle_api_request(request),
+                EventType::SweepPool => self.sweep_pool_at(Utc::now().timestamp()),
+                EventType::FlushOutbound => self.flush_outbound(),
+                EventType::Autosave => self.autosave().await,
+                EventType::RetryRequests => self.retry_requests_at(Instant::now()),
+            }
+            self.refresh_miner();
+        }
+    }
+    // End the startup wait for peers, running any commands that were deferred by it
+    pub(crate) async fn stop_waiting_for_peers(&mut self, reason: &str) {
+        self.peer_wait = None;
+        update!("Stopped waiting for peers: {}.", reason);
+        for cmd in std::mem::take(&mut self.deferred_cmds) {
+            self.handle_std_event(&cmd).await
+        }
+    }
+    // Read commands from a different source than standard input, e.g. a script
+    pub fn set_command_source<R: AsyncRead + Unpin + Send + 'static>(&mut self, source: R) {
+        self.from_stdin = new_command_lines(source);
+        self.stdin_open = true;
+    }
+    // Handle a message as though it was forwarded from the network
+    pub fn inject_pow_msg(&mut self, msg: PowMessage) {
+        self.swarm.forward_pow_msg(msg)
+    }
+    // Local chain, for inspection by library consumers.
+    pub fn chain(&self) -> &Chain {
+        &self.chain
+    }
+    // Pending transactions, for inspection by library consumers.
+    pub fn txns(&self) -> &Mempool {
+        &self.txns
+    }
+    pub fn local_peer_id(&self) -> PeerId {
+        self.swarm.local_peer_id()
+    }
+    // Addresses that we are listening on, for other peers to dial.
+    pub fn listen_addrs(&self) -> Vec<Multiaddr> {
+        self.swarm.listen_addrs()
+    }
+    pub fn is_connected(&self, peer_id: &PeerId) -> bool {
+        self.swarm.is_connected(peer_id)
+    }
+    // Dial a peer directly, logging (rather than failing on) an invalid or unreachable address.
+    pub fn dial(&mut self, addr: Multiaddr) {
+        match self.swarm.dial_addr(addr.clone()) {
+            Ok(()) => update!("Dialling {}", addr),
+            Err(e) => update!("Couldn't dial {}: {:?}", addr, e),
+        }
+    }
+    // Receive events (e.g. accepted blocks) as they happen, e.g. to drive a UI.
+    pub fn subscribe(&self) -> broadcast::Receiver<PeerEvent> {
+        self.events.subscribe()
+    }
+    // Read the main chain from another task or thread, as of its latest change, without going through the peer
+    pub fn chain_snapshot(&self) -> watch::Receiver<ChainSnapshot> {
+        self.snapshot.subscribe()
+    }
+    fn emit(&self, event: PeerEvent) {
+        // sending only fails when there are no subscribers, which is fine
+        let _ = self.events.send(event);
+    }
+    fn emit_block_accepted(&self, idx: usize, hash: &str) {
+        self.archive_block(idx);
+        self.emit(PeerEvent::BlockAccepted {
+            idx,
+            hash: hash.to_string(),
+        });
+        self.emit_head_changed();
+    }
+    // Append a block that just joined the main chain (by extending it, or in a fork it switched to) to the archive, if keeping one
+    fn archive_block(&self, idx: usize) {
+        let (Some(path), Some(block)) = (&self.config.archive_path, self.chain.idx(idx)) else {
+            return;
+        };
+        if let Err(e) = file::append_block(block, path) {
+            eprintln!(
+                "Error appending block {} to archive \"{}\":\n\t\"{}\"",
+                idx, path, e
+            );
+        }
+    }
+    fn emit_chain_status(&self, status: &ChainStatus) {
+        if let ChainStatus::ChooseOther {
+            main_len,
+            other_len,
+            fork_idx,
+            ..
+        } = status
+        {
+            for idx in fork_idx + 1..self.chain.len() {
+                self.archive_block(idx);
+            }
+            self.emit(PeerEvent::ForkSwitched {
+                old_len: *main_len,
+                new_len: *other_len,
+            });
+            self.emit_head_changed();
+        }
+    }
+    fn record_chain_status(&mut self, status: &ChainStatus) {
+        if let ChainStatus::ChooseOther { .. } = status {
+            self.metrics.reorgs += 1;
+            self.chain_dirty = true;
+            self.remove_mined_txns();
+        }
+    }
+    // Remove pooled transactions that the main chain now contains, e.g. once it switches to a fork that mined them
+    fn remove_mined_txns(&mut self) {
+        let mined: Vec<Transaction> = self
+            .txns
+            .iter()
+            .filter(|txn| self.chain.find_txn(&txn.hash).is_some())
+            .cloned()
+            .collect();
+        for txn in &mined {
+            self.txns.remove(txn);
+        }
+        if !mined.is_empty() {
+            update!(
+                "Deleted {} transaction(s) mined by the new main chain from the local pool.",
+                mined.len()
+            );
+        }
+    }
+    /* Return transactions to the pool (e.g. from blocks no longer on the main chain), returning how many were added.
+    Coinbases, transactions that the main chain still contains, and those the pool refuses are dropped. */
+    fn return_to_pool(&mut self, txns: impl IntoIterator<Item = Transaction>) -> usize {
+        let mut returned: usize = 0;
+        for txn in txns {
+            if txn.is_coinbase() || self.chain.find_txn(&txn.hash).is_some() {
+                continue;
+            }
+            match self.txns.insert(txn) {
+                Ok(_) => returned += 1,
+                Err(e) => debug!("Didn't return transaction to the pool: {}", e),
+            }
+        }
+        returned
+    }
+    fn emit_head_changed(&self) {
+        self.snapshot.send_replace(self.chain.snapshot());
+        self.emit(PeerEvent::HeadChanged {
+            head: self.chain.head(),
+        })
+    }
+    // Ignore all messages from a peer and stop gossiping with it, returning whether it wasn't already banned.
+    pub fn ban(&mut self, peer_id: PeerId) -> bool {
+        self.swarm.ban_peer(&peer_id);
+        self.banned.insert(peer_id)
+    }
+    // Stop ignoring messages from a peer, returning whether it was banned.
+    pub fn unban(&mut self, peer_id: &PeerId) -> bool {
+        if !self.banned.remove(peer_id) {
+            return false;
+        }
+        self.invalid_msgs.remove(peer_id);
+        self.swarm.unban_peer(peer_id);
+        if self.swarm.is_connected(peer_id) {
+            self.swarm.add_peer(peer_id);
+        }
+        true
+    }
+    pub fn banned(&self) -> &HashSet<PeerId> {
+        &self.banned
+    }
+    // Whether a message is our own, gossiped back to us, rather than e.g. a response from a peer to our request
+    fn is_own_source(&self, source: &str) -> bool {
+        source == self.swarm.local_peer_id().to_string()
+    }
+    // Whether a message was delivered by, or claims to be from, a banned peer
+    fn is_banned_source(&self, source: &str, delivered_by: &PeerId) -> bool {
+        self.banned.contains(delivered_by)
+            || source
+                .parse::<PeerId>()
+                .is_ok_and(|peer_id| self.banned.contains(&peer_id))
+    }
+    /* Count an invalid block or transaction delivered by a peer, banning it once it has sent too many.
+    This counts the peer that delivered the message rather than its declared source, which is easily forged to get another peer banned.
+    Messages we injected ourselves aren't counted. */
+    fn record_invalid_msg(&mut self, delivered_by: &PeerId) {
+        if *delivered_by == self.swarm.local_peer_id() {
+            return;
+        }
+        if !self.invalid_msgs.contains_key(delivered_by)
+            && self.invalid_msgs.len() >= MAX_INVALID_MSG_PEERS
+        {
+            if let Some(fewest) = self
+                .invalid_msgs
+                .iter()
+                .min_by_key(|(_, count)| **count)
+                .map(|(peer_id, _)| *peer_id)
+            {
+                self.invalid_msgs.remove(&fewest);
+            }
+        }
+        let count: &mut u32 = self.invalid_msgs.entry(*delivered_by).or_insert(0);
+        *count += 1;
+        if *count >= MAX_INVALID_MSGS {
+            update!(
+                "Banning PeerId({}) after {} invalid messages.",
+                abbrev(&delivered_by.to_string()),
+                MAX_INVALID_MSGS
+            );
+            self.invalid_msgs.remove(delivered_by);
+            self.ban(*delivered_by);
+        }
+    }
+    // Channel for other programs to make requests to this peer, e.g. from an HTTP server.
+    pub fn api_sender(&self) -> ApiSender {
+        self.api_sender.clone()
+    }
+    // Rates of messages received and published over the recent window.
+    pub fn network_rates(&self) -> &NetworkRates {
+        &self.rates
+    }
+    // Number of times we've sent a request for a block that hasn't arrived yet, if we're waiting on one
+    pub fn block_request_attempts(&self, hash: &str) -> Option<u32> {
+        self.outstanding_requests.get(hash).map(|req| req.attempts)
+    }
+    // Counts of blocks and transactions handled since startup.
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+    pub fn mine_policy(&self) -> MinePolicy {
+        self.mine_policy
+    }
+    pub fn set_mine_policy(&mut self, policy: MinePolicy) {
+        self.mine_policy = policy
+    }
+    // Number of messages waiting to be published once there are peers to send them to.
+    pub fn queued_msgs(&self) -> usize {
+        self.outbound.len()
+    }
+    fn publish_pow_msg(&mut self, msg: PowMessage) -> PublishStatus {
+        self.rates.record_published(msg.kind());
+        self.publish(OutboundMsg::Pow(msg))
+    }
+    fn publish_txn_msg(&mut self, msg: TxnMessage) -> PublishStatus {
+        self.rates.record_published(msg.kind());
+        self.publish(OutboundMsg::Txn(msg))
+    }
+    // Publish a message, queueing it (and dropping the oldest queued message if full) if there are no peers to send it to
+    fn publish(&mut self, msg: OutboundMsg) -> PublishStatus {
+        let res = match &msg {
+            OutboundMsg::Pow(pow_msg) => self.swarm.publish_pow_msg(pow_msg),
+            OutboundMsg::Txn(txn_msg) => self.swarm.publish_txn_msg(txn_msg),
+        };
+        match res {
+            Ok(()) => PublishStatus::Published,
+            Err(PublishError::InsufficientPeers) => {
+                if self.outbound.len() >= MAX_OUTBOUND_QUEUE {
+                    if let Some(dropped) = self.outbound.pop_front() {
+                        debug!("Outbound queue is full, dropping {:?}", dropped);
+                    }
                 }
+                self.outbound.push_back(msg);
+                PublishStatus::Queued
             }
+            Err(_) => PublishStatus::Dropped,
+        }
+    }
+    // Retry publishing the queued messages in order, keeping those that still can't be sent
+    pub(crate) fn flush_outbound(&mut self) {
+        let queued: VecDeque<OutboundMsg> = std::mem::take(&mut self.outbound);
+        let total: usize = queued.len();
+        let published: usize = queued
+            .into_iter()
+            .filter(|msg| self.publish(msg.clone()) == PublishStatus::Published)
+            .count();
+        if published > 0 {
+            update!("Published {} of {} queued message(s).", published, total);
         }
     }
     // Blockchain event.
-    fn handle_pow_event(&mut self, msg: PowMessage) {
+    pub(crate) fn handle_pow_event(&mut self, msg: PowMessage, delivered_by: PeerId) {
+        if self.is_own_source(msg.source()) {
+            debug!("Ignoring our own \"{}\"", msg);
+            return;
+        }
+        if self.is_banned_source(msg.source(), &delivered_by) {
+            debug!(
+                "Ignoring \"{}\" from banned PeerId({})",
+                msg,
+                abbrev(msg.source())
+            );
+            return;
+        }
         received!("\"{}\" from PeerId({})", msg, abbrev(msg.source()));
+        self.rates.record_received(msg.kind());
+        self.last_seen
+            .insert(msg.source().clone(), Utc::now().timestamp());
+        if self.config.relay {
+            debug!("Relay mode: forwarding without processing \"{}\"", msg);
+            return;
+        }
         match msg.clone() {
             PowMessage::ChainRequest { .. } => {
-                let resp: PowMessage = PowMessage::ChainResponse {
-                    target: msg.source().to_string(),
-                    source: self.swarm.local_peer_id().to_string(),
-                    chain: self.chain.clone(),
-                };
-                swarm::publish_pow_msg(resp.clone(), &mut self.swarm);
-                responded!("\"{}\" to PeerId({})", resp, abbrev(msg.source()));
+                let local_peer_id: String = self.swarm.local_peer_id().to_string();
+                let max_blocks: usize = self
+                    .config
+                    .max_range_blocks
+                    .unwrap_or(DEFAULT_MAX_RANGE_BLOCKS);
+                for resp in chain_response(
+                    &self.chain,
+                    msg.source(),
+                    &local_peer_id,
+                    swarm::MAX_MESSAGE_SIZE,
+                    max_blocks,
+                ) {
+                    self.publish_pow_msg(resp.clone());
+                    responded!("\"{}\" to PeerId({})", resp, abbrev(msg.source()));
+                }
             }
             PowMessage::ChainResponse { chain, .. } => match self.chain.choose_chain(chain) {
-                Ok(res) => update!("{}", res),
+                Ok(res) => {
+                    update!("{}", res);
+                    self.record_chain_status(&res);
+                    self.emit_chain_status(&res);
+                }
                 Err(e) => update!("Remote chain couldn't be validated due to \"{}\"", e),
             },
-            PowMessage::BlockRequest { hash, .. } => {
-                if let Some(block) = self.chain.find(&|b| b.hash == hash) {
-                    let resp: PowMessage = PowMessage::BlockResponse {
-                        target: msg.source().clone(),
-                        source: self.swarm.local_peer_id().to_string(),
-                        block: block.clone(),
-                    };
-                    swarm::publish_pow_msg(resp.clone(), &mut self.swarm);
-                    responded!("\"{}\" to PeerId({}):", resp, abbrev(msg.source()));
-                } else {
-                    update!("Block not found on the main chain.");
+            PowMessage::BlockRequest { idx, hash, ttl, .. } => {
+                let local_peer_id: String = self.swarm.local_peer_id().to_string();
+                match block_response(
+                    &self.chain,
+                    self.serve_blocks,
+                    &hash,
+                    msg.source(),
+                    &local_peer_id,
+                ) {
+                    Some(resp) => {
+                        self.publish_pow_msg(resp.clone());
+                        responded!("\"{}\" to PeerId({}):", resp, abbrev(msg.source()));
+                    }
+                    None if !self.serve_blocks => {
+                        debug!("Ignoring block request, as serving blocks is turned off.")
+                    }
+                    // forward the request to our own peers, unless it has expired
+                    None if ttl > 0 => {
+                        update!("Block not found on the main chain, so forwarding the request.");
+                        self.request_block(idx, &hash, ttl - 1);
+                    }
+                    None => update!("Block not found on the main chain."),
                 }
             }
             PowMessage::BlockResponse { block, .. } => {
-                self.handle_block(block, Chain::store_orphan_block)
+                // the request is only cleared once the block is stored, so an invalid response doesn't stop it being retried
+                self.handle_block(block, &delivered_by, Chain::store_orphan_block)
+            }
+            PowMessage::NewBlock { block, .. } => {
+                self.handle_block(block, &delivered_by, Chain::store_new_block)
+            }
+            PowMessage::BlockRangeRequest {
+                from_idx, to_idx, ..
+            } => {
+                let local_peer_id: String = self.swarm.local_peer_id().to_string();
+                let max_blocks: usize = self
+                    .config
+                    .max_range_blocks
+                    .unwrap_or(DEFAULT_MAX_RANGE_BLOCKS);
+                match range_response(
+                    &self.chain,
+                    from_idx,
+                    to_idx,
+                    max_blocks,
+                    msg.source(),
+                    &local_peer_id,
+                ) {
+                    Some(resp) => {
+                        self.publish_pow_msg(resp.clone());
+                        responded!("\"{}\" to PeerId({}):", resp, abbrev(msg.source()));
+                    }
+                    None => update!("Requested block range is not on the main chain."),
+                }
+            }
+            // store each block in order, so that each one extends the last
+            PowMessage::BlockRangeResponse { blocks, .. } => {
+                // a range must be consecutive blocks, which follow on from its first block's parent if that is in the main chain
+                let checked: Result<(), NextBlockErr> = match blocks
+                    .iter()
+                    .next()
+                    .and_then(|first| self.chain.main_by_hash(&first.prev_hash))
+                {
+                    Some(parent) => blocks.validate_connecting(&parent.hash, parent.idx + 1),
+                    None => blocks.validate(),
+                };
+                if let Err(e) = checked {
+                    update!("Processed block range as invalid due to\n\t\"{}\"", e);
+                    self.metrics.blocks_rejected += 1;
+                    self.record_invalid_msg(&delivered_by);
+                    return;
+                }
+                for block in blocks.to_vec() {
+                    self.handle_block(block, &delivered_by, Chain::store_new_block)
+                }
+            }
+            PowMessage::HeadersRequest { .. } => {
+                let resp: PowMessage = PowMessage::HeadersResponse {
+                    target: msg.source().to_string(),
+                    source: self.swarm.local_peer_id().to_string(),
+                    headers: self.chain.to_headers(),
+                };
+                self.publish_pow_msg(resp.clone());
+                responded!("\"{}\" to PeerId({})", resp, abbrev(msg.source()));
+            }
+            // full nodes sync whole chains or blocks, so only check the headers
+            PowMessage::HeadersResponse { headers, .. } => match block::verify_headers(&headers) {
+                Ok(()) => update!(
+                    "Remote headers are valid, ending at idx {}.",
+                    headers.last().map_or(0, |h| h.idx)
+                ),
+                Err(e) => update!("Remote headers couldn't be verified due to \"{}\"", e),
+            },
+            PowMessage::ChainDigestRequest { .. } => {
+                let resp: PowMessage = PowMessage::ChainDigest {
+                    target: msg.source().to_string(),
+                    source: self.swarm.local_peer_id().to_string(),
+                    hashes: self.chain.to_hashes(),
+                };
+                self.publish_pow_msg(resp.clone());
+                responded!("\"{}\" to PeerId({})", resp, abbrev(msg.source()));
             }
-            PowMessage::NewBlock { block, .. } => self.handle_block(block, Chain::store_new_block),
+            PowMessage::ChainDigest { hashes, source, .. } => update!(
+                "Compared to PeerId({}), {}",
+                abbrev(&source),
+                chain_diff(&self.chain, &hashes)
+            ),
         }
     }
 
-    fn handle_block<F>(&mut self, block: Block, store_block: F)
+    fn handle_block<F>(&mut self, block: Block, delivered_by: &PeerId, store_block: F)
     where
         F: FnOnce(&mut Chain, Block) -> Result<NextBlockResult, NextBlockErr>,
     {
-        if let Ok(txn) = serde_json::from_str::<Transaction>(&block.data) {
-            match Transaction::validate_transaction(&txn) {
-                Ok(()) => {
-                    update!("Processed transaction in block as valid.")
-                }
-                Err(e) => {
-                    update!(
-                        "Processed transaction in block as invalid due to\n\t\"{}\"",
-                        e
-                    );
-                    return;
-                }
-            }
+        // reject a block dated too far in the future, as every block after it would have to be dated later still
+        if let Err(e) = block.validate_timestamp() {
+            update!("Processed block as invalid due to\n\t\"{}\"", e);
+            self.metrics.blocks_rejected += 1;
+            return;
+        }
+        // reject the whole block if any of its transactions are invalid
+        let txns: Vec<Transaction> = block.transactions();
+        if let Some(e) = txns
+            .iter()
+            .find_map(|txn| Transaction::validate_transaction_at(txn, block.timestamp).err())
+        {
+            update!(
+                "Processed transaction in block as invalid due to\n\t\"{}\"",
+                e
+            );
+            self.metrics.blocks_rejected += 1;
+            self.record_invalid_msg(delivered_by);
+            return;
+        }
+        if !txns.is_empty() {
+            update!("Processed {} transaction(s) in block as valid.", txns.len())
         }
 
         match store_block(&mut self.chain, block.clone()) {
             Ok(res) => {
                 update!("Block resulted in update:\n\t\"{}\"", res);
-                if remove_from_pool(&mut self.txns, &block) {
-                    update!("Deleted mined transaction from the local pool.");
+                self.outstanding_requests.remove(&block.hash);
+                self.metrics.blocks_accepted += 1;
+                self.chain_dirty = true;
+                match &res {
+                    NextBlockResult::ExtendedMain { end_idx, end_hash } => {
+                        self.emit_block_accepted(*end_idx, end_hash)
+                    }
+                    NextBlockResult::NewFork { .. } => self.metrics.forks_created += 1,
+                    NextBlockResult::ExtendedFork { .. } => {}
+                }
+                let removed: usize = self.txns.remove_block(&block);
+                if removed > 0 {
+                    update!(
+                        "Deleted {} mined transaction(s) from the local pool.",
+                        removed
+                    );
+                }
+                // the block may connect orphaned branches to each other, or to the main chain
+                let linked: usize = self.chain.link_orphans().len();
+                if linked > 0 {
+                    update!("Linked orphaned branches into {} new fork(s).", linked);
+                    self.metrics.forks_created += linked as u64;
                 }
                 // Update the state of the main chain
                 if let Ok(res) = self.chain.choose_fork() {
                     update!("{}", res);
+                    self.record_chain_status(&res);
+                    self.emit_chain_status(&res);
                 }
             }
             Err(e) => {
@@ -157,81 +988,355 @@ impl Peer {
                     "Block resulted in no update to chain or forks:\n\t\"{}\"",
                     e
                 );
+                // a duplicate or orphaned block isn't invalid, just not new or not yet connected
+                if !matches!(
+                    e,
+                    NextBlockErr::Duplicate { .. } | NextBlockErr::MissingParent { .. }
+                ) {
+                    self.metrics.blocks_rejected += 1;
+                }
+                if is_malformed(&e) {
+                    self.record_invalid_msg(delivered_by);
+                }
+                // we have the block anyway, or have kept it as an orphan until its parent arrives
+                if matches!(
+                    e,
+                    NextBlockErr::Duplicate { .. } | NextBlockErr::MissingParent { .. }
+                ) {
+                    self.outstanding_requests.remove(&block.hash);
+                }
                 if let NextBlockErr::MissingParent {
                     parent_hash,
                     parent_idx,
                 } = e
                 {
-                    let req = PowMessage::BlockRequest {
-                        target: None,
-                        source: self.swarm.local_peer_id().to_string(),
-                        idx: parent_idx,
-                        hash: parent_hash.clone(),
-                    };
-                    swarm::publish_pow_msg(req.clone(), &mut self.swarm);
-                    responded!("\"{}\" to all connected peers.", req);
+                    self.request_block(parent_idx, &parent_hash, BLOCK_REQUEST_TTL);
+                }
+            }
+        }
+    }
+    // Request a block from all peers, unless we are still waiting on a response to an earlier request for it
+    fn request_block(&mut self, idx: usize, hash: &str, ttl: u8) {
+        if self.outstanding_requests.contains_key(hash) {
+            debug!(
+                "Not requesting block {} again, as we are waiting on a response.",
+                abbrev(hash)
+            );
+            return;
+        }
+        self.outstanding_requests.insert(
+            hash.to_string(),
+            OutstandingRequest {
+                idx,
+                ttl,
+                attempts: 0,
+                sent: Instant::now(),
+            },
+        );
+        self.send_block_request(hash);
+    }
+    // Send (or re-send) an outstanding block request to all peers
+    fn send_block_request(&mut self, hash: &str) {
+        let Some(outstanding) = self.outstanding_requests.get_mut(hash) else {
+            return;
+        };
+        outstanding.attempts += 1;
+        let (idx, ttl) = (outstanding.idx, outstanding.ttl);
+        let req = PowMessage::BlockRequest {
+            target: None,
+            source: self.swarm.local_peer_id().to_string(),
+            idx,
+            hash: hash.to_string(),
+            ttl,
+        };
+        self.publish_pow_msg(req.clone());
+        responded!("\"{}\" to all connected peers.", req);
+    }
+
+    // Re-send block requests that weren't responded to in time, backing off after each attempt, and give up on those out of attempts
+    pub(crate) fn retry_requests_at(&mut self, now: Instant) {
+        let timed_out: Vec<(String, u32)> = self
+            .outstanding_requests
+            .iter()
+            .filter(|(_, req)| {
+                now.saturating_duration_since(req.sent)
+                    >= BLOCK_REQUEST_TIMEOUT * 2u32.pow(req.attempts.saturating_sub(1))
+            })
+            .map(|(hash, req)| (hash.clone(), req.attempts))
+            .collect();
+        for (hash, attempts) in timed_out {
+            if attempts >= MAX_BLOCK_REQUEST_ATTEMPTS {
+                self.outstanding_requests.remove(&hash);
+                update!(
+                    "Gave up on block {}, after requesting it {} time(s) without a response.",
+                    abbrev(&hash),
+                    attempts
+                );
+            } else {
+                if let Some(req) = self.outstanding_requests.get_mut(&hash) {
+                    req.sent = now;
                 }
+                self.send_block_request(&hash);
+            }
+        }
+    }
+
+    // Re-request the missing parents of orphaned branches, up to a limit, and expire those that stay disconnected
+    pub(crate) fn retry_orphans_at(&mut self, now: Instant) {
+        if self.config.relay {
+            return;
+        }
+        let forkpoints: Vec<(usize, String)> = self.chain.orphans().forkpoints();
+        // forget orphans that have since been connected or removed
+        self.orphan_retries
+            .retain(|forkpoint, _| forkpoints.iter().any(|(_, hash)| hash == forkpoint));
+        for (idx, forkpoint) in forkpoints {
+            let retry: &mut OrphanRetry =
+                self.orphan_retries
+                    .entry(forkpoint.clone())
+                    .or_insert(OrphanRetry {
+                        attempts: 0,
+                        since: now,
+                    });
+            if now.duration_since(retry.since) > ORPHAN_TTL {
+                self.orphan_retries.remove(&forkpoint);
+                self.chain.remove_orphan(&forkpoint);
+                update!(
+                    "Expired orphaned branch missing parent {}, after it stayed disconnected.",
+                    abbrev(&forkpoint)
+                );
+            } else if retry.attempts < MAX_ORPHAN_RETRIES {
+                retry.attempts += 1;
+                self.request_block(idx, &forkpoint, BLOCK_REQUEST_TTL);
             }
         }
     }
 
+    // Evict transactions that expired before the given time from the pool
+    pub(crate) fn sweep_pool_at(&mut self, now: i64) {
+        let removed: usize = self.txns.remove_expired(now);
+        if removed > 0 {
+            update!("Evicted {} expired transaction(s) from the pool.", removed);
+        }
+    }
+
     // Transaction event.
-    fn handle_txn_event(&mut self, msg: TxnMessage) {
+    pub(crate) fn handle_txn_event(&mut self, msg: TxnMessage, delivered_by: PeerId) {
+        if self.is_own_source(msg.source()) {
+            debug!("Ignoring our own \"{}\"", msg);
+            return;
+        }
+        if self.is_banned_source(msg.source(), &delivered_by) {
+            debug!(
+                "Ignoring \"{}\" from banned PeerId({})",
+                msg,
+                abbrev(msg.source())
+            );
+            return;
+        }
         received!("\"{}\" from PeerId({})", msg, abbrev(msg.source()));
+        self.rates.record_received(msg.kind());
+        self.last_seen
+            .insert(msg.source().clone(), Utc::now().timestamp());
+        if self.config.relay {
+            debug!("Relay mode: forwarding without processing \"{}\"", msg);
+            return;
+        }
         match msg {
             TxnMessage::NewTransaction { txn, .. } => {
-                match Transaction::validate_transaction(&txn) {
-                    Ok(()) => {
-                        self.txns.insert(txn);
-                        update!("Added new transaction to pool.");
-                    }
+                match Transaction::validate_transaction(&txn)
+                    .and_then(|()| self.check_admission(&txn))
+                {
+                    Ok(()) => match self.txns.insert(txn.clone()) {
+                        Ok(None) => {
+                            update!("Added new transaction to pool.");
+                            self.metrics.txns_accepted += 1;
+                            self.emit(PeerEvent::TxnAdded { hash: txn.hash });
+                        }
+                        Ok(Some(evicted)) => {
+                            update!(
+                                "Added new transaction to pool, evicting transaction with hash {}.",
+                                evicted.hash
+                            );
+                            self.metrics.txns_accepted += 1;
+                            self.emit(PeerEvent::TxnAdded { hash: txn.hash });
+                        }
+                        Err(e) => update!("Didn't add transaction to pool:\n\t\"{}\"", e),
+                    },
                     Err(e) => {
                         update!("Processed transaction as invalid:\n\t\"{}\"", e);
+                        self.metrics.txns_rejected += 1;
+                        // an honest peer may not have seen the blocks that spent the sender's funds or mined the transaction,
+                        // or may have a clock that is behind
+                        if !matches!(
+                            e,
+                            TransactionErr::InsufficientFunds { .. }
+                                | TransactionErr::Expired { .. }
+                                | TransactionErr::AlreadyMined { .. }
+                        ) {
+                            self.record_invalid_msg(&delivered_by);
+                        }
                     }
                 }
             }
         }
     }
     // Stdin event for a local user command.
-    async fn handle_std_event(&mut self, cmd: &str) {
+    pub(crate) async fn handle_std_event(&mut self, cmd: &str) {
+        // commands that broadcast would go nowhere before a peer connects, so defer them during the startup wait
+        if self.peer_wait.is_some() && is_broadcast_cmd(cmd) {
+            println!(
+                "Waiting for peers... \"{}\" will run once a peer connects.",
+                cmd
+            );
+            self.deferred_cmds.push(cmd.to_string());
+            return;
+        }
         match cmd {
-            // `reset`, deletes the current local chain and writes a new one with a single block.
-            cmd if cmd.starts_with("reset") => self.handle_cmd_reset(),
+            // `reset confirm`, deletes the current local chain and writes a new one with a single block, or `reset <file>` to seed it from a file.
+            cmd if cmd.starts_with("reset") => {
+                let arg = cmd.strip_prefix("reset").expect("can strip `reset`").trim();
+                self.handle_cmd_reset(arg).await
+            }
+            // `rollback N`, removes the last N blocks of the main chain, keeping them as a fork.
+            cmd if cmd.starts_with("rollback") => {
+                let arg = cmd
+                    .strip_prefix("rollback")
+                    .expect("can strip `rollback`")
+                    .trim();
+                self.handle_cmd_rollback(arg)
+            }
             // `load`, loads a chain from a local file.
             cmd if cmd.starts_with("load") => {
                 let file_name = cmd.strip_prefix("load").expect("can strip `load`").trim();
                 self.handle_cmd_load(file_name).await
             }
+            // `autosave <secs | off>`, saves the chain to the default file periodically, whenever it has changed
+            cmd if cmd.starts_with("autosave") => {
+                let arg = cmd
+                    .strip_prefix("autosave")
+                    .expect("can strip `autosave`")
+                    .trim();
+                self.handle_cmd_autosave(arg);
+            }
             // `save`, saves a chain from a local file.
             cmd if cmd.starts_with("save") => {
                 let file_name = cmd.strip_prefix("save").expect("can strip `save`").trim();
                 self.handle_cmd_save(file_name).await
             }
+            // `serve_blocks <on | off>`, toggles whether we respond to block requests
+            cmd if cmd.starts_with("serve_blocks") => {
+                let arg = cmd
+                    .strip_prefix("serve_blocks")
+                    .expect("can strip `serve_blocks`")
+                    .trim();
+                self.handle_cmd_serve_blocks(arg)
+            }
             // `redial`, dial all discovered peers
             cmd if cmd.starts_with("redial") => self.handle_cmd_redial(),
             cmd if cmd.starts_with("help") => {
                 print_user_commands();
             }
+            // `sync [range <from> <to>]`, requests the chains (or a range of main chain blocks) of all peers and synchronises to them
+            cmd if cmd.starts_with("sync") => {
+                let arg = cmd.strip_prefix("sync").expect("can strip `sync`").trim();
+                self.handle_cmd_sync(arg)
+            }
+            // `diff <peer_id>`, requests a peer's chain digest to find where our chains diverge
+            cmd if cmd.starts_with("diff") => {
+                let arg = cmd.strip_prefix("diff").expect("can strip `diff`").trim();
+                self.handle_cmd_diff(arg)
+            }
             //`req <all | [peer_id]>`, requiring us to publish a ChainRequest to the network.
             cmd if cmd.starts_with("req") => {
                 let arg = cmd.strip_prefix("req").expect("can strip `req`").trim();
                 self.handle_cmd_req(arg)
             }
-            // `mine [data]` makes and writes a new block with the given data (and an incrementing id)
+            // `mine [data | empty | N]` makes and writes a new block with the given data (and an incrementing id)
             cmd if cmd.starts_with("mine") => {
                 let arg = cmd.strip_prefix("mine").expect("can strip `mine`").trim();
                 self.handle_cmd_mine(arg)
             }
-            // `show <chain | forks | orphans | peers | txns >` lists the main chain, forks, orphans, discovered & connected peers, or transaction pool
+            // `peer info <peer_id>` reports everything known about a remote peer
+            cmd if cmd.starts_with("peer") => {
+                let arg = cmd.strip_prefix("peer").expect("can strip `peer`").trim();
+                self.handle_cmd_peer(arg);
+            }
+            // `stats [--json]` summarises the local chain, pool, and peers, optionally as json
+            cmd if cmd.starts_with("stats") => {
+                let arg = cmd.strip_prefix("stats").expect("can strip `stats`").trim();
+                self.handle_cmd_stats(arg);
+            }
+            // `show <chain | forks | orphans | peers | connections | txns > [--json]` lists the main chain, forks, orphans, discovered & connected peers, connection counts, or transaction pool, optionally as json
             cmd if cmd.starts_with("show") => {
                 let arg = cmd.strip_prefix("show").expect("can strip `show`").trim();
                 self.handle_cmd_show(arg);
             }
-            // `txn [data]`, broadcasts a random transaction with the "amount" set to [data]
+            // `txn import <json | file>`, broadcasts a transaction that was signed elsewhere, given as json or in a file
+            cmd if cmd.starts_with("txn import") => {
+                let arg = cmd
+                    .strip_prefix("txn import")
+                    .expect("can strip `txn import`")
+                    .trim();
+                self.handle_cmd_txn_import(arg).await;
+            }
+            // `txn [receiver?] [amount]`, broadcasts a transaction of that amount to the receiver, or to a random one
             cmd if cmd.starts_with("txn") => {
                 let arg = cmd.strip_prefix("txn").expect("can strip `txn`").trim();
                 self.handle_cmd_txn(arg);
             }
+            // `network stats`, shows the recent rates of messages received and published, by type
+            cmd if cmd.starts_with("network") => {
+                let arg = cmd
+                    .strip_prefix("network")
+                    .expect("can strip `network`")
+                    .trim();
+                self.handle_cmd_network(arg);
+            }
+            // `prove <txn_hash>`, prints a proof that a main chain block contains a transaction
+            cmd if cmd.starts_with("prove") => {
+                let arg = cmd.strip_prefix("prove").expect("can strip `prove`").trim();
+                self.handle_cmd_prove(arg);
+            }
+            // `confirmations <block_hash>`, prints how many main chain blocks have been built on a block, including itself
+            cmd if cmd.starts_with("confirmations") => {
+                let arg = cmd
+                    .strip_prefix("confirmations")
+                    .expect("can strip `confirmations`")
+                    .trim();
+                self.handle_cmd_confirmations(arg);
+            }
+            // `verify <txn | proof> <json>`, checks a transaction's encodings, hash, and signature without broadcasting it, or checks a proof of inclusion
+            cmd if cmd.starts_with("verify") => {
+                let arg = cmd
+                    .strip_prefix("verify")
+                    .expect("can strip `verify`")
+                    .trim();
+                self.handle_cmd_verify(arg);
+            }
+            // `validate <block <json> | chain <file>>`, checks a block or a chain file without changing our chain
+            cmd if cmd.starts_with("validate") => {
+                let arg = cmd
+                    .strip_prefix("validate")
+                    .expect("can strip `validate`")
+                    .trim();
+                self.handle_cmd_validate(arg).await;
+            }
+            // `dial <multiaddr>`, connects directly to a peer that can't be discovered by mdns
+            cmd if cmd.starts_with("dial") => {
+                let arg = cmd.strip_prefix("dial").expect("can strip `dial`").trim();
+                self.handle_cmd_dial(arg);
+            }
+            // `ban <peer_id>`, ignores all messages from a peer
+            cmd if cmd.starts_with("ban") => {
+                let arg = cmd.strip_prefix("ban").expect("can strip `ban`").trim();
+                self.handle_cmd_ban(arg, true);
+            }
+            // `unban <peer_id>`, stops ignoring messages from a peer
+            cmd if cmd.starts_with("unban") => {
+                let arg = cmd.strip_prefix("unban").expect("can strip `unban`").trim();
+                self.handle_cmd_ban(arg, false);
+            }
             _ => {
                 println!(
                     "Unknown command: \"{}\" \nWrite `help` to show available commands.",
@@ -240,67 +1345,501 @@ impl Peer {
             }
         }
     }
-    fn handle_cmd_txn(&mut self, arg: &str) {
-        if arg.is_empty() {
-            println!("Command error: `req` missing an argument.\nUsage: req <all | [peer_id]>");
-        } else {
-            let txn: Transaction =
-                Transaction::random_transaction(arg.to_string(), swarm::LOCAL_KEYS.clone());
-            self.txns.insert(txn.clone());
-            update!("Added a new transaction to pool:\n{}", txn);
-            let txn_msg: TxnMessage = TxnMessage::NewTransaction {
-                txn,
-                source: self.swarm.local_peer_id().to_string(),
-            };
-            swarm::publish_txn_msg(txn_msg.clone(), &mut self.swarm);
-            responded!("Broadcasted \"{}\" to all connected peers.", txn_msg);
-        }
-    }
-    async fn handle_cmd_load(&mut self, file_name: &str) {
-        let file_name = if file_name.is_empty() {
-            DEFAULT_FILE_PATH
-        } else {
-            file_name
-        };
-        match file::read_chain(file_name).await {
-            Ok(chain) => {
-                self.chain = chain;
-                update!("Loaded chain from local file \"{}\"", file_name)
+    fn handle_cmd_dial(&mut self, arg: &str) {
+        match arg.parse::<Multiaddr>() {
+            _ if arg.is_empty() => {
+                println!("Command error: `dial` missing an argument.\nUsage: dial <multiaddr>")
             }
-            Err(e) => eprintln!(
-                "Error loading chain from local file:\n\
-                                                \"{}\"",
+            Ok(addr) => self.dial(addr),
+            Err(e) => println!(
+                "Command error: `dial` has an invalid multiaddr: {}\nUsage: dial <multiaddr>",
                 e
             ),
         }
     }
-    async fn handle_cmd_save(&mut self, file_name: &str) {
-        let file_name = if file_name.is_empty() {
-            DEFAULT_FILE_PATH
-        } else {
-            file_name
-        };
+    fn handle_cmd_ban(&mut self, arg: &str, ban: bool) {
+        let name: &str = if ban { "ban" } else { "unban" };
+        match arg.parse::<PeerId>() {
+            _ if arg.is_empty() => println!(
+                "Command error: `{}` missing an argument.\nUsage: {} <peer_id>",
+                name, name
+            ),
+            Ok(peer_id) if ban => match self.ban(peer_id) {
+                true => update!("Banned PeerId({}).", abbrev(arg)),
+                false => update!("PeerId({}) is already banned.", abbrev(arg)),
+            },
+            Ok(peer_id) => match self.unban(&peer_id) {
+                true => update!("Unbanned PeerId({}).", abbrev(arg)),
+                false => update!("PeerId({}) isn't banned.", abbrev(arg)),
+            },
+            Err(e) => println!(
+                "Command error: `{}` has an invalid peer id: {}\nUsage: {} <peer_id>",
+                name, e, name
+            ),
+        }
+    }
+    fn handle_cmd_txn(&mut self, arg: &str) {
+        const USAGE: &str = "Usage: txn [receiver?] [amount] | txn [receiver] [amount] [nonce]";
+        let args: Vec<&str> = arg.split_whitespace().collect();
+        let (receiver, amount, nonce): (Option<&str>, &str, Option<&str>) = match args[..] {
+            _ if self.config.relay => {
+                println!("Command error: `txn` is unavailable in relay mode.");
+                return;
+            }
+            [amount] => (None, amount, None),
+            [receiver, amount] => (Some(receiver), amount, None),
+            [receiver, amount, nonce] => (Some(receiver), amount, Some(nonce)),
+            [] => {
+                println!("Command error: `txn` missing an argument.\n{}", USAGE);
+                return;
+            }
+            _ => {
+                println!("Command error: `txn` has too many arguments.\n{}", USAGE);
+                return;
+            }
+        };
+        let Ok(amount) = amount.parse::<u64>() else {
+            println!(
+                "Command error: `txn` amount must be a whole number.\n{}",
+                USAGE
+            );
+            return;
+        };
+        let nonce: Option<u64> = match nonce.map(|nonce| nonce.parse::<u64>()) {
+            None => None,
+            Some(Ok(nonce)) => Some(nonce),
+            Some(Err(_)) => {
+                println!(
+                    "Command error: `txn` nonce must be a whole number.\n{}",
+                    USAGE
+                );
+                return;
+            }
+        };
+        let txn: Transaction = match (receiver, nonce) {
+            (Some(receiver), Some(nonce)) => {
+                Transaction::with_nonce(receiver.to_string(), amount, nonce, self.keys.clone())
+            }
+            (Some(receiver), None) => {
+                Transaction::new(receiver.to_string(), amount, self.keys.clone())
+            }
+            (None, _) => Transaction::random_transaction(amount, self.keys.clone()),
+        };
+        if let Err(e) = self.check_admission(&txn) {
+            println!(
+                "Command error: `txn` would overdraw this peer.\n\t\"{}\"",
+                e
+            );
+            return;
+        }
+        if let Err(e) = self.txns.insert(txn.clone()) {
+            println!(
+                "Command error: `txn` couldn't be added to the pool.\n\t\"{}\"",
+                e
+            );
+            return;
+        }
+        update!("Added a new transaction to pool:\n{}", txn);
+        self.broadcast_txn(txn);
+    }
+    async fn handle_cmd_txn_import(&mut self, arg: &str) {
+        if self.config.relay {
+            println!("Command error: `txn import` is unavailable in relay mode.");
+            return;
+        }
+        // json is given inline as an object, and anything else is taken to be a file holding it
+        let json: String = match arg {
+            "" => {
+                println!("Command error: `txn import` missing an argument.\nUsage: txn import <json | file>");
+                return;
+            }
+            _ if arg.starts_with('{') => arg.to_string(),
+            file_name => match tokio::fs::read_to_string(file_name).await {
+                Ok(json) => json,
+                Err(e) => {
+                    println!(
+                        "Command error: `txn import` couldn't read \"{}\":\n\t\"{}\"",
+                        file_name, e
+                    );
+                    return;
+                }
+            },
+        };
+        let txn: Transaction = match Transaction::verify_json(&json)
+            .and_then(|txn| self.check_admission(&txn).map(|()| txn))
+        {
+            Ok(txn) => txn,
+            Err(e) => {
+                println!(
+                    "Command error: `txn import` rejected the transaction.\n\t\"{}\"",
+                    e
+                );
+                return;
+            }
+        };
+        if let Err(e) = self.txns.insert(txn.clone()) {
+            println!(
+                "Command error: `txn import` couldn't add the transaction to the pool.\n\t\"{}\"",
+                e
+            );
+            return;
+        }
+        update!("Imported a transaction into the pool:\n{}", txn);
+        self.broadcast_txn(txn);
+    }
+    // Announce a transaction that was added to the pool locally, to subscribers and to all connected peers
+    fn broadcast_txn(&mut self, txn: Transaction) {
+        self.emit(PeerEvent::TxnAdded {
+            hash: txn.hash.clone(),
+        });
+        let txn_msg: TxnMessage = TxnMessage::NewTransaction {
+            txn,
+            source: self.swarm.local_peer_id().to_string(),
+        };
+        self.publish_txn_msg(txn_msg.clone());
+        responded!("Broadcasted \"{}\" to all connected peers.", txn_msg);
+    }
+    // Request from another program, replying over the channel it came with. A closed reply channel is ignored.
+    fn handle_api_request(&mut self, request: ApiRequest) {
+        debug!("Handling api request {:?}", request);
+        match request {
+            ApiRequest::Height { reply } => {
+                let _ = reply.send(self.chain.last().idx);
+            }
+            ApiRequest::Block { idx, reply } => {
+                let _ = reply.send(self.chain.idx(idx).cloned());
+            }
+            ApiRequest::Txn { txn, reply } => {
+                let res: Result<(), String> = if self.config.relay {
+                    Err("Transactions are unavailable in relay mode".to_string())
+                } else {
+                    Transaction::validate_transaction(&txn)
+                        .and_then(|()| self.check_admission(&txn))
+                        .map_err(|e| e.to_string())
+                        .and_then(|()| match self.txns.insert(txn.clone()) {
+                            Ok(_) => Ok(()),
+                            Err(e) => Err(e.to_string()),
+                        })
+                };
+                if res.is_ok() {
+                    update!("Added a new transaction to pool:\n{}", txn);
+                    self.broadcast_txn(txn);
+                }
+                let _ = reply.send(res);
+            }
+            ApiRequest::Peers { reply } => {
+                let conn_peers: Vec<PeerId> = self.swarm.connected_peers();
+                let _ = reply.send(conn_peers.iter().map(PeerId::to_string).collect());
+            }
+            ApiRequest::Metrics { reply } => {
+                let _ = reply.send(self.metrics.clone());
+            }
+        }
+    }
+    /* Check that a transaction may join the pool: it isn't already on the main chain (so isn't replayed),
+    and the sender can afford it, given its confirmed balance and the debits already pending in the pool */
+    fn check_admission(&self, txn: &Transaction) -> Result<(), TransactionErr> {
+        if let Some(block) = self.chain.find_txn(&txn.hash) {
+            return Err(TransactionErr::AlreadyMined {
+                hash: txn.hash.clone(),
+                idx: block.idx,
+            });
+        }
+        let pending: u64 = self
+            .txns
+            .iter()
+            // a pending transaction that this one would replace is no longer counted against it
+            .filter(|pending| {
+                pending.sender == txn.sender
+                    && pending.hash != txn.hash
+                    && !pending.conflicts_with(txn)
+            })
+            .fold(0, |debits, pending| {
+                debits.saturating_add(pending.total_debit().unwrap_or(u64::MAX))
+            });
+        let ledger: Ledger = self.chain.ledger();
+        if ledger.can_afford(txn, pending) {
+            Ok(())
+        } else {
+            Err(TransactionErr::InsufficientFunds {
+                sender: txn.sender.clone(),
+                balance: ledger
+                    .balance_of(&txn.sender)
+                    .saturating_sub(i64::try_from(pending).unwrap_or(i64::MAX)),
+                amount: txn.total_debit().unwrap_or(u64::MAX),
+            })
+        }
+    }
+    fn handle_cmd_verify(&self, args: &str) {
+        if let Some(json) = args.strip_prefix("proof").map(str::trim) {
+            match TxnInclusionProof::from_json(json) {
+                _ if json.is_empty() => println!("Command error: `verify proof` missing an argument.\nUsage: verify proof <json>"),
+                // the proof is checked against our own copy of its block, as its recorded root could be forged along with its path
+                Ok(proof) => match self.chain.main_by_hash(&proof.block_hash) {
+                    Some(block) if merkle::verify_proof(&proof, &merkle::block_merkle_root(block)) => {
+                        update!("Proof is valid.")
+                    }
+                    Some(_) => update!("Proof is invalid."),
+                    None => update!("Proof is unverified: its block {} isn't in the main chain.", proof.block_hash),
+                },
+                Err(e) => println!("Command error: `verify proof` couldn't parse the proof: {}", e),
+            }
+            return;
+        }
+        match args.strip_prefix("txn").map(str::trim) {
+            Some(json) if !json.is_empty() => match Transaction::verify_json(json) {
+                Ok(_) => update!("Transaction is valid."),
+                Err(e) => update!("Transaction is invalid:\n\t\"{}\"", e),
+            },
+            _ => println!(
+                "Command error: `verify` missing an argument.\nUsage: verify <txn | proof> <json>"
+            ),
+        }
+    }
+    async fn handle_cmd_validate(&mut self, args: &str) {
+        const USAGE: &str = "Usage: validate <block <json> | chain <file>>";
+        let res: Result<String, String> = match args.split_once(' ').map(|(k, v)| (k, v.trim())) {
+            Some(("block", json)) => dry_run_block(&self.chain, json),
+            Some(("chain", file_name)) => match file::read_chain(file_name).await {
+                Ok(chain) => Ok(chain_summary(&self.chain, &chain)),
+                Err(e) => Err(e.to_string()),
+            },
+            _ => {
+                println!(
+                    "Command error: `validate` expects a block or chain.\n{}",
+                    USAGE
+                );
+                return;
+            }
+        };
+        match res {
+            Ok(summary) => update!("Valid: {}", summary),
+            Err(e) => update!("Invalid:\n\t\"{}\"", e),
+        }
+    }
+    fn handle_cmd_prove(&self, txn_hash: &str) {
+        if txn_hash.is_empty() {
+            println!("Command error: `prove` missing an argument.\nUsage: prove <txn_hash>");
+            return;
+        }
+        match self.chain.prove_txn(txn_hash).map(|proof| proof.to_json()) {
+            Some(Ok(json)) => println!("{}", json),
+            Some(Err(e)) => eprintln!("Error serializing proof to json: {}", e),
+            None => println!(
+                "Command error: `prove` found no transaction ({}) in the main chain.",
+                abbrev(txn_hash)
+            ),
+        }
+    }
+    fn handle_cmd_confirmations(&self, block_hash: &str) {
+        if block_hash.is_empty() {
+            println!("Command error: `confirmations` missing an argument.\nUsage: confirmations <block_hash>");
+            return;
+        }
+        match self.chain.confirmations(block_hash) {
+            Some(n) => println!("Block ({}) has {} confirmation(s).", abbrev(block_hash), n),
+            None => println!(
+                "Command error: `confirmations` found no block ({}) in the main chain.",
+                abbrev(block_hash)
+            ),
+        }
+    }
+    async fn handle_cmd_load(&mut self, file_name: &str) {
+        let file_name = if file_name.is_empty() {
+            DEFAULT_FILE_PATH
+        } else {
+            file_name
+        };
+        match file::read_chain(file_name).await {
+            Ok(chain) => {
+                self.chain = chain;
+                self.config.configure_chain(&mut self.chain);
+                self.chain_dirty = true;
+                self.emit_head_changed();
+                update!("Loaded chain from local file \"{}\"", file_name)
+            }
+            Err(e) => eprintln!(
+                "Error loading chain from local file:\n\
+                                                \"{}\"",
+                e
+            ),
+        }
+    }
+    async fn handle_cmd_save(&mut self, file_name: &str) {
+        let file_name = if file_name.is_empty() {
+            DEFAULT_FILE_PATH
+        } else {
+            file_name
+        };
         match file::write_chain(&self.chain, file_name).await {
-            Ok(()) => update!("Saved chain to local file \"{}\"", file_name),
+            Ok(()) => {
+                if file_name == DEFAULT_FILE_PATH {
+                    self.chain_dirty = false;
+                }
+                update!("Saved chain to local file \"{}\"", file_name)
+            }
             Err(e) => update!("Error saving chain to local file:\"{}\"", e),
         }
     }
-    fn handle_cmd_reset(&mut self) {
-        self.chain = chain::Chain::genesis();
-        update!("Main chain reset to a single genesis block. Forks emptied.")
+    fn handle_cmd_autosave(&mut self, arg: &str) {
+        match arg {
+            "off" => {
+                self.autosave = None;
+                update!("Autosave turned off.")
+            }
+            secs => match secs.parse::<u64>() {
+                Ok(secs) if secs > 0 => {
+                    let period = Duration::from_secs(secs);
+                    self.autosave = Some(period);
+                    self.autosave_timer = tokio::time::interval_at(Instant::now() + period, period);
+                    update!(
+                        "Autosaving the chain to \"{}\" every {} second(s), whenever it has changed.",
+                        DEFAULT_FILE_PATH,
+                        secs
+                    )
+                }
+                _ => println!(
+                    "Command error: `autosave` expects a positive number of seconds, or off.\nUsage: autosave <secs | off>"
+                ),
+            },
+        }
     }
-    fn handle_cmd_mine(&mut self, args: &str) {
-        let opt_data: Option<String> =
-            // Retrieve data as the next transaction (as a string) from the pool
-            if args.is_empty()  {
-                extract_from_pool(&mut self.txns)
-                .map(|txn|
-                    {   // assuming we can always safely serialize a transaction (which should be the case)..
-                        update!("Retrieved and transaction with hash {} from the pool.", txn.hash);
-                        serde_json::to_string(&txn).unwrap()
+    // Save the chain to the default file if it has changed since it was last saved, atomically so that a crash can't corrupt it
+    async fn autosave(&mut self) {
+        if !self.chain_dirty {
+            return;
+        }
+        match file::write_chain(&self.chain, DEFAULT_FILE_PATH).await {
+            Ok(()) => {
+                self.chain_dirty = false;
+                info!("Autosaved chain to \"{}\"", DEFAULT_FILE_PATH)
+            }
+            Err(e) => eprintln!("Error autosaving chain: \"{}\"", e),
+        }
+    }
+    // Whether the chain has changed since it was last saved (or persisted) to the default file
+    pub fn is_chain_dirty(&self) -> bool {
+        self.chain_dirty
+    }
+    async fn handle_cmd_reset(&mut self, arg: &str) {
+        match arg {
+            "" => {
+                println!(
+                    "Command error: `reset` would delete the local chain. To go ahead, enter `reset confirm`, or `reset <file>` to start from a file instead."
+                );
+                return;
+            }
+            "confirm" => {
+                self.chain = chain::Chain::genesis_with(self.config.genesis_config());
+            }
+            file_name => {
+                let saved: Chain = match file::read_chain(file_name).await {
+                    Ok(chain) => chain,
+                    Err(e) => {
+                        println!(
+                            "Command error: `reset` couldn't load \"{}\":\n\t\"{}\"",
+                            file_name, e
+                        );
+                        return;
                     }
+                };
+                // keep only the file's main chain, dropping any forks and orphans it was saved with
+                self.chain = Chain::from_vec(saved.to_vec())
+                    .expect("chain was validated when loaded from the file");
+            }
+        }
+        self.config.configure_chain(&mut self.chain);
+        self.chain_dirty = true;
+        self.emit_head_changed();
+        update!(
+            "Main chain reset to {} block(s). Forks and orphans emptied.",
+            self.chain.len()
+        )
+    }
+    fn handle_cmd_rollback(&mut self, arg: &str) {
+        let n: usize = match arg.parse::<usize>() {
+            _ if self.config.relay => {
+                println!("Command error: `rollback` is unavailable in relay mode.");
+                return;
+            }
+            Ok(n) => n,
+            Err(_) => {
+                println!(
+                    "Command error: `rollback` expects a number of blocks.\nUsage: rollback N"
+                );
+                return;
+            }
+        };
+        match self.chain.rollback(n) {
+            Ok(removed) => {
+                self.chain_dirty = true;
+                self.emit_head_changed();
+                // return the removed blocks' transactions to the pool, to be mined again, except their coinbases which are never pooled
+                let returned: usize =
+                    self.return_to_pool(removed.iter().flat_map(|b| b.transactions()));
+                update!(
+                    "Rolled back {} block(s), keeping them as a fork, and returned {} transaction(s) to the pool.",
+                    removed.len(),
+                    returned
+                )
+            }
+            Err(e) => println!("Command error: `rollback` failed:\n\t\"{}\"", e),
+        }
+    }
+    fn handle_cmd_mine(&mut self, args: &str) {
+        if self.config.relay {
+            println!("Command error: `mine` is unavailable in relay mode.");
+            return;
+        }
+        // Keep mining pooled transactions in the background, until stopped
+        if args == "start" {
+            self.auto_mine = true;
+            self.start_miner();
+            if self.miner.is_none() {
+                update!(
+                    "No transactions in the pool to mine for; mining will start when some arrive."
                 )
             }
+            return;
+        }
+        if args == "stop" {
+            self.auto_mine = false;
+            self.stop_miner();
+            update!("Stopped mining in the background.");
+            return;
+        }
+        // Check the tip before taking transactions from the pool, so that they aren't lost if we can't mine on it
+        if let Err(e) = self.chain.verify_tip() {
+            update!(
+                "Refusing to mine on top of the main chain, as its tip is invalid:\n\t\"{}\"",
+                e
+            );
+            return;
+        }
+        // Number of transactions to pack from the pool, if mining for transactions
+        let opt_txn_count: Option<usize> = match args.parse::<usize>() {
+            _ if args.is_empty() => Some(MAX_BLOCK_TXNS),
+            Ok(n) if n > 0 => Some(n),
+            _ => None,
+        };
+        let opt_data: Option<String> =
+            // Retrieve data as the next transactions (as a string) from the pool
+            if let Some(n) = opt_txn_count {
+                self.sweep_pool_at(Utc::now().timestamp());
+                let txns: Vec<Transaction> = self.txns.take(n);
+                for txn in &txns {
+                    update!("Retrieved transaction with hash {} from the pool.", txn.hash);
+                }
+                (!txns.is_empty()).then(|| Block::transactions_data(&txns))
+            }
+            // Mine a block with no transactions, regardless of the pool, if allowed to
+            else if args == "empty" {
+                if self.mine_policy == MinePolicy::RequireTxns {
+                    println!("Command error: `mine empty` is unavailable, as blocks must contain transactions.");
+                    return;
+                }
+                Some (EMPTY_BLOCK_DATA.to_string())
+            }
             else {
                 Some (args.to_string())
             };
@@ -309,18 +1848,160 @@ impl Peer {
                 update!("No transactions in the pool to mine for.")
             }
             Some(data) => {
-                self.chain.mine_block(&data);
+                let data: String = self.rewarded_data(data);
+                if let Err(e) = self.chain.try_mine_block(&data, &self.keys) {
+                    update!(
+                        "Refusing to mine on top of the main chain, as its tip is invalid:\n\t\"{}\"",
+                        e
+                    );
+                    return;
+                }
+                self.chain_dirty = true;
                 update!(
                     "Mined and pushed a new block to main chain:\n{}",
                     self.chain.last()
                 );
+                self.emit_block_accepted(self.chain.last().idx, &self.chain.last().hash);
                 let msg: PowMessage = PowMessage::NewBlock {
                     source: self.swarm.local_peer_id().to_string(),
                     block: self.chain.last().clone(),
                 };
-                swarm::publish_pow_msg(msg.clone(), &mut self.swarm);
+                self.publish_pow_msg(msg.clone());
+                responded!("\"{}\" to all connected peers", msg);
+            }
+        }
+    }
+    // Start mining pooled transactions on the main chain's tip in the background, if not already doing so
+    fn start_miner(&mut self) {
+        if self.miner.is_some() {
+            return;
+        }
+        if let Err(e) = self.chain.verify_tip() {
+            update!(
+                "Refusing to mine on top of the main chain, as its tip is invalid:\n\t\"{}\"",
+                e
+            );
+            return;
+        }
+        self.sweep_pool_at(Utc::now().timestamp());
+        let txns: Vec<Transaction> = self.txns.take(MAX_BLOCK_TXNS);
+        if txns.is_empty() {
+            debug!("No transactions in the pool to mine for.");
+            return;
+        }
+        let (tip, difficulty) = (self.chain.last().clone(), self.chain.difficulty());
+        let data: String = self.rewarded_data(Block::transactions_data(&txns));
+        let cancel = Arc::new(AtomicBool::new(false));
+        update!(
+            "Started mining {} transaction(s) on top of block {}.",
+            txns.len(),
+            tip.idx
+        );
+        self.miner = Some(MiningTask {
+            cancel: cancel.clone(),
+            tip_hash: tip.hash.clone(),
+            txns,
+        });
+        // mine on a blocking thread, so that we can keep handling events
+        let (mined_sender, keys) = (self.mined_sender.clone(), self.keys.clone());
+        tokio::task::spawn_blocking(move || {
+            if let Some(block) =
+                Block::mine_block_cancellable(&tip, &data, difficulty, &keys, &cancel)
+            {
+                let _ = mined_sender.send(block);
+            }
+        });
+    }
+    // Block data that rewards us through a coinbase, if the chain has a block reward
+    fn rewarded_data(&self, data: String) -> String {
+        self.chain
+            .coinbase_data(&data, &self.keys.public(), self.chain.block_reward())
+    }
+    // Cancel the block being mined in the background (if any), returning its transactions to the pool unless the new tip has them
+    fn stop_miner(&mut self) {
+        if let Some(miner) = self.miner.take() {
+            miner.cancel.store(true, Ordering::Relaxed);
+            self.return_to_pool(miner.txns);
+        }
+    }
+    // Restart background mining if the main chain's tip has moved on, or start it if there are new transactions
+    pub(crate) fn refresh_miner(&mut self) {
+        if let Some(miner) = &self.miner {
+            if miner.tip_hash != self.chain.last().hash {
+                update!("Main chain has a new tip, so restarting mining on top of it.");
+                self.stop_miner();
+            }
+        }
+        if self.auto_mine {
+            self.start_miner();
+        }
+    }
+    // Block mined in the background.
+    fn handle_mined_block(&mut self, block: Block) {
+        // ignore blocks from miners that were cancelled after finding a nonce
+        let Some(miner) = self
+            .miner
+            .take_if(|miner| miner.tip_hash == block.prev_hash)
+        else {
+            debug!("Discarding block mined on top of an old tip.");
+            return;
+        };
+        match self.chain.store_new_block(block.clone()) {
+            Ok(_) => {
+                update!("Mined and pushed a new block to main chain:\n{}", block);
+                self.chain_dirty = true;
+                self.emit_block_accepted(block.idx, &block.hash);
+                let msg: PowMessage = PowMessage::NewBlock {
+                    source: self.swarm.local_peer_id().to_string(),
+                    block,
+                };
+                self.publish_pow_msg(msg.clone());
                 responded!("\"{}\" to all connected peers", msg);
             }
+            Err(e) => {
+                update!("Mined block couldn't be stored due to \"{}\"", e);
+                self.return_to_pool(miner.txns);
+            }
+        }
+    }
+    fn handle_cmd_sync(&mut self, args: &str) {
+        if args.is_empty() {
+            return self.handle_cmd_req("all");
+        }
+        let range: Option<(usize, usize)> = match args.split_whitespace().collect::<Vec<_>>()[..] {
+            ["range", from, to] => from.parse().ok().zip(to.parse().ok()),
+            _ => None,
+        };
+        match range {
+            Some((from_idx, to_idx)) if from_idx <= to_idx => {
+                let req = PowMessage::BlockRangeRequest {
+                    from_idx,
+                    to_idx,
+                    target: None,
+                    source: self.swarm.local_peer_id().to_string(),
+                };
+                responded!("\"{}\" to all connected peers.", req);
+                self.publish_pow_msg(req);
+            }
+            _ => println!(
+                "Command error: `sync` has unrecognised argument(s).\nUsage: sync [range <from> <to>]"
+            ),
+        }
+    }
+    fn handle_cmd_diff(&mut self, args: &str) {
+        match args.parse::<PeerId>() {
+            Ok(target) => {
+                let req = PowMessage::ChainDigestRequest {
+                    target: target.to_string(),
+                    source: self.swarm.local_peer_id().to_string(),
+                };
+                responded!("\"{}\" to PeerId({}).", req, abbrev(args));
+                self.publish_pow_msg(req);
+            }
+            Err(_) if args.is_empty() => {
+                println!("Command error: `diff` missing an argument.\nUsage: diff <peer_id>")
+            }
+            Err(e) => println!("Command error: `diff` has an invalid peer id: {}", e),
         }
     }
     fn handle_cmd_req(&mut self, args: &str) {
@@ -334,7 +2015,7 @@ impl Peer {
                     source: self.swarm.local_peer_id().to_string(),
                 };
                 responded!("\"{}\" to all connected peers.", req);
-                swarm::publish_pow_msg(req, &mut self.swarm);
+                self.publish_pow_msg(req);
             }
             target => {
                 let req = PowMessage::ChainRequest {
@@ -342,14 +2023,36 @@ impl Peer {
                     source: self.swarm.local_peer_id().to_string(),
                 };
                 responded!("\"{}\" to PeerId({}).", req, abbrev(target));
-                swarm::publish_pow_msg(req, &mut self.swarm);
+                self.publish_pow_msg(req);
             }
         }
     }
+    // Serialize the main chain, forks, orphans, or pool as json, or None if there is no such structure
+    pub fn show_json(&self, target: &str) -> Option<Result<String, serde_json::Error>> {
+        match target {
+            "chain" => Some(serde_json::to_string(self.chain.blocks())),
+            "head" => Some(serde_json::to_string(&self.chain.head())),
+            "forks" => Some(serde_json::to_string(&self.chain.forks().summaries())),
+            "orphans" => Some(serde_json::to_string(&self.chain.orphans().summaries())),
+            "metrics" => Some(serde_json::to_string(&self.metrics)),
+            "pool" | "txns" => Some(serde_json::to_string(
+                &self.txns.iter().collect::<Vec<&Transaction>>(),
+            )),
+            _ => None,
+        }
+    }
     fn handle_cmd_show(&mut self, args: &str) {
+        if let Some(target) = args.strip_suffix("--json").map(str::trim) {
+            match self.show_json(target) {
+                Some(Ok(json)) => println!("{}", json),
+                Some(Err(e)) => eprintln!("Error serializing {} to json: {}", target, e),
+                None => println!("Command error: `show --json` has unrecognised argument(s).\nUsage: show <chain | head | forks | orphans | metrics | txns> --json"),
+            }
+            return;
+        }
         match args {
             _ if args.is_empty() => {
-                println!("Command error: `show` missing an argument.\nUsage: show <chain | forks | peers | txns>")
+                println!("Command error: `show` missing an argument.\nUsage: show <chain | head | block <idx> | forks | metrics | peers | connections | txns>")
             }
             "chain" => {
                 println!(
@@ -358,6 +2061,26 @@ impl Peer {
                     self.chain
                 );
             }
+            "head" => {
+                println!("Current head of the main chain:\n{}", self.chain.head());
+            }
+            args if args.starts_with("block") => {
+                let arg: &str = args
+                    .strip_prefix("block")
+                    .expect("can strip `block`")
+                    .trim();
+                match arg.parse::<usize>() {
+                    Ok(idx) => match block_contents(&self.chain, idx) {
+                        Some(contents) => println!("{}", contents),
+                        None => println!(
+                            "No block at idx {}, as the main chain ends at idx {}.",
+                            idx,
+                            self.chain.last().idx
+                        ),
+                    },
+                    Err(_) => println!("Command error: `show block` expects the idx of a block.\nUsage: show block <idx>"),
+                }
+            }
             "forks" => {
                 println!("Current forks:\n");
                 self.chain.print_forks();
@@ -367,68 +2090,185 @@ impl Peer {
                 self.chain.print_orphans();
             }
             "peers" => {
-                let (dscv_peers, conn_peers): (Vec<PeerId>, Vec<PeerId>) =
-                    swarm::get_peers(&mut self.swarm);
-                println!("Discovered Peers ({})", dscv_peers.len());
-                dscv_peers.iter().for_each(|p| println!("{}", p));
-                println!("Connected Peers ({})", conn_peers.len());
-                conn_peers.iter().for_each(|p| println!("{}", p));
-            }
-            "pool" => {
-                println!("Current transaction pool:\n");
+                println!("{}", peers_table(&self.swarm.get_peers()));
+            }
+            "connections" => {
+                println!(
+                    "{}",
+                    connections_summary(&self.swarm.connection_counts(), &self.config.swarm)
+                );
+            }
+            "metrics" => {
+                println!("{}", self.metrics);
+            }
+            "pool" | "txns" => {
+                println!(
+                    "Current transaction pool ({}/{}), by priority:\n",
+                    self.txns.len(),
+                    self.txns.capacity()
+                );
                 self.txns.iter().for_each(|txn| println!("{}", txn))
             }
             _ => {
-                println!("Command error: `show` has unrecognised argument(s).\nUsage: show <chain | forks | peers | txns>")
+                println!("Command error: `show` has unrecognised argument(s).\nUsage: show <chain | head | block <idx> | forks | metrics | peers | connections | txns>")
             }
         }
     }
+    fn handle_cmd_peer(&mut self, args: &str) {
+        let peer_id: PeerId = match args.strip_prefix("info").map(|arg| arg.trim().parse()) {
+            Some(Ok(peer_id)) => peer_id,
+            Some(Err(e)) => {
+                println!("Command error: `peer info` has an invalid peer id: {}", e);
+                return;
+            }
+            None => {
+                println!("Command error: `peer` has unrecognised argument(s).\nUsage: peer info <peer_id>");
+                return;
+            }
+        };
+        let info = PeerInfo {
+            peer_id: peer_id.to_string(),
+            discovered: self.swarm.discovered_peers().contains(&peer_id),
+            connected: self.swarm.connected_peers().contains(&peer_id),
+            addresses: self.swarm.peer_addresses(&peer_id),
+            last_seen: self.last_seen.get(&peer_id.to_string()).cloned(),
+        };
+        println!("{}", info)
+    }
+    fn handle_cmd_stats(&mut self, args: &str) {
+        let peers: Vec<(PeerId, Vec<Multiaddr>, bool)> = self.swarm.get_peers();
+        let dscv_peers: usize = peers
+            .iter()
+            .filter(|(_, addrs, _)| !addrs.is_empty())
+            .count();
+        let conn_peers: usize = peers.iter().filter(|(_, _, connected)| *connected).count();
+        let stats = Stats::new(&self.chain, &self.txns, dscv_peers, conn_peers);
+        match args {
+            "" => println!("{}", stats),
+            "--json" => match serde_json::to_string(&stats) {
+                Ok(json) => println!("{}", json),
+                Err(e) => eprintln!("Error serializing stats to json: {}", e),
+            },
+            _ => {
+                println!(
+                    "Command error: `stats` has unrecognised argument(s).\nUsage: stats [--json]"
+                )
+            }
+        }
+    }
+    fn handle_cmd_network(&self, args: &str) {
+        match args {
+            "stats" => println!("{}", self.rates),
+            _ => println!(
+                "Command error: `network` has unrecognised argument(s).\nUsage: network stats"
+            ),
+        }
+    }
+    fn handle_cmd_serve_blocks(&mut self, args: &str) {
+        match args {
+            "on" => self.serve_blocks = true,
+            "off" => self.serve_blocks = false,
+            _ => {
+                println!("Command error: `serve_blocks` has unrecognised argument(s).\nUsage: serve_blocks <on | off>");
+                return;
+            }
+        }
+        update!("Serving block requests turned {}.", args)
+    }
     fn handle_cmd_redial(&mut self) {
-        let discovered_peers: Vec<libp2p::PeerId> = swarm::discovered_peers(&mut self.swarm);
+        let discovered_peers: Vec<libp2p::PeerId> = self.swarm.discovered_peers();
         if discovered_peers.is_empty() {
             println!("No discovered peers to dial!");
             return;
         }
         for peer_id in discovered_peers {
-            match self.swarm.dial(&peer_id) {
+            match self.swarm.dial_peer(&peer_id) {
                 Ok(()) => println!("Dial for {}", peer_id),
                 Err(e) => eprintln!("Dial error {}", e),
             }
         }
     }
-    // (Predefined) Swarm event. For debugging purposes.
-    fn handle_swarm_event<E: std::fmt::Debug>(swarm_event: SwarmEvent<(), E>) {
-        match swarm_event {
-            SwarmEvent::ConnectionEstablished { peer_id, .. } => update!(
-                "Connection established with PeerId({})",
-                abbrev(&peer_id.to_string())
-            ),
-            SwarmEvent::ConnectionClosed { peer_id, .. } => update!(
-                "Connection closed with PeerId({})",
-                abbrev(&peer_id.to_string())
-            ),
-            SwarmEvent::NewListenAddr {
-                listener_id,
-                address,
-                ..
-            } => info!("SwarmEvent: {:?} listening on {}", listener_id, address),
-            SwarmEvent::Dialing(peer_id) => info!(
-                "SwarmEvent: dialling PeerId({})",
-                abbrev(&peer_id.to_string())
-            ),
-            SwarmEvent::IncomingConnection {
-                local_addr,
-                send_back_addr,
-            } => info!(
-                "SwarmEvent: incoming connection on addr {:?} with send-back addr {}",
-                local_addr, send_back_addr
-            ),
-            _ => info!("Unhandled swarm event: {:?}", swarm_event),
+}
+
+pub async fn set_up_peer(config: PeerConfig) -> Peer {
+    // Load chain from local file
+    let chain: Chain = match file::read_chain(DEFAULT_FILE_PATH).await {
+        Err(e) => match file::recover_chain(DEFAULT_FILE_PATH).await {
+            // salvage what we can of a corrupt file, rather than discarding it
+            Ok((chain, dropped)) => {
+                eprintln!(
+                    "\nProblem loading chain from the default file: \"{}\" \n\
+                           Recovered {} block(s) from it, dropping {} invalid block(s) and any forks. ",
+                    e,
+                    chain.len(),
+                    dropped
+                );
+                chain
+            }
+            Err(_) => {
+                eprintln!(
+                    "\nProblem loading chain from the default file: \"{}\" \n\
+                           Instantiating a fresh chain instead. ",
+                    e
+                );
+                Chain::genesis_with(config.genesis_config())
+            }
+        },
+        Ok(chain) => {
+            println!(
+                "\nLoaded chain from default file \"{}\".",
+                DEFAULT_FILE_PATH
+            );
+            chain
+        }
+    };
+    // Load the node's keypair from a local file
+    let key_path: &str = config.key_path.as_deref().unwrap_or(DEFAULT_KEY_PATH);
+    let keys: Keypair = match crypt::load_or_create_keypair(key_path) {
+        Err(e) => {
+            eprintln!(
+                "\nProblem loading the node's keypair: \"{}\" \n\
+                           Using a temporary keypair (and peer id) instead. ",
+                e
+            );
+            Keypair::generate_ed25519()
+        }
+        Ok(keys) => keys,
+    };
+    let mut peer: Peer = set_up_peer_with_keys(chain, keys, config).await;
+    // Load any transactions still pending from the last run
+    if let Ok(txns) = file::read_pool(DEFAULT_POOL_PATH).await {
+        peer.txns.extend(
+            txns.into_iter()
+                .filter(|txn| Transaction::validate_transaction(txn).is_ok()),
+        );
+        println!(
+            "\nLoaded {} pending transaction(s) from \"{}\".",
+            peer.txns.len(),
+            DEFAULT_POOL_PATH
+        );
+    }
+    // Load the peers banned in the last run
+    if let Ok(bans) = file::read_bans(DEFAULT_BANS_PATH).await {
+        for peer_id in bans.iter().filter_map(|peer_id| peer_id.parse().ok()) {
+            peer.ban(peer_id);
         }
+        println!(
+            "\nLoaded {} banned peer(s) from \"{}\".",
+            peer.banned.len(),
+            DEFAULT_BANS_PATH
+        );
     }
+    peer
 }
 
-pub async fn set_up_peer() -> Peer {
+// Set up a peer with a fresh keypair, that isn't persisted
+pub async fn set_up_peer_with_chain(chain: Chain, config: PeerConfig) -> Peer {
+    set_up_peer_with_keys(chain, Keypair::generate_ed25519(), config).await
+}
+
+pub async fn set_up_peer_with_keys(mut chain: Chain, keys: Keypair, config: PeerConfig) -> Peer {
+    config.configure_chain(&mut chain);
     /* Asynchronous channel, to communicate between different parts of our application.
     1. to_peer is an output channel, provided to network.rs.
         After network receieves a remote message, it forwards any requests here back to the peer (from_network)
@@ -441,57 +2281,536 @@ pub async fn set_up_peer() -> Peer {
         , txn_receiver) // used to receive the messages sent by response_sender.
         = mpsc::unbounded_channel();
 
-    // Swarm, with our network behaviour
-    let swarm = swarm::set_up_blockchain_swarm(pow_sender, txn_sender).await;
-
-    // Async Reader for StdIn, which reads the stream line by line.
-    let from_stdin = tokio::io::BufReader::new(tokio::io::stdin()).lines();
+    let (mined_sender, mined_receiver) = mpsc::unbounded_channel();
+    let (api_sender, api_receiver) = mpsc::unbounded_channel();
 
-    // Load chain from local file
-    let chain: Chain = match file::read_chain(DEFAULT_FILE_PATH).await {
+    // Swarm, with the network behaviour for our protocol
+    let listen_addr: &str = config
+        .listen_addr
+        .as_deref()
+        .unwrap_or(swarm::DEFAULT_LISTEN_ADDR);
+    let swarm: Box<dyn Broadcast> = match network::set_up_network(
+        config.protocol,
+        keys.clone(),
+        listen_addr,
+        &config.swarm,
+        pow_sender.clone(),
+        txn_sender.clone(),
+    )
+    .await
+    {
+        Ok(swarm) => swarm,
         Err(e) => {
             eprintln!(
-                "\nProblem loading chain from the default file: \"{}\" \n\
-                           Instantiating a fresh chain instead. ",
+                "\nProblem setting up the network: \"{}\" \n\
+                           Listening on the default address, with the default gossipsub parameters, instead. ",
                 e
             );
-            Chain::genesis()
-        }
-        Ok(chain) => {
-            println!(
-                "\nLoaded chain from default file \"{}\".",
-                DEFAULT_FILE_PATH
-            );
-            chain
+            network::set_up_network(
+                config.protocol,
+                keys.clone(),
+                swarm::DEFAULT_LISTEN_ADDR,
+                &SwarmConfig {
+                    roles: config.swarm.roles,
+                    source_check: config.swarm.source_check,
+                    ..SwarmConfig::default()
+                },
+                pow_sender,
+                txn_sender,
+            )
+            .await
+            .expect("can listen on the default address with the default config")
         }
     };
 
+    // Async Reader for StdIn, which reads the stream line by line.
+    let from_stdin = new_command_lines(tokio::io::stdin());
+
+    if config.relay {
+        println!("\nRunning in relay mode: forwarding messages without maintaining a chain.");
+    }
+    if let Some(wait) = config.wait_for_peers {
+        println!(
+            "\nWaiting for peers... (broadcasting commands are deferred for up to {}s, until a peer connects)",
+            wait.as_secs()
+        );
+    }
     println!("\n## Your Peer Id ##\n{}", swarm.local_peer_id());
-    Peer {
+    let mut peer = Peer {
         from_stdin,
+        stdin_open: true,
         pow_receiver,
         txn_receiver,
         swarm,
+        snapshot: watch::channel(chain.snapshot()).0,
         chain,
-        txns: HashSet::new(),
+        txns: Mempool::default(),
+        serve_blocks: true,
+        mine_policy: config.mine_policy,
+        metrics: Metrics::default(),
+        chain_dirty: false,
+        autosave: None,
+        autosave_timer: tokio::time::interval(DEFAULT_AUTOSAVE_PERIOD),
+        last_seen: HashMap::new(),
+        rates: NetworkRates::default(),
+        peer_wait: config.wait_for_peers.map(|wait| Instant::now() + wait),
+        deferred_cmds: Vec::new(),
+        outstanding_requests: HashMap::new(),
+        request_timer: tokio::time::interval_at(
+            Instant::now() + BLOCK_REQUEST_RETRY_PERIOD,
+            BLOCK_REQUEST_RETRY_PERIOD,
+        ),
+        mined_sender,
+        mined_receiver,
+        miner: None,
+        auto_mine: false,
+        keys,
+        orphan_timer: tokio::time::interval_at(
+            Instant::now() + ORPHAN_RETRY_PERIOD,
+            ORPHAN_RETRY_PERIOD,
+        ),
+        orphan_retries: HashMap::new(),
+        events: broadcast::channel(EVENT_CAPACITY).0,
+        api_sender,
+        api_receiver,
+        banned: HashSet::new(),
+        invalid_msgs: HashMap::new(),
+        pool_timer: tokio::time::interval_at(Instant::now() + POOL_SWEEP_PERIOD, POOL_SWEEP_PERIOD),
+        outbound: VecDeque::new(),
+        outbound_timer: tokio::time::interval(OUTBOUND_RETRY_PERIOD),
+        config,
+    };
+    for addr in peer.config.bootstrap.clone() {
+        peer.dial(addr);
+    }
+    peer
+}
+
+// Whether a block failed to be stored because it is invalid in itself, rather than because it doesn't fit the chain
+fn is_malformed(e: &NextBlockErr) -> bool {
+    matches!(
+        e,
+        NextBlockErr::DifficultyCheckFailed { .. }
+            | NextBlockErr::DifficultyBelowMinimum { .. }
+            | NextBlockErr::InconsistentHash { .. }
+            | NextBlockErr::InvalidBlockSignature { .. }
+            | NextBlockErr::InvalidCoinbase { .. }
+    )
+}
+
+// Whether a command broadcasts a message to the network
+fn is_broadcast_cmd(cmd: &str) -> bool {
+    ["mine", "txn", "req", "sync", "diff"]
+        .iter()
+        .any(|prefix| cmd.starts_with(prefix))
+}
+
+fn new_command_lines<R: AsyncRead + Unpin + Send + 'static>(source: R) -> CommandLines {
+    let source: Box<dyn AsyncRead + Unpin + Send> = Box::new(source);
+    BufReader::new(source).lines()
+}
+
+// Window over which message rates are reported
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+// Recent rates of messages received and published, by message type
+#[derive(Clone, Debug, Default)]
+pub struct NetworkRates {
+    received: HashMap<&'static str, RateCounter>,
+    published: HashMap<&'static str, RateCounter>,
+}
+
+impl NetworkRates {
+    pub fn record_received(&mut self, kind: &'static str) {
+        Self::record(&mut self.received, kind)
+    }
+    pub fn record_published(&mut self, kind: &'static str) {
+        Self::record(&mut self.published, kind)
+    }
+    // Messages received per second of the given type
+    pub fn received_rate(&self, kind: &str) -> f64 {
+        self.received.get(kind).map_or(0.0, RateCounter::rate)
+    }
+    // Messages published per second of the given type
+    pub fn published_rate(&self, kind: &str) -> f64 {
+        self.published.get(kind).map_or(0.0, RateCounter::rate)
+    }
+    fn record(counters: &mut HashMap<&'static str, RateCounter>, kind: &'static str) {
+        counters
+            .entry(kind)
+            .or_insert_with(|| RateCounter::new(RATE_WINDOW))
+            .record()
+    }
+}
+
+impl std::fmt::Display for NetworkRates {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let mut kinds: Vec<&str> = self
+            .received
+            .keys()
+            .chain(self.published.keys())
+            .copied()
+            .collect();
+        kinds.sort();
+        kinds.dedup();
+        write!(
+            f,
+            "Message rates over the last {}s (received/s, published/s):",
+            RATE_WINDOW.as_secs()
+        )?;
+        if kinds.is_empty() {
+            return write!(f, "\n\tNo messages yet.");
+        }
+        for kind in kinds {
+            write!(
+                f,
+                "\n\t{}: {:.2}, {:.2}",
+                kind,
+                self.received_rate(kind),
+                self.published_rate(kind)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+// Counts of the blocks and transactions a peer has accepted or rejected, and of the forks and reorgs they caused, since startup
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Metrics {
+    pub blocks_accepted: u64,
+    pub blocks_rejected: u64,
+    pub forks_created: u64,
+    pub reorgs: u64,
+    pub txns_accepted: u64,
+    pub txns_rejected: u64,
+}
+
+impl std::fmt::Display for Metrics {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "Metrics since startup:\n\
+             \tBlocks: {} accepted, {} rejected\n\
+             \tForks created: {}\n\
+             \tReorgs: {}\n\
+             \tTransactions: {} accepted, {} rejected",
+            self.blocks_accepted,
+            self.blocks_rejected,
+            self.forks_created,
+            self.reorgs,
+            self.txns_accepted,
+            self.txns_rejected
+        )
+    }
+}
+
+// A snapshot of the peer's local state, shared by the text and json renderings of `stats`
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Stats {
+    pub chain_len: usize,
+    pub tip_hash: String,
+    pub forks: usize,
+    pub orphans: usize,
+    pub txns: usize,
+    pub discovered_peers: usize,
+    pub connected_peers: usize,
+}
+
+impl Stats {
+    pub fn new(
+        chain: &Chain,
+        txns: &Mempool,
+        discovered_peers: usize,
+        connected_peers: usize,
+    ) -> Self {
+        Stats {
+            chain_len: chain.len(),
+            tip_hash: chain.last().hash.clone(),
+            forks: chain.forks().len(),
+            orphans: chain.orphans().len(),
+            txns: txns.len(),
+            discovered_peers,
+            connected_peers,
+        }
+    }
+}
+
+impl std::fmt::Display for Stats {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "\
+            ================================================\n\
+            Chain length:     {}\n\
+            Tip hash:         {}\n\
+            Forks:            {}\n\
+            Orphans:          {}\n\
+            Pool size:        {}\n\
+            Discovered peers: {}\n\
+            Connected peers:  {}\n\
+            ================================================",
+            self.chain_len,
+            abbrev(&self.tip_hash),
+            self.forks,
+            self.orphans,
+            self.txns,
+            self.discovered_peers,
+            self.connected_peers
+        )
+    }
+}
+
+// Everything known locally about a remote peer, as reported by `peer info`
+#[derive(Clone, Debug)]
+pub struct PeerInfo {
+    pub peer_id: String,
+    pub discovered: bool,
+    pub connected: bool,
+    pub addresses: Vec<Multiaddr>,
+    pub last_seen: Option<i64>, // time of the last message received from the peer
+}
+
+impl PeerInfo {
+    pub fn is_unknown(&self) -> bool {
+        !self.discovered && !self.connected && self.addresses.is_empty() && self.last_seen.is_none()
+    }
+}
+
+impl std::fmt::Display for PeerInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if self.is_unknown() {
+            return write!(f, "PeerId({}) has never been seen.", self.peer_id);
+        }
+        let addresses: Vec<String> = self.addresses.iter().map(|a| a.to_string()).collect();
+        let last_seen: String = self
+            .last_seen
+            .and_then(|t| DateTime::from_timestamp(t, 0))
+            .map_or("never".to_string(), |t| t.to_string());
+        write!(
+            f,
+            "\
+            ================================================\n\
+            Peer Id:         {}\n\
+            Discovered:      {}\n\
+            Connected:       {}\n\
+            Addresses:       [{}]\n\
+            Last message:    {}\n\
+            ================================================",
+            self.peer_id,
+            self.discovered,
+            self.connected,
+            addresses.join(", "),
+            last_seen
+        )
+    }
+}
+
+// Describe a main chain block and the transactions it contains (or its raw data if it has none), or None if there is no such block
+pub fn block_contents(chain: &Chain, idx: usize) -> Option<String> {
+    let block: &Block = chain.idx(idx)?;
+    let header: String = format!(
+        "Block {} with hash {}, mined by {}:",
+        block.idx,
+        abbrev(&block.hash),
+        abbrev(&block.miner_pubk)
+    );
+    let txns: Vec<Transaction> = block.transactions();
+    let contents: String = if !txns.is_empty() {
+        let txns: Vec<String> = txns.iter().map(Transaction::to_string).collect();
+        format!("{} transaction(s):\n{}", txns.len(), txns.join("\n"))
+    } else if block.data == EMPTY_BLOCK_DATA {
+        "No transactions.".to_string()
+    } else {
+        format!("Data: {}", block.data)
+    };
+    Some(format!("{}\n{}", header, contents))
+}
+
+// Summarise where our main chain and another peer's (given by its hashes) diverge, and how far each extends past that point
+pub fn chain_diff(chain: &Chain, remote_hashes: &[String]) -> String {
+    match chain.fork_point(remote_hashes) {
+        None => "no common ancestor, as the genesis blocks differ".to_string(),
+        Some((idx, _)) if chain.len() == remote_hashes.len() && chain.len() == idx + 1 => {
+            format!("no divergence; both chains end at idx {}", idx)
+        }
+        Some((idx, hash)) => format!(
+            "diverged at idx {} ({}); local +{}, remote +{}",
+            idx,
+            abbrev(&hash),
+            chain.len() - 1 - idx,
+            remote_hashes.len() - 1 - idx
+        ),
     }
 }
 
-fn remove_from_pool(txns: &mut HashSet<Transaction>, block: &Block) -> bool {
-    if let Ok(txn) = serde_json::from_str::<Transaction>(&block.data) {
-        return txns.remove(&txn);
+// Render peers as a table of their ids, whether we are connected to them, and their addresses (one per row)
+pub fn peers_table(peers: &[(PeerId, Vec<Multiaddr>, bool)]) -> String {
+    let connected: usize = peers.iter().filter(|(_, _, connected)| *connected).count();
+    let mut table: String = format!(
+        "Peers ({}, {} connected)\n{:<52} | {:<9} | Addresses\n{}",
+        peers.len(),
+        connected,
+        "Peer Id",
+        "Connected",
+        "-".repeat(80)
+    );
+    for (peer_id, addrs, connected) in peers {
+        let mut addrs = addrs.iter().map(Multiaddr::to_string);
+        let first: String = addrs.next().unwrap_or_else(|| "-".to_string());
+        table += &format!("\n{:<52} | {:<9} | {}", peer_id, connected, first);
+        for addr in addrs {
+            table += &format!("\n{:<52} | {:<9} | {}", "", "", addr);
+        }
     }
-    false
+    table
+}
+
+/* Check a block given as json as it would be checked before being stored, without storing it.
+Returns a summary of the block if valid, or why it is invalid. */
+pub fn dry_run_block(chain: &Chain, json: &str) -> Result<String, String> {
+    let block: Block =
+        serde_json::from_str(json).map_err(|e| format!("Couldn't parse the block: {}", e))?;
+    let parent: String = match chain.check_block(&block).map_err(|e| e.to_string())? {
+        Some(parent) if chain.main_by_hash(&parent.hash).is_some() => {
+            format!("extends main chain block {}", parent.idx)
+        }
+        Some(parent) => format!("extends fork block {}", parent.idx),
+        None => "its parent isn't in our main chain or forks, so it would be an orphan".to_string(),
+    };
+    Ok(format!(
+        "block {} with hash {} ({})",
+        block.idx,
+        abbrev(&block.hash),
+        parent
+    ))
 }
-fn extract_from_pool(txns: &mut HashSet<Transaction>) -> Option<Transaction> {
-    if let Some(txn) = txns.iter().next() {
-        // txns.remove(&txn); //  doesn't work: we immutably borrowing txns, via &txn, while mutably borrowing it, via txns.remove(..)
-        let txn_to_return = txn.clone(); // clone the value, so that we stop immutably borrowing txns
-        txns.remove(&txn_to_return);
-        Some(txn_to_return)
+
+// Summarise a chain that was validated, compared to our own
+pub fn chain_summary(chain: &Chain, other: &Chain) -> String {
+    let genesis: &str = if other.idx(0).map(|b| &b.hash) == chain.idx(0).map(|b| &b.hash) {
+        "the same genesis block as ours"
     } else {
-        None // If the pool is empty
+        "a different genesis block from ours"
+    };
+    format!(
+        "chain of {} blocks ending at {} with total work {}, from {}",
+        other.len(),
+        abbrev(&other.last().hash),
+        other.total_work(),
+        genesis
+    )
+}
+
+// Describe how many connections the swarm has against the limits it was configured with
+pub fn connections_summary(counts: &ConnectionCounts, config: &SwarmConfig) -> String {
+    let limit = |max: Option<u32>| max.map_or("unlimited".to_string(), |max| max.to_string());
+    format!(
+        "Connections:\n\
+        Established incoming: {}/{}\n\
+        Established outgoing: {}/{}\n\
+        Pending incoming:     {}/{}",
+        counts.established_incoming,
+        limit(config.max_established_incoming),
+        counts.established_outgoing,
+        limit(config.max_established_outgoing),
+        counts.pending_incoming,
+        limit(config.max_pending_incoming)
+    )
+}
+
+// Construct a response to a block request, if we are serving blocks and have the block on the main chain
+pub fn block_response(
+    chain: &Chain,
+    serve_blocks: bool,
+    hash: &str,
+    requester: &str,
+    local_peer_id: &str,
+) -> Option<PowMessage> {
+    if !serve_blocks {
+        return None;
+    }
+    chain
+        .main_by_hash(hash)
+        .map(|block| PowMessage::BlockResponse {
+            target: requester.to_string(),
+            source: local_peer_id.to_string(),
+            block: block.clone(),
+        })
+}
+
+/* Construct a response to a block range request, with the main chain's blocks from `from_idx` up to `to_idx`
+(inclusive), sending at most `max_blocks` blocks. Returns None if the range doesn't start on the main chain. */
+pub fn range_response(
+    chain: &Chain,
+    from_idx: usize,
+    to_idx: usize,
+    max_blocks: usize,
+    requester: &str,
+    local_peer_id: &str,
+) -> Option<PowMessage> {
+    let to_idx: usize = to_idx
+        .min(chain.len().saturating_sub(1))
+        .min(from_idx.saturating_add(max_blocks.max(1) - 1));
+    chain
+        .range(from_idx, to_idx)
+        .map(|blocks| PowMessage::BlockRangeResponse {
+            blocks,
+            target: requester.to_string(),
+            source: local_peer_id.to_string(),
+        })
+}
+
+// Whether a message fits within a size limit, as it would be sent
+fn fits(msg: &PowMessage, max_size: usize) -> bool {
+    encode_msg(msg).is_ok_and(|bytes| bytes.len() <= max_size)
+}
+
+/* Construct the responses to a chain request.
+If the chain is too large to publish in one message, it is sent instead as consecutive block ranges (after the genesis block)
+of up to max_blocks each, halving a range until it fits, which the requester adds block by block, as for any range. */
+pub fn chain_response(
+    chain: &Chain,
+    requester: &str,
+    local_peer_id: &str,
+    max_size: usize,
+    max_blocks: usize,
+) -> Vec<PowMessage> {
+    let resp = PowMessage::ChainResponse {
+        target: requester.to_string(),
+        source: local_peer_id.to_string(),
+        chain: chain.clone(),
+    };
+    if fits(&resp, max_size) {
+        return vec![resp];
+    }
+    debug!("Chain is too large to publish, so responding with it in ranges.");
+    let mut pages: Vec<PowMessage> = vec![];
+    let mut from_idx: usize = 1;
+    while from_idx < chain.len() {
+        let mut page_blocks: usize = max_blocks.max(1);
+        let page: PowMessage = loop {
+            let Some(page) = range_response(
+                chain,
+                from_idx,
+                usize::MAX,
+                page_blocks,
+                requester,
+                local_peer_id,
+            ) else {
+                return pages;
+            };
+            // a single block is sent even if it is too large, for the swarm to refuse
+            if page_blocks == 1 || fits(&page, max_size) {
+                break page;
+            }
+            page_blocks /= 2;
+        };
+        if let PowMessage::BlockRangeResponse { blocks, .. } = &page {
+            from_idx = blocks.last().idx + 1;
+        }
+        pages.push(page);
     }
+    pages
 }
 
 fn print_user_commands() {
@@ -1,55 +1,295 @@
 /*
     *Swarm*: Contains the network logic using GossipSub as the communication protocol and Mdns as the peer discovery protocol.
     (GossipSub, unlike FloodSub, can have its max transmit message size be changed.)
-    - Configures PeerId, Keypair, and Topic(s) for the network.
+    - Configures PeerId (from a given Keypair) and Topic(s) for the network.
     - Sets up NetworkBehaviour (that defines how peer discovery and message events are handled).
     - Sets up Swarm (that executes the NetworkBehaviour).
 */
 
-use super::message::{PowMessage, TxnMessage};
+use super::{
+    message::{compress_json, msg_json, msg_variant, PowMessage, TxnMessage},
+    network::{network_event, Broadcast, ConnectionCounts, NetworkEvent},
+};
 
 use libp2p::{
-    core::{muxing::StreamMuxerBox, transport::Boxed, upgrade},
+    core::{
+        muxing::StreamMuxerBox,
+        network::ConnectionLimits,
+        transport::{Boxed, OrTransport},
+        upgrade,
+    },
+    futures::{future::BoxFuture, StreamExt},
     gossipsub::{
-        self, Gossipsub, GossipsubConfig, GossipsubConfigBuilder, GossipsubEvent, GossipsubMessage,
-        IdentTopic, MessageAuthenticity, MessageId, Topic, ValidationMode,
+        self, error::PublishError, DataTransform, Gossipsub, GossipsubConfig,
+        GossipsubConfigBuilder, GossipsubEvent, GossipsubMessage, IdentTopic, MessageAuthenticity,
+        MessageId, RawGossipsubMessage, Topic, TopicHash, ValidationMode,
     },
     identity::Keypair,
     mdns::{Mdns, MdnsConfig, MdnsEvent},
     mplex, noise,
-    swarm::{NetworkBehaviourEventProcess, Swarm, SwarmBuilder},
+    swarm::{DialError, NetworkBehaviourEventProcess, Swarm, SwarmBuilder, SwarmEvent},
     tcp::TokioTcpConfig,
+    websocket::WsConfig,
     Multiaddr, NetworkBehaviour, PeerId, Transport,
 };
 use log::{debug, error, info, warn};
 use once_cell::sync::Lazy;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use std::time::Duration;
 use std::{
-    collections::HashSet,
+    collections::HashMap,
     hash::{DefaultHasher, Hash, Hasher},
 };
 use tokio::sync::mpsc::{self, UnboundedSender};
 
-pub static LOCAL_KEYS: Lazy<Keypair> = Lazy::new(|| Keypair::generate_ed25519());
-static LOCAL_PEER_ID: Lazy<PeerId> = Lazy::new(|| PeerId::from(LOCAL_KEYS.public()));
+pub static CHAIN_TOPIC: Lazy<IdentTopic> = Lazy::new(|| Topic::new("chain"));
+pub static TXN_TOPIC: Lazy<IdentTopic> = Lazy::new(|| Topic::new("transactions"));
+
+pub const MAX_MESSAGE_SIZE: usize = 10 * 1_048_576; // 10mb
 
-static CHAIN_TOPIC: Lazy<IdentTopic> = Lazy::new(|| Topic::new("chain"));
-static TXN_TOPIC: Lazy<IdentTopic> = Lazy::new(|| Topic::new("transactions"));
+// Listen on all interfaces, at a port chosen by the OS
+pub const DEFAULT_LISTEN_ADDR: &str = "/ip4/0.0.0.0/tcp/0";
+
+// Gossipsub parameters, to tune for different network conditions
+#[derive(Clone, Debug)]
+pub struct SwarmConfig {
+    // time between heartbeats, kept long by default to aid debugging by not cluttering the log space
+    pub heartbeat_interval: Duration,
+    // time a connection is maintained to a peer without being in the mesh and without receiving/sending a message to them
+    pub idle_timeout: Duration,
+    // number of heartbeats to keep in cache
+    pub history_length: usize,
+    // max size of messages published or received
+    pub max_transmit_size: usize,
+    // which topics to subscribe to
+    pub roles: Roles,
+    // what to do with messages whose declared source didn't deliver them
+    pub source_check: SourceCheck,
+    // max connections still being negotiated with peers that dialled us, beyond which more are refused (None for no limit)
+    pub max_pending_incoming: Option<u32>,
+    // max connections established by peers that dialled us, beyond which more are refused (None for no limit)
+    pub max_established_incoming: Option<u32>,
+    // max connections established by dialling peers ourselves (None for no limit)
+    pub max_established_outgoing: Option<u32>,
+}
 
-const MAX_MESSAGE_SIZE: usize = 10 * 1_048_576; // 10mb
+impl Default for SwarmConfig {
+    fn default() -> Self {
+        SwarmConfig {
+            heartbeat_interval: Duration::from_secs(10),
+            idle_timeout: Duration::from_secs(600),
+            history_length: 12,
+            max_transmit_size: MAX_MESSAGE_SIZE,
+            roles: Roles::default(),
+            source_check: SourceCheck::default(),
+            max_pending_incoming: Some(16),
+            max_established_incoming: Some(50),
+            max_established_outgoing: Some(50),
+        }
+    }
+}
+
+impl SwarmConfig {
+    pub fn validate(&self) -> Result<(), SwarmErr> {
+        let invalid = |msg: &str| {
+            Err(SwarmErr::InvalidConfig {
+                msg: msg.to_string(),
+            })
+        };
+        if self.heartbeat_interval.is_zero() {
+            return invalid("heartbeat interval must be nonzero");
+        }
+        if self.max_transmit_size == 0 {
+            return invalid("max transmit size must be positive");
+        }
+        if !self.roles.blocks && !self.roles.txns {
+            return invalid("must subscribe to blocks or transactions");
+        }
+        Ok(())
+    }
+
+    // Limits on the number of connections the swarm keeps, independent of the messaging protocol
+    pub fn connection_limits(&self) -> ConnectionLimits {
+        ConnectionLimits::default()
+            .with_max_pending_incoming(self.max_pending_incoming)
+            .with_max_established_incoming(self.max_established_incoming)
+            .with_max_established_outgoing(self.max_established_outgoing)
+    }
+
+    fn gossipsub_config(&self) -> Result<GossipsubConfig, SwarmErr> {
+        self.validate()?;
+        GossipsubConfigBuilder::default()
+            // custom hashing for message_ids, to filter out duplicate transactions and blocks
+            .message_id_fn(message_id)
+            .heartbeat_interval(self.heartbeat_interval)
+            // by default, the gossipsub implementation will sign all messages with the author’s private key, and require a valid signature before accepting or propagating a message further.
+            .validation_mode(ValidationMode::Strict)
+            // increase max size of messages published size
+            .max_transmit_size(self.max_transmit_size)
+            .idle_timeout(self.idle_timeout)
+            .history_length(self.history_length)
+            .max_messages_per_rpc(Some(500))
+            .build()
+            .map_err(|msg| SwarmErr::InvalidConfig {
+                msg: msg.to_string(),
+            })
+    }
+}
+
+/* Topics that a peer subscribes to, and so messages that it handles, e.g. only blocks for a pure block relay,
+or only transactions for a transaction aggregator. Subscribes to both by default. */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Roles {
+    pub blocks: bool,
+    pub txns: bool,
+}
+
+impl Default for Roles {
+    fn default() -> Self {
+        Roles {
+            blocks: true,
+            txns: true,
+        }
+    }
+}
+
+impl std::str::FromStr for Roles {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "all" => Ok(Roles::default()),
+            "blocks" => Ok(Roles {
+                blocks: true,
+                txns: false,
+            }),
+            "txns" => Ok(Roles {
+                blocks: false,
+                txns: true,
+            }),
+            _ => Err(format!("unknown role \"{}\"", s)),
+        }
+    }
+}
+
+/* What to do with a message whose declared source isn't the peer that delivered it, for those that should come
+straight from their sender (new blocks and block responses). Messages relayed through other peers are also delivered
+by someone other than their sender, so dropping them is only suitable for networks where peers are directly connected. */
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SourceCheck {
+    #[default]
+    Lenient, // log a warning, but keep the message
+    Strict, // log a warning, and drop the message
+}
+
+// A message from the network, with the peer that delivered it to us (rather than the source it declares)
+pub type Delivered<T> = (T, PeerId);
+
+// Whether to keep a message delivered by a peer, given how strictly its declared source is checked
+pub fn check_source(msg: &PowMessage, delivered_by: &PeerId, source_check: SourceCheck) -> bool {
+    match msg {
+        PowMessage::NewBlock { source, .. } | PowMessage::BlockResponse { source, .. }
+            if *source != delivered_by.to_string() =>
+        {
+            warn!(
+                "{} claims to be from PeerId({}), but was delivered by PeerId({})",
+                msg.kind(),
+                source,
+                delivered_by
+            );
+            source_check == SourceCheck::Lenient
+        }
+        _ => true,
+    }
+}
 
 // Custom network behaviour that combines Gossipsub and Mdns
 #[derive(NetworkBehaviour)]
 pub struct BlockchainBehaviour {
-    pub gossipsub: gossipsub::Gossipsub,
+    pub gossipsub: Gossipsub<Compression>,
     pub mdns: Mdns,
 
     // ** relevant only to a specific local peer that we are setting up
     #[behaviour(ignore)]
-    pow_sender: mpsc::UnboundedSender<PowMessage>,
+    pow_sender: mpsc::UnboundedSender<Delivered<PowMessage>>,
+    #[behaviour(ignore)]
+    txn_sender: mpsc::UnboundedSender<Delivered<TxnMessage>>,
     #[behaviour(ignore)]
-    txn_sender: mpsc::UnboundedSender<TxnMessage>,
+    local_peer_id: PeerId,
+    #[behaviour(ignore)]
+    source_check: SourceCheck,
+    #[behaviour(ignore)]
+    peer_table: PeerTable,
+}
+
+impl BlockchainBehaviour {
+    // Forward a message to the local peer as though it was received from the network, delivered by the local peer
+    pub fn forward_pow_msg(&self, msg: PowMessage) {
+        send_local_peer(&self.pow_sender, (msg, self.local_peer_id))
+    }
+}
+
+/* Every known peer, with the addresses mdns discovered it at and whether we are connected to it.
+Kept up to date as mdns discovers and expires addresses and as connections open and close, so that listing peers doesn't
+rescan mdns. A peer is forgotten once it has no unexpired addresses and no connections. */
+#[derive(Clone, Debug, Default)]
+pub struct PeerTable {
+    peers: HashMap<PeerId, (Vec<Multiaddr>, bool)>,
+}
+
+impl PeerTable {
+    pub fn discover(&mut self, peer_id: PeerId, addr: Multiaddr) {
+        let (addrs, _) = self.peers.entry(peer_id).or_default();
+        if !addrs.contains(&addr) {
+            addrs.push(addr);
+        }
+    }
+
+    pub fn expire(&mut self, peer_id: &PeerId, addr: &Multiaddr) {
+        if let Some((addrs, _)) = self.peers.get_mut(peer_id) {
+            addrs.retain(|a| a != addr);
+        }
+        self.forget_if_unknown(peer_id);
+    }
+
+    // Record whether we still have any connections to a peer, e.g. after one opens or closes
+    pub fn set_connected(&mut self, peer_id: PeerId, connected: bool) {
+        self.peers.entry(peer_id).or_default().1 = connected;
+        self.forget_if_unknown(&peer_id);
+    }
+
+    fn forget_if_unknown(&mut self, peer_id: &PeerId) {
+        if let Some((addrs, false)) = self.peers.get(peer_id) {
+            if addrs.is_empty() {
+                self.peers.remove(peer_id);
+            }
+        }
+    }
+
+    pub fn addresses(&self, peer_id: &PeerId) -> Vec<Multiaddr> {
+        self.peers
+            .get(peer_id)
+            .map_or(vec![], |(addrs, _)| addrs.clone())
+    }
+
+    pub fn peers(&self) -> Vec<(PeerId, Vec<Multiaddr>, bool)> {
+        self.peers
+            .iter()
+            .map(|(peer_id, (addrs, connected))| (*peer_id, addrs.clone(), *connected))
+            .collect()
+    }
+
+    // Update the table from a swarm event, which is otherwise only logged
+    pub fn observe<T, E>(&mut self, event: &SwarmEvent<T, E>) {
+        match event {
+            SwarmEvent::ConnectionEstablished { peer_id, .. } => self.set_connected(*peer_id, true),
+            SwarmEvent::ConnectionClosed {
+                peer_id,
+                num_established,
+                ..
+            } => self.set_connected(*peer_id, *num_established > 0),
+            _ => {}
+        }
+    }
 }
 
 impl NetworkBehaviourEventProcess<MdnsEvent> for BlockchainBehaviour {
@@ -57,15 +297,17 @@ impl NetworkBehaviourEventProcess<MdnsEvent> for BlockchainBehaviour {
         match event {
             // Event for discovering (a list of) new peers
             MdnsEvent::Discovered(discovered_list) => {
-                for (peer, _addr) in discovered_list {
+                for (peer, addr) in discovered_list {
                     info!("MdnsEvent: discovered new peer: {}", peer);
                     self.gossipsub.add_explicit_peer(&peer);
+                    self.peer_table.discover(peer, addr);
                 }
             }
             // Event for (a list of) expired peers
             MdnsEvent::Expired(expired_list) => {
-                for (peer, _addr) in expired_list {
+                for (peer, addr) in expired_list {
                     info!("MdnsEvent: removed peer: {}", peer);
+                    self.peer_table.expire(&peer, &addr);
                     if !self.mdns.has_node(&peer) {
                         self.gossipsub.remove_explicit_peer(&peer);
                     }
@@ -83,58 +325,107 @@ impl NetworkBehaviourEventProcess<GossipsubEvent> for BlockchainBehaviour {
         } = event
         {
             info!("Received {:?} from {:?}", message, propagation_source);
-            if let Ok(pow_msg) = serde_json::from_slice::<PowMessage>(&message.data) {
-                match pow_msg {
-                    PowMessage::ChainRequest { ref target, .. }
-                    | PowMessage::BlockRequest { ref target, .. } => match target {
-                        Some(target) if *target == LOCAL_PEER_ID.to_string() => {
-                            send_local_peer(&self.pow_sender, pow_msg)
-                        }
-                        None => send_local_peer(&self.pow_sender, pow_msg),
-                        _ => info!("Ignoring request. Not for us."),
-                    },
-                    PowMessage::ChainResponse { ref target, .. }
-                    | PowMessage::BlockResponse { ref target, .. } => {
-                        if *target == LOCAL_PEER_ID.to_string() {
-                            send_local_peer(&self.pow_sender, pow_msg)
-                        } else {
-                            info!("Ignoring response. Not for us.")
-                        }
-                    }
-                    PowMessage::NewBlock { .. } => send_local_peer(&self.pow_sender, pow_msg),
+            route_msg(
+                &message.data,
+                &self.local_peer_id,
+                &propagation_source,
+                self.source_check,
+                &self.pow_sender,
+                &self.txn_sender,
+            )
+        }
+    }
+}
+
+/* Forward a message received from the network to the local peer, unless it is a request or response for another peer,
+or (with a strict source check) it wasn't delivered by its declared source */
+pub(crate) fn route_msg(
+    data: &[u8],
+    local_peer_id: &PeerId,
+    delivered_by: &PeerId,
+    source_check: SourceCheck,
+    pow_sender: &UnboundedSender<Delivered<PowMessage>>,
+    txn_sender: &UnboundedSender<Delivered<TxnMessage>>,
+) {
+    // decompressed once, whichever type of message it turns out to be
+    let Ok(json) = msg_json(data) else {
+        return;
+    };
+    if let Ok(pow_msg) = serde_json::from_slice::<PowMessage>(&json) {
+        if !check_source(&pow_msg, delivered_by, source_check) {
+            return;
+        }
+        match pow_msg {
+            PowMessage::ChainRequest { ref target, .. }
+            | PowMessage::BlockRequest { ref target, .. }
+            | PowMessage::BlockRangeRequest { ref target, .. }
+            | PowMessage::HeadersRequest { ref target, .. } => match target {
+                Some(target) if *target == local_peer_id.to_string() => {
+                    send_local_peer(pow_sender, (pow_msg, *delivered_by))
+                }
+                None => send_local_peer(pow_sender, (pow_msg, *delivered_by)),
+                _ => info!("Ignoring request. Not for us."),
+            },
+            PowMessage::ChainResponse { ref target, .. }
+            | PowMessage::BlockResponse { ref target, .. }
+            | PowMessage::BlockRangeResponse { ref target, .. }
+            | PowMessage::HeadersResponse { ref target, .. }
+            | PowMessage::ChainDigest { ref target, .. } => {
+                if *target == local_peer_id.to_string() {
+                    send_local_peer(pow_sender, (pow_msg, *delivered_by))
+                } else {
+                    info!("Ignoring response. Not for us.")
+                }
+            }
+            PowMessage::ChainDigestRequest { ref target, .. } => {
+                if *target == local_peer_id.to_string() {
+                    send_local_peer(pow_sender, (pow_msg, *delivered_by))
+                } else {
+                    info!("Ignoring request. Not for us.")
                 }
-            } else if let Ok(txn_msg) = serde_json::from_slice::<TxnMessage>(&message.data) {
-                send_local_peer(&self.txn_sender, txn_msg)
             }
+            PowMessage::NewBlock { .. } => send_local_peer(pow_sender, (pow_msg, *delivered_by)),
         }
+    } else if let Ok(txn_msg) = serde_json::from_slice::<TxnMessage>(&json) {
+        send_local_peer(txn_sender, (txn_msg, *delivered_by))
     }
 }
 
-fn new_tcp_transport() -> Boxed<(PeerId, StreamMuxerBox)> {
+// Transport over TCP, or over WebSockets for `/ws` addresses (e.g. for browser-based or proxied peers)
+pub(crate) fn new_transport(keys: &Keypair) -> Boxed<(PeerId, StreamMuxerBox)> {
     // Authentication keys, for the `Noise` crypto-protocol, used to secure traffic within the p2p network
     let local_auth_keys: noise::AuthenticKeypair<noise::X25519Spec> =
         noise::Keypair::<noise::X25519Spec>::new()
-            .into_authentic(&LOCAL_KEYS.clone())
+            .into_authentic(keys)
             .expect("can create auth keys");
 
-    TokioTcpConfig::new()
+    // each rejects the other's addresses, so `/ws` addresses are handled by websockets and the rest by plain tcp
+    OrTransport::new(WsConfig::new(TokioTcpConfig::new()), TokioTcpConfig::new())
         .upgrade(upgrade::Version::V1)
         .authenticate(noise::NoiseConfig::xx(local_auth_keys).into_authenticated())
         .multiplex(mplex::MplexConfig::new())
         .boxed()
 }
 
-async fn new_mdns_discovery() -> Mdns {
+pub(crate) async fn new_mdns_discovery() -> Mdns {
     let mdns_config: MdnsConfig = MdnsConfig::default();
     Mdns::new(mdns_config).await.expect("can create mdns")
 }
 
 pub async fn set_up_blockchain_swarm(
-    pow_sender: UnboundedSender<PowMessage>,
-    txn_sender: UnboundedSender<TxnMessage>,
-) -> Swarm<BlockchainBehaviour> {
+    keys: Keypair,
+    listen_addr: &str,
+    config: &SwarmConfig,
+    pow_sender: UnboundedSender<Delivered<PowMessage>>,
+    txn_sender: UnboundedSender<Delivered<TxnMessage>>,
+) -> Result<Swarm<BlockchainBehaviour>, SwarmErr> {
+    let listen_addr: Multiaddr = parse_listen_addr(listen_addr)?;
+
+    let gossipsub_config: GossipsubConfig = config.gossipsub_config()?;
+
     // Transport
-    let transp = new_tcp_transport();
+    let transp = new_transport(&keys);
+    let local_peer_id: PeerId = PeerId::from(keys.public());
 
     // Network behaviour
     let mut behaviour: BlockchainBehaviour = {
@@ -142,26 +433,10 @@ pub async fn set_up_blockchain_swarm(
         let mdns = new_mdns_discovery().await;
 
         // Communication Protocol
-        let gossipsub_config: GossipsubConfig = GossipsubConfigBuilder::default()
-            // custom hashing for message_ids, to filter out duplicate transactions
-            .message_id_fn(filter_dup_transactions)
-            // aid debugging by not cluttering the log space
-            .heartbeat_interval(Duration::from_secs(10))
-            // by default, the gossipsub implementation will sign all messages with the author’s private key, and require a valid signature before accepting or propagating a message further.
-            .validation_mode(ValidationMode::Strict)
-            // increase max size of messages published size
-            .max_transmit_size(MAX_MESSAGE_SIZE)
-            // time a connection is maintained to a peer without being in the mesh and without receiving/sending a message to them
-            .idle_timeout(Duration::from_secs(600))
-            // number of heartbeats to keep in cache
-            .history_length(12)
-            .max_messages_per_rpc(Some(500))
-            .build()
-            .expect("valid gossipsub config");
-
-        let gossipsub: Gossipsub = Gossipsub::new(
-            MessageAuthenticity::Signed(LOCAL_KEYS.clone()),
+        let gossipsub: Gossipsub<Compression> = Gossipsub::new_with_transform(
+            MessageAuthenticity::Signed(keys),
             gossipsub_config,
+            Compression,
         )
         .expect("can create gossipsub");
 
@@ -170,34 +445,113 @@ pub async fn set_up_blockchain_swarm(
             gossipsub,
             pow_sender,
             txn_sender,
+            local_peer_id,
+            source_check: config.source_check,
+            peer_table: PeerTable::default(),
         }
     };
-    match behaviour.gossipsub.subscribe(&CHAIN_TOPIC) {
-        Ok(b) => info!("gossipsub.subscribe() returned {}", b),
-        Err(e) => warn!("gossipsub.subscribe() error: {:?}", e),
-    };
-    match behaviour.gossipsub.subscribe(&TXN_TOPIC) {
-        Ok(b) => info!("gossipsub.subscribe() returned {}", b),
-        Err(e) => warn!("gossipsub.subscribe() error: {:?}", e),
-    };
+    let topics = [
+        config.roles.blocks.then_some(&*CHAIN_TOPIC),
+        config.roles.txns.then_some(&*TXN_TOPIC),
+    ];
+    for topic in topics.into_iter().flatten() {
+        match behaviour.gossipsub.subscribe(topic) {
+            Ok(b) => info!("gossipsub.subscribe() returned {}", b),
+            Err(e) => warn!("gossipsub.subscribe() error: {:?}", e),
+        };
+    }
 
     // Swarm
-    let mut swarm = SwarmBuilder::new(transp, behaviour, *LOCAL_PEER_ID)
+    let mut swarm = SwarmBuilder::new(transp, behaviour, local_peer_id)
         .executor(Box::new(|fut| {
             tokio::spawn(fut);
         }))
+        .connection_limits(config.connection_limits())
         .build();
 
-    // Listen on a memory transport.
-    let listen_addr: Multiaddr = "/ip4/0.0.0.0/tcp/0"
-        .parse()
-        .expect("can get a local socket");
-    Swarm::listen_on(&mut swarm, listen_addr.clone()).expect("swarm can be started");
+    // Listen on a tcp socket.
+    Swarm::listen_on(&mut swarm, listen_addr.clone()).map_err(|e| SwarmErr::ListenFailed {
+        addr: listen_addr.to_string(),
+        msg: e.to_string(),
+    })?;
     println!("Listening on {:?}", listen_addr);
-    swarm
+    Ok(swarm)
+}
+
+pub(crate) fn parse_listen_addr(listen_addr: &str) -> Result<Multiaddr, SwarmErr> {
+    listen_addr
+        .parse()
+        .map_err(|e: libp2p::multiaddr::Error| SwarmErr::InvalidListenAddr {
+            addr: listen_addr.to_string(),
+            msg: e.to_string(),
+        })
+}
+
+#[derive(Debug)]
+pub enum SwarmErr {
+    InvalidListenAddr { addr: String, msg: String },
+    ListenFailed { addr: String, msg: String },
+    InvalidConfig { msg: String },
+}
+
+impl std::fmt::Display for SwarmErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SwarmErr::InvalidListenAddr { addr, msg } => {
+                write!(
+                    f,
+                    "Invalid Listen Address: \"{}\" is not a multiaddr: {}",
+                    addr, msg
+                )
+            }
+            SwarmErr::ListenFailed { addr, msg } => {
+                write!(f, "Listen Failed: couldn't listen on \"{}\": {}", addr, msg)
+            }
+            SwarmErr::InvalidConfig { msg } => {
+                write!(
+                    f,
+                    "Invalid Config: gossipsub parameters are invalid: {}",
+                    msg
+                )
+            }
+        }
+    }
 }
 
-fn filter_dup_transactions(message: &gossipsub::GossipsubMessage) -> MessageId {
+impl std::error::Error for SwarmErr {}
+
+/* Compresses messages as they are published, and decompresses them once as they are received, before they are identified or handled.
+A message that can't be decompressed is rejected by gossipsub, rather than propagated. */
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Compression;
+
+impl DataTransform for Compression {
+    fn inbound_transform(
+        &self,
+        raw_message: RawGossipsubMessage,
+    ) -> Result<GossipsubMessage, std::io::Error> {
+        let data: Vec<u8> = msg_json(&raw_message.data)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e.to_string()))?
+            .into_owned();
+        Ok(GossipsubMessage {
+            source: raw_message.source,
+            data,
+            sequence_number: raw_message.sequence_number,
+            topic: raw_message.topic,
+        })
+    }
+
+    fn outbound_transform(
+        &self,
+        _topic: &TopicHash,
+        data: Vec<u8>,
+    ) -> Result<Vec<u8>, std::io::Error> {
+        Ok(compress_json(data))
+    }
+}
+
+// Identify a gossipsub message (as json, before compression), so that those with the same id are only processed and propagated once
+pub fn message_id(message: &gossipsub::GossipsubMessage) -> MessageId {
     let mut hasher: DefaultHasher = DefaultHasher::new();
     let GossipsubMessage { data, topic, .. } = message;
 
@@ -205,64 +559,159 @@ fn filter_dup_transactions(message: &gossipsub::GossipsubMessage) -> MessageId {
     if *topic == TXN_TOPIC.hash() {
         data.hash(&mut hasher);
     }
-    // allow duplicates of other message payloads (e.g. several requests).
+    // filter out duplicate new blocks by hashing only on the block, whichever peer announced them.
+    else if let Some(block_id) = new_block_id(message) {
+        "new block".hash(&mut hasher);
+        block_id.hash(&mut hasher);
+    }
+    // allow duplicates of other message payloads (e.g. several requests), which also hash on their source.
     else {
         message.hash(&mut hasher);
     }
     gossipsub::MessageId::from(hasher.finish().to_string())
 }
 
-fn send_local_peer<T>(sender: &UnboundedSender<T>, msg: T) {
+// What identifies an announced block: its hash, and the signature of it that the hash can't cover,
+// so that a copy with a corrupted signature can't be mistaken for (and suppress) the genuine block
+#[derive(Hash, Deserialize)]
+struct BlockId {
+    hash: String,
+    #[serde(default)]
+    sig: String,
+}
+
+#[derive(Deserialize)]
+enum NewBlockId {
+    NewBlock { block: BlockId },
+}
+
+// The identity of the block announced by a message, if it is a new block, decoding only the fields needed
+fn new_block_id(message: &GossipsubMessage) -> Option<BlockId> {
+    if message.topic != CHAIN_TOPIC.hash() {
+        return None;
+    }
+    if msg_variant(&message.data)? != "NewBlock" {
+        return None;
+    }
+    match serde_json::from_slice::<NewBlockId>(&message.data) {
+        Ok(NewBlockId::NewBlock { block }) => Some(block),
+        Err(_) => None,
+    }
+}
+
+pub(crate) fn send_local_peer<T>(sender: &UnboundedSender<T>, msg: T) {
     if let Err(e) = sender.send(msg) {
         error!("Error sending message to peer via local channel: {}", e);
     }
 }
 
-pub fn publish_pow_msg(msg: PowMessage, swarm: &mut Swarm<BlockchainBehaviour>) {
+pub fn publish_pow_msg(
+    msg: &PowMessage,
+    swarm: &mut Swarm<BlockchainBehaviour>,
+) -> Result<(), PublishError> {
     publish_msg(msg, CHAIN_TOPIC.clone(), swarm)
 }
 
-pub fn publish_txn_msg(msg: TxnMessage, swarm: &mut Swarm<BlockchainBehaviour>) {
+pub fn publish_txn_msg(
+    msg: &TxnMessage,
+    swarm: &mut Swarm<BlockchainBehaviour>,
+) -> Result<(), PublishError> {
     publish_msg(msg, TXN_TOPIC.clone(), swarm)
 }
 
-fn publish_msg<T: Serialize>(msg: T, topic: IdentTopic, swarm: &mut Swarm<BlockchainBehaviour>) {
-    let s: String = match serde_json::to_string(&msg) {
-        Ok(json) => json,
-        Err(e) => {
-            error!("Couldn't jsonify message, {}", e);
-            return;
-        }
-    };
-    let res = swarm.behaviour_mut().gossipsub.publish(topic, s.as_bytes());
-    match res {
+// Publish a message to a topic, or return why it couldn't be sent to any peer (e.g. there are none yet)
+fn publish_msg<T: Serialize>(
+    msg: &T,
+    topic: IdentTopic,
+    swarm: &mut Swarm<BlockchainBehaviour>,
+) -> Result<(), PublishError> {
+    // compressed as it is published, by the gossipsub data transform
+    let json: Vec<u8> = serde_json::to_vec(msg).map_err(|e| {
+        error!("Couldn't jsonify message, {}", e);
+        PublishError::TransformFailed(e.into())
+    })?;
+    let res = swarm.behaviour_mut().gossipsub.publish(topic, json);
+    match &res {
         Err(e) => info!("Publish message error: {:?}", e),
         Ok(_) => info!("Publish message successful."),
     }
+    res.map(|_| ())
 }
 
-pub fn get_peers(swarm: &mut Swarm<BlockchainBehaviour>) -> (Vec<PeerId>, Vec<PeerId>) {
+/* Every known peer, with the addresses it was discovered at by mdns and whether we are connected to it, read from the peer table.
+Peers dialled directly (e.g. bootstrap peers) aren't discovered by mdns, so are only listed while connected, without addresses. */
+pub fn get_peers(swarm: &mut Swarm<BlockchainBehaviour>) -> Vec<(PeerId, Vec<Multiaddr>, bool)> {
     debug!("get_peers()");
-    let nodes = swarm.behaviour().mdns.discovered_nodes();
-    let mut discovered_peers: HashSet<&PeerId> = HashSet::new();
-    let mut connected_peers: HashSet<&PeerId> = HashSet::new();
-    for peer in nodes {
-        discovered_peers.insert(peer);
-        if swarm.is_connected(peer) {
-            connected_peers.insert(peer);
-        }
-    }
-    let collect_peers = |peers: HashSet<&PeerId>| peers.into_iter().cloned().collect();
-
-    (
-        collect_peers(discovered_peers),
-        collect_peers(connected_peers),
-    )
+    swarm.behaviour().peer_table.peers()
 }
 
 pub fn connected_peers(swarm: &mut Swarm<BlockchainBehaviour>) -> Vec<PeerId> {
-    get_peers(swarm).1
+    get_peers(swarm)
+        .into_iter()
+        .filter_map(|(peer, _, connected)| connected.then_some(peer))
+        .collect()
 }
 pub fn discovered_peers(swarm: &mut Swarm<BlockchainBehaviour>) -> Vec<PeerId> {
-    get_peers(swarm).0
+    get_peers(swarm)
+        .into_iter()
+        .filter_map(|(peer, addrs, _)| (!addrs.is_empty()).then_some(peer))
+        .collect()
+}
+
+pub fn peer_addresses(swarm: &mut Swarm<BlockchainBehaviour>, peer_id: &PeerId) -> Vec<Multiaddr> {
+    swarm.behaviour().peer_table.addresses(peer_id)
+}
+
+impl Broadcast for Swarm<BlockchainBehaviour> {
+    fn publish_pow_msg(&mut self, msg: &PowMessage) -> Result<(), PublishError> {
+        publish_pow_msg(msg, self)
+    }
+    fn publish_txn_msg(&mut self, msg: &TxnMessage) -> Result<(), PublishError> {
+        publish_txn_msg(msg, self)
+    }
+    fn get_peers(&mut self) -> Vec<(PeerId, Vec<Multiaddr>, bool)> {
+        get_peers(self)
+    }
+    fn local_peer_id(&self) -> PeerId {
+        *Swarm::local_peer_id(self)
+    }
+    fn listen_addrs(&self) -> Vec<Multiaddr> {
+        self.listeners().cloned().collect()
+    }
+    fn is_connected(&self, peer_id: &PeerId) -> bool {
+        Swarm::is_connected(self, peer_id)
+    }
+    fn connection_counts(&self) -> ConnectionCounts {
+        self.network_info().connection_counters().into()
+    }
+    fn dial_addr(&mut self, addr: Multiaddr) -> Result<(), DialError> {
+        Swarm::dial_addr(self, addr)
+    }
+    fn dial_peer(&mut self, peer_id: &PeerId) -> Result<(), DialError> {
+        self.dial(peer_id)
+    }
+    // gossip with a peer even if it isn't in our mesh
+    fn add_peer(&mut self, peer_id: &PeerId) {
+        self.behaviour_mut().gossipsub.add_explicit_peer(peer_id);
+    }
+    fn ban_peer(&mut self, peer_id: &PeerId) {
+        let gossipsub = &mut self.behaviour_mut().gossipsub;
+        gossipsub.remove_explicit_peer(peer_id);
+        gossipsub.blacklist_peer(peer_id);
+    }
+    fn unban_peer(&mut self, peer_id: &PeerId) {
+        self.behaviour_mut()
+            .gossipsub
+            .remove_blacklisted_peer(peer_id);
+    }
+    fn forward_pow_msg(&self, msg: PowMessage) {
+        self.behaviour().forward_pow_msg(msg)
+    }
+    fn next_event(&mut self) -> BoxFuture<'_, NetworkEvent> {
+        Box::pin(async move {
+            let event = self.select_next_some().await;
+            self.behaviour_mut().peer_table.observe(&event);
+            network_event(event)
+        })
+    }
 }
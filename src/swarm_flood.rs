@@ -0,0 +1,233 @@
+/*
+    *Swarm (FloodSub)*: Contains the network logic using FloodSub as the communication protocol and Mdns as the peer discovery protocol.
+    (FloodSub is simpler than GossipSub, forwarding every message to every peer, but can't have its parameters tuned.)
+    - Configures Topic(s) for the network.
+    - Sets up NetworkBehaviour (that defines how peer discovery and message events are handled).
+    - Sets up Swarm (that executes the NetworkBehaviour).
+*/
+
+use super::{
+    message::{encode_msg, PowMessage, TxnMessage},
+    network::{network_event, Broadcast, ConnectionCounts, NetworkEvent},
+    swarm::{
+        new_mdns_discovery, new_transport, parse_listen_addr, route_msg, send_local_peer,
+        Delivered, SourceCheck, SwarmConfig, SwarmErr,
+    },
+};
+
+use libp2p::{
+    floodsub::{Floodsub, FloodsubEvent, Topic},
+    futures::{future::BoxFuture, StreamExt},
+    gossipsub::error::PublishError,
+    identity::Keypair,
+    mdns::{Mdns, MdnsEvent},
+    swarm::{DialError, NetworkBehaviour as _, NetworkBehaviourEventProcess, Swarm, SwarmBuilder},
+    Multiaddr, NetworkBehaviour, PeerId,
+};
+use log::{debug, error, info};
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use std::collections::HashSet;
+use tokio::sync::mpsc::{self, UnboundedSender};
+
+// the same topic names as gossipsub's, though peers on different protocols can't talk to each other
+static CHAIN_TOPIC: Lazy<Topic> = Lazy::new(|| Topic::new("chain"));
+static TXN_TOPIC: Lazy<Topic> = Lazy::new(|| Topic::new("transactions"));
+
+// Custom network behaviour that combines Floodsub and Mdns
+#[derive(NetworkBehaviour)]
+pub struct FloodBehaviour {
+    pub floodsub: Floodsub,
+    pub mdns: Mdns,
+
+    // ** relevant only to a specific local peer that we are setting up
+    #[behaviour(ignore)]
+    pow_sender: mpsc::UnboundedSender<Delivered<PowMessage>>,
+    #[behaviour(ignore)]
+    txn_sender: mpsc::UnboundedSender<Delivered<TxnMessage>>,
+    #[behaviour(ignore)]
+    local_peer_id: PeerId,
+    // peers added to floodsub's view, which floodsub doesn't expose
+    #[behaviour(ignore)]
+    flood_peers: HashSet<PeerId>,
+    #[behaviour(ignore)]
+    source_check: SourceCheck,
+}
+
+impl FloodBehaviour {
+    fn add_peer(&mut self, peer_id: PeerId) {
+        self.flood_peers.insert(peer_id);
+        self.floodsub.add_node_to_partial_view(peer_id);
+    }
+
+    fn remove_peer(&mut self, peer_id: &PeerId) {
+        self.flood_peers.remove(peer_id);
+        self.floodsub.remove_node_from_partial_view(peer_id);
+    }
+}
+
+impl NetworkBehaviourEventProcess<MdnsEvent> for FloodBehaviour {
+    fn inject_event(&mut self, event: MdnsEvent) {
+        match event {
+            // Event for discovering (a list of) new peers
+            MdnsEvent::Discovered(discovered_list) => {
+                for (peer, _addr) in discovered_list {
+                    info!("MdnsEvent: discovered new peer: {}", peer);
+                    self.add_peer(peer);
+                }
+            }
+            // Event for (a list of) expired peers
+            MdnsEvent::Expired(expired_list) => {
+                for (peer, _addr) in expired_list {
+                    info!("MdnsEvent: removed peer: {}", peer);
+                    if !self.mdns.has_node(&peer) {
+                        self.remove_peer(&peer);
+                    }
+                }
+            }
+        }
+    }
+}
+impl NetworkBehaviourEventProcess<FloodsubEvent> for FloodBehaviour {
+    fn inject_event(&mut self, event: FloodsubEvent) {
+        if let FloodsubEvent::Message(message) = event {
+            info!("Received {:?} from {:?}", message, message.source);
+            // floodsub only tells us who published a message, not who relayed it to us
+            route_msg(
+                &message.data,
+                &self.local_peer_id,
+                &message.source,
+                self.source_check,
+                &self.pow_sender,
+                &self.txn_sender,
+            )
+        }
+    }
+}
+
+// Set up a floodsub swarm, which has no parameters to tune other than its topics and connection limits, so otherwise only checks that the config is valid
+pub async fn set_up_flood_swarm(
+    keys: Keypair,
+    listen_addr: &str,
+    config: &SwarmConfig,
+    pow_sender: UnboundedSender<Delivered<PowMessage>>,
+    txn_sender: UnboundedSender<Delivered<TxnMessage>>,
+) -> Result<Swarm<FloodBehaviour>, SwarmErr> {
+    let listen_addr: Multiaddr = parse_listen_addr(listen_addr)?;
+    config.validate()?;
+
+    // Transport
+    let transp = new_transport(&keys);
+    let local_peer_id: PeerId = PeerId::from(keys.public());
+
+    // Network behaviour
+    let mut behaviour: FloodBehaviour = FloodBehaviour {
+        floodsub: Floodsub::new(local_peer_id),
+        mdns: new_mdns_discovery().await,
+        pow_sender,
+        txn_sender,
+        local_peer_id,
+        flood_peers: HashSet::new(),
+        source_check: config.source_check,
+    };
+    if config.roles.blocks {
+        behaviour.floodsub.subscribe(CHAIN_TOPIC.clone());
+    }
+    if config.roles.txns {
+        behaviour.floodsub.subscribe(TXN_TOPIC.clone());
+    }
+
+    // Swarm
+    let mut swarm = SwarmBuilder::new(transp, behaviour, local_peer_id)
+        .executor(Box::new(|fut| {
+            tokio::spawn(fut);
+        }))
+        .connection_limits(config.connection_limits())
+        .build();
+
+    // Listen on a tcp socket.
+    Swarm::listen_on(&mut swarm, listen_addr.clone()).map_err(|e| SwarmErr::ListenFailed {
+        addr: listen_addr.to_string(),
+        msg: e.to_string(),
+    })?;
+    println!("Listening on {:?}", listen_addr);
+    Ok(swarm)
+}
+
+// Publish a message to a topic, which floodsub can only fail to do when we aren't connected to anyone
+fn publish_msg<T: Serialize>(
+    msg: &T,
+    topic: Topic,
+    swarm: &mut Swarm<FloodBehaviour>,
+) -> Result<(), PublishError> {
+    let data: Vec<u8> = encode_msg(msg).map_err(|e| {
+        error!("Couldn't jsonify message, {}", e);
+        PublishError::TransformFailed(e.into())
+    })?;
+    if swarm.network_info().num_peers() == 0 {
+        info!("Publish message error: no connected peers");
+        return Err(PublishError::InsufficientPeers);
+    }
+    swarm.behaviour_mut().floodsub.publish(topic, data);
+    info!("Publish message successful.");
+    Ok(())
+}
+
+impl Broadcast for Swarm<FloodBehaviour> {
+    fn publish_pow_msg(&mut self, msg: &PowMessage) -> Result<(), PublishError> {
+        publish_msg(msg, CHAIN_TOPIC.clone(), self)
+    }
+    fn publish_txn_msg(&mut self, msg: &TxnMessage) -> Result<(), PublishError> {
+        publish_msg(msg, TXN_TOPIC.clone(), self)
+    }
+    // Peers discovered by mdns, and those added to floodsub's view while connected (e.g. bootstrap peers)
+    fn get_peers(&mut self) -> Vec<(PeerId, Vec<Multiaddr>, bool)> {
+        debug!("get_peers()");
+        let mut peers: HashSet<PeerId> =
+            self.behaviour().mdns.discovered_nodes().cloned().collect();
+        peers.extend(self.behaviour().flood_peers.iter().cloned());
+        peers
+            .into_iter()
+            .filter_map(|peer| {
+                let addrs: Vec<Multiaddr> = self.behaviour_mut().mdns.addresses_of_peer(&peer);
+                let connected: bool = Swarm::is_connected(self, &peer);
+                (connected || !addrs.is_empty()).then_some((peer, addrs, connected))
+            })
+            .collect()
+    }
+    fn local_peer_id(&self) -> PeerId {
+        *Swarm::local_peer_id(self)
+    }
+    fn listen_addrs(&self) -> Vec<Multiaddr> {
+        self.listeners().cloned().collect()
+    }
+    fn is_connected(&self, peer_id: &PeerId) -> bool {
+        Swarm::is_connected(self, peer_id)
+    }
+    fn connection_counts(&self) -> ConnectionCounts {
+        self.network_info().connection_counters().into()
+    }
+    fn dial_addr(&mut self, addr: Multiaddr) -> Result<(), DialError> {
+        Swarm::dial_addr(self, addr)
+    }
+    fn dial_peer(&mut self, peer_id: &PeerId) -> Result<(), DialError> {
+        self.dial(peer_id)
+    }
+    fn add_peer(&mut self, peer_id: &PeerId) {
+        self.behaviour_mut().add_peer(*peer_id);
+    }
+    // floodsub has no blacklist, so messages a banned peer relays through others are still filtered by the peer
+    fn ban_peer(&mut self, peer_id: &PeerId) {
+        self.behaviour_mut().remove_peer(peer_id);
+    }
+    fn unban_peer(&mut self, _peer_id: &PeerId) {}
+    fn forward_pow_msg(&self, msg: PowMessage) {
+        send_local_peer(
+            &self.behaviour().pow_sender,
+            (msg, self.behaviour().local_peer_id),
+        )
+    }
+    fn next_event(&mut self) -> BoxFuture<'_, NetworkEvent> {
+        Box::pin(async move { network_event(self.select_next_some().await) })
+    }
+}
@@ -0,0 +1,114 @@
+/******************
+      TESTS
+********************/
+#[cfg(all(test, feature = "http-api"))] // cargo test api --features http-api -- --nocapture
+mod api_tests {
+    use crate::{
+        api,
+        block::Block,
+        chain::Chain,
+        message::PowMessage,
+        peer::{set_up_peer_with_chain, Peer, PeerConfig},
+        tests::common::{MINER, REMOTE},
+        transaction::Transaction,
+        util::trace,
+    };
+    use axum::{
+        body::{self, Body},
+        http::{Request, StatusCode},
+    };
+    use libp2p::{identity, PeerId};
+    use serde_json::Value;
+    use tower::ServiceExt;
+
+    // Make a request to the peer's api, stepping the peer until it replies
+    async fn call(peer: &mut Peer, request: Request<Body>) -> (StatusCode, Value) {
+        let response = api::router(peer.api_sender()).oneshot(request);
+        tokio::pin!(response);
+        let response = loop {
+            tokio::select! {
+                response = &mut response => break response.unwrap(),
+                _ = peer.step() => {}
+            }
+        };
+        let status: StatusCode = response.status();
+        let bytes = body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (
+            status,
+            serde_json::from_slice(&bytes).unwrap_or(Value::Null),
+        )
+    }
+
+    fn get(uri: &str) -> Request<Body> {
+        Request::get(uri).body(Body::empty()).unwrap()
+    }
+
+    async fn peer_with_blocks(n: usize) -> Peer {
+        let mut chain: Chain = Chain::genesis();
+        for i in 1..=n {
+            chain.mine_block(&format!("block {}", i), &MINER);
+        }
+        let mut peer = set_up_peer_with_chain(chain, PeerConfig::default()).await;
+        peer.set_command_source(tokio::io::empty());
+        peer
+    }
+
+    #[tokio::test]
+    async fn test_height() {
+        let mut peer = peer_with_blocks(2).await;
+        let (status, json) = call(&mut peer, get("/chain/height")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(trace(json)["height"], 2);
+    }
+
+    #[tokio::test]
+    async fn test_block() {
+        let mut peer = peer_with_blocks(2).await;
+        let (status, json) = call(&mut peer, get("/block/1")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(json["hash"], peer.chain().idx(1).unwrap().hash.as_str());
+
+        let (status, _) = call(&mut peer, get("/block/3")).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_metrics() {
+        let mut peer = peer_with_blocks(0).await;
+        let block: Block = Block::mine_block(&Block::genesis(), "block 1", &MINER);
+        peer.handle_pow_event(
+            PowMessage::NewBlock {
+                block,
+                source: "remote".to_string(),
+            },
+            *REMOTE,
+        );
+        let (status, json) = call(&mut peer, get("/metrics")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(trace(json)["blocks_accepted"], 1);
+    }
+
+    #[tokio::test]
+    async fn test_submit_txn() {
+        let mut peer = peer_with_blocks(0).await;
+        let receiver = PeerId::from(identity::Keypair::generate_ed25519().public()).to_string();
+        let txn = Transaction::new(receiver, 10, identity::Keypair::generate_ed25519());
+        let post = |txn: &Transaction| {
+            Request::post("/txn")
+                .header("content-type", "application/json")
+                .body(Body::from(serde_json::to_string(txn).unwrap()))
+                .unwrap()
+        };
+
+        let (status, json) = call(&mut peer, post(&txn)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(json["hash"], txn.hash.as_str());
+        assert!(peer.txns().contains(&txn));
+        // the same transaction can't be added twice
+        let (status, _) = call(&mut peer, post(&txn)).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(peer.txns().len(), 1);
+    }
+}
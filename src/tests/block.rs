@@ -4,14 +4,30 @@
 #[cfg(test)] // cargo test block -- --nocapture
 mod block_tests {
     use crate::{
-        block::{Block, NextBlockErr},
-        crypt::{encode_bytes_to_hex, ZERO_U32},
+        block::{
+            available_threads, leading_zero_bits, meets_difficulty, Block, Blocks, NextBlockErr,
+            DEFAULT_DIFFICULTY, GENESIS_TIMESTAMP, MAX_FUTURE_DRIFT,
+        },
+        crypt::{encode_bytes_to_hex, encode_pubk_to_hex, ZERO_U32},
+        tests::common::MINER,
         util::trace,
     };
+    use chrono::Utc;
+    use libp2p::identity::{self, Keypair};
+    use std::{
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        time::{Duration, Instant},
+    };
+
+    // The hash of the hardcoded genesis block; changing it splits the network
+    const GENESIS_HASH: &str = "057e398b877e1e3875c522e0b725c35d43eedd6df3b3798df147011424a68b60";
 
     #[test]
     fn test_invalid_block_difficulty_check() {
-        let valid_block = Block::mine_block(&Block::genesis(), "test");
+        let valid_block = Block::mine_block(&Block::genesis(), "test", &MINER);
 
         let invalid_difficulty_prefix = Block {
             hash: hex::encode([255; 32]),
@@ -25,8 +41,46 @@ mod block_tests {
         ));
     }
     #[test]
+    fn test_difficulty_counts_leading_zero_bits() {
+        for k in [0, 1, 7, 8, 9, 15, 16, 20, 255] {
+            // a hash whose first k bits are zero, followed by a one
+            let mut bytes: [u8; 32] = [0xff; 32];
+            bytes[..k / 8].fill(0);
+            bytes[k / 8] = 0xff >> (k % 8);
+            assert_eq!(leading_zero_bits(&bytes), k);
+
+            let hash: String = encode_bytes_to_hex(bytes);
+            assert!((0..=k).all(|difficulty| meets_difficulty(&hash, difficulty)));
+            assert!(!meets_difficulty(&hash, k + 1));
+        }
+        assert_eq!(leading_zero_bits(&ZERO_U32), 256);
+
+        // a hash that isn't 32 bytes of hex never meets a difficulty, rather than being misread
+        assert!(!meets_difficulty("00", 0));
+        assert!(!meets_difficulty(&"0g".repeat(32), 0));
+        let malformed = Block {
+            hash: "not a hash".to_string(),
+            ..Block::mine_block(&Block::genesis(), "test", &MINER)
+        };
+        assert!(matches!(
+            trace(malformed.validate()),
+            Err(NextBlockErr::DifficultyCheckFailed { .. })
+        ));
+    }
+    #[test]
+    fn test_mine_block_at_difficulty() {
+        for difficulty in [1, 4] {
+            let genesis = Block::genesis_with(difficulty);
+            let block = Block::mine_block(&genesis, "test", &MINER);
+            assert_eq!(block.difficulty, difficulty);
+            assert!(block.hash.starts_with(&"0".repeat(difficulty / 4)));
+            assert!(trace(block.validate()).is_ok());
+            assert!(block.validate_parent(&genesis).is_ok());
+        }
+    }
+    #[test]
     fn test_invalid_block_hash_mismatch() {
-        let valid_block = Block::mine_block(&Block::genesis(), "test");
+        let valid_block = Block::mine_block(&Block::genesis(), "test", &MINER);
 
         let invalid_hash = Block {
             hash: encode_bytes_to_hex(ZERO_U32),
@@ -40,8 +94,385 @@ mod block_tests {
     }
     #[test]
     fn test_valid_block() {
-        let valid_block = Block::mine_block(&Block::genesis(), "test");
+        let valid_block = Block::mine_block(&Block::genesis(), "test", &MINER);
 
         assert!(matches!(valid_block.validate(), Ok(())));
     }
+    #[test]
+    fn test_genesis_hash_is_stable() {
+        let genesis = Block::genesis();
+        assert_eq!(trace(genesis.hash.as_str()), GENESIS_HASH);
+        assert!(matches!(genesis.validate(), Ok(())));
+    }
+    #[test]
+    fn test_mined_hash_is_stable() {
+        // a block mined on the genesis block by a fixed miner, with the first nonce that meets the default difficulty
+        let secret = identity::ed25519::SecretKey::from_bytes([7u8; 32]).unwrap();
+        let miner = Keypair::Ed25519(secret.into());
+        let miner_pubk: String = encode_pubk_to_hex(miner.public());
+        let (timestamp, nonce) = (GENESIS_TIMESTAMP + 29, 2);
+        let hash: String = Block::compute_hash(
+            1,
+            "sample",
+            timestamp,
+            GENESIS_HASH,
+            DEFAULT_DIFFICULTY,
+            nonce,
+            &miner_pubk,
+        );
+        assert_eq!(
+            trace(hash.as_str()),
+            "08a7d452b3935099b6ad377335615f63e5ab7b48737dcba7ee87b2c7f3d165f5"
+        );
+
+        let sig: Vec<u8> = miner.sign(hash.as_bytes()).unwrap();
+        let block = Block {
+            idx: 1,
+            data: "sample".to_string(),
+            timestamp,
+            prev_hash: GENESIS_HASH.to_string(),
+            difficulty: DEFAULT_DIFFICULTY,
+            nonce,
+            hash,
+            miner_pubk,
+            sig: encode_bytes_to_hex(sig),
+        };
+        assert!(trace(block.validate()).is_ok());
+        assert!(block.validate_parent(&Block::genesis()).is_ok());
+    }
+
+    /* blocks tests */
+    fn init_blocks(n: usize) -> Vec<Block> {
+        let mut blocks: Vec<Block> = vec![Block::genesis()];
+        for i in 1..n {
+            let next = Block::mine_block(blocks.last().unwrap(), &format!("block {}", i), &MINER);
+            blocks.push(next);
+        }
+        blocks
+    }
+    #[test]
+    fn test_push_front_valid() {
+        let blocks: Vec<Block> = init_blocks(4);
+        let mut orphan: Blocks = Blocks::from_vec(vec![blocks[3].clone()]).unwrap();
+
+        assert!(trace(orphan.push_front(blocks[2].clone())).is_ok());
+        assert_eq!(orphan.first().idx, 2);
+        assert!(orphan.validate().is_ok());
+    }
+    #[test]
+    fn test_push_front_wrong_idx() {
+        let blocks: Vec<Block> = init_blocks(4);
+        let mut orphan: Blocks = Blocks::from_vec(vec![blocks[3].clone()]).unwrap();
+
+        // a block relabelled with the wrong idx no longer matches its own hash
+        let relabelled = Block {
+            idx: 1,
+            ..blocks[2].clone()
+        };
+        assert!(matches!(
+            trace(orphan.push_front(relabelled)),
+            Err(NextBlockErr::InconsistentHash { idx: 1, .. })
+        ));
+        // a validly mined block with the wrong idx doesn't connect at the seam
+        assert!(matches!(
+            trace(orphan.push_front(blocks[1].clone())),
+            Err(NextBlockErr::InvalidParent {
+                idx: 3,
+                parent_idx: 1,
+                ..
+            })
+        ));
+        assert_eq!(orphan.len(), 1);
+    }
+    #[test]
+    fn test_validate_connecting() {
+        let blocks: Vec<Block> = init_blocks(5);
+        let segment: Blocks = Blocks::from_vec(blocks[2..].to_vec()).unwrap();
+        assert!(trace(segment.validate_connecting(&blocks[1].hash, 2)).is_ok());
+
+        // a segment whose first block doesn't follow the expected parent
+        assert!(matches!(
+            trace(segment.validate_connecting(&blocks[0].hash, 2)),
+            Err(NextBlockErr::InvalidParent {
+                idx: 2,
+                parent_idx: 1,
+                ..
+            })
+        ));
+        assert!(matches!(
+            segment.validate_connecting(&blocks[1].hash, 3),
+            Err(NextBlockErr::InvalidParent { idx: 2, .. })
+        ));
+        // a segment that follows the parent but has a gap; deserializing skips the validation of `from_vec`
+        let gapped: Blocks =
+            serde_json::from_str(&serde_json::to_string(&[&blocks[2], &blocks[4]]).unwrap())
+                .unwrap();
+        assert!(matches!(
+            trace(gapped.validate_connecting(&blocks[1].hash, 2)),
+            Err(NextBlockErr::InvalidParent { idx: 4, .. })
+        ));
+    }
+    #[test]
+    fn test_append_reports_invalid_position() {
+        let blocks: Vec<Block> = init_blocks(6);
+        let mut prefix: Blocks = Blocks::from_vec(blocks[..2].to_vec()).unwrap();
+
+        // corrupt the third block of the suffix; deserializing skips the validation of `from_vec`
+        let mut suffix: Vec<Block> = blocks[2..].to_vec();
+        suffix[2].data = "corrupt".to_string();
+        let suffix: Blocks =
+            serde_json::from_str(&serde_json::to_string(&suffix).unwrap()).unwrap();
+
+        assert!(matches!(
+            trace(prefix.append(suffix)),
+            Err(NextBlockErr::InvalidSuffix { position: 2, ref e })
+                if matches!(**e, NextBlockErr::InconsistentHash { idx: 4, .. })
+        ));
+        assert_eq!(prefix.len(), 2);
+    }
+    #[test]
+    fn test_validate_genesis() {
+        assert!(trace(Block::genesis().validate()).is_ok());
+        assert!(trace(Block::genesis_with(4).validate()).is_ok());
+
+        // a non-genesis block relabelled as idx 0 is rejected, rather than being exempt from the difficulty check
+        let crafted = Block {
+            idx: 0,
+            ..Block::mine_block(&Block::genesis(), "test", &MINER)
+        };
+        assert!(matches!(
+            trace(crafted.validate()),
+            Err(NextBlockErr::InvalidGenesis { idx: 0, .. })
+        ));
+    }
+    #[test]
+    fn test_mine_block_cancelled() {
+        // an unreachable difficulty, so mining only ends when cancelled
+        let (genesis, difficulty) = (Block::genesis(), 255);
+
+        let cancel = AtomicBool::new(true);
+        assert!(
+            Block::mine_block_cancellable(&genesis, "test", difficulty, &MINER, &cancel).is_none()
+        );
+
+        let cancel = Arc::new(AtomicBool::new(false));
+        let miner = {
+            let cancel = cancel.clone();
+            std::thread::spawn(move || {
+                Block::mine_block_cancellable(&genesis, "test", difficulty, &MINER, &cancel)
+            })
+        };
+        std::thread::sleep(Duration::from_millis(50));
+        cancel.store(true, Ordering::Relaxed);
+        let start = Instant::now();
+        assert!(miner.join().unwrap().is_none());
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_timestamp_regressed() {
+        let genesis: Block = Block::genesis();
+        let block: Block = Block::mine_block(&genesis, "test", &MINER);
+        assert!(block.validate_parent(&genesis).is_ok());
+
+        let regressed = Block {
+            timestamp: genesis.timestamp - 1,
+            ..block
+        };
+        assert!(matches!(
+            trace(regressed.validate_parent(&genesis)),
+            Err(NextBlockErr::TimestampRegressed { .. })
+        ));
+    }
+
+    #[test]
+    fn test_mine_block_parallel() {
+        let parent: Block = Block::mine_block_at(&Block::genesis(), "parent", 8, &MINER);
+        for threads in [0, 1, 4, available_threads()] {
+            let block: Block = Block::mine_block_parallel(&parent, "test", threads, &MINER);
+            assert!(trace(block.validate()).is_ok());
+            assert!(block.validate_parent(&parent).is_ok());
+            assert_eq!(block.difficulty, 8);
+        }
+    }
+
+    #[test]
+    fn test_mine_block_nonce_exhaustion() {
+        let parent: Block = Block::mine_block_at(&Block::genesis(), "parent", 8, &MINER);
+        let before: i64 = Utc::now().timestamp().max(parent.timestamp);
+        let start: u64 = u64::MAX - 2;
+        let block: Block = Block::mine_block_from(&parent, "test", 8, &MINER, start);
+        assert!(trace(block.validate()).is_ok());
+        assert!(block.validate_parent(&parent).is_ok());
+        // unless one of the last few nonces was valid, the search wrapped around to a later timestamp
+        if block.nonce < start {
+            assert!(block.timestamp > before);
+        }
+    }
+
+    #[test]
+    fn test_signed_block() {
+        let block: Block = Block::mine_block(&Block::genesis(), "test", &MINER);
+        assert_eq!(block.miner_pubk, encode_pubk_to_hex(MINER.public()));
+        assert!(trace(block.validate()).is_ok());
+    }
+
+    #[test]
+    fn test_forged_block_signature() {
+        let block: Block = Block::mine_block(&Block::genesis(), "test", &MINER);
+        let forger = Keypair::generate_ed25519();
+        let forged_sig = encode_bytes_to_hex(forger.sign(block.hash.as_bytes()).unwrap());
+
+        // the forger's signature doesn't verify against the miner's key
+        let forged = Block {
+            sig: forged_sig.clone(),
+            ..block.clone()
+        };
+        let unsigned = Block {
+            sig: String::new(),
+            ..block.clone()
+        };
+        for invalid in [forged, unsigned] {
+            assert!(matches!(
+                trace(invalid.validate()),
+                Err(NextBlockErr::InvalidBlockSignature { .. })
+            ));
+        }
+        // nor can the forger claim the block, even re-signing its hash, as the hash covers the miner's key
+        let claimed = Block {
+            miner_pubk: encode_pubk_to_hex(forger.public()),
+            ..block.clone()
+        };
+        let resigned = Block {
+            sig: forged_sig,
+            ..claimed.clone()
+        };
+        for invalid in [claimed, resigned] {
+            assert!(matches!(
+                trace(invalid.validate()),
+                Err(NextBlockErr::InconsistentHash { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn test_timestamp_too_far_ahead() {
+        let block: Block = Block::mine_block(&Block::genesis(), "test", &MINER);
+        assert!(block.validate_timestamp().is_ok());
+        assert!(block
+            .validate_timestamp_at(block.timestamp - MAX_FUTURE_DRIFT)
+            .is_ok());
+        assert!(matches!(
+            trace(block.validate_timestamp_at(block.timestamp - MAX_FUTURE_DRIFT - 1)),
+            Err(NextBlockErr::TimestampTooFarAhead { .. })
+        ));
+    }
+
+    // Names every variant without a wildcard, so that adding a variant fails to compile until it is listed here
+    fn variant_name(e: &NextBlockErr) -> &'static str {
+        match e {
+            NextBlockErr::DifficultyCheckFailed { .. } => "DifficultyCheckFailed",
+            NextBlockErr::DifficultyBelowMinimum { .. } => "DifficultyBelowMinimum",
+            NextBlockErr::InconsistentHash { .. } => "InconsistentHash",
+            NextBlockErr::InvalidGenesis { .. } => "InvalidGenesis",
+            NextBlockErr::InvalidParent { .. } => "InvalidParent",
+            NextBlockErr::MissingParent { .. } => "MissingParent",
+            NextBlockErr::StrayOrphan { .. } => "StrayOrphan",
+            NextBlockErr::Duplicate { .. } => "Duplicate",
+            NextBlockErr::NoBlocks => "NoBlocks",
+            NextBlockErr::InvalidSuffix { .. } => "InvalidSuffix",
+            NextBlockErr::TimestampRegressed { .. } => "TimestampRegressed",
+            NextBlockErr::TimestampTooFarAhead { .. } => "TimestampTooFarAhead",
+            NextBlockErr::UnrelatedGenesis { .. } => "UnrelatedGenesis",
+            NextBlockErr::InvalidRollback { .. } => "InvalidRollback",
+            NextBlockErr::InvalidBlockSignature { .. } => "InvalidBlockSignature",
+            NextBlockErr::ReorgBelowCheckpoint { .. } => "ReorgBelowCheckpoint",
+            NextBlockErr::OrphanDropped { .. } => "OrphanDropped",
+            NextBlockErr::InvalidCoinbase { .. } => "InvalidCoinbase",
+        }
+    }
+
+    #[test]
+    fn test_next_block_err_variants() {
+        let hash = || encode_bytes_to_hex(ZERO_U32);
+        let errs: Vec<NextBlockErr> = vec![
+            NextBlockErr::DifficultyCheckFailed {
+                idx: 1,
+                hash: hash(),
+                difficulty: 1,
+            },
+            NextBlockErr::DifficultyBelowMinimum {
+                idx: 1,
+                difficulty: 1,
+                minimum: 2,
+            },
+            NextBlockErr::InconsistentHash {
+                idx: 1,
+                hash: hash(),
+                computed_hash: hash(),
+            },
+            NextBlockErr::InvalidGenesis {
+                idx: 0,
+                hash: hash(),
+            },
+            NextBlockErr::InvalidParent {
+                idx: 2,
+                prev_hash: hash(),
+                parent_idx: 1,
+                parent_hash: hash(),
+            },
+            NextBlockErr::MissingParent {
+                parent_idx: 1,
+                parent_hash: hash(),
+            },
+            NextBlockErr::StrayOrphan {
+                idx: 1,
+                hash: hash(),
+            },
+            NextBlockErr::Duplicate {
+                idx: 1,
+                hash: hash(),
+            },
+            NextBlockErr::NoBlocks,
+            NextBlockErr::InvalidSuffix {
+                position: 0,
+                e: Box::new(NextBlockErr::NoBlocks),
+            },
+            NextBlockErr::TimestampRegressed {
+                idx: 1,
+                timestamp: 0,
+                parent_timestamp: 1,
+            },
+            NextBlockErr::TimestampTooFarAhead {
+                idx: 1,
+                timestamp: 1,
+                now: 0,
+            },
+            NextBlockErr::UnrelatedGenesis {
+                genesis_hash: hash(),
+            },
+            NextBlockErr::InvalidRollback { n: 1, len: 1 },
+            NextBlockErr::InvalidBlockSignature {
+                idx: 1,
+                hash: hash(),
+                reason: "forged".to_string(),
+            },
+            NextBlockErr::ReorgBelowCheckpoint {
+                fork_idx: 1,
+                checkpoint_idx: 2,
+            },
+            NextBlockErr::OrphanDropped {
+                idx: 1,
+                hash: hash(),
+            },
+            NextBlockErr::InvalidCoinbase {
+                idx: 1,
+                hash: hash(),
+                reason: "forged".to_string(),
+            },
+        ];
+        let names: std::collections::HashSet<&str> = errs.iter().map(variant_name).collect();
+        assert_eq!(trace(names).len(), errs.len());
+        assert!(errs.iter().all(|e| !e.to_string().is_empty()));
+    }
 }
@@ -4,11 +4,16 @@
 #[cfg(test)] // cargo test chain -- --nocapture
 mod chain_tests {
     use crate::{
-        block::{Block, Blocks, NextBlockErr, NextBlockResult},
-        chain::{Chain, ChainStatus},
-        fork::Forks,
+        block::{
+            verify_headers, Block, BlockHeaderSummary, Blocks, NextBlockErr, NextBlockResult,
+            DEFAULT_DIFFICULTY,
+        },
+        chain::{Chain, ChainSnapshot, ChainStatus, GenesisConfig, HeadInfo},
+        tests::common::MINER,
+        transaction::Transaction,
         util::trace,
     };
+    use libp2p::identity::{self, Keypair};
 
     const CHAIN_LEN: usize = 5;
     const FORK_PREFIX_LEN: usize = 3;
@@ -16,7 +21,7 @@ mod chain_tests {
     fn init_chain(n: usize) -> Chain {
         let mut chain: Chain = Chain::genesis();
         for i in 1..n {
-            chain.mine_block(&format!("block {}", i));
+            chain.mine_block(&format!("block {}", i), &MINER);
         }
         chain
     }
@@ -24,10 +29,231 @@ mod chain_tests {
     /*****************************
      * Tests for valid chains    *
      *****************************/
+    #[test]
+    fn test_forged_coinbase() {
+        let mut chain: Chain = Chain::genesis();
+        chain.set_block_reward(50);
+        let txns: String = Block::transactions_data(&[]);
+
+        // a coinbase claiming more than the reward
+        let mut remote: Chain = chain.clone();
+        remote.mine_block_with_reward(&txns, &MINER, 500);
+        assert!(matches!(
+            trace(chain.store_new_block(remote.last().clone())),
+            Err(NextBlockErr::InvalidCoinbase { idx: 1, .. })
+        ));
+        assert!(matches!(
+            chain.choose_chain(remote),
+            Err(NextBlockErr::InvalidCoinbase { idx: 1, .. })
+        ));
+        // a coinbase rewarding someone other than the block's miner
+        let thief = Keypair::generate_ed25519();
+        let data: String = chain.coinbase_data(&txns, &thief.public(), 50);
+        let stolen: Block = Block::mine_block(chain.last(), &data, &MINER);
+        assert!(matches!(
+            trace(chain.store_new_block(stolen)),
+            Err(NextBlockErr::InvalidCoinbase { .. })
+        ));
+        // a coinbase copied from an earlier block
+        let mut remote: Chain = chain.clone();
+        remote.mine_block_with_reward(&txns, &MINER, 50);
+        let copied: Block = Block::mine_block(remote.last(), &remote.last().data, &MINER);
+        assert!(matches!(
+            trace(chain.store_new_block(copied)),
+            Err(NextBlockErr::InvalidCoinbase { .. })
+        ));
+        // a coinbase that isn't placed first
+        let txn = Transaction::random_transaction(1, Keypair::generate_ed25519());
+        let mut misplaced: Vec<Transaction> = remote.last().transactions();
+        misplaced.insert(0, txn);
+        let misplaced: Block =
+            Block::mine_block(chain.last(), &Block::transactions_data(&misplaced), &MINER);
+        assert!(matches!(
+            trace(chain.store_new_block(misplaced)),
+            Err(NextBlockErr::InvalidCoinbase { .. })
+        ));
+
+        assert!(chain.store_new_block(remote.last().clone()).is_ok());
+        assert_eq!(chain.last().hash, remote.last().hash);
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let mut chain: Chain = init_chain(3);
+        let snapshot: ChainSnapshot = chain.snapshot();
+        let tip_hash: String = chain.last().hash.clone();
+
+        chain.mine_block("after the snapshot", &MINER);
+        // the snapshot keeps the old height, while the live chain advances
+        assert_eq!(trace(snapshot.head()).height, 2);
+        assert_eq!(chain.head().height, 3);
+        assert_eq!(snapshot.last().hash, tip_hash);
+        assert!(snapshot.idx(3).is_none());
+        assert!(snapshot.get_by_hash(&chain.last().hash).is_none());
+        assert_eq!(snapshot.get_by_hash(&tip_hash).map(|b| b.idx), Some(2));
+        assert_eq!(snapshot.range(1, 2).unwrap().len(), 2);
+        assert!(snapshot.range(1, 3).is_none());
+        assert_eq!(chain.snapshot().head(), &chain.head());
+
+        // it can be read from another thread
+        let height = std::thread::spawn(move || snapshot.head().height)
+            .join()
+            .unwrap();
+        assert_eq!(height, 2);
+    }
+
+    #[test]
+    fn test_orphan_forkpoints() {
+        let mut chain: Chain = Chain::genesis();
+        let remote: Chain = init_chain(4);
+        let orphan: Block = remote.last().clone();
+        assert!(matches!(
+            trace(chain.store_new_block(orphan.clone())),
+            Err(NextBlockErr::MissingParent { .. })
+        ));
+
+        // the orphan's missing parent is surfaced, so that it can be re-requested
+        assert_eq!(
+            chain.orphans().forkpoints(),
+            vec![(orphan.idx - 1, orphan.prev_hash.clone())]
+        );
+        assert!(chain.remove_orphan(&orphan.prev_hash).is_some());
+        assert!(chain.orphans().forkpoints().is_empty());
+    }
+
+    #[test]
+    fn test_link_orphans() {
+        let local: Chain = init_chain(2);
+        let mut remote: Chain = local.clone();
+        for i in 2..7 {
+            remote.mine_block(&format!("remote block {}", i), &MINER);
+        }
+        let block = |idx: usize| remote.idx(idx).unwrap().clone();
+
+        // orphans: [3]---[4]   [5]---[6], received out of order as four separate branches
+        let mut chain: Chain = local.clone();
+        for idx in [5, 6, 3, 4] {
+            assert!(chain.store_new_block(block(idx)).is_err());
+        }
+        assert_eq!(chain.orphans().len(), 4);
+        // they link into a single branch, which doesn't reach the main chain yet
+        assert!(chain.link_orphans().is_empty());
+        assert_eq!(trace(chain.orphans().len()), 1);
+        assert_eq!(chain.orphans().get(&block(2).hash).unwrap().len(), 4);
+
+        // the missing parent arrives as a response, completing the branch as a fork
+        let mut responded: Chain = chain.clone();
+        assert!(responded.store_orphan_block(block(2)).is_ok());
+        assert!(responded.choose_fork().is_ok());
+        assert_eq!(responded.last().hash, remote.last().hash);
+
+        // the missing parent arrives as a new block, extending the main chain that the branch then forks from
+        assert!(chain.store_new_block(block(2)).is_ok());
+        let forks = chain.link_orphans();
+        assert_eq!(forks.len(), 1);
+        assert!(chain.orphans().is_empty());
+        assert!(chain.choose_fork().is_ok());
+        assert_eq!(chain.last().hash, remote.last().hash);
+    }
+
+    #[test]
+    fn test_orphan_branch_cap() {
+        let mut chain: Chain = Chain::genesis();
+        chain.set_orphan_limits(2, 10);
+        let remote: Chain = init_chain(5);
+        // every block from idx 2 is disconnected from the local chain and each other, so forms its own branch
+        for idx in 2..5 {
+            assert!(matches!(
+                trace(chain.store_new_block(remote.idx(idx).unwrap().clone())),
+                Err(NextBlockErr::MissingParent { .. })
+            ));
+            assert!(chain.orphans().len() <= 2);
+        }
+        // the oldest branch was evicted to make room for the newest
+        let forkpoint = |idx: usize| remote.idx(idx).unwrap().hash.clone();
+        assert!(chain.orphans().get(&forkpoint(1)).is_none());
+        assert!(chain.orphans().get(&forkpoint(2)).is_some());
+        assert!(chain.orphans().get(&forkpoint(3)).is_some());
+    }
+
+    #[test]
+    fn test_orphan_block_cap() {
+        let mut chain: Chain = Chain::genesis();
+        chain.set_orphan_limits(10, 3);
+        let remote: Chain = init_chain(8);
+        let block = |idx: usize| remote.idx(idx).unwrap().clone();
+
+        // orphans: [6]---[7]
+        assert!(chain.store_new_block(block(7)).is_err());
+        assert!(matches!(
+            trace(chain.store_orphan_block(block(6))),
+            Err(NextBlockErr::MissingParent { parent_idx: 5, .. })
+        ));
+        // orphans: [6]---[7], [4], [3], so the oldest branch is evicted to keep to 3 blocks
+        assert!(chain.store_new_block(block(4)).is_err());
+        assert!(chain.store_new_block(block(3)).is_err());
+        assert_eq!(chain.orphans().num_blocks(), 2);
+        assert!(chain.orphans().get(&block(5).hash).is_none());
+
+        // a branch that alone is too long for the pool is dropped with the block that extended it
+        chain.set_orphan_limits(10, 1);
+        assert_eq!(chain.orphans().num_blocks(), 1);
+        assert!(matches!(
+            trace(chain.store_orphan_block(block(2))),
+            Err(NextBlockErr::OrphanDropped { idx: 2, .. })
+        ));
+        assert!(chain.orphans().num_blocks() <= 1);
+    }
+
+    #[test]
+    fn test_iter() {
+        let chain: Chain = init_chain(CHAIN_LEN);
+        let idxs: Vec<usize> = chain.iter().map(|block| block.idx).collect();
+        assert_eq!(trace(idxs), (0..CHAIN_LEN).collect::<Vec<usize>>());
+
+        let mut hashes: Vec<&str> = vec![];
+        for block in &chain {
+            hashes.push(&block.hash);
+        }
+        assert_eq!(hashes.len(), CHAIN_LEN);
+        assert_eq!(hashes.last().copied(), Some(chain.last().hash.as_str()));
+        assert!(chain.iter().eq(chain.clone().to_vec().iter()));
+    }
+
+    #[test]
+    fn test_contains_hash() {
+        let mut chain: Chain = init_chain(CHAIN_LEN);
+        // the last two blocks move to a fork, and a block whose parent is unknown is orphaned
+        chain.rollback(2).unwrap();
+        let fork_hash: String = chain.forks().summaries()[0].endpoint.clone();
+        let unrelated: Block = Block::mine_block(&Block::genesis(), "unrelated", &MINER);
+        let orphan: Block = Block::mine_block(&unrelated, "orphan", &MINER);
+        assert!(chain.store_new_block(orphan.clone()).is_err());
+
+        let main_hash: String = chain.last().hash.clone();
+        assert!(trace(chain.blocks().contains_hash(&main_hash)));
+        assert!(!chain.forks().contains_hash(&main_hash));
+        assert!(!chain.orphans().contains_hash(&main_hash));
+
+        // a hash only in a fork
+        assert!(!chain.blocks().contains_hash(&fork_hash));
+        assert!(chain.forks().contains_hash(&fork_hash));
+        assert!(!chain.orphans().contains_hash(&fork_hash));
+
+        assert!(chain.orphans().contains_hash(&orphan.hash));
+        assert!(!chain.forks().contains_hash(&orphan.hash));
+
+        for absent in ["missing", ""] {
+            assert!(!chain.blocks().contains_hash(absent));
+            assert!(!chain.forks().contains_hash(absent));
+            assert!(!chain.orphans().contains_hash(absent));
+        }
+    }
+
     #[test]
     fn test_validate_chain() {
         let chain: Chain = init_chain(CHAIN_LEN);
-        assert!(matches!(trace(Chain::from_vec(chain.to_vec())), Ok(_)));
+        assert!(trace(Chain::from_vec(chain.to_vec())).is_ok());
     }
     #[test]
     fn test_validate_chain_empty() {
@@ -48,13 +274,137 @@ mod chain_tests {
             Err(NextBlockErr::InvalidGenesis { idx: 3, .. })
         ));
     }
+    #[test]
+    fn test_from_vec_lossy() {
+        let chain: Chain = init_chain(CHAIN_LEN);
+        let (salvaged, dropped) = Chain::from_vec_lossy(chain.clone().to_vec()).unwrap();
+        assert_eq!((salvaged.len(), dropped), (CHAIN_LEN, 0));
+
+        // everything from the first corrupt block onwards is dropped, even if later blocks are valid
+        let mut blocks: Vec<Block> = chain.clone().to_vec();
+        blocks[3].data = "tampered".to_string();
+        let (salvaged, dropped) = trace(Chain::from_vec_lossy(blocks)).unwrap();
+        assert_eq!((salvaged.len(), dropped), (3, 2));
+        assert_eq!(salvaged.last().hash, chain.idx(2).unwrap().hash);
+
+        // nothing is salvaged without a valid genesis block
+        let blocks: Vec<Block> = chain.to_vec().split_off(1);
+        assert!(matches!(
+            trace(Chain::from_vec_lossy(blocks)),
+            Err(NextBlockErr::InvalidGenesis { idx: 1, .. })
+        ));
+        assert!(matches!(
+            Chain::from_vec_lossy(vec![]),
+            Err(NextBlockErr::NoBlocks)
+        ));
+    }
+    #[test]
+    fn test_mine_block_corrupted_tip() {
+        // Deserializing bypasses validation, modelling a chain loaded without being checked
+        let mut json: serde_json::Value = serde_json::to_value(init_chain(CHAIN_LEN)).unwrap();
+        json["main"][CHAIN_LEN - 1]["data"] = serde_json::json!("tampered");
+        let mut chain: Chain = serde_json::from_value(json).unwrap();
+
+        assert!(matches!(
+            trace(chain.try_mine_block("next block", &MINER)),
+            Err(NextBlockErr::InconsistentHash { idx: 4, .. })
+        ));
+        assert_eq!(chain.len(), CHAIN_LEN);
+    }
+    /*****************************
+     * Tests for traversing chains *
+     *****************************/
+    #[test]
+    fn test_walk_back() {
+        let chain: Chain = init_chain(CHAIN_LEN);
+
+        // from the tip: [0]---[1]---[*2*]---[*3*]---[4]
+        let ancestors: Vec<usize> = chain
+            .walk_back(&chain.last().hash, 2)
+            .iter()
+            .map(|b| b.idx)
+            .collect();
+        assert_eq!(trace(ancestors), vec![3, 2]);
+
+        // from a mid-chain block: [*0*]---[*1*]---[2]---[3]---[4]
+        let mid_hash: String = chain.idx(2).unwrap().hash.clone();
+        let ancestors: Vec<usize> = chain
+            .walk_back(&mid_hash, 2)
+            .iter()
+            .map(|b| b.idx)
+            .collect();
+        assert_eq!(trace(ancestors), vec![1, 0]);
+    }
+    #[test]
+    fn test_walk_back_exceeds_available() {
+        let chain: Chain = init_chain(CHAIN_LEN);
+        let ancestors: Vec<usize> = chain
+            .walk_back(&chain.last().hash, CHAIN_LEN + 10)
+            .iter()
+            .map(|b| b.idx)
+            .collect();
+        assert_eq!(trace(ancestors), vec![3, 2, 1, 0]);
+        assert!(chain.walk_back("unknown hash", 3).is_empty());
+    }
+    /*****************************
+     * Tests for indexing transactions *
+     *****************************/
+    fn txn_data(amount: u64) -> (String, String) {
+        let txn = Transaction::random_transaction(amount, identity::Keypair::generate_ed25519());
+        (txn.hash.clone(), serde_json::to_string(&txn).unwrap())
+    }
+    #[test]
+    fn test_find_txn() {
+        let mut chain: Chain = init_chain(CHAIN_LEN);
+        let (txn_hash, data) = txn_data(1);
+        chain.mine_block(&data, &MINER);
+        chain.mine_block("block 6", &MINER);
+
+        assert_eq!(trace(chain.find_txn(&txn_hash)).map(|b| b.idx), Some(5));
+        assert!(chain.find_txn("unknown hash").is_none());
+
+        // the index is rebuilt when loading a chain
+        let loaded: Chain = Chain::from_vec(chain.to_vec()).unwrap();
+        assert_eq!(loaded.find_txn(&txn_hash).map(|b| b.idx), Some(5));
+    }
+    #[test]
+    fn test_find_txn_after_reorg() {
+        // chain: [0]---[1]---[2]---[3]---[4*txn_main*]
+        // fork:               |----[3]---[4]---[5*txn_fork*]---[6]
+        let mut main_chain: Chain = init_chain(CHAIN_LEN - 1);
+        let (main_txn_hash, main_data) = txn_data(1);
+        main_chain.mine_block(&main_data, &MINER);
+
+        let (fork_txn_hash, fork_data) = txn_data(2);
+        let fork: Blocks = {
+            let mut forked_chain = main_chain.clone();
+            forked_chain.split_off(FORK_PREFIX_LEN);
+            forked_chain.mine_block("block 0 in fork", &MINER);
+            forked_chain.mine_block("block 1 in fork", &MINER);
+            forked_chain.mine_block(&fork_data, &MINER);
+            forked_chain.mine_block("block 3 in fork", &MINER);
+            forked_chain.split_off(FORK_PREFIX_LEN).unwrap()
+        };
+        assert_eq!(main_chain.find_txn(&main_txn_hash).map(|b| b.idx), Some(4));
+
+        main_chain.store_new_fork(fork).unwrap();
+        assert!(matches!(
+            trace(main_chain.choose_fork()),
+            Ok(ChainStatus::ChooseOther { .. })
+        ));
+        assert!(main_chain.find_txn(&main_txn_hash).is_none());
+        assert_eq!(
+            trace(main_chain.find_txn(&fork_txn_hash)).map(|b| b.idx),
+            Some(5)
+        );
+    }
     /*****************************
      * Tests for handling new blocks *
      *****************************/
     #[test]
     fn test_store_new_block_next() {
         let mut chain: Chain = init_chain(CHAIN_LEN);
-        let next_block: Block = Block::mine_block(chain.last(), "next valid block");
+        let next_block: Block = Block::mine_block(chain.last(), "next valid block", &MINER);
 
         // chain: [0]---[1]---[2]---[3]---[4]----[*5*]
         assert!(matches!(
@@ -63,16 +413,43 @@ mod chain_tests {
         ));
     }
 
+    #[test]
+    fn test_store_new_block_below_minimum_difficulty() {
+        let mut chain: Chain = init_chain(CHAIN_LEN);
+        let next_block: Block = Block::mine_block_at(chain.last(), "easier block", 1, &MINER);
+
+        assert!(matches!(
+            trace(chain.store_new_block(next_block)),
+            Err(NextBlockErr::DifficultyBelowMinimum {
+                difficulty: 1,
+                minimum: 2,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_store_new_block_above_minimum_difficulty() {
+        let mut chain: Chain = init_chain(CHAIN_LEN);
+        let next_block: Block = Block::mine_block_at(chain.last(), "harder block", 4, &MINER);
+
+        assert!(matches!(
+            trace(chain.store_new_block(next_block)),
+            Ok(NextBlockResult::ExtendedMain { .. })
+        ));
+        assert!(chain.validate().is_ok());
+    }
+
     #[test]
     fn test_store_new_block_missing_parent() {
         let mut chain: Chain = Chain::genesis();
         for i in 1..CHAIN_LEN {
-            chain.mine_block(&format!("block {}", i));
+            chain.mine_block(&format!("block {}", i), &MINER);
         }
         // handle a block from an up-to-date chain that is at a height 2 more than the current chain
         let mut dup_chain: Chain = chain.clone();
-        dup_chain.mine_block("next block in dup chain");
-        dup_chain.mine_block("next block in dup chain");
+        dup_chain.mine_block("next block in dup chain", &MINER);
+        dup_chain.mine_block("next block in dup chain", &MINER);
         // chain:      [0]---[1]---[2]---[3]---[4]---[?]---[*6*]
         assert!(matches!(
             trace(chain.store_new_block(dup_chain.last().clone())),
@@ -84,7 +461,7 @@ mod chain_tests {
     fn test_store_new_block_duplicate() {
         let mut chain: Chain = Chain::genesis();
         for i in 1..CHAIN_LEN {
-            chain.mine_block(&format!("block {}", i))
+            chain.mine_block(&format!("block {}", i), &MINER)
         }
         // handle an old block from the current chain that is one block older than the tip
         let out_of_date_block: Block = chain.idx(chain.last().idx - 1).unwrap().clone();
@@ -96,6 +473,33 @@ mod chain_tests {
         ));
     }
 
+    #[test]
+    fn test_check_block() {
+        let chain: Chain = init_chain(CHAIN_LEN);
+        // a block is checked as it would be stored, without being stored
+        let next_block: Block = Block::mine_block(chain.last(), "next block", &MINER);
+        assert!(matches!(
+            trace(chain.check_block(&next_block)),
+            Ok(Some(parent)) if parent.hash == chain.last().hash
+        ));
+        assert_eq!(chain.len(), CHAIN_LEN);
+        let easier_block: Block = Block::mine_block_at(chain.last(), "easier block", 1, &MINER);
+        assert!(matches!(
+            chain.check_block(&easier_block),
+            Err(NextBlockErr::DifficultyBelowMinimum { .. })
+        ));
+        assert!(matches!(
+            chain.check_block(chain.last()),
+            Err(NextBlockErr::Duplicate { .. })
+        ));
+        // a block without a known parent would be an orphan
+        let mut dup_chain: Chain = chain.clone();
+        dup_chain.mine_block("next block in dup chain", &MINER);
+        dup_chain.mine_block("next block in dup chain", &MINER);
+        assert!(matches!(chain.check_block(dup_chain.last()), Ok(None)));
+        assert!(chain.orphans().is_empty());
+    }
+
     #[test]
     fn test_store_new_block_fork() {
         let mut main_chain: Chain = init_chain(CHAIN_LEN);
@@ -109,7 +513,7 @@ mod chain_tests {
             // Adding new forks
             // chain: [0]---[1]---[2]---[3]---[4]
             // fork:               |----[*3*]
-            forked_chain.mine_block(&format!("block {} in fork", 0));
+            forked_chain.mine_block(&format!("block {} in fork", 0), &MINER);
             println!("Forked chain {}", forked_chain);
             let res = main_chain.store_new_block(forked_chain.last().clone());
             assert!(matches!(
@@ -127,7 +531,7 @@ mod chain_tests {
             // chain: [0]---[1]---[2]---[3]---[4]
             // fork:               |----[3]---[*4*]---[*5*]
             for i in 1..3 {
-                forked_chain.mine_block(&format!("block {} in fork", i));
+                forked_chain.mine_block(&format!("block {} in fork", i), &MINER);
                 assert!(matches!(
                     trace(main_chain.store_new_block(forked_chain.last().clone())),
                     Ok(NextBlockResult::ExtendedFork { fork_idx: 2, .. })
@@ -147,7 +551,7 @@ mod chain_tests {
             // chain: [0]---[1]---[2]---[3]---[4]
             // fork:               |----[3]---[4]---[5]
             // nested fork:                    |----[*5*]
-            nested_forked_chain.mine_block(&format!("block {} in nested fork", 0));
+            nested_forked_chain.mine_block(&format!("block {} in nested fork", 0), &MINER);
             println!("Nested forked chain {}", nested_forked_chain);
             assert!(matches!(
                 trace(main_chain.store_new_block(nested_forked_chain.last().clone())),
@@ -165,7 +569,7 @@ mod chain_tests {
             // fork:               |----[3]---[4]---[5]
             // nested fork:                    |----[5]---[6]---[7]
             for i in 1..3 {
-                nested_forked_chain.mine_block(&format!("block {} in nested fork", i));
+                nested_forked_chain.mine_block(&format!("block {} in nested fork", i), &MINER);
                 assert!(matches!(
                     trace(main_chain.store_new_block(nested_forked_chain.last().clone())),
                     Ok(NextBlockResult::ExtendedFork { fork_idx: 2, .. })
@@ -187,7 +591,7 @@ mod chain_tests {
             f
         };
         for i in 0..(CHAIN_LEN - FORK_PREFIX_LEN) {
-            forked_chain.mine_block(&format!("block {} in fork", i))
+            forked_chain.mine_block(&format!("block {} in fork", i), &MINER)
         }
         assert!(matches!(
             trace(main_chain.store_new_block(forked_chain.last().clone())),
@@ -195,6 +599,44 @@ mod chain_tests {
         ));
     }
 
+    #[test]
+    fn test_store_new_block_tie_break() {
+        // two competing blocks mined on the same tip, arriving at two peers in opposite orders
+        // chain: [0]---[1]---[2]---[3]---[4]---[*5a*]
+        //                                 |----[*5b*]
+        let chain: Chain = init_chain(CHAIN_LEN);
+        let mine_competing = |data: &str| {
+            let mut c = chain.clone();
+            c.mine_block(data, &MINER);
+            c.last().clone()
+        };
+        let (block_a, block_b) = (mine_competing("block a"), mine_competing("block b"));
+        let (winner, loser) = if block_a.beats(&block_b) {
+            (block_a.clone(), block_b.clone())
+        } else {
+            (block_b.clone(), block_a.clone())
+        };
+        assert!(!loser.beats(&winner));
+
+        let mut peer_1: Chain = chain.clone();
+        peer_1.store_new_block(block_a.clone()).unwrap();
+        peer_1.store_new_block(block_b.clone()).unwrap();
+        let mut peer_2: Chain = chain.clone();
+        peer_2.store_new_block(block_b).unwrap();
+        peer_2.store_new_block(block_a).unwrap();
+
+        for chain in [&mut peer_1, &mut peer_2] {
+            assert!(matches!(
+                trace(chain.choose_fork()),
+                Ok(ChainStatus::KeepMain { .. })
+            ));
+            assert_eq!(chain.last().hash, winner.hash);
+            assert!(chain.main_by_hash(&loser.hash).is_none());
+            assert!(chain.forks().get(&loser.prev_hash, &loser.hash).is_some());
+        }
+        assert!(peer_1.validate().is_ok() && peer_2.validate().is_ok());
+    }
+
     // /*****************************
     //  * Tests for merging forks *
     // *****************************/
@@ -208,7 +650,7 @@ mod chain_tests {
             let mut forked_chain = main_chain.clone();
             forked_chain.split_off(FORK_PREFIX_LEN);
             for i in 0..(CHAIN_LEN - FORK_PREFIX_LEN) + 2 {
-                forked_chain.mine_block(&format!("block {} in fork", i));
+                forked_chain.mine_block(&format!("block {} in fork", i), &MINER);
             }
             // strip the common prefix between the current and forked chain
             forked_chain.split_off(FORK_PREFIX_LEN).unwrap()
@@ -225,7 +667,7 @@ mod chain_tests {
             let mut forked_chain = main_chain.clone();
             forked_chain.split_off(FORK_PREFIX_LEN);
             for i in 0..(CHAIN_LEN - FORK_PREFIX_LEN) + 2 {
-                forked_chain.mine_block(&format!("block {} in fork", i));
+                forked_chain.mine_block(&format!("block {} in fork", i), &MINER);
             }
             forked_chain.split_off(FORK_PREFIX_LEN + 1).unwrap()
         };
@@ -235,6 +677,29 @@ mod chain_tests {
         ));
     }
 
+    #[test]
+    fn test_common_ancestor() {
+        // chain: [0]---[1]---[2]---[3]---[4]
+        // fork:               |----[3]---[4]---[5]
+        let main_chain: Chain = init_chain(CHAIN_LEN);
+        let mut forked_chain = main_chain.clone();
+        forked_chain.split_off(FORK_PREFIX_LEN);
+        for i in 0..3 {
+            forked_chain.mine_block(&format!("block {} in fork", i), &MINER);
+        }
+        let fork: Blocks = forked_chain.clone().split_off(FORK_PREFIX_LEN).unwrap();
+        let ancestor: &Block = main_chain.common_ancestor(&fork).unwrap();
+        assert_eq!(
+            (trace(ancestor).idx, &ancestor.hash),
+            (2, &main_chain.idx(2).unwrap().hash)
+        );
+
+        // a fork whose forkpoint isn't on the main chain doesn't attach
+        // fork:               |----[?]---[4]---[5]
+        let detached: Blocks = forked_chain.split_off(FORK_PREFIX_LEN + 1).unwrap();
+        assert!(main_chain.common_ancestor(&detached).is_none());
+    }
+
     #[test]
     fn test_sync_to_fork_longer() {
         let mut main_chain: Chain = init_chain(CHAIN_LEN);
@@ -247,7 +712,7 @@ mod chain_tests {
             let mut forked_chain = main_chain.clone();
             forked_chain.split_off(FORK_PREFIX_LEN);
             for i in 0..(CHAIN_LEN - FORK_PREFIX_LEN) + 2 {
-                forked_chain.mine_block(&format!("block {} in fork", i));
+                forked_chain.mine_block(&format!("block {} in fork", i), &MINER);
             }
             forked_chain.split_off(FORK_PREFIX_LEN).unwrap()
         };
@@ -256,8 +721,8 @@ mod chain_tests {
         // Assert initial state of chain and its stored forks
         let forks = main_chain.forks();
         assert!(matches!(trace(main_chain.len()), 5));
-        assert!(matches!(trace(forks.get(&forkpoint, &endpoint)), None));
-        assert!(matches!(trace(forks.get(&forkpoint, &main_endpoint)), None));
+        assert!(trace(forks.get(&forkpoint, &endpoint)).is_none());
+        assert!(trace(forks.get(&forkpoint, &main_endpoint)).is_none());
         println!("Chain: {}\n\nFork: {:?}\n", main_chain, fork);
 
         // Then synchronise:
@@ -272,7 +737,8 @@ mod chain_tests {
             trace(res),
             Ok(ChainStatus::ChooseOther {
                 main_len: 5,
-                other_len: 7
+                other_len: 7,
+                ..
             })
         ));
         println!("Merged chain and fork : {}", main_chain);
@@ -284,7 +750,7 @@ mod chain_tests {
             trace(forks.get(&forkpoint, &main_endpoint)),
             Some(..)
         ));
-        assert!(matches!(trace(forks.get(&forkpoint, &endpoint)), None));
+        assert!(trace(forks.get(&forkpoint, &endpoint)).is_none());
     }
 
     #[test]
@@ -299,7 +765,7 @@ mod chain_tests {
             let mut forked_chain = main_chain.clone();
             forked_chain.split_off(FORK_PREFIX_LEN);
             for i in 0..(CHAIN_LEN - FORK_PREFIX_LEN) - 1 {
-                forked_chain.mine_block(&format!("block {} in fork", i));
+                forked_chain.mine_block(&format!("block {} in fork", i), &MINER);
             }
             forked_chain.split_off(FORK_PREFIX_LEN).unwrap()
         };
@@ -308,8 +774,8 @@ mod chain_tests {
         // Assert initial state of chain and its stored forks
         let forks = main_chain.forks();
         assert!(matches!(trace(main_chain.len()), 5));
-        assert!(matches!(trace(forks.get(&forkpoint, &endpoint)), None));
-        assert!(matches!(trace(forks.get(&forkpoint, &main_endpoint)), None));
+        assert!(trace(forks.get(&forkpoint, &endpoint)).is_none());
+        assert!(trace(forks.get(&forkpoint, &main_endpoint)).is_none());
 
         println!("Chain: {}\n\nFork: {:?}\n", main_chain, fork);
 
@@ -324,7 +790,8 @@ mod chain_tests {
             trace(res),
             Ok(ChainStatus::KeepMain {
                 main_len: 5,
-                other_len: Some(4)
+                other: Some((4, _)),
+                ..
             })
         ));
         println!("Merged chain and fork : {}", main_chain);
@@ -332,18 +799,17 @@ mod chain_tests {
         // Assert final state of the chain and its stored forks
         let forks = main_chain.forks();
         assert!(matches!(trace(main_chain.len()), 5));
-        assert!(matches!(trace(forks.get(&forkpoint, &main_endpoint)), None));
+        assert!(trace(forks.get(&forkpoint, &main_endpoint)).is_none());
         assert!(matches!(trace(forks.get(&forkpoint, &endpoint)), Some(..)));
     }
 
     // /*****************************
     //  * Tests for automating the merging of forks *
     // *****************************/
-
     // fn test_sync_main(){
     //     let mut chain: Chain = Chain::genesis();
     //     for i in 1..CHAIN_LEN {
-    //         chain.mine_block(&format!("block {}", i));
+    //         chain.mine_block(&format!("block {}", i), &MINER);
     //     }
 
     //     let mut forked_chain = {
@@ -354,9 +820,9 @@ mod chain_tests {
     //     {
     //         // chain: [0]---[1]---[2]---[3]---[4]
     //         // fork:               |----[*3*]---[*4*]
-    //         forked_chain.mine_block("block 0 in fork");
+    //         forked_chain.mine_block("block 0 in fork", &MINER);
     //         for i in 0..2 {
-    //             forked_chain.mine_block(&format!("block {} in fork", i));
+    //             forked_chain.mine_block(&format!("block {} in fork", i), &MINER);
     //             let NextBlockResult::_ = chain.store_new_block(forked_chain.last().clone());
     //         }
 
@@ -364,4 +830,633 @@ mod chain_tests {
     //         println!("Forked chain {}", forked_chain);
     //     }
     // }
+
+    #[test]
+    fn test_choose_chain_longer() {
+        let mut chain: Chain = init_chain(CHAIN_LEN);
+        let mut longer: Chain = init_chain(CHAIN_LEN);
+        longer.mine_block("block 5", &MINER);
+
+        // a shorter chain is kept in favour of the main chain
+        assert!(matches!(
+            trace(longer.choose_chain(chain.clone())),
+            Ok(ChainStatus::KeepMain { .. })
+        ));
+        // a longer valid chain replaces the main chain
+        assert!(matches!(
+            trace(chain.choose_chain(longer.clone())),
+            Ok(ChainStatus::ChooseOther {
+                main_len: 5,
+                other_len: 6,
+                ..
+            })
+        ));
+        assert_eq!(chain.last().hash, longer.last().hash);
+    }
+
+    #[test]
+    fn test_choose_chain_invalid() {
+        let mut chain: Chain = init_chain(CHAIN_LEN);
+        let mut longer: Chain = init_chain(CHAIN_LEN + 1);
+        // a longer chain that fails validation is rejected
+        let mut json: serde_json::Value = serde_json::to_value(&longer).unwrap();
+        json["main"][CHAIN_LEN]["data"] = serde_json::Value::from("tampered");
+        longer = serde_json::from_value(json).unwrap();
+
+        assert!(trace(chain.choose_chain(longer)).is_err());
+        assert_eq!(chain.len(), CHAIN_LEN);
+    }
+
+    #[test]
+    fn test_choose_chain_genesis() {
+        let mut chain: Chain = init_chain(CHAIN_LEN);
+
+        // a chain sharing our genesis is compared as usual
+        let same_genesis: Chain = init_chain(CHAIN_LEN + 1);
+        assert_eq!(chain.genesis_hash(), same_genesis.genesis_hash());
+        assert!(trace(chain.clone().choose_chain(same_genesis)).is_ok());
+
+        // a longer chain from a different genesis is rejected
+        let mut other_genesis: Chain = Chain::genesis_with(GenesisConfig {
+            difficulty: DEFAULT_DIFFICULTY + 1,
+            ..GenesisConfig::default()
+        });
+        for i in 1..CHAIN_LEN + 1 {
+            other_genesis.mine_block(&format!("block {}", i), &MINER);
+        }
+        assert_ne!(chain.genesis_hash(), other_genesis.genesis_hash());
+        assert!(matches!(
+            trace(chain.choose_chain(other_genesis.clone())),
+            Err(NextBlockErr::UnrelatedGenesis { ref genesis_hash })
+                if genesis_hash == other_genesis.genesis_hash()
+        ));
+        assert_eq!(chain.len(), CHAIN_LEN);
+    }
+
+    #[test]
+    fn test_genesis_config() {
+        let testnet = GenesisConfig {
+            timestamp: 1735689600,
+            data: "testnet".to_string(),
+            ..GenesisConfig::default()
+        };
+        let mut chain: Chain = Chain::genesis();
+        let mut other: Chain = Chain::genesis_with(testnet.clone());
+        assert_ne!(chain.genesis_hash(), other.genesis_hash());
+        // the same config always yields the same genesis
+        assert_eq!(
+            other.genesis_hash(),
+            Chain::genesis_with(testnet).genesis_hash()
+        );
+        assert!(trace(Chain::from_vec(other.clone().to_vec())).is_ok());
+
+        // chains from either genesis reject each other, however long
+        for i in 1..CHAIN_LEN {
+            chain.mine_block(&format!("block {}", i), &MINER);
+            other.mine_block(&format!("block {}", i), &MINER);
+        }
+        other.mine_block("extra block", &MINER);
+        assert!(matches!(
+            trace(chain.clone().choose_chain(other.clone())),
+            Err(NextBlockErr::UnrelatedGenesis { .. })
+        ));
+        other.rollback(2).unwrap();
+        assert!(matches!(
+            other.choose_chain(chain),
+            Err(NextBlockErr::UnrelatedGenesis { .. })
+        ));
+    }
+
+    #[test]
+    fn test_rollback() {
+        let mut chain: Chain = init_chain(CHAIN_LEN);
+        let tip_hash: String = chain.last().hash.clone();
+
+        let removed: Blocks = trace(chain.rollback(2)).unwrap();
+        assert_eq!((removed.first().idx, removed.last().idx), (3, 4));
+        assert_eq!(chain.len(), CHAIN_LEN - 2);
+        assert_eq!(chain.forks().len(), 1);
+
+        // the removed blocks are kept as a fork, which can still win
+        assert!(matches!(
+            trace(chain.choose_fork()),
+            Ok(ChainStatus::ChooseOther { .. })
+        ));
+        assert_eq!(chain.last().hash, tip_hash);
+    }
+
+    #[test]
+    fn test_rollback_invalid_fork() {
+        let mut chain: Chain = Chain::genesis();
+        chain.set_block_reward(50);
+        let txns: Vec<Transaction> = vec![Transaction::random_transaction(
+            1,
+            Keypair::generate_ed25519(),
+        )];
+        for _ in 0..2 {
+            chain.mine_block_with_reward(&Block::transactions_data(&[]), &MINER, 50);
+        }
+        chain.mine_block_with_reward(&Block::transactions_data(&txns), &MINER, 50);
+        let (tip_hash, work): (String, u128) = (chain.last().hash.clone(), chain.total_work());
+
+        // blocks that are no longer valid as a fork, since the reward changed, are kept on the main chain
+        chain.set_block_reward(10);
+        assert!(matches!(
+            trace(chain.rollback(2)),
+            Err(NextBlockErr::InvalidCoinbase { .. })
+        ));
+        assert_eq!((chain.len(), &chain.last().hash), (4, &tip_hash));
+        assert_eq!(chain.total_work(), work);
+        assert!(chain.forks().is_empty());
+        assert_eq!(chain.find_txn(&txns[0].hash).map(|b| b.idx), Some(3));
+        assert_eq!(chain.main_by_hash(&tip_hash).map(|b| b.idx), Some(3));
+    }
+
+    #[test]
+    fn test_rollback_past_genesis() {
+        let mut chain: Chain = init_chain(CHAIN_LEN);
+        for n in [0, CHAIN_LEN, CHAIN_LEN + 1] {
+            assert!(matches!(
+                trace(chain.rollback(n)),
+                Err(NextBlockErr::InvalidRollback { .. })
+            ));
+        }
+        assert_eq!(chain.len(), CHAIN_LEN);
+        assert!(chain.forks().is_empty());
+    }
+
+    #[test]
+    fn test_fork_tips() {
+        let mut main_chain: Chain = init_chain(CHAIN_LEN);
+        let mine_fork = |n: usize, tag: &str| -> Blocks {
+            let mut forked_chain = main_chain.clone();
+            forked_chain.split_off(FORK_PREFIX_LEN);
+            for i in 0..n {
+                forked_chain.mine_block(&format!("block {} in fork {}", i, tag), &MINER);
+            }
+            forked_chain.split_off(FORK_PREFIX_LEN).unwrap()
+        };
+        // chain: [0]---[1]---[2]---[3]---[4]
+        // forks:              |----[3]---[4]---[5]
+        //                     |----[3]
+        let (long_fork, short_fork) = (mine_fork(3, "a"), mine_fork(1, "b"));
+        let (long_end, short_end) = (
+            long_fork.last().hash.clone(),
+            short_fork.last().hash.clone(),
+        );
+        main_chain.store_new_fork(long_fork).unwrap();
+        main_chain.store_new_fork(short_fork).unwrap();
+
+        let tips: Vec<(String, usize)> = main_chain
+            .fork_tips()
+            .into_iter()
+            .map(|(id, len)| (id.end_hash, len))
+            .collect();
+        assert_eq!(
+            trace(tips),
+            vec![
+                (long_end, 6),
+                (main_chain.last().hash.clone(), 5),
+                (short_end, 4)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_fork_summaries() {
+        let mut main_chain: Chain = init_chain(CHAIN_LEN);
+        let mine_fork = |len: usize, n: usize| -> Blocks {
+            let mut forked_chain = main_chain.clone();
+            forked_chain.split_off(len);
+            for i in 0..n {
+                forked_chain.mine_block(&format!("block {} in fork from {}", i, len), &MINER);
+            }
+            forked_chain.split_off(len).unwrap()
+        };
+        // chain: [0]---[1]---[2]---[3]---[4]
+        // forks:        |----[2]---[3]
+        //                     |----[3]---[4]---[5]---[6]
+        //                           |----[4]---[5]
+        let forks: Vec<Blocks> = vec![mine_fork(2, 2), mine_fork(3, 4), mine_fork(4, 2)];
+        let end_hashes: Vec<String> = forks.iter().map(|f| f.last().hash.clone()).collect();
+        for fork in forks {
+            main_chain.store_new_fork(fork).unwrap();
+        }
+
+        let summaries: Vec<(String, usize, usize)> = main_chain
+            .forks()
+            .summaries()
+            .into_iter()
+            .map(|s| (s.endpoint, s.endpoint_idx, s.len))
+            .collect();
+        assert_eq!(
+            trace(summaries),
+            vec![
+                (end_hashes[1].clone(), 6, 4),
+                (end_hashes[2].clone(), 5, 2),
+                (end_hashes[0].clone(), 3, 2)
+            ]
+        );
+    }
+
+    /*****************************
+     * Tests for chain work      *
+     *****************************/
+    // A chain of `n` blocks, mined at the given difficulty on top of a genesis block with the minimum difficulty
+    fn init_chain_at(n: usize, difficulty: usize) -> Vec<Block> {
+        let mut blocks: Vec<Block> = vec![Block::genesis_with(1)];
+        for i in 1..n {
+            let next = Block::mine_block_at(
+                blocks.last().unwrap(),
+                &format!("block {}", i),
+                difficulty,
+                &MINER,
+            );
+            blocks.push(next);
+        }
+        blocks
+    }
+
+    #[test]
+    fn test_choose_chain_more_work() {
+        // chain: [0]---[1]---[2]---[3]---[4]   at difficulty 1
+        // other: [0]---[1]---[2]               at difficulty 4
+        let mut chain: Chain = Chain::from_vec(init_chain_at(CHAIN_LEN, 1)).unwrap();
+        let other: Chain = Chain::from_vec(init_chain_at(3, 4)).unwrap();
+
+        assert!(matches!(
+            trace(chain.choose_chain(other.clone())),
+            Ok(ChainStatus::ChooseOther {
+                main_len: 5,
+                main_work: 10,
+                other_len: 3,
+                other_work: 34,
+                fork_idx: 0
+            })
+        ));
+        assert_eq!(chain.last().hash, other.last().hash);
+    }
+
+    #[test]
+    fn test_choose_fork_more_work() {
+        // chain: [0]---[1]---[2]---[3]---[4]   at difficulty 1
+        // fork:   |----[1]---[2]---[3]         at difficulty 4
+        let mut chain: Chain = Chain::from_vec(init_chain_at(CHAIN_LEN, 1)).unwrap();
+        let fork: Blocks = Blocks::from_vec(init_chain_at(4, 4).split_off(1)).unwrap();
+        let fork_end: String = fork.last().hash.clone();
+        chain.store_new_fork(fork).unwrap();
+
+        assert!(matches!(
+            trace(chain.choose_fork()),
+            Ok(ChainStatus::ChooseOther {
+                main_len: 5,
+                main_work: 10,
+                other_len: 4,
+                other_work: 50,
+                fork_idx: 0
+            })
+        ));
+        assert_eq!(chain.last().hash, fork_end);
+        assert_eq!(chain.len(), 4);
+    }
+
+    #[test]
+    fn test_choose_fork_tie_break() {
+        // chain: [0]---[1]---[2]
+        // fork:         |----[2]---[3]   (two of these, differing only in their hashes)
+        let chain: Chain = init_chain(3);
+        let fork_from = |data: &str| {
+            let mut forked_chain: Chain = chain.clone();
+            forked_chain.split_off(2);
+            forked_chain.mine_block(data, &MINER);
+            forked_chain.mine_block(data, &MINER);
+            forked_chain.split_off(2).unwrap()
+        };
+        let (fork_a, fork_b) = (fork_from("fork a"), fork_from("fork b"));
+        let expected: String = std::cmp::min(&fork_a.last().hash, &fork_b.last().hash).clone();
+
+        // whichever order the forks arrive in, the one ending in the smaller hash is promoted
+        for (first, second) in [(&fork_a, &fork_b), (&fork_b, &fork_a)] {
+            let mut chain: Chain = chain.clone();
+            assert!(chain.store_new_fork(first.clone()).is_ok());
+            assert!(chain.store_new_fork(second.clone()).is_ok());
+            assert!(matches!(
+                trace(chain.choose_fork()),
+                Ok(ChainStatus::ChooseOther { other_len: 4, .. })
+            ));
+            assert_eq!(chain.last().hash, expected);
+        }
+    }
+
+    #[test]
+    fn test_verify_headers() {
+        let chain: Chain = init_chain(CHAIN_LEN);
+        let headers: Vec<BlockHeaderSummary> = chain.to_headers();
+        assert_eq!(headers.len(), CHAIN_LEN);
+        assert!(trace(verify_headers(&headers)).is_ok());
+        // any contiguous range of headers also verifies
+        assert!(verify_headers(&headers[2..]).is_ok());
+
+        let mut tampered: Vec<BlockHeaderSummary> = headers.clone();
+        tampered[3].prev_hash = tampered[1].hash.clone();
+        assert!(matches!(
+            trace(verify_headers(&tampered)),
+            Err(NextBlockErr::InvalidParent { idx: 3, .. })
+        ));
+
+        let mut tampered: Vec<BlockHeaderSummary> = headers;
+        tampered[3].hash = hex::encode([255; 32]);
+        assert!(matches!(
+            trace(verify_headers(&tampered)),
+            Err(NextBlockErr::DifficultyCheckFailed { idx: 3, .. })
+        ));
+    }
+
+    #[test]
+    fn test_checkpoint_fork() {
+        // chain: [0]---[1]---[2]---[3]---[4]---[5]
+        // fork:                     |----[4]---[5]---[6]   (branches above the checkpoint)
+        // fork:         |----[2]---[3]---[4]---[5]---[6]   (branches below the checkpoint)
+        let mut chain: Chain = init_chain(6);
+        chain.set_checkpoint_depth(3);
+        assert_eq!(chain.checkpoint(), Some(2));
+        let fork_from = |chain: &Chain, len: usize, n: usize| {
+            let mut forked_chain: Chain = chain.clone();
+            forked_chain.split_off(len);
+            for i in 0..n {
+                forked_chain.mine_block(&format!("block {} in fork", i), &MINER);
+            }
+            forked_chain.split_off(len).unwrap()
+        };
+        let deep_fork: Blocks = fork_from(&chain, 2, 5);
+        let shallow_fork: Blocks = fork_from(&chain, 4, 3);
+
+        assert!(chain.store_new_fork(deep_fork).is_ok());
+        assert!(matches!(
+            trace(chain.choose_fork()),
+            Err(NextBlockErr::ReorgBelowCheckpoint {
+                fork_idx: 1,
+                checkpoint_idx: 2
+            })
+        ));
+        // the fork is discarded, and the main chain kept
+        assert!(chain.forks().is_empty());
+        assert_eq!(chain.len(), 6);
+
+        let end_hash: String = shallow_fork.last().hash.clone();
+        assert!(chain.store_new_fork(shallow_fork).is_ok());
+        assert!(matches!(
+            trace(chain.choose_fork()),
+            Ok(ChainStatus::ChooseOther { other_len: 7, .. })
+        ));
+        assert_eq!(chain.last().hash, end_hash);
+        assert_eq!(chain.checkpoint(), Some(3));
+    }
+
+    #[test]
+    fn test_checkpoint_chain() {
+        let mut chain: Chain = init_chain(6);
+        chain.set_checkpoint_depth(3);
+        let longer_from = |chain: &Chain, len: usize, n: usize| {
+            let mut other: Chain = chain.clone();
+            other.split_off(len);
+            for i in 0..n {
+                other.mine_block(&format!("block {} in other chain", i), &MINER);
+            }
+            other
+        };
+        let (deep, shallow) = (longer_from(&chain, 2, 5), longer_from(&chain, 3, 4));
+
+        assert!(matches!(
+            trace(chain.choose_chain(deep)),
+            Err(NextBlockErr::ReorgBelowCheckpoint { fork_idx: 1, .. })
+        ));
+        assert_eq!(chain.len(), 6);
+        assert!(matches!(
+            trace(chain.choose_chain(shallow.clone())),
+            Ok(ChainStatus::ChooseOther { other_len: 7, .. })
+        ));
+        assert_eq!(chain.last().hash, shallow.last().hash);
+
+        // without a checkpoint, any reorg is allowed
+        let mut chain: Chain = init_chain(6);
+        assert_eq!(chain.checkpoint(), None);
+        let other: Chain = longer_from(&chain, 2, 5);
+        assert!(chain.choose_chain(other).is_ok());
+    }
+
+    #[test]
+    fn test_prune_forks() {
+        // chain: [0]---[1]---[2]---[3]---[4]---[5]---[6]---[7]---[8]---[9]
+        // fork:         |----[2]---[3]                                       (stale)
+        // fork:                                           |----[8]---[9]   (recent)
+        let mut chain: Chain = init_chain(10);
+        chain.set_fork_prune_depth(4);
+        let fork_from = |chain: &Chain, len: usize, n: usize| {
+            let mut forked_chain: Chain = chain.clone();
+            forked_chain.split_off(len);
+            for i in 0..n {
+                forked_chain.mine_block(&format!("block {} in fork", i), &MINER);
+            }
+            forked_chain.split_off(len).unwrap()
+        };
+        let stale_fork: Blocks = fork_from(&chain, 2, 2);
+        let recent_fork: Blocks = fork_from(&chain, 8, 2);
+        let recent_end: String = recent_fork.last().hash.clone();
+        assert!(chain.store_new_fork(stale_fork).is_ok());
+        assert!(chain.store_new_fork(recent_fork).is_ok());
+        assert_eq!(chain.forks().len(), 2);
+
+        assert!(matches!(
+            trace(chain.choose_fork()),
+            Ok(ChainStatus::KeepMain { .. })
+        ));
+        let ends: Vec<String> = chain
+            .forks()
+            .ids()
+            .into_iter()
+            .map(|id| id.end_hash)
+            .collect();
+        assert_eq!(ends, vec![recent_end]);
+
+        // when every fork is stale, the one that ends highest survives
+        let mut chain: Chain = init_chain(10);
+        chain.set_fork_prune_depth(4);
+        let (shorter, longer) = (fork_from(&chain, 2, 1), fork_from(&chain, 3, 2));
+        let longer_end: String = longer.last().hash.clone();
+        assert!(chain.store_new_fork(shorter).is_ok());
+        assert!(chain.store_new_fork(longer).is_ok());
+        assert!(chain.choose_fork().is_ok());
+        let ends: Vec<String> = chain
+            .forks()
+            .ids()
+            .into_iter()
+            .map(|id| id.end_hash)
+            .collect();
+        assert_eq!(ends, vec![longer_end]);
+
+        // without a prune depth, forks are kept however far behind they are
+        let mut chain: Chain = init_chain(10);
+        assert!(chain.store_new_fork(fork_from(&chain, 2, 1)).is_ok());
+        assert!(chain.choose_fork().is_ok());
+        assert_eq!(chain.forks().len(), 1);
+    }
+
+    #[test]
+    fn test_head() {
+        // chain: [0]---[1]---[2]---[3]
+        // fork:               |----[*3*]
+        let mut chain: Chain = init_chain(4);
+        let mut forked_chain: Chain = chain.clone();
+        forked_chain.split_off(3);
+        forked_chain.mine_block("block in fork", &MINER);
+        assert!(chain
+            .store_new_fork(forked_chain.split_off(3).unwrap())
+            .is_ok());
+
+        let head: HeadInfo = chain.head();
+        assert_eq!(
+            trace(&head),
+            &HeadInfo {
+                height: 3,
+                tip_hash: chain.last().hash.clone(),
+                tip_timestamp: chain.last().timestamp,
+                total_work: chain.blocks().total_work(),
+                num_forks: 1,
+            }
+        );
+        // the cached work is kept up to date as blocks leave the main chain
+        chain.rollback(2).unwrap();
+        assert_eq!(chain.head().total_work, chain.blocks().total_work());
+        assert_eq!(chain.head().height, 1);
+    }
+
+    #[test]
+    fn test_get_by_hash() {
+        let mut chain: Chain = init_chain(CHAIN_LEN);
+        let fork: Blocks = {
+            let mut forked_chain = chain.clone();
+            forked_chain.split_off(FORK_PREFIX_LEN);
+            forked_chain.mine_block("block 0 in fork", &MINER);
+            forked_chain.split_off(FORK_PREFIX_LEN).unwrap()
+        };
+        let fork_hash: String = fork.last().hash.clone();
+        chain.store_new_fork(fork).unwrap();
+
+        let main_hash: String = chain.idx(2).unwrap().hash.clone();
+        assert_eq!(chain.get_by_hash(&main_hash).map(|b| b.idx), Some(2));
+        assert_eq!(chain.get_by_hash(&fork_hash).map(|b| b.idx), Some(3));
+        assert!(chain.get_by_hash("missing").is_none());
+    }
+
+    #[test]
+    fn test_confirmations() {
+        // chain: [0]---[1]---[2]---[3]
+        // fork:               |----[*3*]
+        let mut chain: Chain = init_chain(4);
+        let mut forked_chain: Chain = chain.clone();
+        forked_chain.split_off(3);
+        forked_chain.mine_block("block in fork", &MINER);
+        let fork_hash: String = forked_chain.last().hash.clone();
+        assert!(chain
+            .store_new_fork(forked_chain.split_off(3).unwrap())
+            .is_ok());
+
+        let tip_hash: String = chain.last().hash.clone();
+        assert_eq!(chain.confirmations(&tip_hash), Some(1));
+        let deeper_hash: String = chain.idx(1).unwrap().hash.clone();
+        assert_eq!(trace(chain.confirmations(&deeper_hash)), Some(3));
+        assert!(chain.is_confirmed(&deeper_hash, 3));
+        assert!(!chain.is_confirmed(&deeper_hash, 4));
+
+        assert!(chain.get_by_hash(&fork_hash).is_some());
+        assert_eq!(chain.confirmations(&fork_hash), None);
+        assert!(!chain.is_confirmed(&fork_hash, 0));
+        assert_eq!(chain.confirmations("not a block hash"), None);
+    }
+
+    #[test]
+    fn test_range() {
+        let chain: Chain = init_chain(CHAIN_LEN);
+
+        let range: Blocks = trace(chain.range(1, 3)).unwrap();
+        assert_eq!((range.first().idx, range.last().idx), (1, 3));
+        assert!(range.validate().is_ok());
+        assert_eq!(
+            chain.range(0, CHAIN_LEN - 1).map(|r| r.len()),
+            Some(CHAIN_LEN)
+        );
+        assert_eq!(chain.range(2, 2).map(|r| r.len()), Some(1));
+
+        assert!(chain.range(3, 1).is_none());
+        assert!(chain.range(1, CHAIN_LEN).is_none());
+    }
+
+    #[test]
+    fn test_fork_point() {
+        let chain: Chain = init_chain(CHAIN_LEN);
+        let tip: (usize, String) = (CHAIN_LEN - 1, chain.last().hash.clone());
+        // identical chains share every block
+        assert_eq!(
+            trace(chain.fork_point(&chain.to_hashes())),
+            Some(tip.clone())
+        );
+
+        // chains sharing a prefix meet at its last block, regardless of which is longer
+        let mut other: Chain = chain.clone();
+        other.rollback(CHAIN_LEN - FORK_PREFIX_LEN).unwrap();
+        for i in 0..4 {
+            other.mine_block(&format!("other block {}", i), &MINER);
+        }
+        let prefix_end: (usize, String) = (
+            FORK_PREFIX_LEN - 1,
+            chain.idx(FORK_PREFIX_LEN - 1).unwrap().hash.clone(),
+        );
+        assert_eq!(
+            trace(chain.fork_point(&other.to_hashes())),
+            Some(prefix_end.clone())
+        );
+        assert_eq!(other.fork_point(&chain.to_hashes()), Some(prefix_end));
+        assert_eq!(
+            chain.fork_point(&chain.to_hashes()[..2]),
+            Some((1, chain.idx(1).unwrap().hash.clone()))
+        );
+
+        // chains with different genesis blocks share nothing
+        let unrelated: Chain = Chain::genesis_with(GenesisConfig {
+            data: "unrelated".to_string(),
+            ..GenesisConfig::default()
+        });
+        assert!(chain.fork_point(&unrelated.to_hashes()).is_none());
+        assert!(chain.fork_point(&[]).is_none());
+    }
+
+    #[test]
+    fn test_hash_index() {
+        let mut chain: Chain = init_chain(CHAIN_LEN);
+        let hashes: Vec<String> = chain.iter().map(|b| b.hash.clone()).collect();
+
+        // every main chain block is found at its index, and an unknown hash isn't found
+        for (idx, hash) in hashes.iter().enumerate() {
+            assert_eq!(chain.main_by_hash(hash).map(|b| b.idx), Some(idx));
+        }
+        assert!(chain.main_by_hash("not a block hash").is_none());
+
+        // the index follows the main chain as it is rolled back and switched back to the heavier fork
+        let removed: Blocks = chain.rollback(2).unwrap();
+        assert!(chain.main_by_hash(&removed.first().hash).is_none());
+        assert!(chain.get_by_hash(&removed.first().hash).is_some());
+        chain.mine_block("replacement", &MINER);
+        let replacement: String = chain.last().hash.clone();
+        assert_eq!(
+            chain.main_by_hash(&replacement).map(|b| b.idx),
+            Some(CHAIN_LEN - 2)
+        );
+        chain.choose_fork().unwrap();
+        assert_eq!(trace(chain.last().idx), CHAIN_LEN - 1);
+        for (idx, hash) in hashes.iter().enumerate() {
+            assert_eq!(chain.main_by_hash(hash).map(|b| b.idx), Some(idx));
+        }
+        assert!(chain.main_by_hash(&replacement).is_none());
+    }
 }
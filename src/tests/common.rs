@@ -0,0 +1,21 @@
+/******************
+   SHARED FIXTURES
+********************/
+#![cfg(test)]
+// Fixtures shared by the tests of several modules
+
+use libp2p::{identity::Keypair, PeerId};
+use once_cell::sync::Lazy;
+
+// who mines the blocks in these tests doesn't matter, so they share a miner
+pub static MINER: Lazy<Keypair> = Lazy::new(Keypair::generate_ed25519);
+// likewise, who delivers the messages that a peer handles in these tests
+pub static REMOTE: Lazy<PeerId> = Lazy::new(PeerId::random);
+
+// A path in the temporary directory, unique to this test run
+pub fn temp_path(name: &str) -> String {
+    std::env::temp_dir()
+        .join(format!("powp2p_{}_{}", std::process::id(), name))
+        .to_string_lossy()
+        .to_string()
+}
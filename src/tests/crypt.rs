@@ -0,0 +1,44 @@
+/******************
+      TESTS
+********************/
+#[cfg(test)] // cargo test crypt -- --nocapture
+mod crypt_tests {
+    use crate::{
+        crypt::{load_or_create_keypair, KeyFileErr},
+        tests::common::temp_path,
+        util::trace,
+    };
+    use libp2p::PeerId;
+
+    #[test]
+    fn test_keypair_persists() {
+        let path = temp_path("node_key");
+        let _ = std::fs::remove_file(&path);
+
+        let created = load_or_create_keypair(&path).unwrap();
+        let reloaded = load_or_create_keypair(&path).unwrap();
+        assert_eq!(
+            trace(PeerId::from(created.public())),
+            PeerId::from(reloaded.public())
+        );
+        // only the owner may read the private key
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode: u32 = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_corrupt_keypair() {
+        let path = temp_path("corrupt_node_key");
+        std::fs::write(&path, b"not a keypair").unwrap();
+        assert!(matches!(
+            load_or_create_keypair(&path).map_err(trace),
+            Err(KeyFileErr::Corrupt { .. })
+        ));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
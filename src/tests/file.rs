@@ -0,0 +1,210 @@
+/******************
+      TESTS
+********************/
+#[cfg(test)] // cargo test file -- --nocapture
+mod file_tests {
+    use crate::{
+        block::{Block, Blocks},
+        chain::{Chain, GenesisConfig},
+        file::{
+            append_block, read_blocks_jsonl, read_chain, recover_chain, temp_path_for,
+            write_atomic, write_chain, ChainFormat,
+        },
+        tests::common::{temp_path, MINER},
+        util::trace,
+    };
+
+    #[tokio::test]
+    async fn test_read_chain_keeps_difficulty() {
+        let mut chain: Chain = Chain::genesis_with(GenesisConfig {
+            difficulty: 4,
+            ..GenesisConfig::default()
+        });
+        chain.mine_block("block 1", &MINER);
+        chain.mine_block("block 2", &MINER);
+
+        let path: String = temp_path("difficulty.json");
+        write_chain(&chain, &path).await.unwrap();
+        // the loaded chain validates against the difficulty it was built with, not the default
+        let loaded: Chain = trace(read_chain(&path).await.unwrap());
+        let _ = std::fs::remove_file(&path);
+
+        assert_eq!(loaded.difficulty(), 4);
+        assert_eq!(loaded.len(), 3);
+        assert!(loaded.validate().is_ok());
+        for idx in 1..3 {
+            let block = loaded.idx(idx).unwrap();
+            // 4 leading zero bits is a leading zero hex digit
+            assert!(block.difficulty == 4 && block.hash.starts_with('0'));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_read_chain_keeps_forks_and_orphans() {
+        let mut chain: Chain = Chain::genesis();
+        for i in 1..5 {
+            chain.mine_block(&format!("block {}", i), &MINER);
+        }
+        // chain:  [0]---[1]---[2]---[3]---[4]
+        // forks:         |----[2]---[3]
+        //                      |----[3]
+        // orphan:                          [5]   (missing [4] of the first fork)
+        let mine_fork = |fork_idx: usize, n: usize, tag: &str| -> Vec<Block> {
+            let mut blocks: Vec<Block> = vec![chain.idx(fork_idx).unwrap().clone()];
+            for i in 0..n {
+                let next = Block::mine_block(
+                    blocks.last().unwrap(),
+                    &format!("block {} in {}", i, tag),
+                    &MINER,
+                );
+                blocks.push(next);
+            }
+            blocks.split_off(1)
+        };
+        let (mut fork_a, fork_b) = (mine_fork(1, 4, "a"), mine_fork(2, 1, "b"));
+        let orphan: Block = fork_a.pop().unwrap();
+        fork_a.pop();
+        chain
+            .store_new_fork(Blocks::from_vec(fork_a).unwrap())
+            .unwrap();
+        chain
+            .store_new_fork(Blocks::from_vec(fork_b).unwrap())
+            .unwrap();
+        assert!(chain.store_new_block(orphan.clone()).is_err());
+        assert_eq!((chain.forks().len(), chain.orphans().len()), (2, 1));
+
+        let path: String = temp_path("forks.json");
+        write_chain(&chain, &path).await.unwrap();
+        let loaded: Chain = trace(read_chain(&path).await.unwrap());
+        let _ = std::fs::remove_file(&path);
+
+        assert_eq!(loaded.last().hash, chain.last().hash);
+        assert_eq!(loaded.forks().len(), 2);
+        assert_eq!(loaded.orphans().len(), 1);
+        assert!(loaded.orphans().get(&orphan.prev_hash).is_some());
+    }
+
+    #[tokio::test]
+    async fn test_roundtrip_both_formats() {
+        let mut chain: Chain = Chain::genesis();
+        for i in 1..4 {
+            chain.mine_block(&format!("block {}", i), &MINER);
+        }
+        let fork: Block = Block::mine_block(chain.idx(1).unwrap(), "fork block", &MINER);
+        chain
+            .store_new_fork(Blocks::from_vec(vec![fork]).unwrap())
+            .unwrap();
+
+        let mut sizes: Vec<u64> = vec![];
+        for name in ["roundtrip.json", "roundtrip.bin"] {
+            let path: String = temp_path(name);
+            write_chain(&chain, &path).await.unwrap();
+            sizes.push(std::fs::metadata(&path).unwrap().len());
+            let loaded: Chain = trace(read_chain(&path).await.unwrap());
+            let _ = std::fs::remove_file(&path);
+
+            assert!(loaded.blocks().iter().eq(chain.blocks().iter()));
+            assert_eq!(loaded.forks().len(), 1);
+            assert_eq!(loaded.difficulty(), chain.difficulty());
+        }
+        // the binary encoding is the smaller one
+        assert!(sizes[1] < sizes[0]);
+    }
+
+    #[tokio::test]
+    async fn test_roundtrip_blocks_jsonl() {
+        let mut chain: Chain = Chain::genesis();
+        for i in 1..4 {
+            chain.mine_block(&format!("block {}", i), &MINER);
+        }
+        let path: String = temp_path("archive.jsonl");
+        let _ = std::fs::remove_file(&path);
+
+        // each append adds a line, rather than overwriting the archive
+        for block in chain.iter() {
+            append_block(block, &path).unwrap();
+        }
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 4);
+        let blocks: Vec<Block> = trace(read_blocks_jsonl(&path).await.unwrap());
+        assert_eq!(blocks, chain.iter().cloned().collect::<Vec<Block>>());
+        assert!(Chain::from_vec(blocks).is_ok());
+
+        // a line that isn't a block fails the read
+        std::fs::write(&path, "not a block\n").unwrap();
+        assert!(read_blocks_jsonl(&path).await.is_err());
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_format_from_path() {
+        assert_eq!(ChainFormat::from_path("blocks.json"), ChainFormat::Json);
+        assert_eq!(ChainFormat::from_path("dir/blocks.bin"), ChainFormat::Bin);
+        // JSON is the default, for any other (or no) extension
+        assert_eq!(ChainFormat::from_path("blocks"), ChainFormat::Json);
+        assert_eq!(ChainFormat::from_path("blocks.bin.txt"), ChainFormat::Json);
+    }
+
+    #[tokio::test]
+    async fn test_partial_write_keeps_original() {
+        let mut chain: Chain = Chain::genesis();
+        chain.mine_block("block 1", &MINER);
+        let path: String = temp_path("partial.json");
+        write_chain(&chain, &path).await.unwrap();
+
+        // a crash mid-write leaves a truncated temporary file, that is never renamed over the original
+        let json: String = chain.to_json().unwrap();
+        std::fs::write(temp_path_for(&path), &json[..json.len() / 2]).unwrap();
+        let loaded: Chain = trace(read_chain(&path).await.unwrap());
+        assert_eq!(loaded.last().hash, chain.last().hash);
+
+        // the next write replaces the leftover temporary file
+        chain.mine_block("block 2", &MINER);
+        write_chain(&chain, &path).await.unwrap();
+        assert!(!temp_path_for(&path).exists());
+        let loaded: Chain = read_chain(&path).await.unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(loaded.last().hash, chain.last().hash);
+    }
+
+    #[tokio::test]
+    async fn test_failed_rename_keeps_original() {
+        // a file can't be renamed over a (non-empty) directory
+        let path: String = temp_path("rename_target");
+        std::fs::create_dir_all(std::path::Path::new(&path).join("inner")).unwrap();
+
+        assert!(trace(write_atomic(&path, "contents").await).is_err());
+        assert!(std::path::Path::new(&path).join("inner").is_dir());
+        assert!(!temp_path_for(&path).exists());
+        let _ = std::fs::remove_dir_all(&path);
+    }
+
+    #[tokio::test]
+    async fn test_recover_chain() {
+        let mut chain: Chain = Chain::genesis();
+        for i in 1..4 {
+            chain.mine_block(&format!("block {}", i), &MINER);
+        }
+        let path: String = temp_path("corrupt.json");
+
+        // a chain whose last block is corrupt can't be read, but the blocks before it are salvaged
+        let mut json: serde_json::Value = serde_json::to_value(&chain).unwrap();
+        json["main"][3]["data"] = serde_json::json!("corrupt");
+        std::fs::write(&path, json.to_string()).unwrap();
+        assert!(read_chain(&path).await.is_err());
+        let (recovered, dropped) = trace(recover_chain(&path).await.unwrap());
+        assert_eq!((recovered.len(), dropped), (3, 1));
+        assert_eq!(recovered.last().hash, chain.idx(2).unwrap().hash);
+
+        // likewise for a list of blocks cut off partway through its last block
+        let json: String = serde_json::to_string(chain.blocks()).unwrap();
+        std::fs::write(&path, &json[..json.len() - 20]).unwrap();
+        assert!(read_chain(&path).await.is_err());
+        let (recovered, dropped) = recover_chain(&path).await.unwrap();
+        assert_eq!((recovered.len(), dropped), (3, 0));
+
+        // with no valid genesis block, there is nothing to recover
+        std::fs::write(&path, "garbage").unwrap();
+        assert!(trace(recover_chain(&path).await).is_err());
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,129 @@
+/******************
+      TESTS
+********************/
+#[cfg(test)] // cargo test ledger -- --nocapture
+mod ledger_tests {
+    use crate::{
+        block::Block,
+        chain::Chain,
+        ledger::{Ledger, GENESIS_ALLOCATION},
+        tests::common::MINER,
+        transaction::Transaction,
+        util::trace,
+    };
+    use libp2p::{identity, PeerId};
+
+    fn account(keys: &identity::Keypair) -> String {
+        PeerId::from(keys.public()).to_string()
+    }
+
+    #[test]
+    fn test_valid_spend() {
+        let alice = identity::Keypair::generate_ed25519();
+        let bob = identity::Keypair::generate_ed25519();
+        let txn = Transaction::new(account(&bob), 300, alice.clone());
+        assert!(Ledger::new().can_afford(&txn, 0));
+
+        let mut chain: Chain = Chain::genesis();
+        chain.mine_block(&Block::transactions_data(&[txn]), &MINER);
+        assert_eq!(
+            trace(chain.balance_of(&account(&alice))),
+            GENESIS_ALLOCATION - 300
+        );
+        assert_eq!(chain.balance_of(&account(&bob)), GENESIS_ALLOCATION + 300);
+    }
+
+    #[test]
+    fn test_overdraw() {
+        let alice = identity::Keypair::generate_ed25519();
+        let bob = identity::Keypair::generate_ed25519();
+        let ledger: Ledger = Chain::genesis().ledger();
+
+        let everything = Transaction::new(account(&bob), GENESIS_ALLOCATION as u64, alice.clone());
+        assert!(ledger.can_afford(&everything, 0));
+        let too_much = Transaction::new(account(&bob), GENESIS_ALLOCATION as u64 + 1, alice);
+        assert!(!ledger.can_afford(&too_much, 0));
+        // pending debits count against the balance
+        assert!(!ledger.can_afford(&everything, 1));
+    }
+
+    #[test]
+    fn test_fee() {
+        let alice = identity::Keypair::generate_ed25519();
+        let bob = identity::Keypair::generate_ed25519();
+        let (reward, miner) = (50, account(&MINER));
+        let ledger: Ledger = Chain::genesis().ledger();
+
+        // the fee is debited along with the amount, so must be covered by the balance too
+        let everything = GENESIS_ALLOCATION as u64;
+        let with_fee = Transaction::with_fee(account(&bob), everything - 10, 10, alice.clone());
+        assert!(ledger.can_afford(&with_fee, 0));
+        let over = Transaction::with_fee(account(&bob), everything - 10, 11, alice.clone());
+        assert!(!trace(ledger.can_afford(&over, 0)));
+        assert!(!ledger.can_afford(&with_fee, 1));
+
+        // and is collected by the block's miner through its coinbase
+        let mut chain: Chain = Chain::genesis();
+        chain.set_block_reward(reward);
+        chain.mine_block_with_reward(&Block::transactions_data(&[with_fee]), &MINER, reward);
+        assert!(chain.verify_tip().is_ok());
+        assert_eq!(chain.balance_of(&account(&alice)), 0);
+        assert_eq!(
+            chain.balance_of(&account(&bob)),
+            2 * GENESIS_ALLOCATION - 10
+        );
+        assert_eq!(
+            chain.balance_of(&miner),
+            GENESIS_ALLOCATION + reward as i64 + 10
+        );
+    }
+
+    #[test]
+    fn test_spend_after_receive() {
+        let alice = identity::Keypair::generate_ed25519();
+        let bob = identity::Keypair::generate_ed25519();
+        let spend = Transaction::new(account(&alice), 1500, bob.clone());
+
+        let mut chain: Chain = Chain::genesis();
+        assert!(!chain.ledger().can_afford(&spend, 0));
+
+        let receive = Transaction::new(account(&bob), 600, alice.clone());
+        chain.mine_block(&Block::transactions_data(&[receive]), &MINER);
+        assert!(trace(chain.ledger()).can_afford(&spend, 0));
+
+        chain.mine_block(&Block::transactions_data(&[spend]), &MINER);
+        assert_eq!(chain.balance_of(&account(&bob)), 100);
+        assert_eq!(chain.balance_of(&account(&alice)), 1900);
+    }
+
+    #[test]
+    fn test_block_reward() {
+        let (reward, miner) = (50, account(&MINER));
+        let mut chain: Chain = Chain::genesis();
+        chain.set_block_reward(reward);
+        for n in 1..=3 {
+            chain.mine_block_with_reward(&Block::transactions_data(&[]), &MINER, reward);
+            assert_eq!(
+                trace(chain.balance_of(&miner)),
+                GENESIS_ALLOCATION + n * reward as i64
+            );
+        }
+        // a coinbase's sender is never debited, and each block's coinbase is distinct
+        assert_eq!(chain.balance_of("coinbase"), GENESIS_ALLOCATION);
+        let coinbases: Vec<String> = chain
+            .iter()
+            .skip(1)
+            .map(|b| b.transactions()[0].hash.clone())
+            .collect();
+        assert!(coinbases[0] != coinbases[1] && coinbases[1] != coinbases[2]);
+
+        // the reward is credited before the block's other transactions, so can be spent by them
+        let receiver = identity::Keypair::generate_ed25519();
+        let spend = Transaction::new(account(&receiver), 1, MINER.clone());
+        chain.mine_block_with_reward(&Block::transactions_data(&[spend]), &MINER, reward);
+        assert_eq!(
+            chain.balance_of(&miner),
+            GENESIS_ALLOCATION + 4 * reward as i64 - 1
+        );
+    }
+}
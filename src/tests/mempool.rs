@@ -0,0 +1,162 @@
+/******************
+      TESTS
+********************/
+#[cfg(test)] // cargo test mempool -- --nocapture
+mod mempool_tests {
+    use crate::{
+        mempool::{Mempool, MempoolErr},
+        transaction::Transaction,
+        util::trace,
+    };
+    use libp2p::identity;
+
+    fn txn_at(timestamp: i64) -> Transaction {
+        Transaction {
+            timestamp,
+            ..Transaction::random_transaction(1, identity::Keypair::generate_ed25519())
+        }
+    }
+
+    #[test]
+    fn test_mempool_remove_expired() {
+        let mut mempool = Mempool::default();
+        for valid_until in [10, 20, 30] {
+            let txn = Transaction {
+                valid_until,
+                ..txn_at(valid_until)
+            };
+            assert!(mempool.insert(txn).is_ok());
+        }
+        assert_eq!(mempool.remove_expired(10), 0);
+        assert_eq!(trace(mempool.remove_expired(21)), 2);
+        assert!(mempool.iter().all(|txn| txn.valid_until == 30));
+    }
+
+    #[test]
+    fn test_mempool_ordering() {
+        let mut mempool = Mempool::default();
+        for timestamp in [30, 10, 20] {
+            assert!(mempool.insert(txn_at(timestamp)).is_ok());
+        }
+        let timestamps: Vec<i64> = mempool.iter().map(|txn| txn.timestamp).collect();
+        assert_eq!(trace(timestamps), vec![10, 20, 30]);
+
+        // the highest-priority transactions are taken first
+        let taken: Vec<i64> = mempool.take(2).iter().map(|txn| txn.timestamp).collect();
+        assert_eq!(taken, vec![10, 20]);
+        assert_eq!(mempool.len(), 1);
+    }
+
+    #[test]
+    fn test_mempool_fee_priority() {
+        let mut mempool = Mempool::default();
+        for (fee, timestamp) in [(0, 10), (5, 30), (1, 20), (5, 40)] {
+            assert!(mempool
+                .insert(Transaction {
+                    fee,
+                    ..txn_at(timestamp)
+                })
+                .is_ok());
+        }
+        // the highest fee is taken first however late it is, and equal fees are taken earliest first
+        let taken: Vec<(u64, i64)> = mempool
+            .take(3)
+            .iter()
+            .map(|txn| (txn.fee, txn.timestamp))
+            .collect();
+        assert_eq!(trace(taken), vec![(5, 30), (5, 40), (1, 20)]);
+        assert_eq!(mempool.iter().next().map(|txn| txn.fee), Some(0));
+    }
+
+    #[test]
+    fn test_mempool_capacity_eviction() {
+        let mut mempool = Mempool::new(2);
+        let (early, mid, late) = (txn_at(10), txn_at(20), txn_at(30));
+        assert!(mempool.insert(mid.clone()).is_ok());
+        assert!(mempool.insert(late.clone()).is_ok());
+
+        // a lower-priority transaction is rejected when full
+        assert!(matches!(
+            trace(mempool.insert(txn_at(40))),
+            Err(MempoolErr::Full { capacity: 2 })
+        ));
+        // a higher-priority transaction evicts the lowest-priority one
+        assert_eq!(mempool.insert(early.clone()).ok(), Some(Some(late.clone())));
+        assert_eq!(mempool.len(), 2);
+        assert!(mempool.contains(&early) && mempool.contains(&mid) && !mempool.contains(&late));
+    }
+
+    #[test]
+    fn test_mempool_duplicate_rejection() {
+        let mut mempool = Mempool::default();
+        let txn = txn_at(10);
+        assert!(mempool.insert(txn.clone()).is_ok());
+        assert!(matches!(
+            trace(mempool.insert(txn.clone())),
+            Err(MempoolErr::Duplicate { ref hash }) if *hash == txn.hash
+        ));
+        assert_eq!(mempool.len(), 1);
+    }
+
+    #[test]
+    fn test_mempool_replacement() {
+        let mut mempool = Mempool::default();
+        let sender = identity::Keypair::generate_ed25519();
+        let with_nonce = |nonce: u64, amount: u64, timestamp: i64| Transaction {
+            timestamp,
+            ..Transaction::with_nonce("receiver".to_string(), amount, nonce, sender.clone())
+        };
+        let original = with_nonce(1, 10, 10);
+        assert!(mempool.insert(original.clone()).is_ok());
+
+        // a newer transaction with the same sender and nonce replaces the pending one
+        let bumped = with_nonce(1, 20, 20);
+        assert_eq!(
+            trace(mempool.insert(bumped.clone())).ok(),
+            Some(Some(original.clone()))
+        );
+        assert!(mempool.contains(&bumped) && !mempool.contains(&original));
+        // but one that is no newer doesn't
+        assert!(matches!(
+            trace(mempool.insert(with_nonce(1, 30, 20))),
+            Err(MempoolErr::ReplacementUnderpriced { ref pending_hash, .. }) if *pending_hash == bumped.hash
+        ));
+        assert!(mempool.insert(original).is_err());
+        assert_eq!(mempool.len(), 1);
+
+        // transactions with other nonces, other senders, or none at all don't conflict
+        assert_eq!(mempool.insert(with_nonce(2, 10, 5)).ok(), Some(None));
+        let other_sender = identity::Keypair::generate_ed25519();
+        let other = Transaction::with_nonce("receiver".to_string(), 10, 1, other_sender);
+        assert_eq!(mempool.insert(other).ok(), Some(None));
+        assert_eq!(mempool.insert(txn_at(5)).ok(), Some(None));
+        assert_eq!(mempool.len(), 4);
+    }
+
+    #[test]
+    fn test_mempool_fee_replacement() {
+        let mut mempool = Mempool::default();
+        let sender = identity::Keypair::generate_ed25519();
+        let with_fee = |fee: u64, amount: u64, timestamp: i64| Transaction {
+            fee,
+            timestamp,
+            ..Transaction::with_nonce("receiver".to_string(), amount, 1, sender.clone())
+        };
+        let original = with_fee(2, 10, 10);
+        assert!(mempool.insert(original.clone()).is_ok());
+
+        // a fee bump replaces the pending transaction, even within the same second
+        let bumped = with_fee(3, 10, 10);
+        assert_eq!(
+            trace(mempool.insert(bumped.clone())).ok(),
+            Some(Some(original))
+        );
+        // but a newer transaction paying a lower fee doesn't
+        assert!(matches!(
+            trace(mempool.insert(with_fee(1, 10, 20))),
+            Err(MempoolErr::ReplacementUnderpriced { ref pending_hash, .. }) if *pending_hash == bumped.hash
+        ));
+        assert!(mempool.contains(&bumped));
+        assert_eq!(mempool.len(), 1);
+    }
+}
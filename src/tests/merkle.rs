@@ -0,0 +1,99 @@
+/******************
+      TESTS
+********************/
+#[cfg(test)] // cargo test merkle -- --nocapture
+mod merkle_tests {
+    use crate::{
+        block::Block,
+        chain::Chain,
+        merkle::{block_merkle_root, merkle_proof, merkle_root, verify_proof, TxnInclusionProof},
+        tests::common::MINER,
+        transaction::Transaction,
+        util::trace,
+    };
+    use libp2p::identity::Keypair;
+
+    fn random_txns(n: u64) -> Vec<Transaction> {
+        (0..n)
+            .map(|amount| Transaction::random_transaction(amount, Keypair::generate_ed25519()))
+            .collect()
+    }
+
+    #[test]
+    fn test_valid_proof() {
+        // trees with an odd node out at some levels, and none at others
+        for n in [1, 2, 3, 5, 8] {
+            let txns: Vec<Transaction> = random_txns(n);
+            let block: Block =
+                Block::mine_block(&Block::genesis(), &Block::transactions_data(&txns), &MINER);
+            let txn_hashes: Vec<String> = txns.iter().map(|t| t.hash.clone()).collect();
+            for txn in &txns {
+                let proof = TxnInclusionProof::new(&block, &txn.hash).unwrap();
+                assert_eq!(proof.merkle_root, merkle_root(&txn_hashes));
+                assert_eq!(proof.merkle_root, block_merkle_root(&block));
+                assert!(trace(verify_proof(&proof, &proof.merkle_root)));
+            }
+            assert!(merkle_proof(&txn_hashes, n as usize).is_none());
+        }
+    }
+
+    #[test]
+    fn test_tampered_proof() {
+        let txns: Vec<Transaction> = random_txns(4);
+        let block: Block =
+            Block::mine_block(&Block::genesis(), &Block::transactions_data(&txns), &MINER);
+        let proof = TxnInclusionProof::new(&block, &txns[1].hash).unwrap();
+        let root: String = block_merkle_root(&block);
+        assert!(verify_proof(&proof, &root));
+
+        // a transaction that isn't in the block
+        let other_txn = TxnInclusionProof {
+            txn: random_txns(1).remove(0),
+            ..proof.clone()
+        };
+        assert!(!verify_proof(&other_txn, &root));
+        // a transaction whose contents were altered
+        let mut altered_txn = proof.clone();
+        altered_txn.txn.amount += 1;
+        assert!(!verify_proof(&altered_txn, &root));
+        // a path with a sibling swapped or missing
+        let mut altered_path = proof.clone();
+        altered_path.proof[0] = txns[0].hash.clone();
+        assert!(!verify_proof(&altered_path, &root));
+        let mut short_path = proof.clone();
+        short_path.proof.pop();
+        assert!(!verify_proof(&short_path, &root));
+        // a path forged to lead to its own recorded root, rather than the block's
+        let forged_txn: Transaction = random_txns(1).remove(0);
+        let forged = TxnInclusionProof {
+            merkle_root: merkle_root(std::slice::from_ref(&forged_txn.hash)),
+            proof: vec![],
+            txn: forged_txn,
+            ..proof.clone()
+        };
+        assert!(verify_proof(&forged, &forged.merkle_root));
+        assert!(!verify_proof(&forged, &root));
+        // a different root
+        assert!(!verify_proof(&proof, &merkle_root(&[txns[1].hash.clone()])));
+    }
+
+    #[test]
+    fn test_prove_txn() {
+        let txns: Vec<Transaction> = random_txns(3);
+        let mut chain: Chain = Chain::genesis();
+        chain.mine_block(&Block::transactions_data(&txns), &MINER);
+        chain.mine_block("later block", &MINER);
+
+        let proof: TxnInclusionProof = chain.prove_txn(&txns[2].hash).unwrap();
+        assert_eq!(
+            (proof.block_idx, &proof.block_hash),
+            (1, &chain.idx(1).unwrap().hash)
+        );
+        let json: String = proof.to_json().unwrap();
+        assert!(verify_proof(
+            &TxnInclusionProof::from_json(&json).unwrap(),
+            &block_merkle_root(chain.idx(1).unwrap())
+        ));
+        assert!(chain.prove_txn("not a txn hash").is_none());
+    }
+}
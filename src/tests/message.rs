@@ -0,0 +1,114 @@
+/******************
+      TESTS
+********************/
+#[cfg(test)] // cargo test message -- --nocapture
+mod message_tests {
+    use crate::{
+        block::{Block, Blocks},
+        chain::Chain,
+        message::{decode_msg, encode_msg, PowMessage, TxnMessage, COMPRESSED_PREFIX},
+        tests::common::MINER,
+        transaction::Transaction,
+        util::trace,
+    };
+    use libp2p::identity::Keypair;
+
+    // A block response for a block packed with transactions
+    fn large_msg() -> PowMessage {
+        let txns: Vec<Transaction> = (0..200)
+            .map(|amount| Transaction::random_transaction(amount, Keypair::generate_ed25519()))
+            .collect();
+        PowMessage::BlockResponse {
+            block: Block::mine_block(&Block::genesis(), &Block::transactions_data(&txns), &MINER),
+            target: "requester".to_string(),
+            source: "responder".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_compressed_round_trip() {
+        let msg: PowMessage = large_msg();
+        let json: Vec<u8> = serde_json::to_vec(&msg).unwrap();
+        let data: Vec<u8> = encode_msg(&msg).unwrap();
+        println!("{} bytes of json compressed to {}", json.len(), data.len());
+        assert!(data.starts_with(COMPRESSED_PREFIX));
+        assert!(data.len() < json.len());
+
+        let decoded: PowMessage = decode_msg(&data).unwrap();
+        assert_eq!(serde_json::to_vec(&decoded).unwrap(), json);
+    }
+
+    #[test]
+    fn test_plain_messages_still_parse() {
+        // small messages aren't compressed, and plain json (e.g. from older peers) still decodes
+        let request = PowMessage::ChainRequest {
+            target: None,
+            source: "requester".to_string(),
+        };
+        let data: Vec<u8> = encode_msg(&request).unwrap();
+        assert_eq!(data, serde_json::to_vec(&request).unwrap());
+        assert!(matches!(
+            decode_msg::<PowMessage>(&data),
+            Ok(PowMessage::ChainRequest { .. })
+        ));
+        let response = PowMessage::ChainResponse {
+            target: "requester".to_string(),
+            source: "responder".to_string(),
+            chain: Chain::genesis(),
+        };
+        let json: Vec<u8> = serde_json::to_vec(&response).unwrap();
+        assert!(matches!(
+            decode_msg::<PowMessage>(&json),
+            Ok(PowMessage::ChainResponse { .. })
+        ));
+
+        // but a corrupted compressed message, or one of the wrong type, doesn't
+        let mut corrupted: Vec<u8> = encode_msg(&large_msg()).unwrap();
+        corrupted.truncate(corrupted.len() / 2);
+        assert!(trace(decode_msg::<PowMessage>(&corrupted)).is_err());
+        assert!(decode_msg::<TxnMessage>(&data).is_err());
+        let oversized: Vec<u8> = [COMPRESSED_PREFIX, &u32::MAX.to_le_bytes()].concat();
+        assert!(trace(decode_msg::<PowMessage>(&oversized)).is_err());
+    }
+
+    #[test]
+    fn test_empty_blocks_rejected() {
+        // a chain response whose chain has an empty orphaned branch (or fork, or main chain) is rejected rather than panicking
+        let response = PowMessage::ChainResponse {
+            target: "requester".to_string(),
+            source: "responder".to_string(),
+            chain: Chain::genesis(),
+        };
+        for (field, empty) in [
+            ("orphans", serde_json::json!({ "x": [] })),
+            ("forks", serde_json::json!({ "x": { "y": [] } })),
+            ("main", serde_json::json!([])),
+        ] {
+            let mut json: serde_json::Value = serde_json::to_value(&response).unwrap();
+            json["ChainResponse"]["chain"][field] = empty;
+            let data: Vec<u8> = serde_json::to_vec(&json).unwrap();
+            assert!(trace(decode_msg::<PowMessage>(&data)).is_err());
+        }
+    }
+
+    #[test]
+    fn test_empty_range_rejected() {
+        // a range response without blocks is rejected when decoded, before it can be logged or handled
+        let data: Vec<u8> = serde_json::to_vec(&serde_json::json!({
+            "BlockRangeResponse": { "blocks": [], "target": "requester", "source": "responder" }
+        }))
+        .unwrap();
+        assert!(trace(decode_msg::<PowMessage>(&data)).is_err());
+
+        // whereas a non-empty one is described by its idxs
+        let mut chain: Chain = Chain::genesis();
+        chain.mine_block("block 1", &MINER);
+        let response = PowMessage::BlockRangeResponse {
+            blocks: Blocks::from_vec(vec![chain.idx(1).unwrap().clone()]).unwrap(),
+            target: "requester".to_string(),
+            source: "responder".to_string(),
+        };
+        let decoded: PowMessage = decode_msg(&encode_msg(&response).unwrap()).unwrap();
+        assert_eq!(decoded.to_string(), "Block range response for idxs 1 to 1");
+    }
+}
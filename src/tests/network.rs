@@ -0,0 +1,100 @@
+/******************
+      TESTS
+********************/
+#[cfg(test)] // cargo test network -- --nocapture
+mod network_tests {
+    use crate::{
+        block::Block,
+        message::PowMessage,
+        network::{set_up_network, Broadcast, Protocol},
+        swarm::{new_transport, SwarmConfig, SwarmErr},
+        util::trace,
+    };
+    use libp2p::{gossipsub::error::PublishError, identity::Keypair, Multiaddr, PeerId, Transport};
+    use tokio::sync::mpsc;
+
+    #[test]
+    fn test_parse_protocol() {
+        assert_eq!("gossipsub".parse::<Protocol>(), Ok(Protocol::Gossipsub));
+        assert_eq!("floodsub".parse::<Protocol>(), Ok(Protocol::Floodsub));
+        assert!(trace("plumtree".parse::<Protocol>()).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_set_up_network() {
+        for protocol in [Protocol::Gossipsub, Protocol::Floodsub] {
+            let (pow_sender, _pow_receiver) = mpsc::unbounded_channel();
+            let (txn_sender, _txn_receiver) = mpsc::unbounded_channel();
+            let keys = Keypair::generate_ed25519();
+            let mut swarm: Box<dyn Broadcast> = set_up_network(
+                protocol,
+                keys.clone(),
+                "/ip4/127.0.0.1/tcp/0",
+                &SwarmConfig::default(),
+                pow_sender,
+                txn_sender,
+            )
+            .await
+            .unwrap();
+            assert_eq!(swarm.local_peer_id(), PeerId::from(keys.public()));
+            assert!(swarm.connected_peers().is_empty());
+
+            // with no one to send to, either protocol fails to publish, so that the message can be retried
+            let msg = PowMessage::NewBlock {
+                block: Block::genesis(),
+                source: swarm.local_peer_id().to_string(),
+            };
+            assert!(matches!(
+                trace(swarm.publish_pow_msg(&msg)),
+                Err(PublishError::InsufficientPeers)
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_websocket_listen_addr() {
+        let ws_addr: Multiaddr = "/ip4/127.0.0.1/tcp/0/ws".parse().unwrap();
+        let tcp_addr: Multiaddr = "/ip4/127.0.0.1/tcp/0".parse().unwrap();
+        for addr in [ws_addr.clone(), tcp_addr] {
+            assert!(new_transport(&Keypair::generate_ed25519())
+                .listen_on(addr)
+                .map_err(trace)
+                .is_ok());
+        }
+        for protocol in [Protocol::Gossipsub, Protocol::Floodsub] {
+            let (pow_sender, _pow_receiver) = mpsc::unbounded_channel();
+            let (txn_sender, _txn_receiver) = mpsc::unbounded_channel();
+            let swarm = set_up_network(
+                protocol,
+                Keypair::generate_ed25519(),
+                &ws_addr.to_string(),
+                &SwarmConfig::default(),
+                pow_sender,
+                txn_sender,
+            )
+            .await;
+            assert!(swarm.map_err(trace).is_ok());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_invalid_listen_addr() {
+        for protocol in [Protocol::Gossipsub, Protocol::Floodsub] {
+            let (pow_sender, _pow_receiver) = mpsc::unbounded_channel();
+            let (txn_sender, _txn_receiver) = mpsc::unbounded_channel();
+            let swarm = set_up_network(
+                protocol,
+                Keypair::generate_ed25519(),
+                "not-a-multiaddr",
+                &SwarmConfig::default(),
+                pow_sender,
+                txn_sender,
+            )
+            .await;
+            assert!(matches!(
+                swarm.map(|_| ()).map_err(trace),
+                Err(SwarmErr::InvalidListenAddr { .. })
+            ));
+        }
+    }
+}
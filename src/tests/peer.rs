@@ -0,0 +1,1411 @@
+/******************
+      TESTS
+********************/
+#[cfg(test)] // cargo test peer -- --nocapture
+mod peer_tests {
+    use crate::{
+        block::{Block, Blocks, DEFAULT_DIFFICULTY, EMPTY_BLOCK_DATA},
+        chain::{Chain, HeadInfo},
+        file,
+        fork::BranchSummary,
+        ledger::GENESIS_ALLOCATION,
+        mempool::Mempool,
+        message::{encode_msg, PowMessage, TxnMessage},
+        network::Protocol,
+        peer::{
+            block_contents, block_response, chain_diff, chain_response, chain_summary,
+            dry_run_block, peers_table, range_response, set_up_peer_with_chain, Metrics,
+            MinePolicy, Peer, PeerConfig, PeerEvent, PeerInfo, Stats, BLOCK_REQUEST_TIMEOUT,
+            BLOCK_REQUEST_TTL, MAX_BLOCK_REQUEST_ATTEMPTS, MAX_INVALID_MSGS, ORPHAN_TTL,
+        },
+        tests::common::{temp_path, MINER, REMOTE},
+        transaction::Transaction,
+        util::trace,
+    };
+    use chrono::Utc;
+    use libp2p::{
+        identity::{self, Keypair},
+        Multiaddr, PeerId,
+    };
+
+    #[test]
+    fn test_stats_json_roundtrip() {
+        let mut chain: Chain = Chain::genesis();
+        chain.mine_block("block 1", &MINER);
+        chain.mine_block("block 2", &MINER);
+        let mut txns: Mempool = Mempool::default();
+        assert!(txns
+            .insert(Transaction::random_transaction(
+                1,
+                identity::Keypair::generate_ed25519(),
+            ))
+            .is_ok());
+
+        let stats = Stats::new(&chain, &txns, 2, 1);
+        let json: String = serde_json::to_string(&stats).unwrap();
+        let parsed: Stats = trace(serde_json::from_str(&json).unwrap());
+
+        assert_eq!(parsed, stats);
+        assert_eq!(parsed.chain_len, 3);
+        assert_eq!(parsed.tip_hash, chain.last().hash);
+        assert_eq!(parsed.txns, 1);
+        assert_eq!((parsed.discovered_peers, parsed.connected_peers), (2, 1));
+    }
+
+    #[test]
+    fn test_block_response_serving() {
+        let mut chain: Chain = Chain::genesis();
+        chain.mine_block("block 1", &MINER);
+        let hash: String = chain.last().hash.clone();
+
+        assert!(matches!(
+            trace(block_response(&chain, true, &hash, "requester", "local")),
+            Some(PowMessage::BlockResponse { block, .. }) if block.hash == hash
+        ));
+        assert!(trace(block_response(
+            &chain,
+            true,
+            "unknown",
+            "requester",
+            "local"
+        ))
+        .is_none());
+    }
+
+    #[test]
+    fn test_block_response_not_serving() {
+        let mut chain: Chain = Chain::genesis();
+        chain.mine_block("block 1", &MINER);
+        let hash: String = chain.last().hash.clone();
+
+        assert!(trace(block_response(&chain, false, &hash, "requester", "local")).is_none());
+    }
+
+    #[test]
+    fn test_peer_info_known() {
+        let info = PeerInfo {
+            peer_id: "12D3KooWPeer".to_string(),
+            discovered: true,
+            connected: true,
+            addresses: vec!["/ip4/127.0.0.1/tcp/40001".parse().unwrap()],
+            last_seen: Some(1730051971),
+        };
+        let report: String = trace(info.to_string());
+        assert!(report.contains("Connected:       true"));
+        assert!(report.contains("/ip4/127.0.0.1/tcp/40001"));
+        assert!(report.contains("2024-10-27"));
+        assert!(!report.contains("never been seen"));
+    }
+
+    #[test]
+    fn test_peer_info_unknown() {
+        let info = PeerInfo {
+            peer_id: "12D3KooWPeer".to_string(),
+            discovered: false,
+            connected: false,
+            addresses: vec![],
+            last_seen: None,
+        };
+        assert!(trace(info.to_string()).contains("never been seen"));
+    }
+
+    fn new_block_msg(block: Block) -> PowMessage {
+        PowMessage::NewBlock {
+            block,
+            source: "remote".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ignore_own_messages() {
+        let mut peer = set_up_peer_with_chain(Chain::genesis(), PeerConfig::default()).await;
+        let own_source: String = peer.local_peer_id().to_string();
+
+        // our own messages, gossiped back to us, are short-circuited
+        let block: Block = Block::mine_block(&Block::genesis(), "block 1", &MINER);
+        peer.handle_pow_event(
+            PowMessage::NewBlock {
+                block: block.clone(),
+                source: own_source.clone(),
+            },
+            *REMOTE,
+        );
+        let TxnMessage::NewTransaction { txn, .. } = new_txn_msg(1);
+        peer.handle_txn_event(
+            TxnMessage::NewTransaction {
+                txn: txn.clone(),
+                source: own_source,
+            },
+            *REMOTE,
+        );
+        assert_eq!(trace(peer.chain().len()), 1);
+        assert!(peer.txns().is_empty());
+        assert_eq!(*peer.metrics(), Metrics::default());
+
+        // while the same messages from anyone else are handled
+        peer.handle_pow_event(new_block_msg(block), *REMOTE);
+        peer.handle_txn_event(
+            TxnMessage::NewTransaction {
+                txn,
+                source: "remote".to_string(),
+            },
+            *REMOTE,
+        );
+        assert_eq!(peer.chain().len(), 2);
+        assert_eq!(peer.txns().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_relay_does_not_store_new_block() {
+        let config = PeerConfig {
+            relay: true,
+            ..PeerConfig::default()
+        };
+        let mut peer = set_up_peer_with_chain(Chain::genesis(), config).await;
+
+        let block: Block = Block::mine_block(&Block::genesis(), "block 1", &MINER);
+        peer.handle_pow_event(new_block_msg(block), *REMOTE);
+        assert_eq!(trace(peer.chain().len()), 1);
+    }
+
+    #[tokio::test]
+    async fn test_non_relay_stores_new_block() {
+        let mut peer = set_up_peer_with_chain(Chain::genesis(), PeerConfig::default()).await;
+
+        let block: Block = Block::mine_block(&Block::genesis(), "block 1", &MINER);
+        peer.handle_pow_event(new_block_msg(block), *REMOTE);
+        assert_eq!(trace(peer.chain().len()), 2);
+    }
+
+    // Config for peers that mine empty blocks, e.g. to advance the chain without any transactions
+    fn allow_empty() -> PeerConfig {
+        PeerConfig {
+            mine_policy: MinePolicy::AllowEmpty,
+            ..PeerConfig::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_mine_empty() {
+        let mut peer = set_up_peer_with_chain(Chain::genesis(), allow_empty()).await;
+        assert_eq!(peer.mine_policy(), MinePolicy::AllowEmpty);
+
+        // with an empty pool, plain `mine` does nothing
+        peer.handle_std_event("mine").await;
+        assert_eq!(peer.chain().len(), 1);
+
+        peer.handle_std_event("mine empty").await;
+        assert_eq!(trace(peer.chain().len()), 2);
+        assert!(peer.chain().last().transactions().is_empty());
+        assert_eq!(peer.chain().last().data, EMPTY_BLOCK_DATA);
+    }
+
+    #[tokio::test]
+    async fn test_mine_empty_policy() {
+        // by default, blocks must contain transactions
+        let mut peer = set_up_peer_with_chain(Chain::genesis(), PeerConfig::default()).await;
+        assert_eq!(peer.mine_policy(), MinePolicy::RequireTxns);
+        peer.handle_std_event("mine").await;
+        peer.handle_std_event("mine empty").await;
+        assert_eq!(peer.chain().len(), 1);
+
+        peer.set_mine_policy(MinePolicy::AllowEmpty);
+        peer.handle_std_event("mine empty").await;
+        assert_eq!(peer.chain().len(), 2);
+
+        let config = PeerConfig::from_args(["--allow-empty-blocks".to_string()].into_iter());
+        assert_eq!(config.mine_policy, MinePolicy::AllowEmpty);
+    }
+
+    #[tokio::test]
+    async fn test_piped_commands_do_not_starve_network() {
+        let mut peer = set_up_peer_with_chain(Chain::genesis(), PeerConfig::default()).await;
+        let script: String = "stats\n".repeat(50);
+        peer.set_command_source(std::io::Cursor::new(script.into_bytes()));
+
+        // a network message arriving while commands are queued is handled first
+        let block: Block = Block::mine_block(&Block::genesis(), "block 1", &MINER);
+        peer.inject_pow_msg(new_block_msg(block));
+        peer.step().await;
+        assert_eq!(trace(peer.chain().len()), 2);
+
+        // then the queued commands, and the end of input, are handled without panicking
+        for _ in 0..51 {
+            peer.step().await;
+        }
+        let block: Block = Block::mine_block(peer.chain().last(), "block 2", &MINER);
+        peer.inject_pow_msg(new_block_msg(block));
+        peer.step().await;
+        assert_eq!(trace(peer.chain().len()), 3);
+    }
+
+    #[tokio::test]
+    async fn test_txns_role_skips_blocks() {
+        let config = PeerConfig::from_args(["--role", "txns"].map(String::from).into_iter());
+        assert!(!config.swarm.roles.blocks && config.swarm.roles.txns);
+        let mut peer = set_up_peer_with_chain(Chain::genesis(), config).await;
+
+        let block: Block = Block::mine_block(&Block::genesis(), "block 1", &MINER);
+        peer.inject_pow_msg(new_block_msg(block));
+        peer.step().await;
+        assert_eq!(trace(peer.chain().len()), 1);
+    }
+
+    #[test]
+    fn test_config_difficulty_from_args() {
+        let args = ["--difficulty", "1"].map(String::from).into_iter();
+        let config = PeerConfig::from_args(args);
+        assert_eq!(config.genesis_config().difficulty, 1);
+        assert_eq!(Chain::genesis_with(config.genesis_config()).difficulty(), 1);
+
+        let config = PeerConfig::from_args(["--difficulty", "x"].map(String::from).into_iter());
+        assert_eq!(config.genesis_config().difficulty, DEFAULT_DIFFICULTY);
+    }
+
+    #[test]
+    fn test_config_genesis_from_args() {
+        let args = [
+            "--genesis-timestamp",
+            "1735689600",
+            "--genesis-data",
+            "testnet",
+        ];
+        let config = PeerConfig::from_args(args.map(String::from).into_iter());
+        let genesis = config.genesis_config();
+        assert_eq!(
+            (genesis.timestamp, genesis.data.as_str()),
+            (1735689600, "testnet")
+        );
+        assert_ne!(
+            Chain::genesis_with(genesis).genesis_hash(),
+            Chain::genesis().genesis_hash()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_network_rates() {
+        let mut peer = set_up_peer_with_chain(Chain::genesis(), PeerConfig::default()).await;
+        let block: Block = Block::mine_block(&Block::genesis(), "block 1", &MINER);
+        for _ in 0..30 {
+            peer.handle_pow_event(new_block_msg(block.clone()), *REMOTE);
+        }
+        // 30 messages over a 60 second window
+        let rate: f64 = trace(peer.network_rates().received_rate("new block"));
+        assert!(rate > 0.4 && rate <= 0.5);
+        assert_eq!(peer.network_rates().received_rate("block request"), 0.0);
+        assert_eq!(peer.network_rates().published_rate("new block"), 0.0);
+    }
+
+    #[test]
+    fn test_chain_response_size_fallback() {
+        let mut chain: Chain = Chain::genesis();
+        chain.mine_block("block 1", &MINER);
+        chain.mine_block("block 2", &MINER);
+        chain.mine_block("block 3", &MINER);
+
+        let resps: Vec<PowMessage> = chain_response(&chain, "requester", "local", usize::MAX, 10);
+        assert!(matches!(
+            trace(&resps[..]),
+            [PowMessage::ChainResponse { chain: ref resp, .. }] if resp.len() == 4
+        ));
+        // a chain larger than the message limit falls back to sending it in ranges after the genesis block,
+        // each within the limit as it is sent, and of up to the given number of blocks
+        let idxs = |resps: Vec<PowMessage>| -> Vec<Vec<usize>> {
+            resps
+                .iter()
+                .map(|resp| match resp {
+                    PowMessage::BlockRangeResponse { blocks, .. } => {
+                        blocks.iter().map(|b| b.idx).collect()
+                    }
+                    _ => panic!("expected a block range response, got {}", resp),
+                })
+                .collect()
+        };
+        // as messages are compressed, the whole chain may be sent in less space than its blocks after the genesis block
+        let max_size: usize = [
+            &resps[0],
+            &range_response(&chain, 1, 3, 10, "requester", "local").unwrap(),
+        ]
+        .iter()
+        .map(|resp| encode_msg(resp).unwrap().len())
+        .min()
+        .unwrap()
+            - 1;
+        let resps: Vec<PowMessage> = chain_response(&chain, "requester", "local", max_size, 10);
+        assert!(resps
+            .iter()
+            .all(|resp| encode_msg(resp).unwrap().len() <= max_size));
+        let pages: Vec<Vec<usize>> = trace(idxs(resps));
+        assert!(pages.len() > 1);
+        assert_eq!(pages.concat(), vec![1, 2, 3]);
+        assert_eq!(
+            idxs(chain_response(&chain, "requester", "local", max_size, 1)),
+            vec![vec![1], vec![2], vec![3]]
+        );
+        // a block too large for any message is still sent on its own
+        assert_eq!(
+            idxs(chain_response(&chain, "requester", "local", 64, 10)),
+            vec![vec![1], vec![2], vec![3]]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_chain_response_syncs_longer_chain() {
+        let mut peer = set_up_peer_with_chain(Chain::genesis(), PeerConfig::default()).await;
+        let mut remote: Chain = Chain::genesis();
+        for i in 1..4 {
+            remote.mine_block(&format!("block {}", i), &MINER);
+        }
+        peer.handle_pow_event(
+            PowMessage::ChainResponse {
+                target: "local".to_string(),
+                source: "remote".to_string(),
+                chain: remote.clone(),
+            },
+            *REMOTE,
+        );
+        assert_eq!(peer.chain().last().hash, remote.last().hash);
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_peers_defers_broadcasts() {
+        let config = PeerConfig {
+            wait_for_peers: Some(std::time::Duration::from_secs(60)),
+            ..allow_empty()
+        };
+        let mut peer = set_up_peer_with_chain(Chain::genesis(), config).await;
+
+        // broadcasting commands issued before a peer connects are deferred
+        peer.handle_std_event("mine empty").await;
+        peer.handle_std_event("stats").await;
+        assert_eq!(peer.chain().len(), 1);
+
+        // and run once one connects
+        peer.stop_waiting_for_peers("a peer connected").await;
+        assert_eq!(peer.chain().len(), 2);
+        peer.handle_std_event("mine empty").await;
+        assert_eq!(peer.chain().len(), 3);
+    }
+
+    fn new_txn_msg(amount: u64) -> TxnMessage {
+        let keys = identity::Keypair::generate_ed25519();
+        TxnMessage::NewTransaction {
+            txn: Transaction::random_transaction(amount, keys),
+            source: "remote".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_txn_amount_must_be_numeric() {
+        let mut peer = set_up_peer_with_chain(Chain::genesis(), PeerConfig::default()).await;
+        for bad in ["abc", "-1", "1.5"] {
+            peer.handle_std_event(&format!("txn {}", bad)).await;
+        }
+        assert!(peer.txns().is_empty());
+
+        peer.handle_std_event("txn 5").await;
+        assert!(peer.txns().iter().all(|txn| txn.amount == 5));
+        assert_eq!(peer.txns().len(), 1);
+
+        // to a chosen receiver, with no more than one
+        peer.handle_std_event("txn alice bob 7").await;
+        peer.handle_std_event("txn bob 7").await;
+        assert_eq!(peer.txns().len(), 2);
+        assert!(peer
+            .txns()
+            .iter()
+            .any(|txn| (txn.receiver.as_str(), txn.amount) == ("bob", 7)));
+    }
+
+    #[tokio::test]
+    async fn test_archive_accepted_blocks() {
+        let path: String = temp_path("peer_archive.jsonl");
+        let _ = std::fs::remove_file(&path);
+        let config = PeerConfig {
+            archive_path: Some(path.clone()),
+            ..PeerConfig::default()
+        };
+        let mut peer = set_up_peer_with_chain(Chain::genesis(), config).await;
+
+        // blocks that extend the main chain are archived, and those that only start a fork aren't
+        let block_1: Block = Block::mine_block(&Block::genesis(), "block 1", &MINER);
+        let block_2: Block = Block::mine_block(&block_1, "block 2", &MINER);
+        let fork_1: Block = Block::mine_block(&Block::genesis(), "fork block 1", &MINER);
+        let mut send = |blocks: &[Block]| {
+            for block in blocks {
+                peer.handle_pow_event(
+                    PowMessage::NewBlock {
+                        block: block.clone(),
+                        source: "remote".to_string(),
+                    },
+                    *REMOTE,
+                );
+            }
+        };
+        send(&[block_1.clone(), block_2.clone(), fork_1.clone()]);
+        let archived: Vec<Block> = file::read_blocks_jsonl(&path).await.unwrap();
+        assert_eq!(trace(archived), vec![block_1.clone(), block_2.clone()]);
+
+        // as are the blocks of a fork that the main chain switches to
+        let fork_2: Block = Block::mine_block(&fork_1, "fork block 2", &MINER);
+        let fork_3: Block = Block::mine_block(&fork_2, "fork block 3", &MINER);
+        send(&[fork_2.clone(), fork_3.clone()]);
+        assert_eq!(peer.chain().last().hash, fork_3.hash);
+        let archived: Vec<Block> = file::read_blocks_jsonl(&path).await.unwrap();
+        assert_eq!(
+            trace(archived),
+            vec![block_1, block_2, fork_1, fork_2, fork_3]
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_validate_cmd() {
+        let mut chain: Chain = Chain::genesis();
+        chain.mine_block("block 1", &MINER);
+        let mut peer = set_up_peer_with_chain(chain.clone(), PeerConfig::default()).await;
+
+        // a block that extends the tip is valid, but isn't added
+        let block: Block = Block::mine_block(chain.last(), "block 2", &MINER);
+        let json: String = serde_json::to_string(&block).unwrap();
+        assert!(trace(dry_run_block(&chain, &json))
+            .unwrap()
+            .contains("extends main chain block 1"));
+        peer.handle_std_event(&format!("validate block {}", json))
+            .await;
+        assert_eq!(peer.chain().len(), 2);
+
+        // one altered after it was mined is invalid, with the reason it was rejected
+        let tampered = Block {
+            data: "other data".to_string(),
+            ..block
+        };
+        let json: String = serde_json::to_string(&tampered).unwrap();
+        assert_eq!(
+            trace(dry_run_block(&chain, &json)),
+            Err(tampered.validate().unwrap_err().to_string())
+        );
+        peer.handle_std_event(&format!("validate block {}", json))
+            .await;
+        assert!(dry_run_block(&chain, "{\"idx\": ").is_err());
+        // as is one we already have
+        let json: String = serde_json::to_string(chain.last()).unwrap();
+        assert!(trace(dry_run_block(&chain, &json))
+            .unwrap_err()
+            .contains("duplicate"));
+
+        // a chain file is checked as it would be loaded, without replacing our chain
+        let path: String = temp_path("validate.json");
+        let mut other: Chain = chain.clone();
+        other.mine_block("block 2", &MINER);
+        assert!(file::write_chain(&other, &path).await.is_ok());
+        peer.handle_std_event(&format!("validate chain {}", path))
+            .await;
+        assert_eq!(peer.chain().last().hash, chain.last().hash);
+        assert!(trace(chain_summary(&chain, &other)).contains("the same genesis block"));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_txn_import() {
+        let mut peer = set_up_peer_with_chain(Chain::genesis(), PeerConfig::default()).await;
+        let wallet = identity::Keypair::generate_ed25519();
+        let txn: Transaction = Transaction::new("bob".to_string(), 7, wallet.clone());
+
+        // a transaction whose amount was altered after it was signed is rejected
+        let mut tampered: Transaction = txn.clone();
+        tampered.amount += 1;
+        let json: String = serde_json::to_string(&tampered).unwrap();
+        peer.handle_std_event(&format!("txn import {}", json)).await;
+        assert!(peer.txns().is_empty());
+
+        // one signed by its sender is added as it is, not re-signed by the peer
+        let json: String = serde_json::to_string(&txn).unwrap();
+        peer.handle_std_event(&format!("txn import {}", json)).await;
+        assert_eq!(trace(peer.txns()).len(), 1);
+        assert!(peer.txns().contains(&txn));
+
+        // including from a file
+        let other: Transaction = Transaction::new("carol".to_string(), 3, wallet);
+        let path: String = temp_path("txn.json");
+        std::fs::write(&path, serde_json::to_string(&other).unwrap()).unwrap();
+        peer.handle_std_event(&format!("txn import {}", path)).await;
+        assert!(peer.txns().contains(&other));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_reject_overdrawn_transaction() {
+        let mut peer = set_up_peer_with_chain(Chain::genesis(), PeerConfig::default()).await;
+        let keys = identity::Keypair::generate_ed25519();
+        let new_txn_msg = |amount: u64| TxnMessage::NewTransaction {
+            txn: Transaction::random_transaction(amount, keys.clone()),
+            source: "remote".to_string(),
+        };
+        peer.handle_txn_event(new_txn_msg(GENESIS_ALLOCATION as u64 + 1), *REMOTE);
+        assert!(peer.txns().is_empty());
+
+        // the second spend would overdraw once the first, still pending, is counted
+        peer.handle_txn_event(new_txn_msg(600), *REMOTE);
+        peer.handle_txn_event(new_txn_msg(600), *REMOTE);
+        assert_eq!(peer.txns().len(), 1);
+    }
+
+    #[test]
+    fn test_peers_table() {
+        let (discovered, dialled) = (PeerId::random(), PeerId::random());
+        let addrs: Vec<Multiaddr> = vec![
+            "/ip4/192.168.1.2/tcp/40001".parse().unwrap(),
+            "/ip6/::1/tcp/40001".parse().unwrap(),
+        ];
+        let table: String =
+            peers_table(&[(discovered, addrs.clone(), false), (dialled, vec![], true)]);
+        println!("{}", table);
+        let rows: Vec<&str> = table.lines().collect();
+        assert_eq!(rows[0], "Peers (2, 1 connected)");
+        assert!(rows[1].starts_with("Peer Id") && rows[1].ends_with("Addresses"));
+        // each address is on its own row, under its peer
+        assert!(rows[3].starts_with(&discovered.to_string()));
+        assert!(rows[3].contains("false") && rows[3].ends_with(&addrs[0].to_string()));
+        assert!(rows[4].trim_start().starts_with('|') && rows[4].ends_with(&addrs[1].to_string()));
+        assert!(rows[5].starts_with(&dialled.to_string()));
+        assert!(rows[5].contains("true") && rows[5].ends_with('-'));
+        assert_eq!(rows.len(), 6);
+
+        assert_eq!(peers_table(&[]).lines().count(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_sweep_expired_transactions() {
+        let mut peer = set_up_peer_with_chain(Chain::genesis(), PeerConfig::default()).await;
+        let now: i64 = Utc::now().timestamp();
+        let txn_until = |valid_until: i64| TxnMessage::NewTransaction {
+            txn: Transaction::with_expiry(
+                "receiver".to_string(),
+                1,
+                valid_until,
+                identity::Keypair::generate_ed25519(),
+            ),
+            source: "remote".to_string(),
+        };
+        // already expired transactions aren't added at all
+        peer.handle_txn_event(txn_until(now - 1), *REMOTE);
+        assert!(peer.txns().is_empty());
+
+        peer.handle_txn_event(txn_until(now + 60), *REMOTE);
+        peer.handle_txn_event(txn_until(now + 600), *REMOTE);
+        peer.sweep_pool_at(now);
+        assert_eq!(peer.txns().len(), 2);
+        peer.sweep_pool_at(now + 61);
+        assert_eq!(trace(peer.txns().len()), 1);
+        assert!(peer.txns().iter().all(|txn| txn.valid_until == now + 600));
+    }
+
+    #[tokio::test]
+    async fn test_rollback_returns_transactions() {
+        let mut peer = set_up_peer_with_chain(Chain::genesis(), PeerConfig::default()).await;
+        peer.handle_txn_event(new_txn_msg(1), *REMOTE);
+        peer.handle_std_event("mine").await;
+        assert!(peer.txns().is_empty());
+
+        peer.handle_std_event("rollback 1").await;
+        assert_eq!(peer.chain().len(), 1);
+        assert_eq!(trace(peer.txns().len()), 1);
+
+        // the genesis block can't be rolled back
+        peer.handle_std_event("rollback 1").await;
+        assert_eq!(peer.chain().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_mine_on_invalid_tip_keeps_txns() {
+        let mut chain: Chain = Chain::genesis();
+        chain.mine_block("block 1", &MINER);
+        let mut json: serde_json::Value = serde_json::to_value(&chain).unwrap();
+        json["main"][1]["data"] = serde_json::json!("tampered");
+        let chain: Chain = serde_json::from_value(json).unwrap();
+        assert!(chain.verify_tip().is_err());
+
+        // the pooled transactions stay in the pool when mining is refused
+        let mut peer = set_up_peer_with_chain(chain, PeerConfig::default()).await;
+        peer.handle_txn_event(new_txn_msg(1), *REMOTE);
+        peer.handle_std_event("mine").await;
+        assert_eq!(peer.chain().len(), 2);
+        assert_eq!(trace(peer.txns().len()), 1);
+    }
+
+    #[tokio::test]
+    async fn test_reorg_removes_mined_txns() {
+        let mut peer = set_up_peer_with_chain(Chain::genesis(), PeerConfig::default()).await;
+        let msg: TxnMessage = new_txn_msg(1);
+        peer.handle_txn_event(msg.clone(), *REMOTE);
+        peer.handle_std_event("mine").await;
+        let mined: Chain = peer.chain().clone();
+
+        // a mined transaction can't be replayed into the pool
+        peer.handle_txn_event(msg.clone(), *REMOTE);
+        assert!(peer.txns().is_empty());
+
+        // a rolled back transaction returns to the pool, until the fork that mined it becomes the main chain again
+        peer.handle_std_event("rollback 1").await;
+        assert_eq!(peer.txns().len(), 1);
+        let mut remote: Chain = mined.clone();
+        remote.mine_block("block 2", &MINER);
+        peer.handle_pow_event(
+            PowMessage::NewBlock {
+                block: remote.last().clone(),
+                source: "remote".to_string(),
+            },
+            *REMOTE,
+        );
+        assert_eq!(peer.chain().len(), 3);
+        assert!(trace(peer.txns()).is_empty());
+        peer.handle_txn_event(msg, *REMOTE);
+        assert!(peer.txns().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_rollback_drops_coinbases() {
+        let config = PeerConfig {
+            block_reward: Some(50),
+            ..PeerConfig::default()
+        };
+        let mut peer = set_up_peer_with_chain(Chain::genesis(), config).await;
+        peer.handle_txn_event(new_txn_msg(1), *REMOTE);
+        peer.handle_std_event("mine").await;
+        assert_eq!(peer.chain().last().transactions().len(), 2);
+
+        // only the pooled transaction is returned, so that the block mined next has just its own coinbase
+        peer.handle_std_event("rollback 1").await;
+        assert_eq!(trace(peer.txns().len()), 1);
+        peer.handle_std_event("mine").await;
+        assert_eq!(peer.chain().len(), 2);
+        assert_eq!(
+            peer.chain()
+                .last()
+                .transactions()
+                .iter()
+                .filter(|txn| txn.is_coinbase())
+                .count(),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reset() {
+        let mut peer = set_up_peer_with_chain(Chain::genesis(), allow_empty()).await;
+        peer.handle_std_event("mine empty").await;
+        peer.handle_std_event("mine empty").await;
+        let saved: Chain = peer.chain().clone();
+
+        // without confirmation, nothing is deleted
+        peer.handle_std_event("reset").await;
+        assert_eq!(peer.chain().len(), 3);
+
+        peer.handle_std_event("reset confirm").await;
+        assert_eq!(trace(peer.chain().len()), 1);
+        assert_eq!(peer.chain().last().hash, Chain::genesis().last().hash);
+
+        // seeding from a file keeps its main chain but none of its forks
+        let path: String = temp_path("reset.json");
+        let mut forked: Chain = saved.clone();
+        assert!(forked.rollback(1).is_ok());
+        assert!(file::write_chain(&forked, &path).await.is_ok());
+        peer.handle_std_event(&format!("reset {}", path)).await;
+        assert_eq!(peer.chain().len(), 2);
+        assert!(peer.chain().forks().is_empty());
+        assert_eq!(peer.chain().last().hash, saved.idx(1).unwrap().hash);
+        std::fs::remove_file(path).unwrap();
+
+        // a missing file leaves the chain as it was
+        peer.handle_std_event("reset no_such_file.json").await;
+        assert_eq!(peer.chain().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_show_json() {
+        let mut chain: Chain = Chain::genesis();
+        for i in 1..4 {
+            chain.mine_block(&format!("block {}", i), &MINER);
+        }
+        // keep the last block as a fork
+        assert!(chain.rollback(1).is_ok());
+        let mut peer = set_up_peer_with_chain(chain.clone(), PeerConfig::default()).await;
+        peer.handle_txn_event(new_txn_msg(1), *REMOTE);
+
+        let json = |target: &str| trace(peer.show_json(target).unwrap().unwrap());
+        let blocks: Blocks = serde_json::from_str(&json("chain")).unwrap();
+        assert!(blocks.validate().is_ok());
+        let hashes = |blocks: &Blocks| blocks.iter().map(|b| b.hash.clone()).collect::<Vec<_>>();
+        assert_eq!(hashes(&blocks), hashes(chain.blocks()));
+
+        let forks: Vec<BranchSummary> = serde_json::from_str(&json("forks")).unwrap();
+        assert_eq!(forks, chain.forks().summaries());
+        assert_eq!(
+            (forks[0].forkpoint_idx, forks[0].endpoint_idx, forks[0].len),
+            (2, 3, 1)
+        );
+        let head: HeadInfo = serde_json::from_str(&json("head")).unwrap();
+        assert_eq!(head, chain.head());
+        let orphans: Vec<BranchSummary> = serde_json::from_str(&json("orphans")).unwrap();
+        assert!(orphans.is_empty());
+        let pool: Vec<Transaction> = serde_json::from_str(&json("pool")).unwrap();
+        assert_eq!(pool.len(), 1);
+
+        assert!(peer.show_json("peers").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_mine_batch_of_transactions() {
+        let mut peer = set_up_peer_with_chain(Chain::genesis(), PeerConfig::default()).await;
+        for amount in [1, 2, 3, 4] {
+            peer.handle_txn_event(new_txn_msg(amount), *REMOTE);
+        }
+        peer.handle_std_event("mine 3").await;
+
+        let txns: Vec<Transaction> = peer.chain().last().transactions();
+        assert_eq!(trace(txns.len()), 3);
+        assert!(txns
+            .iter()
+            .all(|txn| Transaction::validate_transaction(txn).is_ok()));
+
+        // the rest of the pool is mined by default
+        peer.handle_std_event("mine").await;
+        assert_eq!(peer.chain().last().transactions().len(), 1);
+        assert_eq!(peer.chain().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_reject_block_with_invalid_transaction() {
+        let mut peer = set_up_peer_with_chain(Chain::genesis(), PeerConfig::default()).await;
+        let keys = identity::Keypair::generate_ed25519();
+        let valid: Transaction = Transaction::random_transaction(1, keys.clone());
+        let invalid = Transaction {
+            amount: 2,
+            ..Transaction::random_transaction(1, keys)
+        };
+        let data: String = Block::transactions_data(&[valid.clone(), invalid]);
+        let mixed: Block = Block::mine_block(&Block::genesis(), &data, &MINER);
+        peer.handle_pow_event(new_block_msg(mixed), *REMOTE);
+        assert_eq!(peer.chain().len(), 1);
+
+        let data: String = Block::transactions_data(&[valid]);
+        let valid_block: Block = Block::mine_block(&Block::genesis(), &data, &MINER);
+        peer.handle_pow_event(new_block_msg(valid_block), *REMOTE);
+        assert_eq!(peer.chain().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_metrics() {
+        let mut peer = set_up_peer_with_chain(Chain::genesis(), PeerConfig::default()).await;
+        assert_eq!(*peer.metrics(), Metrics::default());
+
+        // competing blocks, the first of which wins the tie-break, so that the second starts a fork
+        let (block, competitor): (Block, Block) = {
+            let a: Block = Block::mine_block(&Block::genesis(), "block 1", &MINER);
+            let b: Block = Block::mine_block(&Block::genesis(), "other block 1", &MINER);
+            if a.beats(&b) {
+                (a, b)
+            } else {
+                (b, a)
+            }
+        };
+        // and a child that makes the fork the heaviest
+        let child: Block = Block::mine_block(&competitor, "other block 2", &MINER);
+        for b in [block, competitor, child.clone()] {
+            peer.handle_pow_event(new_block_msg(b), *REMOTE);
+        }
+        // a tampered block, and a duplicate, which isn't counted as rejected
+        let mut tampered: Block = Block::mine_block(&child, "block 3", &MINER);
+        tampered.data = "tampered".to_string();
+        peer.handle_pow_event(new_block_msg(tampered), *REMOTE);
+        peer.handle_pow_event(new_block_msg(child), *REMOTE);
+
+        let keys = identity::Keypair::generate_ed25519();
+        peer.handle_txn_event(new_txn_msg(1), *REMOTE);
+        peer.handle_txn_event(
+            TxnMessage::NewTransaction {
+                txn: Transaction {
+                    amount: 2,
+                    ..Transaction::random_transaction(1, keys)
+                },
+                source: "remote".to_string(),
+            },
+            *REMOTE,
+        );
+
+        assert_eq!(
+            trace(peer.metrics()),
+            &Metrics {
+                blocks_accepted: 3,
+                blocks_rejected: 1,
+                forks_created: 1,
+                reorgs: 1,
+                txns_accepted: 1,
+                txns_rejected: 1,
+            }
+        );
+        assert_eq!(
+            peer.show_json("metrics").unwrap().unwrap(),
+            serde_json::to_string(peer.metrics()).unwrap()
+        );
+    }
+
+    fn block_request_msg(hash: &str, ttl: u8) -> PowMessage {
+        PowMessage::BlockRequest {
+            idx: 1,
+            hash: hash.to_string(),
+            target: None,
+            source: "remote".to_string(),
+            ttl,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_block_request_ttl() {
+        let mut peer = set_up_peer_with_chain(Chain::genesis(), PeerConfig::default()).await;
+        let published = |peer: &Peer| peer.network_rates().published_rate("block request");
+
+        // an expired request for a block we don't have is not forwarded
+        peer.handle_pow_event(block_request_msg("unknown 1", 0), *REMOTE);
+        assert_eq!(published(&peer), 0.0);
+
+        // an unexpired request is forwarded once, and not again while we are waiting on it
+        peer.handle_pow_event(block_request_msg("unknown 2", BLOCK_REQUEST_TTL), *REMOTE);
+        let forwarded: f64 = published(&peer);
+        assert!(forwarded > 0.0);
+        peer.handle_pow_event(block_request_msg("unknown 2", BLOCK_REQUEST_TTL), *REMOTE);
+        assert_eq!(published(&peer), forwarded);
+    }
+
+    #[tokio::test]
+    async fn test_retry_and_expire_orphans() {
+        let mut chain: Chain = Chain::genesis();
+        let mut remote: Chain = Chain::genesis();
+        for i in 1..4 {
+            remote.mine_block(&format!("block {}", i), &MINER);
+        }
+        assert!(chain.store_new_block(remote.last().clone()).is_err());
+        assert_eq!(chain.orphans().len(), 1);
+        let mut peer = set_up_peer_with_chain(chain, PeerConfig::default()).await;
+        let published = |peer: &Peer| peer.network_rates().published_rate("block request");
+
+        // the missing parent is re-requested
+        let now = tokio::time::Instant::now();
+        peer.retry_orphans_at(now);
+        assert!(trace(published(&peer)) > 0.0);
+        assert_eq!(peer.chain().orphans().len(), 1);
+
+        // and the orphan is expired once it has stayed disconnected for too long
+        peer.retry_orphans_at(now + ORPHAN_TTL + std::time::Duration::from_secs(1));
+        assert!(peer.chain().orphans().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_retry_block_requests() {
+        let mut remote: Chain = Chain::genesis();
+        for i in 1..4 {
+            remote.mine_block(&format!("block {}", i), &MINER);
+        }
+        let mut peer = set_up_peer_with_chain(Chain::genesis(), PeerConfig::default()).await;
+        let published = |peer: &Peer| peer.network_rates().published_rate("block request");
+        let new_block = |idx: usize| PowMessage::NewBlock {
+            block: remote.idx(idx).unwrap().clone(),
+            source: "remote".to_string(),
+        };
+        let parent_hash: String = remote.idx(2).unwrap().hash.clone();
+
+        // an orphan's missing parent is requested, and recorded until it arrives
+        peer.handle_pow_event(new_block(3), *REMOTE);
+        assert_eq!(peer.block_request_attempts(&parent_hash), Some(1));
+        let requested: f64 = trace(published(&peer));
+        assert!(requested > 0.0);
+
+        // it isn't requested again while the request is in flight, even if timed out requests are retried
+        let now = tokio::time::Instant::now();
+        peer.handle_pow_event(new_block(3), *REMOTE);
+        peer.retry_requests_at(now);
+        assert_eq!(peer.block_request_attempts(&parent_hash), Some(1));
+        assert_eq!(published(&peer), requested);
+
+        // it is re-sent once it times out, and then waited on for longer
+        peer.retry_requests_at(now + BLOCK_REQUEST_TIMEOUT);
+        assert_eq!(peer.block_request_attempts(&parent_hash), Some(2));
+        assert!(published(&peer) > requested);
+        peer.retry_requests_at(now + BLOCK_REQUEST_TIMEOUT * 2);
+        assert_eq!(peer.block_request_attempts(&parent_hash), Some(2));
+
+        // it isn't cleared by a response whose block is invalid
+        let mut tampered: Block = remote.idx(2).unwrap().clone();
+        tampered.data = "tampered".to_string();
+        peer.handle_pow_event(
+            PowMessage::BlockResponse {
+                target: "local".to_string(),
+                source: "remote".to_string(),
+                block: tampered,
+            },
+            *REMOTE,
+        );
+        assert_eq!(peer.block_request_attempts(&parent_hash), Some(2));
+
+        // and is cleared once a valid response arrives
+        peer.handle_pow_event(
+            PowMessage::BlockResponse {
+                target: "local".to_string(),
+                source: "remote".to_string(),
+                block: remote.idx(2).unwrap().clone(),
+            },
+            *REMOTE,
+        );
+        assert_eq!(peer.block_request_attempts(&parent_hash), None);
+
+        // whereas a request that is never responded to is given up on after its last attempt
+        let grandparent_hash: String = remote.idx(1).unwrap().hash.clone();
+        assert_eq!(peer.block_request_attempts(&grandparent_hash), Some(1));
+        let mut later = tokio::time::Instant::now();
+        for attempts in 1..MAX_BLOCK_REQUEST_ATTEMPTS {
+            later += BLOCK_REQUEST_TIMEOUT * 2u32.pow(attempts - 1);
+            peer.retry_requests_at(later);
+            assert_eq!(
+                peer.block_request_attempts(&grandparent_hash),
+                Some(attempts + 1)
+            );
+        }
+        peer.retry_requests_at(
+            later + BLOCK_REQUEST_TIMEOUT * 2u32.pow(MAX_BLOCK_REQUEST_ATTEMPTS),
+        );
+        assert_eq!(peer.block_request_attempts(&grandparent_hash), None);
+    }
+
+    #[tokio::test]
+    async fn test_mine_in_background() {
+        let mut peer = set_up_peer_with_chain(Chain::genesis(), PeerConfig::default()).await;
+        peer.set_command_source(tokio::io::empty());
+        peer.handle_txn_event(new_txn_msg(1), *REMOTE);
+        peer.handle_std_event("mine start").await;
+
+        let mined = tokio::time::timeout(std::time::Duration::from_secs(30), async {
+            while peer.chain().len() < 2 {
+                peer.step().await
+            }
+        });
+        assert!(mined.await.is_ok());
+        assert_eq!(peer.chain().last().transactions().len(), 1);
+        peer.handle_std_event("mine stop").await;
+    }
+
+    #[tokio::test]
+    async fn test_restarted_miner_drops_mined_txns() {
+        let mut peer = set_up_peer_with_chain(Chain::genesis(), PeerConfig::default()).await;
+        let msg: TxnMessage = new_txn_msg(1);
+        let TxnMessage::NewTransaction { txn, .. } = msg.clone();
+        peer.handle_txn_event(msg, *REMOTE);
+        peer.handle_std_event("mine start").await;
+        assert!(peer.txns().is_empty());
+
+        // another miner's block takes the transaction being mined, which isn't returned to the pool when mining restarts
+        let mut remote: Chain = Chain::genesis();
+        remote.mine_block(&Block::transactions_data(&[txn]), &MINER);
+        peer.handle_pow_event(
+            PowMessage::NewBlock {
+                block: remote.last().clone(),
+                source: "remote".to_string(),
+            },
+            *REMOTE,
+        );
+        assert_eq!(peer.chain().len(), 2);
+        peer.refresh_miner();
+        assert!(trace(peer.txns()).is_empty());
+        peer.handle_std_event("mine stop").await;
+    }
+
+    #[tokio::test]
+    async fn test_dial_bootstrap_peer() {
+        let timeout = std::time::Duration::from_secs(30);
+        let mut remote = set_up_peer_with_chain(Chain::genesis(), PeerConfig::default()).await;
+        remote.set_command_source(tokio::io::empty());
+        let listening = tokio::time::timeout(timeout, async {
+            while remote.listen_addrs().is_empty() {
+                remote.step().await
+            }
+        });
+        assert!(listening.await.is_ok());
+        let remote_id = remote.local_peer_id();
+        let remote_addr: Multiaddr = remote
+            .listen_addrs()
+            .into_iter()
+            .find(|addr| addr.to_string().starts_with("/ip4/127.0.0.1"))
+            .unwrap_or_else(|| remote.listen_addrs()[0].clone());
+        tokio::spawn(async move {
+            loop {
+                remote.step().await
+            }
+        });
+
+        let mut peer = set_up_peer_with_chain(
+            Chain::genesis(),
+            PeerConfig {
+                bootstrap: vec![trace(remote_addr.to_string()).parse().unwrap()],
+                ..PeerConfig::default()
+            },
+        )
+        .await;
+        peer.set_command_source(tokio::io::empty());
+        // a malformed or unreachable address is reported, rather than aborting the peer
+        peer.handle_std_event("dial not-a-multiaddr").await;
+        peer.handle_std_event("dial /ip4/127.0.0.1/tcp/1").await;
+
+        let connected = tokio::time::timeout(timeout, async {
+            while !peer.is_connected(&remote_id) {
+                peer.step().await
+            }
+        });
+        assert!(connected.await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_publish_queued_until_peer_connects() {
+        let timeout = std::time::Duration::from_secs(30);
+        let mut remote = set_up_peer_with_chain(Chain::genesis(), PeerConfig::default()).await;
+        remote.set_command_source(tokio::io::empty());
+        let listening = tokio::time::timeout(timeout, async {
+            while remote.listen_addrs().is_empty() {
+                remote.step().await
+            }
+        });
+        assert!(listening.await.is_ok());
+        let remote_addr: Multiaddr = remote.listen_addrs()[0].clone();
+        tokio::spawn(async move {
+            loop {
+                remote.step().await
+            }
+        });
+
+        // before any peer is known, a mined block can't be published, so is queued rather than lost
+        let mut peer = set_up_peer_with_chain(Chain::genesis(), allow_empty()).await;
+        peer.set_command_source(tokio::io::empty());
+        peer.handle_std_event("mine empty").await;
+        assert_eq!(trace(peer.queued_msgs()), 1);
+
+        // and is published once a peer connects and subscribes
+        peer.dial(remote_addr);
+        let flushed = tokio::time::timeout(timeout, async {
+            while peer.queued_msgs() > 0 {
+                peer.step().await
+            }
+        });
+        assert!(flushed.await.is_ok());
+    }
+
+    #[test]
+    fn test_block_contents() {
+        let txns: Vec<Transaction> = (1..=2)
+            .map(|amount| Transaction::random_transaction(amount, Keypair::generate_ed25519()))
+            .collect();
+        let mut chain: Chain = Chain::genesis();
+        chain.mine_block(&Block::transactions_data(&txns), &MINER);
+        chain.mine_block("raw data", &MINER);
+        chain.mine_block(EMPTY_BLOCK_DATA, &MINER);
+
+        // a block of transactions lists each of them
+        let contents: String = trace(block_contents(&chain, 1)).unwrap();
+        assert!(contents.contains("2 transaction(s)"));
+        assert!(txns.iter().all(|txn| contents.contains(&txn.to_string())));
+        // other blocks show their raw data, if any
+        assert!(block_contents(&chain, 2)
+            .unwrap()
+            .ends_with("Data: raw data"));
+        assert!(block_contents(&chain, 3)
+            .unwrap()
+            .ends_with("No transactions."));
+        assert!(block_contents(&chain, 4).is_none());
+    }
+
+    #[test]
+    fn test_chain_diff() {
+        let mut chain: Chain = Chain::genesis();
+        chain.mine_block("shared block", &MINER);
+        assert_eq!(
+            trace(chain_diff(&chain, &chain.to_hashes())),
+            "no divergence; both chains end at idx 1"
+        );
+
+        // a remote chain that shares the first two blocks and then has blocks of its own
+        let mut remote: Chain = chain.clone();
+        chain.mine_block("local block", &MINER);
+        (0..3).for_each(|i| remote.mine_block(&format!("remote block {}", i), &MINER));
+        let diff: String = trace(chain_diff(&chain, &remote.to_hashes()));
+        assert!(diff.starts_with("diverged at idx 1"));
+        assert!(diff.ends_with("local +1, remote +3"));
+        assert!(chain_diff(&chain, &[]).starts_with("no common ancestor"));
+    }
+
+    #[tokio::test]
+    async fn test_floodsub_peers_exchange_blocks() {
+        let timeout = std::time::Duration::from_secs(30);
+        let config = PeerConfig::from_args(
+            ["--protocol", "floodsub", "--allow-empty-blocks"]
+                .map(String::from)
+                .into_iter(),
+        );
+        assert_eq!(config.protocol, Protocol::Floodsub);
+        let mut remote = set_up_peer_with_chain(Chain::genesis(), config.clone()).await;
+        remote.set_command_source(tokio::io::empty());
+        let listening = tokio::time::timeout(timeout, async {
+            while remote.listen_addrs().is_empty() {
+                remote.step().await
+            }
+        });
+        assert!(listening.await.is_ok());
+        let remote_addr: Multiaddr = remote.listen_addrs()[0].clone();
+        let mut remote_events = remote.subscribe();
+        tokio::spawn(async move {
+            loop {
+                remote.step().await
+            }
+        });
+
+        // a block mined before connecting is queued, then flooded to the remote peer once it connects
+        let mut peer = set_up_peer_with_chain(Chain::genesis(), config).await;
+        peer.set_command_source(tokio::io::empty());
+        peer.handle_std_event("mine empty").await;
+        assert_eq!(peer.queued_msgs(), 1);
+        peer.dial(remote_addr);
+        let hash: String = peer.chain().last().hash.clone();
+        let received = tokio::time::timeout(timeout, async {
+            loop {
+                tokio::select! {
+                    _ = peer.step() => {}
+                    Ok(event) = remote_events.recv() => {
+                        if let PeerEvent::BlockAccepted { hash: accepted, .. } = trace(event) {
+                            if accepted == hash {
+                                break;
+                            }
+                        }
+                    }
+                }
+            }
+        });
+        assert!(received.await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_chain_dirty_until_persisted() {
+        let mut peer = set_up_peer_with_chain(Chain::genesis(), allow_empty()).await;
+        assert!(!peer.is_chain_dirty());
+        // a zero period is refused, rather than panicking the timer
+        peer.handle_std_event("autosave 0").await;
+        peer.handle_std_event("autosave 30").await;
+        peer.handle_std_event("mine empty").await;
+        assert!(trace(peer.is_chain_dirty()));
+
+        let paths = [
+            temp_path("dirty_chain"),
+            temp_path("dirty_pool"),
+            temp_path("dirty_bans"),
+        ];
+        // persisting elsewhere (e.g. to back up the chain) doesn't save it to the file it is loaded from
+        assert!(peer
+            .persist_to(&paths[0], &paths[1], &paths[2])
+            .await
+            .is_ok());
+        assert!(peer.is_chain_dirty());
+        for path in paths {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_persist_round_trips() {
+        let mut peer = set_up_peer_with_chain(Chain::genesis(), allow_empty()).await;
+        peer.handle_std_event("mine empty").await;
+        peer.handle_txn_event(new_txn_msg(1), *REMOTE);
+        let banned = PeerId::random();
+        peer.ban(banned);
+        let (chain_path, pool_path, bans_path) = (
+            temp_path("persist_chain"),
+            temp_path("persist_pool"),
+            temp_path("persist_bans"),
+        );
+        assert!(peer
+            .persist_to(&chain_path, &pool_path, &bans_path)
+            .await
+            .map_err(trace)
+            .is_ok());
+
+        let chain: Chain = file::read_chain(&chain_path).await.unwrap();
+        assert_eq!(chain.last().hash, peer.chain().last().hash);
+        let txns: Vec<Transaction> = file::read_pool(&pool_path).await.unwrap();
+        assert_eq!(txns, peer.txns().iter().cloned().collect::<Vec<_>>());
+        assert_eq!(txns.len(), 1);
+        let bans: Vec<String> = file::read_bans(&bans_path).await.unwrap();
+        assert_eq!(bans, vec![banned.to_string()]);
+        std::fs::remove_file(chain_path).unwrap();
+        std::fs::remove_file(pool_path).unwrap();
+        std::fs::remove_file(bans_path).unwrap();
+    }
+
+    #[test]
+    fn test_range_response() {
+        let mut chain: Chain = Chain::genesis();
+        for i in 1..5 {
+            chain.mine_block(&format!("block {}", i), &MINER);
+        }
+        let idxs = |resp: Option<PowMessage>| match resp {
+            Some(PowMessage::BlockRangeResponse { blocks, .. }) => {
+                assert!(blocks.validate().is_ok());
+                Some((blocks.first().idx, blocks.last().idx))
+            }
+            _ => None,
+        };
+        assert_eq!(
+            idxs(range_response(&chain, 1, 3, 10, "requester", "local")),
+            Some((1, 3))
+        );
+        // capped by the maximum count, and by the end of the chain
+        assert_eq!(
+            idxs(range_response(&chain, 0, 4, 2, "requester", "local")),
+            Some((0, 1))
+        );
+        assert_eq!(
+            idxs(range_response(&chain, 3, 100, 10, "requester", "local")),
+            Some((3, 4))
+        );
+        // out-of-bounds ranges have no response
+        assert_eq!(
+            idxs(range_response(&chain, 5, 10, 10, "requester", "local")),
+            None
+        );
+        assert_eq!(
+            idxs(range_response(&chain, 3, 1, 10, "requester", "local")),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_range_response_syncs_blocks() {
+        let mut remote: Chain = Chain::genesis();
+        for i in 1..5 {
+            remote.mine_block(&format!("block {}", i), &MINER);
+        }
+        let mut peer = set_up_peer_with_chain(Chain::genesis(), PeerConfig::default()).await;
+        let resp = range_response(&remote, 1, 4, 10, "local", "remote").unwrap();
+        peer.handle_pow_event(resp, *REMOTE);
+        assert_eq!(peer.chain().last().hash, remote.last().hash);
+    }
+
+    #[tokio::test]
+    async fn test_range_response_with_gap_rejected() {
+        let mut remote: Chain = Chain::genesis();
+        for i in 1..5 {
+            remote.mine_block(&format!("block {}", i), &MINER);
+        }
+        let mut peer = set_up_peer_with_chain(Chain::genesis(), PeerConfig::default()).await;
+        // blocks 1, 3, and 4, which follow on from our genesis block but skip block 2
+        let gapped: Vec<&Block> = [1, 3, 4]
+            .iter()
+            .map(|&idx| remote.idx(idx).unwrap())
+            .collect();
+        let resp = PowMessage::BlockRangeResponse {
+            blocks: serde_json::from_str(&serde_json::to_string(&gapped).unwrap()).unwrap(),
+            target: "local".to_string(),
+            source: "remote".to_string(),
+        };
+        peer.handle_pow_event(resp, *REMOTE);
+        // none of the range is stored, not even the block that connects
+        assert_eq!(trace(peer.chain().len()), 1);
+        assert_eq!(peer.metrics().blocks_rejected, 1);
+    }
+
+    #[tokio::test]
+    async fn test_subscribe_block_accepted() {
+        let mut peer = set_up_peer_with_chain(Chain::genesis(), allow_empty()).await;
+        let mut events = peer.subscribe();
+        peer.handle_std_event("mine empty").await;
+        let tip: String = peer.chain().last().hash.clone();
+        assert!(matches!(
+            trace(events.try_recv()),
+            Ok(PeerEvent::BlockAccepted { idx: 1, hash }) if hash == tip
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_chain_snapshot() {
+        let mut peer = set_up_peer_with_chain(Chain::genesis(), allow_empty()).await;
+        let mut snapshot = peer.chain_snapshot();
+        let before = snapshot.borrow().clone();
+        peer.handle_std_event("mine empty").await;
+        // the shared snapshot follows the main chain, while those already taken stay as they were
+        assert!(snapshot.has_changed().unwrap());
+        assert_eq!(
+            trace(snapshot.borrow_and_update().head()),
+            &peer.chain().head()
+        );
+        assert_eq!(before.head().height, 0);
+    }
+
+    #[tokio::test]
+    async fn test_ban_unban() {
+        let mut peer = set_up_peer_with_chain(Chain::genesis(), PeerConfig::default()).await;
+        let remote = PeerId::random();
+
+        peer.handle_std_event(&format!("ban {}", remote)).await;
+        peer.handle_std_event(&format!("ban {}", remote)).await;
+        assert_eq!(trace(peer.banned()).len(), 1);
+        assert!(peer.banned().contains(&remote));
+        // invalid peer ids are rejected
+        peer.handle_std_event("ban not-a-peer-id").await;
+        assert_eq!(peer.banned().len(), 1);
+
+        peer.handle_std_event(&format!("unban {}", remote)).await;
+        assert!(peer.banned().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_banned_peer_ignored() {
+        let mut peer = set_up_peer_with_chain(Chain::genesis(), PeerConfig::default()).await;
+        let remote = PeerId::random();
+        let block: Block = Block::mine_block(&Block::genesis(), "block 1", &MINER);
+        let msg = PowMessage::NewBlock {
+            block: block.clone(),
+            source: remote.to_string(),
+        };
+
+        assert!(peer.ban(remote));
+        peer.handle_pow_event(msg.clone(), *REMOTE);
+        assert_eq!(trace(peer.chain().len()), 1);
+        // as is a message the banned peer delivers, whatever source it claims
+        let relayed = PowMessage::NewBlock {
+            block,
+            source: REMOTE.to_string(),
+        };
+        peer.handle_pow_event(relayed, remote);
+        assert_eq!(peer.chain().len(), 1);
+        // the same message is handled once the peer is unbanned
+        assert!(peer.unban(&remote));
+        peer.handle_pow_event(msg, *REMOTE);
+        assert_eq!(peer.chain().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_auto_ban_after_invalid_msgs() {
+        let mut peer = set_up_peer_with_chain(Chain::genesis(), PeerConfig::default()).await;
+        let (remote, victim) = (PeerId::random(), PeerId::random());
+        // the messages claim to be from another peer, which mustn't be banned in its place
+        let invalid_txn_msg = || {
+            let mut txn = Transaction::random_transaction(1, identity::Keypair::generate_ed25519());
+            txn.amount = 2; // no longer matches the hash
+            TxnMessage::NewTransaction {
+                txn,
+                source: victim.to_string(),
+            }
+        };
+        for _ in 1..MAX_INVALID_MSGS {
+            peer.handle_txn_event(invalid_txn_msg(), remote);
+        }
+        assert!(peer.banned().is_empty());
+        peer.handle_txn_event(invalid_txn_msg(), remote);
+        assert!(trace(peer.banned()).contains(&remote));
+        assert!(!peer.banned().contains(&victim));
+    }
+}
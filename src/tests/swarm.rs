@@ -0,0 +1,377 @@
+/******************
+      TESTS
+********************/
+#[cfg(test)] // cargo test swarm -- --nocapture
+mod swarm_tests {
+    use crate::{
+        block::Block,
+        message::{PowMessage, COMPRESSED_PREFIX},
+        network::{Broadcast, ConnectionCounts},
+        swarm::{
+            check_source, message_id, set_up_blockchain_swarm, Compression, PeerTable, Roles,
+            SourceCheck, SwarmConfig, SwarmErr, CHAIN_TOPIC,
+        },
+        tests::common::MINER,
+        util::trace,
+    };
+    use libp2p::{
+        core::{connection::PendingConnectionError, ConnectedPoint},
+        futures::StreamExt,
+        gossipsub::{DataTransform, GossipsubMessage, IdentTopic, RawGossipsubMessage},
+        identity::Keypair,
+        swarm::SwarmEvent,
+        Multiaddr, PeerId,
+    };
+    use std::{num::NonZeroU32, time::Duration};
+    use tokio::sync::mpsc;
+
+    // A message as gossiped by a peer and decompressed on receipt, with a random sequence number
+    fn gossip(msg: &PowMessage, source: PeerId, topic: &IdentTopic) -> GossipsubMessage {
+        GossipsubMessage {
+            source: Some(source),
+            data: serde_json::to_vec(msg).unwrap(),
+            sequence_number: Some(rand::random()),
+            topic: topic.hash(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fixed_listen_addr() {
+        let (pow_sender, _pow_receiver) = mpsc::unbounded_channel();
+        let (txn_sender, _txn_receiver) = mpsc::unbounded_channel();
+        let swarm = set_up_blockchain_swarm(
+            Keypair::generate_ed25519(),
+            "/ip4/127.0.0.1/tcp/40001",
+            &SwarmConfig::default(),
+            pow_sender,
+            txn_sender,
+        )
+        .await;
+        assert!(swarm.map_err(trace).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_invalid_listen_addr() {
+        let (pow_sender, _pow_receiver) = mpsc::unbounded_channel();
+        let (txn_sender, _txn_receiver) = mpsc::unbounded_channel();
+        let swarm = set_up_blockchain_swarm(
+            Keypair::generate_ed25519(),
+            "not-a-multiaddr",
+            &SwarmConfig::default(),
+            pow_sender,
+            txn_sender,
+        )
+        .await;
+        assert!(matches!(
+            swarm.map(|_| ()).map_err(trace),
+            Err(SwarmErr::InvalidListenAddr { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_custom_config() {
+        let (pow_sender, _pow_receiver) = mpsc::unbounded_channel();
+        let (txn_sender, _txn_receiver) = mpsc::unbounded_channel();
+        let config = SwarmConfig {
+            heartbeat_interval: Duration::from_secs(1),
+            idle_timeout: Duration::from_secs(60),
+            history_length: 6,
+            max_transmit_size: 1_048_576,
+            roles: Roles::default(),
+            source_check: SourceCheck::Strict,
+            max_pending_incoming: Some(4),
+            max_established_incoming: Some(8),
+            max_established_outgoing: None,
+        };
+        let swarm = set_up_blockchain_swarm(
+            Keypair::generate_ed25519(),
+            "/ip4/127.0.0.1/tcp/0",
+            &config,
+            pow_sender,
+            txn_sender,
+        )
+        .await;
+        assert!(swarm.map_err(trace).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_connection_limits() {
+        let set_up = |config: SwarmConfig| async move {
+            let (pow_sender, _pow_receiver) = mpsc::unbounded_channel();
+            let (txn_sender, _txn_receiver) = mpsc::unbounded_channel();
+            set_up_blockchain_swarm(
+                Keypair::generate_ed25519(),
+                "/ip4/127.0.0.1/tcp/0",
+                &config,
+                pow_sender,
+                txn_sender,
+            )
+            .await
+            .unwrap()
+        };
+        // a peer that accepts no one, and one that dials it
+        let mut full = set_up(SwarmConfig {
+            max_established_incoming: Some(0),
+            ..SwarmConfig::default()
+        })
+        .await;
+        let mut dialler = set_up(SwarmConfig::default()).await;
+        let addr = loop {
+            if let SwarmEvent::NewListenAddr { address, .. } = full.select_next_some().await {
+                break address;
+            }
+        };
+        dialler.dial_addr(addr).unwrap();
+
+        // the connection is refused by the peer at its limit once the handshake completes, and never counted
+        let refused = async {
+            loop {
+                tokio::select! {
+                    event = full.select_next_some() => {
+                        if let SwarmEvent::IncomingConnectionError { error, .. } = event {
+                            break trace(error);
+                        }
+                    }
+                    _ = dialler.select_next_some() => {}
+                }
+            }
+        };
+        let error = tokio::time::timeout(Duration::from_secs(10), refused)
+            .await
+            .expect("connection is refused");
+        assert!(matches!(error, PendingConnectionError::ConnectionLimit(_)));
+        let counts: ConnectionCounts = full.connection_counts();
+        assert_eq!(
+            (counts.established_incoming, counts.established_outgoing),
+            (0, 0)
+        );
+    }
+
+    #[test]
+    fn test_peer_table() {
+        let mut table = PeerTable::default();
+        let (peer, other) = (PeerId::random(), PeerId::random());
+        let addr: Multiaddr = "/ip4/192.168.0.2/tcp/4001".parse().unwrap();
+        let addr_2: Multiaddr = "/ip6/fe80::2/tcp/4001".parse().unwrap();
+
+        // a peer discovered at two addresses, once each
+        table.discover(peer, addr.clone());
+        table.discover(peer, addr_2.clone());
+        table.discover(peer, addr.clone());
+        assert_eq!(
+            trace(table.peers()),
+            vec![(peer, vec![addr.clone(), addr_2.clone()], false)]
+        );
+
+        // is forgotten once both expire
+        table.expire(&peer, &addr);
+        assert_eq!(table.addresses(&peer), vec![addr_2.clone()]);
+        table.expire(&peer, &addr_2);
+        assert!(table.peers().is_empty());
+        // and expiring a peer that isn't known does nothing
+        table.expire(&other, &addr);
+        assert!(table.peers().is_empty());
+
+        // a peer we dialled directly is listed, without addresses, until its last connection closes
+        let endpoint = ConnectedPoint::Dialer {
+            address: addr.clone(),
+        };
+        let established =
+            |num_established: u32| SwarmEvent::<(), std::io::Error>::ConnectionEstablished {
+                peer_id: other,
+                endpoint: endpoint.clone(),
+                num_established: NonZeroU32::new(num_established).unwrap(),
+            };
+        let closed = |num_established: u32| SwarmEvent::<(), std::io::Error>::ConnectionClosed {
+            peer_id: other,
+            endpoint: endpoint.clone(),
+            num_established,
+            cause: None,
+        };
+        table.observe(&established(1));
+        table.observe(&established(2));
+        assert_eq!(table.peers(), vec![(other, vec![], true)]);
+        table.observe(&closed(1));
+        assert_eq!(table.peers(), vec![(other, vec![], true)]);
+        table.observe(&closed(0));
+        assert!(table.peers().is_empty());
+
+        // while a discovered peer that disconnects is still listed at its addresses
+        table.discover(other, addr.clone());
+        table.observe(&established(1));
+        table.observe(&closed(0));
+        assert_eq!(table.peers(), vec![(other, vec![addr], false)]);
+    }
+
+    #[tokio::test]
+    async fn test_roles() {
+        assert_eq!("all".parse::<Roles>(), Ok(Roles::default()));
+        assert!(trace("miner".parse::<Roles>()).is_err());
+
+        let (pow_sender, _pow_receiver) = mpsc::unbounded_channel();
+        let (txn_sender, _txn_receiver) = mpsc::unbounded_channel();
+        let config = SwarmConfig {
+            roles: "blocks".parse().unwrap(),
+            ..SwarmConfig::default()
+        };
+        let swarm = set_up_blockchain_swarm(
+            Keypair::generate_ed25519(),
+            "/ip4/127.0.0.1/tcp/0",
+            &config,
+            pow_sender,
+            txn_sender,
+        )
+        .await
+        .unwrap();
+        let topics: Vec<_> = swarm.behaviour().gossipsub.topics().cloned().collect();
+        assert_eq!(trace(topics), vec![CHAIN_TOPIC.hash()]);
+    }
+
+    #[test]
+    fn test_check_source() {
+        let (sender, relayer) = (PeerId::random(), PeerId::random());
+        let block: Block = Block::mine_block(&Block::genesis(), "block 1", &MINER);
+        let new_block = PowMessage::NewBlock {
+            block: block.clone(),
+            source: sender.to_string(),
+        };
+        let response = PowMessage::BlockResponse {
+            block,
+            target: relayer.to_string(),
+            source: sender.to_string(),
+        };
+        for msg in [&new_block, &response] {
+            // delivered by its declared source, the message is kept either way
+            assert!(check_source(msg, &sender, SourceCheck::Strict));
+            assert!(check_source(msg, &sender, SourceCheck::Lenient));
+            // delivered by anyone else, it is only kept when lenient
+            assert!(!check_source(msg, &relayer, SourceCheck::Strict));
+            assert!(check_source(msg, &relayer, SourceCheck::Lenient));
+        }
+
+        // requests may be forwarded, so aren't checked
+        let request = PowMessage::ChainRequest {
+            target: None,
+            source: sender.to_string(),
+        };
+        assert!(check_source(&request, &relayer, SourceCheck::Strict));
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        let zero_heartbeat = SwarmConfig {
+            heartbeat_interval: Duration::ZERO,
+            ..SwarmConfig::default()
+        };
+        assert!(matches!(
+            trace(zero_heartbeat.validate()),
+            Err(SwarmErr::InvalidConfig { .. })
+        ));
+        let zero_transmit_size = SwarmConfig {
+            max_transmit_size: 0,
+            ..SwarmConfig::default()
+        };
+        assert!(matches!(
+            trace(zero_transmit_size.validate()),
+            Err(SwarmErr::InvalidConfig { .. })
+        ));
+        let no_roles = SwarmConfig {
+            roles: Roles {
+                blocks: false,
+                txns: false,
+            },
+            ..SwarmConfig::default()
+        };
+        assert!(matches!(
+            trace(no_roles.validate()),
+            Err(SwarmErr::InvalidConfig { .. })
+        ));
+        assert!(SwarmConfig::default().validate().is_ok());
+    }
+
+    #[test]
+    fn test_message_id_new_block() {
+        let (peer_1, peer_2) = (PeerId::random(), PeerId::random());
+        let new_block = |block: &Block, source: &PeerId| PowMessage::NewBlock {
+            block: block.clone(),
+            source: source.to_string(),
+        };
+        let block: Block = Block::mine_block(&Block::genesis(), "block", &MINER);
+        let other: Block = Block::mine_block(&Block::genesis(), "other block", &MINER);
+
+        // the same block announced by different peers is the same message
+        assert_eq!(
+            trace(message_id(&gossip(
+                &new_block(&block, &peer_1),
+                peer_1,
+                &CHAIN_TOPIC
+            ))),
+            message_id(&gossip(&new_block(&block, &peer_2), peer_2, &CHAIN_TOPIC))
+        );
+        // different blocks are different messages
+        assert_ne!(
+            message_id(&gossip(&new_block(&block, &peer_1), peer_1, &CHAIN_TOPIC)),
+            message_id(&gossip(&new_block(&other, &peer_1), peer_1, &CHAIN_TOPIC))
+        );
+        // as are copies of a block with a different signature, which its hash doesn't cover
+        let forged: Block = Block {
+            sig: other.sig.clone(),
+            ..block.clone()
+        };
+        assert_ne!(
+            message_id(&gossip(&new_block(&block, &peer_1), peer_1, &CHAIN_TOPIC)),
+            message_id(&gossip(&new_block(&forged, &peer_2), peer_2, &CHAIN_TOPIC))
+        );
+        // including large blocks, whose announcements are compressed on the wire and identified once decompressed
+        let large: Block = Block::mine_block(&Block::genesis(), &"data ".repeat(1000), &MINER);
+        let announcement = gossip(&new_block(&large, &peer_1), peer_1, &CHAIN_TOPIC);
+        let raw = RawGossipsubMessage {
+            source: announcement.source,
+            data: Compression
+                .outbound_transform(&announcement.topic, announcement.data.clone())
+                .unwrap(),
+            sequence_number: announcement.sequence_number,
+            topic: announcement.topic.clone(),
+            signature: None,
+            key: None,
+            validated: false,
+        };
+        assert!(raw.data.starts_with(COMPRESSED_PREFIX));
+        let received: GossipsubMessage = Compression.inbound_transform(raw.clone()).unwrap();
+        assert_eq!(received.data, announcement.data);
+        assert_eq!(
+            message_id(&received),
+            message_id(&gossip(&new_block(&large, &peer_2), peer_2, &CHAIN_TOPIC))
+        );
+        assert_ne!(
+            message_id(&received),
+            message_id(&gossip(&new_block(&block, &peer_1), peer_1, &CHAIN_TOPIC))
+        );
+        // whereas a message that can't be decompressed is rejected before it is identified
+        let mut corrupted: RawGossipsubMessage = raw;
+        corrupted.data.truncate(corrupted.data.len() / 2);
+        assert!(trace(Compression.inbound_transform(corrupted)).is_err());
+    }
+
+    #[test]
+    fn test_message_id_requests() {
+        let request = |source: &PeerId| PowMessage::BlockRequest {
+            target: None,
+            source: source.to_string(),
+            idx: 1,
+            hash: "hash".to_string(),
+            ttl: 1,
+        };
+        let (peer_1, peer_2) = (PeerId::random(), PeerId::random());
+
+        // the same request from different peers, or made twice by a peer, is not deduplicated
+        assert_ne!(
+            message_id(&gossip(&request(&peer_1), peer_1, &CHAIN_TOPIC)),
+            message_id(&gossip(&request(&peer_2), peer_2, &CHAIN_TOPIC))
+        );
+        assert_ne!(
+            message_id(&gossip(&request(&peer_1), peer_1, &CHAIN_TOPIC)),
+            message_id(&gossip(&request(&peer_1), peer_1, &CHAIN_TOPIC))
+        );
+    }
+}
@@ -8,13 +8,144 @@ mod transaction_tests {
         transaction::{Transaction, TransactionErr},
         util::trace,
     };
-    use libp2p::identity;
+    use chrono::Utc;
+    use libp2p::{identity, PeerId};
 
     /* transaction tests */
+    #[test]
+    fn test_nonce_is_signed() {
+        let keys = identity::Keypair::generate_ed25519();
+        let txn = Transaction::with_nonce("receiver".to_string(), 1, 7, keys.clone());
+        assert!(trace(Transaction::validate_transaction(&txn)).is_ok());
+        // the nonce can't be changed or dropped without breaking the hash
+        for nonce in [Some(8), None] {
+            let altered = Transaction {
+                nonce,
+                ..txn.clone()
+            };
+            assert!(matches!(
+                Transaction::validate_signed(&altered),
+                Err(TransactionErr::HashMismatch { .. })
+            ));
+        }
+        // it round-trips through json, which omits it when there is none
+        let json: String = serde_json::to_string(&txn).unwrap();
+        assert_eq!(serde_json::from_str::<Transaction>(&json).unwrap(), txn);
+        let without = Transaction::new("receiver".to_string(), 1, keys);
+        assert!(!serde_json::to_string(&without).unwrap().contains("nonce"));
+    }
+
+    #[test]
+    fn test_no_hash_collisions_across_fields() {
+        let (sender, sender_pk) = ("sender".to_string(), "sender pk".to_string());
+        let hash = |receiver: &str, amount, timestamp, valid_until, nonce, fee| {
+            Transaction::compute_hash(
+                &sender,
+                &sender_pk,
+                &receiver.to_string(),
+                amount,
+                timestamp,
+                valid_until,
+                nonce,
+                fee,
+            )
+        };
+        // a receiver containing ':' can't absorb a field, shifting the others into the place of an optional one
+        assert_ne!(
+            hash("r", 5, 100, 200, Some(300), 0),
+            hash("r:5", 100, 200, 300, None, 0)
+        );
+        assert_ne!(
+            hash("r", 5, 100, 200, None, 300),
+            hash("r:5", 100, 200, 300, None, 0)
+        );
+        assert_ne!(
+            hash("r", 5, 100, 200, Some(300), 400),
+            hash("r:5", 100, 200, 300, None, 400)
+        );
+    }
+
+    #[test]
+    fn test_fee_is_signed() {
+        let keys = identity::Keypair::generate_ed25519();
+        let txn = Transaction::with_fee("receiver".to_string(), 1, 3, keys.clone());
+        assert!(trace(Transaction::validate_transaction(&txn)).is_ok());
+        assert_eq!(txn.total_debit(), Some(4));
+        // the fee can't be changed or dropped without breaking the hash
+        for fee in [4, 0] {
+            assert!(matches!(
+                Transaction::validate_signed(&Transaction { fee, ..txn.clone() }),
+                Err(TransactionErr::HashMismatch { .. })
+            ));
+        }
+        // a transaction without a fee is encoded (and hashed) as it was before fees existed
+        let without = Transaction::new("receiver".to_string(), 1, keys.clone());
+        assert!(!serde_json::to_string(&without).unwrap().contains("\"fee\""));
+
+        // a fee that overflows once added to the amount is rejected, though signed
+        let overflowing = Transaction::with_fee("receiver".to_string(), u64::MAX, 1, keys);
+        assert!(Transaction::validate_signed(&overflowing).is_ok());
+        assert!(matches!(
+            trace(Transaction::validate_transaction(&overflowing)),
+            Err(TransactionErr::FeeOverflow { .. })
+        ));
+    }
+
+    #[test]
+    fn test_coinbase() {
+        let coinbase = Transaction::coinbase("miner".to_string(), 50, 3, Utc::now().timestamp());
+        assert_eq!(coinbase.coinbase_idx(), Some(3));
+        // valid in a block, whenever it is mined, but never in the pool
+        assert!(trace(Transaction::validate_transaction_at(&coinbase, i64::MAX)).is_ok());
+        assert!(matches!(
+            trace(Transaction::validate_transaction(&coinbase)),
+            Err(TransactionErr::InvalidCoinbase { .. })
+        ));
+        // altering its reward or block breaks its hash
+        let mut altered = coinbase.clone();
+        altered.amount = 500;
+        assert!(matches!(
+            Transaction::validate_signed(&altered),
+            Err(TransactionErr::HashMismatch { .. })
+        ));
+        let other_block = Transaction::coinbase("miner".to_string(), 50, 4, coinbase.timestamp);
+        assert_ne!(coinbase.hash, other_block.hash);
+        // a coinbase can't be signed, as it has no sender
+        let signed = Transaction {
+            sig: encode_bytes_to_hex(ZERO_U64),
+            ..coinbase
+        };
+        assert!(matches!(
+            trace(Transaction::validate_signed(&signed)),
+            Err(TransactionErr::InvalidCoinbase { .. })
+        ));
+    }
+
+    #[test]
+    fn test_deterministic_transaction() {
+        // a fixed key, as ed25519 signatures depend only on the key and message
+        let secret = identity::ed25519::SecretKey::from_bytes([7u8; 32]).unwrap();
+        let keys = identity::Keypair::Ed25519(secret.into());
+        let new_txn =
+            || Transaction::new_at("receiver".to_string(), 42, 1_700_000_000, keys.clone());
+
+        let txn: Transaction = new_txn();
+        assert_eq!(txn, new_txn());
+        assert_eq!(
+            (txn.receiver.as_str(), txn.amount, txn.timestamp),
+            ("receiver", 42, 1_700_000_000)
+        );
+        assert!(Transaction::validate_signed(&txn).is_ok());
+        assert_eq!(
+            trace(txn.hash),
+            "73f8473378b0a20964b50d4f3d09ef26e1e612244f9a142afea5eeeece886bd8"
+        );
+    }
+
     #[test]
     fn test_valid_transaction() {
         let keys = identity::Keypair::generate_ed25519();
-        let valid_txn = Transaction::random_transaction("£0".to_string(), keys);
+        let valid_txn = Transaction::random_transaction(0, keys);
         assert!(matches!(
             Transaction::validate_transaction(&valid_txn),
             Ok(())
@@ -24,7 +155,7 @@ mod transaction_tests {
     #[test]
     fn test_invalid_transaction() {
         let keys = identity::Keypair::generate_ed25519();
-        let valid_txn: Transaction = Transaction::random_transaction("£0".to_string(), keys);
+        let valid_txn: Transaction = Transaction::random_transaction(0, keys);
 
         let invalid_hash = Transaction {
             hash: encode_bytes_to_hex(ZERO_U32),
@@ -62,4 +193,143 @@ mod transaction_tests {
             Err(TransactionErr::SigInvalid { .. })
         ));
     }
+
+    #[test]
+    fn test_sender_mismatch() {
+        let (keys, victim) = (
+            identity::Keypair::generate_ed25519(),
+            identity::Keypair::generate_ed25519(),
+        );
+        let valid_txn: Transaction = Transaction::random_transaction(5, keys.clone());
+        assert_eq!(valid_txn.sender, PeerId::from(keys.public()).to_string());
+        assert!(Transaction::validate_transaction(&valid_txn).is_ok());
+
+        // a well-formed key, hash, and signature, but claiming to be sent by someone else
+        let spoofed_sender: String = PeerId::from(victim.public()).to_string();
+        let hash: String = Transaction::compute_hash(
+            &spoofed_sender,
+            &valid_txn.sender_pubk,
+            &valid_txn.receiver,
+            valid_txn.amount,
+            valid_txn.timestamp,
+            valid_txn.valid_until,
+            valid_txn.nonce,
+            valid_txn.fee,
+        );
+        let spoofed = Transaction {
+            sender: spoofed_sender.clone(),
+            sig: encode_bytes_to_hex(keys.sign(hash.as_bytes()).unwrap()),
+            hash,
+            ..valid_txn
+        };
+        assert!(matches!(
+            trace(Transaction::validate_transaction(&spoofed)),
+            Err(TransactionErr::SenderMismatch { ref sender, .. }) if *sender == spoofed_sender
+        ));
+    }
+
+    #[test]
+    fn test_non_canonical_transaction() {
+        let keys = identity::Keypair::generate_ed25519();
+        let valid_txn: Transaction = Transaction::random_transaction(7, keys.clone());
+        assert!(Transaction::validate_canonical(&valid_txn).is_ok());
+
+        // an uppercase signature decodes to the same bytes, so would otherwise still verify
+        let uppercase_sig = Transaction {
+            sig: valid_txn.sig.to_uppercase(),
+            ..valid_txn.clone()
+        };
+        assert!(matches!(
+            trace(Transaction::validate_transaction(&uppercase_sig)),
+            Err(TransactionErr::NonCanonical { ref field, .. }) if field == "sig"
+        ));
+    }
+
+    #[test]
+    fn test_transaction_expiry() {
+        let keys = identity::Keypair::generate_ed25519();
+        let now: i64 = Utc::now().timestamp();
+
+        let txn: Transaction = Transaction::random_transaction(1, keys.clone());
+        assert!(txn.valid_until > now);
+        assert!(trace(Transaction::validate_transaction(&txn)).is_ok());
+
+        let expired: Transaction =
+            Transaction::with_expiry("receiver".to_string(), 1, now - 1, keys);
+        assert!(matches!(
+            trace(Transaction::validate_transaction(&expired)),
+            Err(TransactionErr::Expired { .. })
+        ));
+        // it was valid to mine before it expired, and its signature stays valid
+        assert!(Transaction::validate_transaction_at(&expired, now - 1).is_ok());
+        assert!(Transaction::validate_signed(&expired).is_ok());
+
+        // the expiry is signed, so it can't be extended
+        let extended = Transaction {
+            valid_until: now + 60,
+            ..expired
+        };
+        assert!(matches!(
+            trace(Transaction::validate_transaction(&extended)),
+            Err(TransactionErr::HashMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_amount_encoding() {
+        let keys = identity::Keypair::generate_ed25519();
+        let txn: Transaction = Transaction::random_transaction(7, keys);
+        // the amount is hashed as its decimal form, so hashes match those of string amounts
+        assert_eq!(
+            txn.hash,
+            Transaction::compute_hash(
+                &txn.sender,
+                &txn.sender_pubk,
+                &txn.receiver,
+                7,
+                txn.timestamp,
+                txn.valid_until,
+                txn.nonce,
+                txn.fee
+            )
+        );
+        let json: String = serde_json::to_string(&txn).unwrap();
+        assert!(json.contains("\"amount\":7"));
+        assert!(Transaction::verify_json(&json).is_ok());
+
+        // amounts that aren't non-negative integers are rejected when decoding
+        for bad in ["\"7\"", "\"abc\"", "-7", "7.5"] {
+            let json = json.replace("\"amount\":7", &format!("\"amount\":{}", bad));
+            assert!(matches!(
+                trace(Transaction::verify_json(&json)),
+                Err(TransactionErr::JsonDecodeErr { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn test_verify_json() {
+        let keys = identity::Keypair::generate_ed25519();
+        let valid_txn: Transaction = Transaction::random_transaction(7, keys);
+        let json = serde_json::to_string(&valid_txn).unwrap();
+        assert_eq!(
+            trace(Transaction::verify_json(&json)).ok(),
+            Some(valid_txn.clone())
+        );
+
+        let tampered_hash = Transaction {
+            hash: encode_bytes_to_hex(ZERO_U32),
+            ..valid_txn
+        };
+        let json = serde_json::to_string(&tampered_hash).unwrap();
+        assert!(matches!(
+            trace(Transaction::verify_json(&json)),
+            Err(TransactionErr::HashMismatch { .. })
+        ));
+
+        assert!(matches!(
+            trace(Transaction::verify_json("{\"sender\": ")),
+            Err(TransactionErr::JsonDecodeErr { .. })
+        ));
+    }
 }
@@ -0,0 +1,116 @@
+/******************
+      TESTS
+********************/
+#[cfg(test)] // cargo test util -- --nocapture
+mod util_tests {
+    use crate::util::{
+        console_line, is_quiet, log_json, set_quiet, RateCounter, RECEIVED_TARGET, UPDATE_TARGET,
+    };
+    use log::{Level, LevelFilter, Log, Metadata, Record};
+    use once_cell::sync::Lazy;
+    use std::{
+        sync::Mutex,
+        time::{Duration, Instant},
+    };
+
+    // Records the target, level, and message of every log record, for inspection
+    struct CaptureLogger(Mutex<Vec<(String, Level, String)>>);
+
+    impl Log for CaptureLogger {
+        fn enabled(&self, _: &Metadata) -> bool {
+            true
+        }
+        fn log(&self, record: &Record) {
+            self.0.lock().unwrap().push((
+                record.target().to_string(),
+                record.level(),
+                record.args().to_string(),
+            ))
+        }
+        fn flush(&self) {}
+    }
+
+    static LOGGER: Lazy<CaptureLogger> = Lazy::new(|| CaptureLogger(Mutex::new(vec![])));
+
+    // Capture log records from here on, whichever test does so first
+    fn capture_logs() {
+        let _ = log::set_logger(&*LOGGER);
+        log::set_max_level(LevelFilter::Trace);
+    }
+
+    #[test]
+    fn test_rate_counter_window() {
+        let mut counter = RateCounter::new(Duration::from_secs(10));
+        let start = Instant::now();
+        for _ in 0..20 {
+            counter.record_at(start);
+        }
+        assert_eq!(counter.rate_at(start), 2.0);
+
+        // events older than the window are no longer counted
+        let later = start + Duration::from_secs(11);
+        counter.record_at(later);
+        assert_eq!(counter.rate_at(later), 0.1);
+    }
+
+    #[test]
+    fn test_update_log_record() {
+        capture_logs();
+
+        // other tests may log concurrently, so look for our records by their messages
+        update!("test update {}", 1);
+        received!("test received {}", 2);
+        let records = LOGGER.0.lock().unwrap();
+        let find = |msg: &str| records.iter().find(|(_, _, m)| m == msg).cloned();
+        assert_eq!(
+            find("test update 1"),
+            Some((
+                UPDATE_TARGET.to_string(),
+                Level::Info,
+                "test update 1".into()
+            ))
+        );
+        assert_eq!(
+            find("test received 2"),
+            Some((
+                RECEIVED_TARGET.to_string(),
+                Level::Debug,
+                "test received 2".into()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_log_json() {
+        let json: serde_json::Value =
+            serde_json::from_str(&log_json(UPDATE_TARGET, Level::Info, "a \"quoted\" update"))
+                .unwrap();
+        assert_eq!(json["target"], UPDATE_TARGET);
+        assert_eq!(json["level"], "info");
+        assert_eq!(json["msg"], "a \"quoted\" update");
+        assert!(json["timestamp"].is_string());
+    }
+
+    #[test]
+    fn test_quiet() {
+        capture_logs();
+        let line = || {
+            console_line(
+                UPDATE_TARGET,
+                Level::Info,
+                "Internal update",
+                format_args!("hi"),
+            )
+        };
+        assert!(!is_quiet());
+        assert!(line().is_some_and(|l| l.contains("hi")));
+
+        // nothing is printed, but updates are still logged
+        set_quiet(true);
+        assert_eq!(line(), None);
+        update!("quiet update");
+        set_quiet(false);
+        let records = LOGGER.0.lock().unwrap();
+        assert!(records.iter().any(|(_, _, m)| m == "quiet update"));
+    }
+}
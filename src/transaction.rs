@@ -6,7 +6,7 @@
 
 use super::crypt::{
     decode_hex_to_bytes, decode_hex_to_pubk, encode_bytes_to_hex, encode_pubk_to_hex,
-    random_string, HexDecodeErr,
+    random_string, HexDecodeErr, PUBK_U8S_LEN, SIG_U8S_LEN,
 };
 
 use chrono::{DateTime, Utc};
@@ -20,29 +20,119 @@ use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 use std::fmt;
 
-const PUBK_U8S_LEN: usize = 36;
-const SIG_U8S_LEN: usize = 64;
+// How long (in seconds) a new transaction may wait to be mined before it expires
+pub const TXN_VALIDITY: i64 = 24 * 60 * 60;
+
+// Sender of a coinbase, the transaction through which a block's miner is rewarded with newly created coins
+pub const COINBASE_SENDER: &str = "coinbase";
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
 pub struct Transaction {
     pub sender: String,      // peer id of the sender
-    pub sender_pubk: String, // 32-byte (but stored as 36 bytes!) public key of the sender, assuming ed25519
+    pub sender_pubk: String, // 32-byte (but stored as 36 bytes!) public key of the sender, assuming ed25519 (or for a coinbase, the idx of its block)
     pub receiver: String,    // peer id of the receiver
-    pub amount: String,      // amount transferred, a string for testing
+    pub amount: u64,         // amount transferred
     pub timestamp: i64,      // creation date
+    #[serde(default)]
+    pub valid_until: i64, // expiry date, after which it may no longer be mined
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<u64>, // sender's sequence number (if any), so that a newer transaction with the same one replaces it while pending
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub fee: u64, // amount paid to the miner on top of the amount transferred, so that higher-fee transactions are mined first
 
     pub hash: String, // 32-byte hash of the above data, assuming sha256
     pub sig: String,  // 32-byte signature of the hash, assuming ed25519
 }
 
+fn is_zero(fee: &u64) -> bool {
+    *fee == 0
+}
+
 impl Transaction {
-    pub fn random_transaction(amount: String, keys: Keypair) -> Self {
+    pub fn random_transaction(amount: u64, keys: Keypair) -> Self {
+        Self::new(format!("0x{}", random_string(40)), amount, keys)
+    }
+
+    pub fn new(receiver: String, amount: u64, keys: Keypair) -> Self {
+        Self::new_at(receiver, amount, Utc::now().timestamp(), keys)
+    }
+
+    // Create a transaction at the given time, expiring as usual after it, so that the same inputs always give the same transaction
+    pub fn new_at(receiver: String, amount: u64, timestamp: i64, keys: Keypair) -> Self {
+        Self::signed(
+            receiver,
+            amount,
+            timestamp,
+            timestamp + TXN_VALIDITY,
+            None,
+            0,
+            keys,
+        )
+    }
+
+    pub fn with_expiry(receiver: String, amount: u64, valid_until: i64, keys: Keypair) -> Self {
+        Self::signed(
+            receiver,
+            amount,
+            Utc::now().timestamp(),
+            valid_until,
+            None,
+            0,
+            keys,
+        )
+    }
+
+    // Create a transaction with the sender's sequence number, replacing any pending transaction of theirs with the same one
+    pub fn with_nonce(receiver: String, amount: u64, nonce: u64, keys: Keypair) -> Self {
+        let timestamp: i64 = Utc::now().timestamp();
+        Self::signed(
+            receiver,
+            amount,
+            timestamp,
+            timestamp + TXN_VALIDITY,
+            Some(nonce),
+            0,
+            keys,
+        )
+    }
+
+    // Create a transaction that pays the given fee to whoever mines it, which is debited from the sender along with the amount
+    pub fn with_fee(receiver: String, amount: u64, fee: u64, keys: Keypair) -> Self {
+        let timestamp: i64 = Utc::now().timestamp();
+        Self::signed(
+            receiver,
+            amount,
+            timestamp,
+            timestamp + TXN_VALIDITY,
+            None,
+            fee,
+            keys,
+        )
+    }
+
+    // Sign exactly the given fields with the sender's keys
+    fn signed(
+        receiver: String,
+        amount: u64,
+        timestamp: i64,
+        valid_until: i64,
+        nonce: Option<u64>,
+        fee: u64,
+        keys: Keypair,
+    ) -> Self {
         let sender: String = PeerId::from(keys.public()).to_string();
         let sender_pubk: String = encode_pubk_to_hex(keys.public());
 
-        let receiver: String = format!("0x{}", random_string(40));
-        let timestamp: i64 = Utc::now().timestamp();
-        let hash: String = Self::compute_hash(&sender, &sender_pubk, &receiver, &amount, timestamp);
+        let hash: String = Self::compute_hash(
+            &sender,
+            &sender_pubk,
+            &receiver,
+            amount,
+            timestamp,
+            valid_until,
+            nonce,
+            fee,
+        );
 
         let sig: String = match keys.sign(hash.as_bytes()) {
             Ok(sig_u8s) => encode_bytes_to_hex(sig_u8s),
@@ -60,34 +150,143 @@ impl Transaction {
             receiver,
             amount,
             timestamp,
+            valid_until,
+            nonce,
+            fee,
             hash,
             sig,
         }
     }
 
-    fn compute_hash(
+    /* Reward the miner of the block at the given idx.
+    A coinbase has no sender to sign it, so it records its block's idx in place of the sender's key,
+    which also makes the coinbases of different blocks distinct. */
+    pub fn coinbase(receiver: String, amount: u64, block_idx: usize, timestamp: i64) -> Self {
+        let sender: String = COINBASE_SENDER.to_string();
+        let sender_pubk: String = block_idx.to_string();
+        let hash: String = Self::compute_hash(
+            &sender,
+            &sender_pubk,
+            &receiver,
+            amount,
+            timestamp,
+            timestamp,
+            None,
+            0,
+        );
+        Transaction {
+            sender,
+            sender_pubk,
+            receiver,
+            amount,
+            timestamp,
+            valid_until: timestamp,
+            nonce: None,
+            fee: 0,
+            hash,
+            sig: String::new(),
+        }
+    }
+
+    pub fn is_coinbase(&self) -> bool {
+        self.sender == COINBASE_SENDER
+    }
+
+    // The idx of the block that a coinbase rewards the miner of
+    pub fn coinbase_idx(&self) -> Option<usize> {
+        self.is_coinbase()
+            .then(|| self.sender_pubk.parse().ok())
+            .flatten()
+    }
+
+    // Everything the sender is debited for a transaction, or None if the amount and fee overflow
+    pub fn total_debit(&self) -> Option<u64> {
+        self.amount.checked_add(self.fee)
+    }
+
+    // The fees that a block's miner collects from its transactions (besides any coinbase)
+    pub fn total_fees(txns: &[Transaction]) -> u64 {
+        txns.iter()
+            .filter(|txn| !txn.is_coinbase())
+            .fold(0, |fees, txn| fees.saturating_add(txn.fee))
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn compute_hash(
         sender: &String,
         sender_pk: &String,
         receiver: &String,
-        amount: &String,
+        amount: u64,
         timestamp: i64,
+        valid_until: i64,
+        nonce: Option<u64>,
+        fee: u64,
     ) -> String {
         let mut hasher: Sha256 = Sha256::new();
-        let message: String = format!(
-            "{}:{}:{}:{}:{}",
-            sender, sender_pk, receiver, amount, timestamp
+        // the amount is hashed as its decimal representation, as it was when stored as a string
+        let mut message: String = format!(
+            "{}:{}:{}:{}:{}:{}",
+            sender, sender_pk, receiver, amount, timestamp, valid_until
         );
+        /* only a transaction with a nonce commits to one, so that the hashes of those without stay the same;
+        it is tagged, as the receiver may contain ':' and would otherwise let the fields before it shift into its place */
+        if let Some(nonce) = nonce {
+            message.push_str(&format!(":nonce:{}", nonce));
+        }
+        // likewise for a fee, which is tagged so that it can't be mistaken for a nonce or another field
+        if fee > 0 {
+            message.push_str(&format!(":fee:{}", fee));
+        }
         hasher.update(message);
         encode_bytes_to_hex(hasher.finalize())
     }
 
+    // Validate a transaction that is to be added to the pool or mined now
     pub fn validate_transaction(txn: &Transaction) -> Result<(), TransactionErr> {
+        // a coinbase is created along with the block it rewards, so is never pooled or mined separately
+        if txn.is_coinbase() {
+            return Err(TransactionErr::InvalidCoinbase {
+                hash: txn.hash.clone(),
+                reason: "coinbases can only be mined by the block they reward".to_string(),
+            });
+        }
+        Self::validate_transaction_at(txn, Utc::now().timestamp())
+    }
+
+    // Validate a transaction that is to be mined at the given time, e.g. in a block with that timestamp
+    pub fn validate_transaction_at(txn: &Transaction, now: i64) -> Result<(), TransactionErr> {
+        Self::validate_signed(txn)?;
+        if txn.total_debit().is_none() {
+            return Err(TransactionErr::FeeOverflow {
+                hash: txn.hash.clone(),
+                amount: txn.amount,
+                fee: txn.fee,
+            });
+        }
+        // a coinbase is only ever mined in the block it rewards, so it can't go stale
+        if txn.valid_until < now && !txn.is_coinbase() {
+            return Err(TransactionErr::Expired {
+                hash: txn.hash.clone(),
+                valid_until: txn.valid_until,
+                now,
+            });
+        }
+        Ok(())
+    }
+
+    // Validate that a transaction was signed by its sender and hasn't been altered since, regardless of whether it has expired
+    pub fn validate_signed(txn: &Transaction) -> Result<(), TransactionErr> {
+        // check each field has a single representation, so the hash and signature can't be altered
+        Self::validate_canonical(txn)?;
         let hash: String = Transaction::compute_hash(
             &txn.sender,
             &txn.sender_pubk,
             &txn.receiver,
-            &txn.amount,
+            txn.amount,
             txn.timestamp,
+            txn.valid_until,
+            txn.nonce,
+            txn.fee,
         );
         // check message integrity
         if hash != txn.hash {
@@ -96,6 +295,9 @@ impl Transaction {
                 computed_hash: hash,
             });
         }
+        if txn.is_coinbase() {
+            return Self::validate_coinbase(txn);
+        }
         // check message signature
         let pubk: PublicKey = match decode_hex_to_pubk(&txn.sender_pubk, PUBK_U8S_LEN) {
             Ok(pubk) => pubk,
@@ -103,6 +305,14 @@ impl Transaction {
                 return Err(TransactionErr::PubKeyDecodeErr { e });
             }
         };
+        // check the declared sender is the peer id of the key that signed it, so a sender can't be claimed by anyone else
+        let derived: String = PeerId::from(pubk.clone()).to_string();
+        if derived != txn.sender {
+            return Err(TransactionErr::SenderMismatch {
+                sender: txn.sender.clone(),
+                derived,
+            });
+        }
 
         let sig_u8s: Vec<u8> = match decode_hex_to_bytes(&txn.sig, SIG_U8S_LEN) {
             Ok(sig_u8s) => sig_u8s,
@@ -118,6 +328,60 @@ impl Transaction {
         }
         Ok(())
     }
+
+    // Check that a coinbase records its block's idx in a single representation, and isn't signed as it has no sender
+    fn validate_coinbase(txn: &Transaction) -> Result<(), TransactionErr> {
+        let invalid = |reason: &str| TransactionErr::InvalidCoinbase {
+            hash: txn.hash.clone(),
+            reason: reason.to_string(),
+        };
+        match txn.coinbase_idx() {
+            Some(idx) if idx.to_string() == txn.sender_pubk => {}
+            _ => return Err(invalid("it doesn't record the idx of its block")),
+        }
+        if !txn.sig.is_empty() {
+            return Err(invalid(
+                "it has a signature, though it has no sender to sign it",
+            ));
+        }
+        if txn.fee > 0 {
+            return Err(invalid("it pays a fee, though it has no sender to pay it"));
+        }
+        Ok(())
+    }
+
+    // Parse a transaction from json and validate it, without adding it to any pool
+    pub fn verify_json(json: &str) -> Result<Transaction, TransactionErr> {
+        let txn: Transaction = serde_json::from_str(json)
+            .map_err(|e| TransactionErr::JsonDecodeErr { e: e.to_string() })?;
+        Self::validate_transaction(&txn)?;
+        Ok(txn)
+    }
+
+    // Whether two transactions take the same sequence number of the same sender, so can't both be pending
+    pub fn conflicts_with(&self, other: &Transaction) -> bool {
+        self.nonce.is_some() && self.nonce == other.nonce && self.sender == other.sender
+    }
+
+    // Check that encoded fields are lowercase hex.
+    // (Fixed lengths of the public key and signature are enforced when decoding them.)
+    pub fn validate_canonical(txn: &Transaction) -> Result<(), TransactionErr> {
+        let is_canonical_hex = |hex: &str| hex.chars().all(|c| matches!(c, '0'..='9' | 'a'..='f'));
+        let non_canonical = |field: &str, value: &String| TransactionErr::NonCanonical {
+            field: field.to_string(),
+            value: value.clone(),
+        };
+        if !is_canonical_hex(&txn.sender_pubk) {
+            return Err(non_canonical("sender_pubk", &txn.sender_pubk));
+        }
+        if !is_canonical_hex(&txn.hash) {
+            return Err(non_canonical("hash", &txn.hash));
+        }
+        if !is_canonical_hex(&txn.sig) {
+            return Err(non_canonical("sig", &txn.sig));
+        }
+        Ok(())
+    }
 }
 
 impl std::fmt::Display for Transaction {
@@ -132,6 +396,9 @@ impl std::fmt::Display for Transaction {
             Receiver:        {}\n\
             Amount:          {}\n\
             Timestamp:       {}\n\
+            Valid Until:     {}\n\
+            Nonce:           {}\n\
+            Fee:             {}\n\
             Hash:            {}\n\
             Signature:       {}\n\
             ================================================",
@@ -140,6 +407,11 @@ impl std::fmt::Display for Transaction {
             self.receiver,
             self.amount,
             DateTime::from_timestamp(self.timestamp, 0).expect("can convert timestamp"),
+            DateTime::from_timestamp(self.valid_until, 0)
+                .map_or_else(|| self.valid_until.to_string(), |date| date.to_string()),
+            self.nonce
+                .map_or_else(|| "none".to_string(), |nonce| nonce.to_string()),
+            self.fee,
             self.hash,
             self.sig
         )
@@ -163,6 +435,40 @@ pub enum TransactionErr {
         hash: String,
         sig: String,
     },
+    NonCanonical {
+        field: String,
+        value: String,
+    },
+    JsonDecodeErr {
+        e: String,
+    },
+    InsufficientFunds {
+        sender: String,
+        balance: i64,
+        amount: u64,
+    },
+    Expired {
+        hash: String,
+        valid_until: i64,
+        now: i64,
+    },
+    SenderMismatch {
+        sender: String,
+        derived: String,
+    },
+    InvalidCoinbase {
+        hash: String,
+        reason: String,
+    },
+    FeeOverflow {
+        hash: String,
+        amount: u64,
+        fee: u64,
+    },
+    AlreadyMined {
+        hash: String,
+        idx: usize,
+    },
 }
 
 impl fmt::Display for TransactionErr {
@@ -191,6 +497,66 @@ impl fmt::Display for TransactionErr {
                     pubk, hash, sig
                 )
             }
+            TransactionErr::NonCanonical { field, value } => {
+                write!(
+                    f,
+                    "Non-Canonical Encoding: field {} has non-canonical value ({})",
+                    field, value
+                )
+            }
+            TransactionErr::JsonDecodeErr { e } => {
+                write!(f, "Json Decode Error: {}", e)
+            }
+            TransactionErr::InsufficientFunds {
+                sender,
+                balance,
+                amount,
+            } => {
+                write!(
+                    f,
+                    "Insufficient Funds: sender ({}) with balance ({}) cannot afford amount plus fee ({})",
+                    sender, balance, amount
+                )
+            }
+            TransactionErr::Expired {
+                hash,
+                valid_until,
+                now,
+            } => {
+                write!(
+                    f,
+                    "Expired: transaction ({}) was valid until {}, which is before {}",
+                    hash, valid_until, now
+                )
+            }
+            TransactionErr::SenderMismatch { sender, derived } => {
+                write!(
+                    f,
+                    "Sender Mismatch: sender ({}) is not the peer id of the public key, which is ({})",
+                    sender, derived
+                )
+            }
+            TransactionErr::InvalidCoinbase { hash, reason } => {
+                write!(
+                    f,
+                    "Invalid Coinbase: coinbase ({}) is invalid as {}",
+                    hash, reason
+                )
+            }
+            TransactionErr::FeeOverflow { hash, amount, fee } => {
+                write!(
+                    f,
+                    "Fee Overflow: transaction ({}) debits its amount ({}) plus fee ({}), which overflows",
+                    hash, amount, fee
+                )
+            }
+            TransactionErr::AlreadyMined { hash, idx } => {
+                write!(
+                    f,
+                    "Already Mined: transaction ({}) is already in block {} of the main chain",
+                    hash, idx
+                )
+            }
         }
     }
 }
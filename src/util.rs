@@ -1,13 +1,75 @@
+use chrono::Utc;
+use log::Level;
+use once_cell::sync::Lazy;
+use std::{
+    collections::VecDeque,
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant},
+};
+
+// Whether to print updates as json lines (with `P2P_LOG_JSON=1`), e.g. for a log collector, rather than for people to read
+static LOG_JSON: Lazy<bool> = Lazy::new(|| std::env::var("P2P_LOG_JSON").is_ok_and(|v| v == "1"));
+
+// Whether to stop printing updates to the console (while still logging them), e.g. when embedding the crate as a library
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed)
+}
+
+pub fn is_quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+pub const UPDATE_TARGET: &str = "peer::update";
+pub const RECEIVED_TARGET: &str = "peer::received";
+pub const RESPONDED_TARGET: &str = "peer::responded";
+
 pub fn update(msg: std::fmt::Arguments) {
-    println!("[Internal update]:\n{}", msg);
+    report(UPDATE_TARGET, Level::Info, "Internal update", msg)
 }
 
 pub fn received(msg: std::fmt::Arguments) {
-    println!("[Received message]:\n{}", msg);
+    report(RECEIVED_TARGET, Level::Debug, "Received message", msg)
 }
 
 pub fn responded(msg: std::fmt::Arguments) {
-    println!("[Broadcasted message]:\n{}", msg);
+    report(RESPONDED_TARGET, Level::Debug, "Broadcasted message", msg)
+}
+
+// Log an update under its category (so that it can be filtered by e.g. `RUST_LOG=peer::update=info`), and print it unless quiet
+fn report(target: &str, level: Level, heading: &str, msg: std::fmt::Arguments) {
+    log::log!(target: target, level, "{}", msg);
+    if let Some(line) = console_line(target, level, heading, msg) {
+        println!("{}", line);
+    }
+}
+
+// What an update prints to the console, if anything
+pub fn console_line(
+    target: &str,
+    level: Level,
+    heading: &str,
+    msg: std::fmt::Arguments,
+) -> Option<String> {
+    if is_quiet() {
+        None
+    } else if *LOG_JSON {
+        Some(log_json(target, level, &msg.to_string()))
+    } else {
+        Some(format!("[{}]:\n{}", heading, msg))
+    }
+}
+
+// An update as a single line of json, with its time, level, and category as fields
+pub fn log_json(target: &str, level: Level, msg: &str) -> String {
+    serde_json::json!({
+        "timestamp": Utc::now().to_rfc3339(),
+        "level": level.as_str().to_lowercase(),
+        "target": target,
+        "msg": msg,
+    })
+    .to_string()
 }
 
 pub fn trace<T: std::fmt::Debug>(x: T) -> T {
@@ -24,6 +86,52 @@ pub fn abbrev(hex: &str) -> String {
     s
 }
 
+// Counts events over a sliding window, for reporting how often they occur
+#[derive(Clone, Debug)]
+pub struct RateCounter {
+    window: Duration,
+    events: VecDeque<Instant>,
+}
+
+impl RateCounter {
+    pub fn new(window: Duration) -> Self {
+        RateCounter {
+            window,
+            events: VecDeque::new(),
+        }
+    }
+
+    pub fn record(&mut self) {
+        self.record_at(Instant::now())
+    }
+
+    // Record an event, forgetting any that have fallen out of the window
+    pub fn record_at(&mut self, now: Instant) {
+        while let Some(&oldest) = self.events.front() {
+            if now.duration_since(oldest) > self.window {
+                self.events.pop_front();
+            } else {
+                break;
+            }
+        }
+        self.events.push_back(now);
+    }
+
+    pub fn rate(&self) -> f64 {
+        self.rate_at(Instant::now())
+    }
+
+    // Events per second over the window ending at `now`
+    pub fn rate_at(&self, now: Instant) -> f64 {
+        let in_window: usize = self
+            .events
+            .iter()
+            .filter(|&&t| now.duration_since(t) <= self.window)
+            .count();
+        in_window as f64 / self.window.as_secs_f64()
+    }
+}
+
 #[macro_export]
 macro_rules! update {
     ($msg:expr $(, $args:expr)*) => {